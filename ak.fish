@@ -1,32 +1,246 @@
 # Disable default file completion at top-level
 complete -c ak -f
 
+# Global options
+complete -c ak -l color -x -a "auto always never" -d "Color the output"
+
 # Top-level subcommands
 complete -c ak -n "__fish_use_subcommand" -a init -d "init data"
-complete -c ak -n "__fish_use_subcommand" -a inscribe -d "track data from a path into the current cube"
+complete -c ak -n "__fish_use_subcommand" -a inscribe -d "track data from paths into the current cube"
+complete -c ak -n "__fish_use_subcommand" -a add -d "stage changes for the next seal"
+complete -c ak -n "__fish_use_subcommand" -a mv -d "move or rename a tracked file or directory and stage the rename"
+complete -c ak -n "__fish_use_subcommand" -a rm -d "delete tracked files and stage their removal"
 complete -c ak -n "__fish_use_subcommand" -a seal -d "register a commit into the current cube"
 complete -c ak -n "__fish_use_subcommand" -a timeline -d "show event timeline (commits)"
 complete -c ak -n "__fish_use_subcommand" -a view -d "show the latest commit"
+complete -c ak -n "__fish_use_subcommand" -a hooks -d "run or list the pre-commit steps"
+complete -c ak -n "__fish_use_subcommand" -a check -d "check the repository for CI: hooks, drift from the last seal, messages"
+complete -c ak -n "__fish_use_subcommand" -a check-ignore -d "explain whether paths are ignored, and by which rule"
+complete -c ak -n "__fish_use_subcommand" -a config -d "get or set user.name, user.email, user.signingkey and editor"
+complete -c ak -n "__fish_use_subcommand" -a clone -d "copy a remote repository and check out its latest commit"
+complete -c ak -n "__fish_use_subcommand" -a export-git -d "replay every seal as a commit of a Git repository"
+complete -c ak -n "__fish_use_subcommand" -a import-git -d "seal the history of a Git repository"
+complete -c ak -n "__fish_use_subcommand" -a remote -d "manage the repositories to push to and pull from"
+complete -c ak -n "__fish_use_subcommand" -a push -d "send the records the remote lacks"
+complete -c ak -n "__fish_use_subcommand" -a pull -d "bring in the records of the remote"
 complete -c ak -n "__fish_use_subcommand" -a diff -d "show changes since the last seal"
+complete -c ak -n "__fish_use_subcommand" -a branch -d "list branches, or create one at the current head"
+complete -c ak -n "__fish_use_subcommand" -a switch -d "switch the working directory to another branch"
+complete -c ak -n "__fish_use_subcommand" -a tag -d "name a commit, or list and delete tags"
+complete -c ak -n "__fish_use_subcommand" -a show -d "show a commit in detail"
+complete -c ak -n "__fish_use_subcommand" -a changelog -d "render the commits between two tags as a Markdown changelog"
+complete -c ak -n "__fish_use_subcommand" -a release -d "tag the current head as a release and print its changelog"
+complete -c ak -n "__fish_use_subcommand" -a revert -d "undo a commit with a new seal"
+complete -c ak -n "__fish_use_subcommand" -a cherry-pick -d "re-apply a commit's changes with a new seal"
+complete -c ak -n "__fish_use_subcommand" -a format-patch -d "write seals as mailbox patches"
+complete -c ak -n "__fish_use_subcommand" -a apply -d "apply mailbox patches or unified diffs to the working directory"
+complete -c ak -n "__fish_use_subcommand" -a merge -d "merge another branch into the current one"
+complete -c ak -n "__fish_use_subcommand" -a verify -d "check repository integrity and commit signatures"
+complete -c ak -n "__fish_use_subcommand" -a blame -d "show the commit that last changed every line of a file"
+complete -c ak -n "__fish_use_subcommand" -a grep -d "search the sealed files, or every version of them, for a pattern"
+complete -c ak -n "__fish_use_subcommand" -a bisect -d "search the history for the commit that introduced a bug"
+complete -c ak -n "__fish_use_subcommand" -a note -d "annotate seals without rewriting them"
+complete -c ak -n "__fish_use_subcommand" -a reflog -d "list the operations that moved a head, newest first"
+complete -c ak -n "__fish_use_subcommand" -a gc -d "drop old pending records, superseded commits and unreachable file versions"
+complete -c ak -n "__fish_use_subcommand" -a prune -d "fold old monthly cubes into one and archive the file versions nothing needs"
+complete -c ak -n "__fish_use_subcommand" -a daemon -d "serve the appends to your cube from one process, until stopped"
+complete -c ak -n "__fish_use_subcommand" -a tree -d "inspect the reference tree"
+complete -c ak -n "__fish_use_subcommand" -a snapshots -d "list the per-seal snapshots kept by [storage] keep-snapshots, or prune them"
+complete -c ak -n "__fish_use_subcommand" -a stats -d "summarize the history: commits, most changed files, storage"
+complete -c ak -n "__fish_use_subcommand" -a stash -d "put unsealed changes aside, or bring them back"
+complete -c ak -n "__fish_use_subcommand" -a worktree -d "manage working directories linked to this repository"
+complete -c ak -n "__fish_use_subcommand" -a restore -d "restore files as they were at a past commit"
+complete -c ak -n "__fish_use_subcommand" -a materialize -d "write the whole tree of a commit into a scratch directory"
+complete -c ak -n "__fish_use_subcommand" -a archive -d "pack the files of a commit into a .tar.gz or .zip"
+complete -c ak -n "__fish_use_subcommand" -a status -d "summarize changes since the last seal"
+
+# --- init ---
+complete -c ak -n "__fish_seen_subcommand_from init" -l layout -r -a "per-author shared" -d "Cube layout"
+
+# --- config ---
+complete -c ak -n "__fish_seen_subcommand_from config" -a "user.name user.email user.signingkey editor" -d "Setting"
+complete -c ak -n "__fish_seen_subcommand_from config" -l global -d "Use the user configuration"
+complete -c ak -n "__fish_seen_subcommand_from config" -l unset -d "Remove the setting"
+complete -c ak -n "__fish_seen_subcommand_from config" -s l -l list -d "List the effective settings"
+
+# --- hooks ---
+complete -c ak -n "__fish_seen_subcommand_from hooks; and not __fish_seen_subcommand_from run list" -a run -d "run the pre-commit steps now"
+complete -c ak -n "__fish_seen_subcommand_from hooks; and not __fish_seen_subcommand_from run list" -a list -d "list the steps of .eikyu/hooks.toml"
+complete -c ak -n "__fish_seen_subcommand_from hooks; and __fish_seen_subcommand_from run" -l skip -r -d "Leave out this pre-commit step"
+
+# --- check ---
+complete -c ak -n "__fish_seen_subcommand_from check" -l from -r -a "(ak tag list 2>/dev/null | string split -f1 ' ')" -d "Check the messages after this tag or commit"
+complete -c ak -n "__fish_seen_subcommand_from check" -l to -r -a "(ak tag list 2>/dev/null | string split -f1 ' ')" -d "Check the messages up to this tag or commit"
+complete -c ak -n "__fish_seen_subcommand_from check" -l no-hooks -d "Do not run the pre-commit steps"
+complete -c ak -n "__fish_seen_subcommand_from check" -l skip -r -d "Leave out this pre-commit step"
+complete -c ak -n "__fish_seen_subcommand_from check" -l json -d "Print the findings as JSON"
+
+# --- check-ignore ---
+complete -c ak -n "__fish_seen_subcommand_from check-ignore" -F -d "Path to check"
 
 # --- inscribe ---
-# Positional path (optional) — suggest directories
-complete -c ak -n "__fish_seen_subcommand_from inscribe" -a "(__fish_complete_directories)" -d "Path to scan (default: .)"
+# Positional paths (optional) — suggest files and directories
+complete -c ak -n "__fish_seen_subcommand_from inscribe" -F -d "Path to scan (default: .)"
+complete -c ak -n "__fish_seen_subcommand_from inscribe" -l exclude -r -d "Skip files and directories matching this glob"
+complete -c ak -n "__fish_seen_subcommand_from inscribe" -l no-verify -d "Bypass the pre-commit steps"
+complete -c ak -n "__fish_seen_subcommand_from inscribe" -l skip -r -d "Leave out this pre-commit step"
+
+# --- add ---
+complete -c ak -n "__fish_seen_subcommand_from add" -F -d "Path to stage"
+complete -c ak -n "__fish_seen_subcommand_from add" -s p -l patch -d "Interactively choose the hunks to stage"
+
+# --- mv / rm ---
+complete -c ak -n "__fish_seen_subcommand_from mv" -F -d "Path to move"
+complete -c ak -n "__fish_seen_subcommand_from rm" -F -d "Path to remove"
 
 # --- seal ---
+complete -c ak -n "__fish_seen_subcommand_from seal" -s a -l all -d "Seal the whole working directory"
+complete -c ak -n "__fish_seen_subcommand_from seal" -l amend -d "Replace the last commit of the branch"
+complete -c ak -n "__fish_seen_subcommand_from seal" -l allow-empty -d "Seal even when nothing changed since the last seal"
+complete -c ak -n "__fish_seen_subcommand_from seal" -l sign -d "Sign the commit with the SSH key of user.signingkey"
 # -t/--type with suggestions
 complete -c ak -n "__fish_seen_subcommand_from seal" -s t -l type -r -a "feat fix refactor docs test chore" -d "Commit type"
 # -s/--summary requires a value
 complete -c ak -n "__fish_seen_subcommand_from seal" -s s -l summary -r -d "Commit summary"
 # -b/--body requires a value
 complete -c ak -n "__fish_seen_subcommand_from seal" -s b -l body -r -d "Commit body"
+complete -c ak -n "__fish_seen_subcommand_from seal" -l no-verify -d "Bypass the pre-commit steps"
+complete -c ak -n "__fish_seen_subcommand_from seal" -l skip -r -d "Leave out this pre-commit step"
 
 # --- timeline ---
 complete -c ak -n "__fish_seen_subcommand_from timeline" -l utc -d "Display timestamps in UTC"
 complete -c ak -n "__fish_seen_subcommand_from timeline" -l iso -d "Display timestamps in ISO 8601"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -s n -l limit -r -d "Only show the N most recent matching commits"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l since -r -d "Only commits sealed on or after this date"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l until -r -d "Only commits sealed on or before this date"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -s t -l type -r -a "feat fix refactor docs test chore revert merge" -d "Only commits of this type"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l grep -r -d "Only commits whose summary or body contains this text"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l author -r -d "Only commits whose author name or email contains this text"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l note -r -d "Only commits with a note containing this text"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l reverse -d "Show the newest commits first"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l json -d "Print the commits as a JSON array"
+complete -c ak -n "__fish_seen_subcommand_from timeline" -l graph -d "Draw the parent/branch graph, newest commits first"
 
 # --- view ---
 # no flags/args
 
 # --- diff ---
-# no flags/args
\ No newline at end of file
+complete -c ak -n "__fish_seen_subcommand_from diff" -F
+complete -c ak -n "__fish_seen_subcommand_from diff" -l normalize-eol -d "Treat CRLF and LF line endings as equal"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l ignore-cr-at-eol -d "Treat CRLF and LF line endings as equal"
+complete -c ak -n "__fish_seen_subcommand_from diff" -s b -l ignore-space-change -d "Ignore changes in the amount of whitespace"
+complete -c ak -n "__fish_seen_subcommand_from diff" -s w -l ignore-all-space -d "Ignore whitespace when comparing lines"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l binary-similarity -d "Show the share of bytes modified binary files keep"
+complete -c ak -n "__fish_seen_subcommand_from diff" -s U -l unified -r -d "Lines of context around every change"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l stat -d "Only show the added and removed lines of every file"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l name-only -d "Only list the changed paths"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l name-status -d "List the changed paths with their A, M, D or R code"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l json -d "Print every changed file with its hunks as JSON"
+complete -c ak -n "__fish_seen_subcommand_from diff" -s q -l quiet -d "Print nothing; exit with 1 if there are changes"
+complete -c ak -n "__fish_seen_subcommand_from diff" -l no-pager -d "Print directly instead of through \$PAGER"
+# --- status ---
+complete -c ak -n "__fish_seen_subcommand_from status" -s u -l untracked -d "Only list files that are not in the last sealed tree"
+complete -c ak -n "__fish_seen_subcommand_from status" -l porcelain -d "Stable, uncolored output for scripts"
+
+# --- switch ---
+complete -c ak -n "__fish_seen_subcommand_from switch" -a "(ak branch 2>/dev/null | string sub -s 3 | string split -f1 ' ')" -d "Branch"
+
+# --- grep ---
+complete -c ak -n "__fish_seen_subcommand_from grep" -l all-history -d "Search every version stored in the cubes"
+complete -c ak -n "__fish_seen_subcommand_from grep" -s i -l ignore-case -d "Match regardless of case"
+
+# --- blame ---
+complete -c ak -n "__fish_seen_subcommand_from blame" -F
+complete -c ak -n "__fish_seen_subcommand_from blame" -l commit -r -d "Annotate the file as of this commit"
+
+# --- format-patch / apply ---
+complete -c ak -n "__fish_seen_subcommand_from format-patch" -s o -l output-directory -r -a "(__fish_complete_directories)" -d "Directory of the patch files"
+complete -c ak -n "__fish_seen_subcommand_from format-patch" -l stdout -d "Print the patches instead of writing files"
+complete -c ak -n "__fish_seen_subcommand_from apply" -F
+complete -c ak -n "__fish_seen_subcommand_from apply" -l check -d "Only check that the patches apply"
+complete -c ak -n "__fish_seen_subcommand_from apply" -s R -l reverse -d "Undo the patches, last one first"
+complete -c ak -n "__fish_seen_subcommand_from apply" -l fuzz -r -d "Context lines a hunk may leave out at each end to apply"
+
+# --- bisect ---
+complete -c ak -n "__fish_seen_subcommand_from bisect; and not __fish_seen_subcommand_from start good bad skip run reset" -a "start good bad skip run reset" -d "Bisect command"
+
+# --- note ---
+complete -c ak -n "__fish_seen_subcommand_from note; and not __fish_seen_subcommand_from add show" -a "add show" -d "Note command"
+
+# --- gc ---
+complete -c ak -n "__fish_seen_subcommand_from gc" -l older-than -r -d "Only drop pending records older than this many days"
+complete -c ak -n "__fish_seen_subcommand_from gc" -l dry-run -d "Report what would be dropped without rewriting the cubes"
+
+# --- prune ---
+complete -c ak -n "__fish_seen_subcommand_from prune" -l before -r -d "Prune the cubes of the months before this one (YYYY-MM)"
+complete -c ak -n "__fish_seen_subcommand_from prune" -l archive-dir -r -a "(__fish_complete_directories)" -d "Directory of the archive cubes"
+complete -c ak -n "__fish_seen_subcommand_from prune" -l compress -d "Gzip the archive cubes"
+complete -c ak -n "__fish_seen_subcommand_from prune" -l dry-run -d "Report what would be pruned without touching the cubes"
+complete -c ak -n "__fish_seen_subcommand_from prune" -s y -l yes -d "Do not ask for confirmation"
+
+# --- daemon ---
+complete -c ak -n "__fish_seen_subcommand_from daemon" -l stop -d "Stop the daemon of your cube"
+complete -c ak -n "__fish_seen_subcommand_from daemon" -l status -d "Tell whether a daemon serves your cube"
+
+# --- tree ---
+complete -c ak -n "__fish_seen_subcommand_from tree; and not __fish_seen_subcommand_from verify prune du" -a verify -d "check that the reference tree holds exactly what was sealed"
+complete -c ak -n "__fish_seen_subcommand_from tree; and not __fish_seen_subcommand_from verify prune du" -a prune -d "remove the reference trees and snapshots nothing reads anymore"
+complete -c ak -n "__fish_seen_subcommand_from tree; and not __fish_seen_subcommand_from verify prune du" -a du -d "report the disk usage of the reference trees and snapshots"
+complete -c ak -n "__fish_seen_subcommand_from tree; and __fish_seen_subcommand_from verify" -l snapshots -d "Also check the snapshots kept by keep-snapshots"
+complete -c ak -n "__fish_seen_subcommand_from tree; and __fish_seen_subcommand_from prune" -l dry-run -d "List what would be removed without removing it"
+
+# --- snapshots ---
+complete -c ak -n "__fish_seen_subcommand_from snapshots" -l keep -r -d "Remove all but the N newest snapshots"
+
+# --- stats ---
+complete -c ak -n "__fish_seen_subcommand_from stats" -l top -r -d "Number of most changed files to list"
+complete -c ak -n "__fish_seen_subcommand_from stats" -l json -d "Print the statistics as JSON"
+
+# --- stash ---
+complete -c ak -n "__fish_seen_subcommand_from stash; and not __fish_seen_subcommand_from list pop drop" -a "list pop drop" -d "Stash command"
+complete -c ak -n "__fish_seen_subcommand_from stash; and not __fish_seen_subcommand_from list pop drop" -s m -l message -r -d "Describe the stashed changes"
+complete -c ak -n "__fish_seen_subcommand_from pop drop" -a "(ak stash list 2>/dev/null | string match -r '^#\d+' | string sub -s 2)" -d "Stash"
+
+# --- merge ---
+complete -c ak -n "__fish_seen_subcommand_from merge" -a "(ak branch 2>/dev/null | string sub -s 3 | string split -f1 ' ')" -d "Branch"
+complete -c ak -n "__fish_seen_subcommand_from merge" -l abort -d "Give up the merge in progress and restore the files it changed"
+complete -c ak -n "__fish_seen_subcommand_from merge cherry-pick" -l binary -x -a "conflict ours theirs" -d "Binary files changed on both sides"
+
+# --- worktree ---
+complete -c ak -n "__fish_seen_subcommand_from worktree; and not __fish_seen_subcommand_from add remove list" -a "add remove list" -d "Worktree command"
+complete -c ak -n "__fish_seen_subcommand_from worktree; and __fish_seen_subcommand_from add remove" -a "(__fish_complete_directories)" -d "Worktree directory"
+complete -c ak -n "__fish_seen_subcommand_from worktree; and __fish_seen_subcommand_from add" -a "(ak branch 2>/dev/null | string sub -s 3 | string split -f1 ' ')" -d "Branch"
+complete -c ak -n "__fish_seen_subcommand_from worktree; and __fish_seen_subcommand_from remove" -l force -d "Remove it even with unsealed changes"
+
+# --- archive ---
+complete -c ak -n "__fish_seen_subcommand_from archive" -l commit -r -d "Commit id or tag to archive"
+complete -c ak -n "__fish_seen_subcommand_from archive" -s o -l out -r -F -d "Archive to write: .tar.gz, .tgz or .zip"
+
+# --- restore ---
+complete -c ak -n "__fish_seen_subcommand_from restore" -l commit -r -d "Commit id or tag to restore from"
+complete -c ak -n "__fish_seen_subcommand_from restore" -l into -r -a "(__fish_complete_directories)" -d "Write the files below this directory"
+
+# --- materialize ---
+complete -c ak -n "__fish_seen_subcommand_from materialize" -l commit -r -d "Commit id or tag to materialize"
+complete -c ak -n "__fish_seen_subcommand_from materialize" -l into -r -a "(__fish_complete_directories)" -d "Empty or missing directory to write the tree into"
+
+# --- show ---
+complete -c ak -n "__fish_seen_subcommand_from show" -s p -l patch -d "Include the diff against the parent commit"
+
+# --- clone / remote / push / pull ---
+complete -c ak -n "__fish_seen_subcommand_from clone" -a "(__fish_complete_directories)" -d "URL or directory"
+complete -c ak -n "__fish_seen_subcommand_from export-git import-git" -a "(__fish_complete_directories)" -d "Git repository"
+complete -c ak -n "__fish_seen_subcommand_from import-git" -l first-parent -d "Only follow first parents: merged branches become single seals"
+complete -c ak -n "__fish_seen_subcommand_from remote; and not __fish_seen_subcommand_from add remove list" -a "add remove list" -d "Remote command"
+complete -c ak -n "__fish_seen_subcommand_from push pull; or begin; __fish_seen_subcommand_from remote; and __fish_seen_subcommand_from remove; end" -a "(ak remote list 2>/dev/null | string split -f1 \t)" -d "Remote"
+complete -c ak -n "__fish_seen_subcommand_from push pull" -s n -l dry-run -d "Only report what would be transferred"
+
+# --- tag ---
+complete -c ak -n "__fish_seen_subcommand_from tag; and not __fish_seen_subcommand_from list delete" -a "list delete" -d "Tag command"
+complete -c ak -n "__fish_seen_subcommand_from tag" -l commit -r -d "Commit to tag"
+complete -c ak -n "__fish_seen_subcommand_from tag" -s f -l force -d "Move the tag if it already exists"
+complete -c ak -n "__fish_seen_subcommand_from delete" -a "(ak tag list 2>/dev/null | string split -f1 ' ')" -d "Tag"
+
+# --- changelog ---
+complete -c ak -n "__fish_seen_subcommand_from changelog" -l from -r -a "(ak tag list 2>/dev/null | string split -f1 ' ')" -d "Start after this tag or commit"
+complete -c ak -n "__fish_seen_subcommand_from changelog" -l to -r -a "(ak tag list 2>/dev/null | string split -f1 ' ')" -d "End at this tag or commit"
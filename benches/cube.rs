@@ -0,0 +1,87 @@
+//! Cube throughput benchmarks: append (synced, bulk, bulk with pre-allocation), full scan, and
+//! index rebuild.
+//!
+//! Run with `cargo bench`. Cubes are created under the system temp directory and removed afterwards.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use eikyu::data::storage::IoConfig;
+use eikyu::data::write::Writer;
+use std::path::PathBuf;
+
+/// Records per iteration for append benchmarks.
+const RECORDS: usize = 200;
+/// Records in the cube used by the read benchmarks.
+const SCAN_RECORDS: usize = 10_000;
+
+fn temp_cube(name: &str) -> PathBuf {
+    let p = std::env::temp_dir().join(format!("akasha-bench-{name}-{}.cube", std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p
+}
+
+fn bench_append(c: &mut Criterion) {
+    let payload = "x".repeat(1024);
+    let mut group = c.benchmark_group("append");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    group.sample_size(10);
+
+    for (label, bulk, preallocate) in [
+        ("synced", false, 0),
+        ("bulk", true, 0),
+        ("bulk-prealloc", true, 64 << 20),
+    ] {
+        group.bench_with_input(BenchmarkId::new(label, RECORDS), &bulk, |b, &bulk| {
+            let path = temp_cube(label);
+            let io = IoConfig {
+                preallocate,
+                ..IoConfig::default()
+            };
+            let mut w = Writer::create(path.to_str().unwrap()).unwrap().with_io(io);
+            b.iter(|| {
+                if bulk {
+                    w.begin_bulk().unwrap();
+                }
+                for i in 0..RECORDS {
+                    w.append(&format!("file-{i}.txt"), &payload).unwrap();
+                }
+                if bulk {
+                    w.finish_bulk().unwrap();
+                }
+            });
+            drop(w);
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+    group.finish();
+}
+
+fn bench_read(c: &mut Criterion) {
+    let path = temp_cube("scan");
+    let cube = path.to_str().unwrap();
+    {
+        let payload = "y".repeat(512);
+        let mut w = Writer::create(cube).unwrap();
+        w.begin_bulk().unwrap();
+        for i in 0..SCAN_RECORDS {
+            w.append(&format!("dir/file-{i}.txt"), &payload).unwrap();
+        }
+        w.finish_bulk().unwrap();
+    }
+
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Elements(SCAN_RECORDS as u64));
+    group.bench_function("scan", |b| {
+        let mut w = Writer::create(cube).unwrap();
+        b.iter(|| w.events().unwrap().len());
+    });
+    group.bench_function("rebuild_index", |b| {
+        let mut w = Writer::create(cube).unwrap();
+        b.iter(|| w.rebuild_index().unwrap().len());
+    });
+    group.finish();
+
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, bench_append, bench_read);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+//! Generate the C header from the `extern "C"` surface of `src/lib.rs` into `$OUT_DIR/akasha.h`.
+//!
+//! The build never writes into the source tree; `include/akasha.h` is refreshed by copying the
+//! generated file (or running `cbindgen --config cbindgen.toml -o include/akasha.h`).
+
+fn main() {
+    let crate_dir =
+        std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_root_or_default(&crate_dir);
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{out_dir}/akasha.h"));
+        }
+        Err(e) => println!("cargo:warning=failed to generate akasha.h: {e}"),
+    }
+}
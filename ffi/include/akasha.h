@@ -0,0 +1,98 @@
+#ifndef AKASHA_H
+#define AKASHA_H
+
+/* Generated by cbindgen from ffi/src/lib.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An open cube.
+ */
+typedef struct AkCube AkCube;
+
+/**
+ * A snapshot iterator over the records of a cube.
+ */
+typedef struct AkIter AkIter;
+
+/**
+ * One record, filled by `ak_iter_next`. String pointers are owned by the iterator.
+ */
+typedef struct AkRecord {
+  uint64_t id;
+  /**
+   * Nanoseconds since UNIX epoch.
+   */
+  uint64_t timestamp_ns;
+  const char *phenomenon;
+  const char *noumenon;
+} AkRecord;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message describing the last failure on this thread, or NULL if none.
+ */
+const char *ak_last_error(void);
+
+/**
+ * Open (or create) the cube at `path`. Returns NULL on failure.
+ *
+ * # Safety
+ * `path` must be a valid NUL-terminated string.
+ */
+struct AkCube *ak_cube_open(const char *path);
+
+/**
+ * Append a record and return its id, or -1 on failure.
+ *
+ * # Safety
+ * `cube` must come from `ak_cube_open`; strings must be valid NUL-terminated strings.
+ */
+int64_t ak_cube_append(struct AkCube *cube, const char *phenomenon, const char *noumenon);
+
+/**
+ * Start iterating over the cube's records as of now. Returns NULL on failure.
+ *
+ * # Safety
+ * `cube` must come from `ak_cube_open`.
+ */
+struct AkIter *ak_cube_iter(struct AkCube *cube);
+
+/**
+ * Advance the iterator: fills `out` and returns 1, or returns 0 at the end (-1 on bad input).
+ *
+ * Records containing interior NUL bytes are exposed with those bytes replaced by spaces.
+ *
+ * # Safety
+ * `iter` must come from `ak_cube_iter`; `out` must point to writable memory.
+ */
+int32_t ak_iter_next(struct AkIter *iter, struct AkRecord *out);
+
+/**
+ * Release an iterator. NULL is ignored.
+ *
+ * # Safety
+ * `iter` must come from `ak_cube_iter` and not be used afterwards.
+ */
+void ak_iter_free(struct AkIter *iter);
+
+/**
+ * Close a cube and release its file handle. NULL is ignored.
+ *
+ * # Safety
+ * `cube` must come from `ak_cube_open` and not be used afterwards.
+ */
+void ak_cube_close(struct AkCube *cube);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AKASHA_H */
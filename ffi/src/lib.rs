@@ -0,0 +1,232 @@
+//! Minimal C API for embedding cube access in native tools.
+//!
+//! Conventions:
+//! - Handles (`AkCube`, `AkIter`) are opaque and must be released with their `*_close`/`*_free`.
+//! - Functions report failure through their return value (`NULL`, `-1`, or `0` for "no more");
+//!   the reason is available from `ak_last_error()` on the same thread.
+//! - Strings passed in are NUL-terminated UTF-8. Strings handed out are owned by the handle that
+//!   produced them and stay valid until the next call on that handle.
+//!
+//! The C header `include/akasha.h` is generated with cbindgen; the build script writes a fresh copy
+//! to `$OUT_DIR/akasha.h`, which is what to commit after changing this file.
+
+use eikyu::data::write::Writer;
+use eikyu::event::Event;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: impl ToString) {
+    let msg = CString::new(msg.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Borrow a C string as `&str`, recording an error when it is NULL or not UTF-8.
+///
+/// # Safety
+/// `s` must be NULL or point to a NUL-terminated string valid for the returned lifetime.
+unsafe fn arg<'a>(s: *const c_char, name: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_error(format!("{name} is NULL"));
+        return None;
+    }
+    match unsafe { CStr::from_ptr(s) }.to_str() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            set_error(format!("{name} is not UTF-8: {e}"));
+            None
+        }
+    }
+}
+
+/// An open cube.
+pub struct AkCube {
+    writer: Writer,
+}
+
+/// A snapshot iterator over the records of a cube.
+pub struct AkIter {
+    events: std::vec::IntoIter<Event>,
+    phenomenon: CString,
+    noumenon: CString,
+}
+
+/// One record, filled by `ak_iter_next`. String pointers are owned by the iterator.
+#[repr(C)]
+pub struct AkRecord {
+    pub id: u64,
+    /// Nanoseconds since UNIX epoch.
+    pub timestamp_ns: u64,
+    pub phenomenon: *const c_char,
+    pub noumenon: *const c_char,
+}
+
+/// Message describing the last failure on this thread, or NULL if none.
+#[unsafe(no_mangle)]
+pub extern "C" fn ak_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Open (or create) the cube at `path`. Returns NULL on failure.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_cube_open(path: *const c_char) -> *mut AkCube {
+    let Some(path) = (unsafe { arg(path, "path") }) else {
+        return ptr::null_mut();
+    };
+    match Writer::create(path) {
+        Ok(writer) => Box::into_raw(Box::new(AkCube { writer })),
+        Err(e) => {
+            set_error(format!("{path}: {e}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Append a record and return its id, or -1 on failure.
+///
+/// # Safety
+/// `cube` must come from `ak_cube_open`; strings must be valid NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_cube_append(
+    cube: *mut AkCube,
+    phenomenon: *const c_char,
+    noumenon: *const c_char,
+) -> i64 {
+    let Some(cube) = (unsafe { cube.as_mut() }) else {
+        set_error("cube is NULL");
+        return -1;
+    };
+    let (Some(ph), Some(no)) = (unsafe { arg(phenomenon, "phenomenon") }, unsafe {
+        arg(noumenon, "noumenon")
+    }) else {
+        return -1;
+    };
+    match cube.writer.append_record(ph, no) {
+        Ok(appended) => i64::try_from(appended.id).unwrap_or(i64::MAX),
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Start iterating over the cube's records as of now. Returns NULL on failure.
+///
+/// # Safety
+/// `cube` must come from `ak_cube_open`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_cube_iter(cube: *mut AkCube) -> *mut AkIter {
+    let Some(cube) = (unsafe { cube.as_mut() }) else {
+        set_error("cube is NULL");
+        return ptr::null_mut();
+    };
+    match cube.writer.events() {
+        Ok(events) => Box::into_raw(Box::new(AkIter {
+            events: events.into_iter(),
+            phenomenon: CString::default(),
+            noumenon: CString::default(),
+        })),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Advance the iterator: fills `out` and returns 1, or returns 0 at the end (-1 on bad input).
+///
+/// Records containing interior NUL bytes are exposed with those bytes replaced by spaces.
+///
+/// # Safety
+/// `iter` must come from `ak_cube_iter`; `out` must point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_iter_next(iter: *mut AkIter, out: *mut AkRecord) -> i32 {
+    let (Some(iter), false) = (unsafe { iter.as_mut() }, out.is_null()) else {
+        set_error("iter or out is NULL");
+        return -1;
+    };
+    let Some(ev) = iter.events.next() else {
+        return 0;
+    };
+    let c = |s: String| CString::new(s.replace('\0', " ")).unwrap_or_default();
+    iter.phenomenon = c(ev.phenomenon);
+    iter.noumenon = c(ev.noumenon);
+    unsafe {
+        out.write(AkRecord {
+            id: ev.id,
+            timestamp_ns: u64::try_from(ev.timestamp).unwrap_or(u64::MAX),
+            phenomenon: iter.phenomenon.as_ptr(),
+            noumenon: iter.noumenon.as_ptr(),
+        });
+    }
+    1
+}
+
+/// Release an iterator. NULL is ignored.
+///
+/// # Safety
+/// `iter` must come from `ak_cube_iter` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_iter_free(iter: *mut AkIter) {
+    if !iter.is_null() {
+        drop(unsafe { Box::from_raw(iter) });
+    }
+}
+
+/// Close a cube and release its file handle. NULL is ignored.
+///
+/// # Safety
+/// `cube` must come from `ak_cube_open` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ak_cube_close(cube: *mut AkCube) {
+    if !cube.is_null() {
+        drop(unsafe { Box::from_raw(cube) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_iterate_through_c_api() {
+        let path = std::env::temp_dir().join(format!("akasha-ffi-{}.cube", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let text = |p: *const c_char| unsafe { CStr::from_ptr(p) }.to_str().unwrap().to_string();
+        unsafe {
+            let cube = ak_cube_open(c_path.as_ptr());
+            assert!(!cube.is_null());
+            assert_eq!(ak_cube_append(cube, c"a.txt".as_ptr(), c"one".as_ptr()), 1);
+            assert_eq!(ak_cube_append(cube, c"b.txt".as_ptr(), c"two".as_ptr()), 2);
+            assert_eq!(ak_cube_append(cube, ptr::null(), c"x".as_ptr()), -1);
+            assert_eq!(text(ak_last_error()), "phenomenon is NULL");
+
+            let iter = ak_cube_iter(cube);
+            assert!(!iter.is_null());
+            let mut rec = std::mem::MaybeUninit::<AkRecord>::uninit();
+            let mut seen = Vec::new();
+            while ak_iter_next(iter, rec.as_mut_ptr()) == 1 {
+                let r = rec.assume_init_ref();
+                seen.push((r.id, text(r.phenomenon), text(r.noumenon)));
+            }
+            ak_iter_free(iter);
+            ak_cube_close(cube);
+            assert_eq!(
+                seen,
+                [
+                    (1, "a.txt".to_string(), "one".to_string()),
+                    (2, "b.txt".to_string(), "two".to_string())
+                ]
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
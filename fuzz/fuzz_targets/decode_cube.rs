@@ -0,0 +1,16 @@
+//! Feed arbitrary bytes to the record scanner/parser behind a valid header.
+//!
+//! Run with `cargo +nightly fuzz run decode_cube` from the repository root.
+
+#![no_main]
+
+use eikyu::data::write::Writer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cube = b"AKLA\x01\0\x01\0\0\0\0\0\0\0\0\0".to_vec();
+    cube.extend_from_slice(data);
+    let _ = Writer::decode(&cube);
+    // Raw input too, to exercise header validation.
+    let _ = Writer::decode(data);
+});
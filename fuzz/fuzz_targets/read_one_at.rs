@@ -0,0 +1,19 @@
+//! Random-access reads at arbitrary offsets of an arbitrary file must fail cleanly, never panic.
+//!
+//! Input layout: first 2 bytes = offset (LE), rest = file contents.
+//! Run with `cargo +nightly fuzz run read_one_at` from the repository root.
+
+#![no_main]
+
+use eikyu::data::write::Writer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let offset = u16::from_le_bytes([data[0], data[1]]) as u64;
+    let path = std::env::temp_dir().join(format!("akasha-fuzz-{}.cube", std::process::id()));
+    std::fs::write(&path, &data[2..]).unwrap();
+    let _ = Writer::read_one_at(&path, offset);
+});
@@ -0,0 +1,186 @@
+//! Async access to cubes for services running on a tokio runtime (feature `aio`).
+//!
+//! Cube I/O is blocking: appends wait for `sync_data` and the file lock, reads scan the whole
+//! file. `Writer` and `Reader` keep the sync `data::write::Writer` on a thread of their own and
+//! hand it one job at a time, so awaiting an append or a scan never blocks the runtime's workers.
+//! Jobs run in the order they were submitted, and the thread ends once the handle is dropped.
+//!
+//! Processors configured for the cube are loaded as by `Writer::create` and `Writer::open`, and
+//! appends go through the daemon of the cube when one runs (see `data::daemon`).
+
+use crate::data::write::{self, Appended};
+use crate::event::{Event, RecordType};
+use std::io;
+use std::sync::mpsc::{Sender, channel};
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce(&mut write::Writer) + Send>;
+
+/// Thread owning a sync writer and running the jobs sent to it.
+struct Worker {
+    jobs: Sender<Job>,
+}
+
+impl Worker {
+    /// Start the thread and open the cube there with `open`.
+    async fn start(
+        open: impl FnOnce() -> io::Result<write::Writer> + Send + 'static,
+    ) -> io::Result<Self> {
+        let (jobs, queue) = channel::<Job>();
+        let (opened, ready) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("eikyu-aio".to_string())
+            .spawn(move || {
+                let mut w = match open() {
+                    Ok(w) => w,
+                    Err(e) => {
+                        let _ = opened.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = opened.send(Ok(()));
+                for job in queue {
+                    job(&mut w);
+                }
+            })?;
+        ready.await.map_err(|_| gone())??;
+        Ok(Self { jobs })
+    }
+
+    /// Run `f` on the writer and wait for its outcome.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut write::Writer) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let (tx, rx) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |w| {
+                let _ = tx.send(f(w));
+            }))
+            .map_err(|_| gone())?;
+        rx.await.map_err(|_| gone())?
+    }
+}
+
+fn gone() -> io::Error {
+    io::Error::other("cube worker thread is gone")
+}
+
+/// Async counterpart of `data::write::Writer`, for appending to a cube.
+pub struct Writer {
+    worker: Worker,
+}
+
+impl Writer {
+    /// Open or create the cube at `path`; see `data::write::Writer::create`.
+    pub async fn create(path: &str) -> io::Result<Self> {
+        let path = path.to_string();
+        let worker = Worker::start(move || write::Writer::create(&path)).await?;
+        Ok(Self { worker })
+    }
+
+    /// `data::write::Writer::append_record`.
+    pub async fn append(&self, phenomenon: &str, noumenon: &str) -> io::Result<Appended> {
+        let (ph, no) = (phenomenon.to_string(), noumenon.to_string());
+        self.worker.run(move |w| w.append_record(&ph, &no)).await
+    }
+
+    /// `data::write::Writer::append_typed`.
+    pub async fn append_typed(
+        &self,
+        kind: RecordType,
+        phenomenon: &str,
+        noumenon: &str,
+    ) -> io::Result<Appended> {
+        let (ph, no) = (phenomenon.to_string(), noumenon.to_string());
+        self.worker
+            .run(move |w| w.append_typed(kind, &ph, &no))
+            .await
+    }
+
+    /// `data::write::Writer::append_events`: all or nothing, ids and timestamps kept.
+    pub async fn append_events(&self, events: Vec<Event>) -> io::Result<Vec<Appended>> {
+        self.worker.run(move |w| w.append_events(&events)).await
+    }
+
+    /// Id that the next appended record will receive.
+    pub async fn next_id(&self) -> io::Result<u64> {
+        self.worker.run(|w| Ok(w.next_id())).await
+    }
+
+    /// Every valid record of the cube; see `Reader::scan`.
+    pub async fn scan(&self) -> io::Result<Vec<Event>> {
+        self.worker.run(|w| w.events()).await
+    }
+}
+
+/// Async counterpart of `data::write::Writer::open`, for reading a cube.
+pub struct Reader {
+    worker: Worker,
+}
+
+impl Reader {
+    /// Open the existing cube at `path` for reading only.
+    pub async fn open(path: &str) -> io::Result<Self> {
+        let path = path.to_string();
+        let worker = Worker::start(move || write::Writer::open(&path)).await?;
+        Ok(Self { worker })
+    }
+
+    /// Every valid record of the cube, in on-disk order (`data::write::Writer::events`).
+    pub async fn scan(&self) -> io::Result<Vec<Event>> {
+        self.worker.run(|w| w.events()).await
+    }
+
+    /// Like `scan`, keeping the records of the given `kinds` only.
+    pub async fn scan_kinds(&self, kinds: &[RecordType]) -> io::Result<Vec<Event>> {
+        let kinds = kinds.to_vec();
+        self.worker.run(move |w| w.events_of(&kinds)).await
+    }
+
+    /// The first `n` valid records.
+    pub async fn head(&self, n: usize) -> io::Result<Vec<Event>> {
+        self.worker.run(move |w| w.head(n)).await
+    }
+
+    /// The last `n` valid records, in on-disk order.
+    pub async fn tail(&self, n: usize) -> io::Result<Vec<Event>> {
+        self.worker.run(move |w| w.tail(n)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_async_append_and_scan() {
+        let path = std::env::temp_dir().join(format!("akasha-aio-{}.cube", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let cube = path.to_str().unwrap().to_string();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert!(Reader::open(&cube).await.is_err());
+            let w = Writer::create(&cube).await.unwrap();
+            assert_eq!(w.append("a.txt", "1").await.unwrap().id, 1);
+            assert_eq!(w.append("b.txt", "2").await.unwrap().id, 2);
+            w.append_typed(RecordType::Meta, "note:x", "{}")
+                .await
+                .unwrap();
+            assert_eq!(w.next_id().await.unwrap(), 4);
+
+            let r = Reader::open(&cube).await.unwrap();
+            let ids = |evs: Vec<Event>| evs.iter().map(|e| e.id).collect::<Vec<_>>();
+            assert_eq!(ids(r.scan().await.unwrap()), [1, 2, 3]);
+            assert_eq!(ids(r.scan_kinds(&[RecordType::Meta]).await.unwrap()), [3]);
+            assert_eq!(ids(r.head(1).await.unwrap()), [1]);
+            assert_eq!(ids(r.tail(1).await.unwrap()), [3]);
+            let copied = r.head(1).await.unwrap();
+            assert!(w.append_events(copied).await.is_err());
+        });
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,24 +1,61 @@
-use crate::data::write::Writer;
-use crate::event::Event;
 use chrono::DateTime;
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use inquire::{Editor, Select, Text};
-use serde::Serialize;
+use colored::Colorize;
+use eikyu::data::write::{Identity, Writer};
+use eikyu::event::Event;
+use globset::{Glob, GlobSetBuilder};
+use inquire::{Confirm, Editor, Select, Text};
 use std::collections::HashMap;
-use std::env::var;
 use std::fs::{create_dir_all, read_to_string};
-use std::io::Error;
-use std::path::{MAIN_SEPARATOR_STR, Path};
+use std::io::{Error, IsTerminal, Write};
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
 use std::process::ExitCode;
 
-pub mod data;
-pub mod event;
-use crate::data::diff;
-use crate::data::tree;
+use eikyu::data::akignore;
+use eikyu::data::archive;
+use eikyu::data::bisect::{self, Bisect};
+use eikyu::data::blame;
+use eikyu::data::branch;
+use eikyu::data::changelog::{self, Release};
+use eikyu::data::check;
+use eikyu::data::commit;
+use eikyu::data::config::{self, Config, Layout};
+use eikyu::data::daemon;
+use eikyu::data::diff;
+use eikyu::data::gc;
+use eikyu::data::git;
+use eikyu::data::graph;
+use eikyu::data::grep;
+use eikyu::data::history;
+use eikyu::data::hooks::{self, Step};
+use eikyu::data::index::{self, Index};
+use eikyu::data::integrity;
+use eikyu::data::lint;
+use eikyu::data::manifest::Manifest;
+use eikyu::data::merge::{self, MergeState};
+use eikyu::data::note;
+use eikyu::data::patch;
+use eikyu::data::paths;
+use eikyu::data::pick;
+use eikyu::data::prune;
+use eikyu::data::reflog;
+use eikyu::data::remote;
+use eikyu::data::rename;
+use eikyu::data::restore;
+use eikyu::data::schema;
+use eikyu::data::script;
+use eikyu::data::sign;
+use eikyu::data::stash::{self, Stash};
+use eikyu::data::stats;
+use eikyu::data::status;
+use eikyu::data::tag;
+use eikyu::data::tree;
+use eikyu::data::worktree;
 
 pub const AK_USERNAME: &str = "AK_USERNAME";
 pub const AK_EMAIL: &str = "AK_EMAIL";
 pub const EDITOR: &str = "EDITOR";
+pub const AK_SIGNING_KEY: &str = "AK_SIGNING_KEY";
 
 /// Template for interactive commit messages.
 ///
@@ -41,21 +78,23 @@ pub const COMMIT_TEMPLATE: &str = r#"%type% %summary%
 
 /// Simple pre-commit pipeline orchestrator.
 ///
-/// - Each task is a tuple (program, args) grouped under a logical name.
-/// - Tasks are executed in insertion order in the current working directory.
-/// - If any task fails (non-zero exit code), execution stops and an error is returned.
+/// - Each task is a step (see `data::hooks::Step`): a program and its arguments under a
+///   logical name.
+/// - Tasks are executed in insertion order in the current working directory; consecutive
+///   `parallel` tasks run at the same time.
+/// - A task's output is captured and printed only when it fails.
+/// - If a task fails (non-zero exit code), execution stops and an error is returned, unless the
+///   step allows failure.
 /// - This is intentionally minimal and local-only.
 #[derive(Default)]
 pub struct PreCommit {
-    pub tasks: HashMap<String, HashMap<String, String>>,
+    pub tasks: Vec<Step>,
 }
 
 impl PreCommit {
     /// Construct an empty pipeline.
     pub fn new() -> Self {
-        Self {
-            tasks: HashMap::new(),
-        }
+        Self { tasks: Vec::new() }
     }
 
     /// Add a task to the pipeline.
@@ -64,77 +103,232 @@ impl PreCommit {
     /// - program: executable to run (e.g., "cargo", "npm")
     /// - args: argument string split by whitespace (e.g., "fmt --check")
     pub fn add_task(&mut self, task: &str, program: &str, args: &str) -> &mut Self {
-        let mut x = HashMap::new();
-        x.insert(program.to_string(), args.to_string());
-        self.tasks.insert(task.to_string(), x);
+        self.tasks.push(Step {
+            name: task.to_string(),
+            program: program.to_string(),
+            args: args.split_whitespace().map(str::to_string).collect(),
+            allow_failure: false,
+            only_on_changed: Vec::new(),
+            parallel: false,
+        });
         self
     }
 
-    /// Execute all tasks in sequence.
+    /// Execute all tasks, except those named in `skip`, then print a summary with the time each
+    /// step took. Steps restricted to some paths only run when one of the `changed` paths
+    /// matches.
     ///
     /// Returns:
-    /// - Ok(()) if all tasks succeed
+    /// - Ok(()) if all tasks succeed (or are allowed to fail)
     /// - Err(Error) on the first failure
-    pub fn run(&self) -> Result<(), Error> {
-        for (name, programs) in self.tasks.iter() {
-            for (program, args) in programs {
-                // Spawn the process and wait synchronously for completion.
-                let status = std::process::Command::new(program)
-                    .args(args.split_whitespace())
-                    .current_dir(".")
-                    .status()
-                    .map_err(|e| Error::other(format!("failed to spawn '{program}': {e}")))?;
+    pub fn run(&self, changed: &[String], skip: &[String]) -> Result<(), Error> {
+        for name in skip {
+            if !self.tasks.iter().any(|t| &t.name == name) {
+                eprintln!("warning: no pre-commit step named '{name}' to skip");
+            }
+        }
+        let mut summary: Vec<(&str, String)> = Vec::new();
+        let mut runnable = Vec::new();
+        for step in &self.tasks {
+            let name = &step.name;
+            if skip.contains(name) {
+                println!(">> step {name} skipped (--skip)");
+                summary.push((name, "skipped".into()));
+            } else if !step.applies(changed)? {
+                println!(">> step {name} skipped (no matching change)");
+                summary.push((name, "skipped".into()));
+            } else {
+                runnable.push(step);
+            }
+        }
 
-                if !status.success() {
-                    println!(">> step {name} failed (status: {status})");
-                    return Err(Error::other(format!(
+        let mut failure = None;
+        for batch in hooks::batches(&runnable) {
+            let root = Path::new(".");
+            // Spawn the batch and wait for every process of it to complete.
+            let runs: Vec<Result<hooks::Run, Error>> = std::thread::scope(|s| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|step| s.spawn(move || step.execute(root)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("pre-commit step thread panicked"))
+                    .collect()
+            });
+            for (step, run) in batch.iter().zip(runs) {
+                let name = &step.name;
+                let run = run?;
+                let took = format!("{:.1}s", run.elapsed.as_secs_f64());
+                if run.status.success() {
+                    println!(">> step {name} passed ({took})");
+                    summary.push((name, format!("passed   {took}")));
+                    continue;
+                }
+                let status = run.status;
+                println!(">> step {name} failed (status: {status})");
+                std::io::stdout().write_all(&run.stdout)?;
+                std::io::stderr().write_all(&run.stderr)?;
+                if step.allow_failure {
+                    println!(">> step {name} allowed to fail");
+                    summary.push((name, format!("failed   {took} (allowed)")));
+                } else {
+                    summary.push((name, format!("failed   {took}")));
+                    failure.get_or_insert(format!(
                         "pre-commit step '{name}' failed with status {status}"
-                    )));
+                    ));
                 }
+            }
+            if failure.is_some() {
+                break;
+            }
+        }
 
-                println!(">> step {name} passed");
+        for step in &runnable {
+            if !summary.iter().any(|(name, _)| *name == step.name) {
+                summary.push((&step.name, "not run".into()));
             }
         }
-        Ok(())
+        // Keep the summary in pipeline order.
+        summary.sort_by_key(|(name, _)| self.tasks.iter().position(|t| t.name == *name));
+        let width = summary.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+        if !summary.is_empty() {
+            println!("pre-commit summary:");
+        }
+        for (name, outcome) in &summary {
+            println!("  {name:width$}  {outcome}");
+        }
+        match failure {
+            Some(msg) => Err(Error::other(msg)),
+            None => Ok(()),
+        }
     }
 }
 
-/// Wire-format of a commit event stored as phenomenon "commit" with a JSON noumenon.
-///
-/// This is the durable record extracted from the intermediate "commit:pending" reservation.
-#[derive(Serialize)]
-pub struct CommitRecord<'a> {
-    pub id: u64,
-    pub parent: Option<u64>,
-    pub ty: &'a str,
-    pub summary: &'a str,
-    pub body: &'a str,
-    pub author: &'a str,
-    pub author_email: &'a str,
-    /// Milliseconds since Unix epoch (UTC).
-    pub timestamp: u64,
-}
-
 /// Define the CLI for the local VCS and parse arguments.
 ///
 /// This is side-effect-free and only sets up subcommands and flags.
 pub fn apps() -> ArgMatches {
     Command::new("ak")
         .about("a new vcs")
-        .subcommand(Command::new("init").about("init data"))
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Color the output: auto (a terminal without NO_COLOR), always or never")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .global(true)
+                .action(ArgAction::Set),
+        )
         .subcommand(
+            Command::new("init").about("init data").arg(
+                Arg::new("layout")
+                    .long("layout")
+                    .help("Cube layout: one cube per author and month, or one shared repository cube")
+                    .required(false)
+                    .value_parser(["per-author", "shared"])
+                    .action(ArgAction::Set),
+            ),
+        )
+        .subcommand(verify_args(
             Command::new("inscribe")
-                .about("track data from a path into the current cube")
+                .about("track data from paths into the current cube")
+                .arg(
+                    Arg::new("paths")
+                        .help("Files or directories to scan (defaults to .)")
+                        .required(false)
+                        .num_args(0..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .long("exclude")
+                        .value_name("GLOB")
+                        .help("Skip files and directories matching this glob (repeatable)")
+                        .required(false)
+                        .action(ArgAction::Append),
+                ),
+            true,
+        ))
+        .subcommand(
+            Command::new("add")
+                .about("stage changes for the next seal")
                 .arg(
-                    Arg::new("path")
-                        .help("Path to scan (defaults to .)")
+                    Arg::new("paths")
+                        .help("Files or directories to stage")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("patch")
+                        .short('p')
+                        .long("patch")
+                        .help("Interactively choose the hunks to stage")
                         .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("mv")
+                .about("move or rename a tracked file or directory and stage the rename")
+                .arg(
+                    Arg::new("from")
+                        .help("File or directory to move")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("to")
+                        .help("New path, or an existing directory to move into")
+                        .required(true)
                         .action(ArgAction::Set),
                 ),
         )
         .subcommand(
+            Command::new("rm")
+                .about("delete tracked files and stage their removal")
+                .arg(
+                    Arg::new("paths")
+                        .help("Files or directories to remove")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(verify_args(
             Command::new("seal")
                 .about("register a commit into the current cube")
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .long("all")
+                        .help("Seal the whole working directory instead of the staged changes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("sign")
+                        .long("sign")
+                        .help("Sign the commit with the SSH key of user.signingkey")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("amend")
+                        .long("amend")
+                        .help("Replace the last commit of the branch (message and/or content)")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("allow-empty")
+                        .long("allow-empty")
+                        .help("Seal even when nothing changed since the last seal")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("type")
                         .short('t')
@@ -159,7 +353,8 @@ pub fn apps() -> ArgMatches {
                         .required(false)
                         .action(ArgAction::Set),
                 ),
-        )
+            true,
+        ))
         .subcommand(
             Command::new("timeline")
                 .about("show event timeline (commits)")
@@ -176,412 +371,5530 @@ pub fn apps() -> ArgMatches {
                         .help("Display timestamps in ISO 8601 format with timezone offset")
                         .required(false)
                         .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .help("Only show the N most recent matching commits")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Only commits sealed on or after this date (YYYY-MM-DD or RFC 3339)")
+                        .required(false)
+                        .value_parser(|s: &str| parse_bound(s, false))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .help("Only commits sealed on or before this date (YYYY-MM-DD or RFC 3339)")
+                        .required(false)
+                        .value_parser(|s: &str| parse_bound(s, true))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("type")
+                        .short('t')
+                        .long("type")
+                        .help("Only commits of this type")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("grep")
+                        .long("grep")
+                        .help("Only commits whose summary or body contains this text (case-insensitive)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("author")
+                        .long("author")
+                        .help("Only commits whose author name or email contains this text")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("note")
+                        .long("note")
+                        .help("Only commits with a note containing this text (case-insensitive)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("reverse")
+                        .long("reverse")
+                        .help("Show the newest commits first")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the commits as a JSON array")
+                        .required(false)
+                        .conflicts_with("graph")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("graph")
+                        .long("graph")
+                        .help("Draw the parent/branch graph, newest commits first")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(Command::new("view").about("show the latest commit"))
-        .subcommand(Command::new("diff").about("show changes since the last seal"))
-        .get_matches()
-}
-
-/// Compute the current author's cube file path with year-month bucketing.
-///
-/// Layout:
-/// - .eikyu/cubes/YYYY-MM/<author>.cube
-pub fn cube_path_for(author: &str) -> String {
-    let ym = chrono::Local::now().format("%Y-%m").to_string();
-    create_dir_all(format!(
-        ".eikyu{MAIN_SEPARATOR_STR}cubes{MAIN_SEPARATOR_STR}{ym}"
-    ))
-    .expect("create cubes dir failed");
-    format!(
-        ".eikyu{MAIN_SEPARATOR_STR}cubes{MAIN_SEPARATOR_STR}{ym}{MAIN_SEPARATOR_STR}{author}.cube"
-    )
-}
-
-/// Append a phenomenon/noumenon string pair into the target cube.
-///
-/// Returns the byte offset of the appended record (useful for random access).
-pub fn save_string_in_cube(
-    cube_path: &str,
-    phenomenon: &str,
-    content: &str,
-) -> std::io::Result<u64> {
-    let mut w = Writer::create(cube_path)?;
-    let off = w.append(phenomenon, content)?;
-    Ok(off)
-}
-
-/// Read all events from a cube, filter to commits, and return them ordered by id.
-///
-/// The index is rebuilt from the log and used to fetch each event.
-pub fn read_commits_from_cube(cube_path: &str) -> std::io::Result<Vec<Event>> {
-    let mut w = Writer::create(cube_path)?;
-    let idx = w.rebuild_index()?; // id -> offset
-    let mut out = Vec::with_capacity(idx.len());
-    for (_id, off) in idx {
-        let ev = Writer::read_one_at(cube_path, off)?;
-        if ev.phenomenon == "commit" {
-            out.push(ev);
-        }
-    }
-    Ok(out)
-}
-
-/// Return the last commit id present in the cube, if any.
-pub fn last_commit_id(cube_path: &str) -> std::io::Result<Option<u64>> {
-    let commits = read_commits_from_cube(cube_path)?;
-    Ok(commits.last().map(|e| e.id))
-}
-
-/// Pre-commit checks for Rust/Cargo projects.
-/// - fmt --check
-/// - test --no-fail-fast
-/// - clippy with warnings as errors
-pub fn cargo_project_hook() -> Result<(), Error> {
-    println!("cargo project detected");
-    PreCommit::new()
-        .add_task("fmt", "cargo", "fmt --check")
-        .add_task("test", "cargo", "test --no-fail-fast")
-        .add_task("lint", "cargo", "clippy -- -D clippy::all")
-        .run()
-}
-
-/// Pre-commit checks for Node.js projects detected via package manager files.
-///
-/// Discovers scripts in package.json and attempts to run a reasonable subset
-/// (format/fmt, lint, test). Defaults to running `test` when nothing is found.
-pub fn npm_project_hook() -> Result<(), Error> {
-    println!("npm project detected");
-
-    // Detect package manager and normalize "run" invocation.
-    let (pm_prog, run_args_for): (&str, fn(&str) -> String) =
-        if Path::new("pnpm-lock.yaml").exists() {
-            ("pnpm", |script: &str| format!("run -s {script}"))
-        } else if Path::new("yarn.lock").exists() {
-            // yarn v1: `yarn <script>`
-            ("yarn", |script: &str| script.to_string())
-        } else {
-            // default to npm
-            ("npm", |script: &str| format!("run -s {script}"))
-        };
-
-    // Parse package.json to discover available scripts
-    let mut available: HashMap<String, String> = HashMap::new();
-
-    if let Some(serde_json::Value::Object(obj)) = read_to_string("package.json")
-        .ok()
-        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
-        .and_then(|v| v.get("scripts").cloned())
-    {
-        for (k, v) in obj {
-            if let Some(cmd) = v.as_str() {
-                available.insert(k, cmd.to_string());
-            }
-        }
-    }
-
-    // Build pre-commit pipeline from discovered scripts
-    let mut pc = PreCommit::new();
-
-    // format/fmt
-    if available.contains_key("format") {
-        pc.add_task("format", pm_prog, &run_args_for("format"));
-    } else if available.contains_key("fmt") {
-        pc.add_task("fmt", pm_prog, &run_args_for("fmt"));
-    }
-
-    // lint
-    if available.contains_key("lint") {
-        pc.add_task("lint", pm_prog, &run_args_for("lint"));
-    }
-
-    // test (present by défaut chez npm, mais on vérifie quand même)
-    if available.contains_key("test") || Path::new("package.json").exists() {
-        pc.add_task("test", pm_prog, &run_args_for("test"));
-    }
-
-    // If nothing was detected, at least try tests to gate commits
-    if pc.tasks.is_empty() {
-        pc.add_task("test", pm_prog, &run_args_for("test"));
-    }
-
-    pc.run()
-}
-
-/// Auto-detect project type and run the appropriate pre-commit hook.
-///
-/// No-op for unrecognized projects.
-pub fn hooks() -> Result<(), Error> {
-    if Path::new("Cargo.toml").exists() {
-        cargo_project_hook()
-    } else if Path::new("package.json").exists() {
-        npm_project_hook()
-    } else {
-        Ok(())
-    }
-}
-
-fn main() -> ExitCode {
-    let args = apps();
-
-    // Resolve author identity from the environment. These are required for commit metadata.
-    let author = match var(AK_USERNAME) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Missing {AK_USERNAME}: {e}");
-            return ExitCode::FAILURE;
-        }
-    };
-    let author_email = match var(AK_EMAIL) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Missing {AK_EMAIL}: {e}");
-            return ExitCode::FAILURE;
-        }
-    };
-
-    match args.subcommand() {
-        Some(("init", _)) => {
-            // Layout:
-            // - .eikyu/
-            //   - cubes/<YYYY-MM>/<author>.cube
-            //   - branches/ (reserved)
-            //   - tree/<author>
-            create_dir_all("./.eikyu").expect("create dir failed");
-            create_dir_all(format!(".eikyu{MAIN_SEPARATOR_STR}cubes"))
-                .expect("create cubes dir failed");
-            create_dir_all(format!(".eikyu{MAIN_SEPARATOR_STR}branches"))
-                .expect("create branches dir failed");
-            let tree_path = format!(".eikyu{MAIN_SEPARATOR_STR}tree{MAIN_SEPARATOR_STR}{author}");
-            create_dir_all(&tree_path).expect("create tree dir failed");
-
-            // Ensure the current cube file exists for this author/month.
-            let cube = cube_path_for(&author);
-            let _ = Writer::create(&cube).expect("failed to initialize cube");
-            println!("Initialized repository. Cube: {cube}");
-            println!("Reference tree: {tree_path}");
-            ExitCode::SUCCESS
-        }
+        .subcommand(
+            Command::new("hooks")
+                .about("run or list the pre-commit steps")
+                .subcommand_required(true)
+                .subcommand(verify_args(
+                    Command::new("run").about("run the pre-commit steps now"),
+                    false,
+                ))
+                .subcommand(Command::new("list").about("list the steps of .eikyu/hooks.toml")),
+        )
+        .subcommand(verify_args(
+            Command::new("check")
+                .about("check the repository for CI: hooks, drift from the last seal, messages")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .help("Check the messages after this tag or commit (defaults to the previous release)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Check the messages up to this tag or commit (defaults to the head of the current branch)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("no-hooks")
+                        .long("no-hooks")
+                        .help("Do not run the pre-commit steps")
+                        .required(false)
+                        .conflicts_with("skip")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the findings as JSON (step output goes to standard error)")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+            false,
+        ))
+        .subcommand(
+            Command::new("check-ignore")
+                .about("explain whether paths are ignored, and by which rule")
+                .arg(
+                    Arg::new("paths")
+                        .help("Paths to check")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("remote")
+                .about("manage the repositories to push to and pull from")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("register a remote")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the remote")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        )
+                        .arg(
+                            Arg::new("url")
+                                .help("Path, [user@]host:path or ssh://[user@]host[:port]/path")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove").about("forget a remote").arg(
+                        Arg::new("name")
+                            .help("Name of the remote")
+                            .required(true)
+                            .action(ArgAction::Set),
+                    ),
+                )
+                .subcommand(Command::new("list").about("list remotes")),
+        )
+        .subcommand(
+            Command::new("clone")
+                .about("copy a remote repository and check out its latest commit")
+                .arg(
+                    Arg::new("url")
+                        .help("Path, [user@]host:path or ssh://[user@]host[:port]/path")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .help("Directory to create (defaults to the last part of the URL)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("import-git")
+                .about("seal the history of a Git repository")
+                .arg(
+                    Arg::new("repo")
+                        .help("Git repository to read")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("first-parent")
+                        .long("first-parent")
+                        .help("Only follow first parents: merged branches become single seals")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("export-git")
+                .about("replay every seal as a commit of a Git repository")
+                .arg(
+                    Arg::new("dir")
+                        .help("Git repository to write into (created if missing)")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(sync_args(
+            Command::new("push").about("send the records the remote lacks"),
+        ))
+        .subcommand(sync_args(
+            Command::new("pull").about("bring in the records of the remote"),
+        ))
+        .subcommand(
+            Command::new("remote-helper")
+                .about("serve push and pull over SSH")
+                .hide(true)
+                .arg(
+                    Arg::new("op")
+                        .required(true)
+                        .value_parser(["tips", "fetch", "store"])
+                        .action(ArgAction::Set),
+                )
+                .arg(Arg::new("path").required(true).action(ArgAction::Set))
+                .arg(Arg::new("cube").required(false).action(ArgAction::Set))
+                .arg(
+                    Arg::new("from")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("identity")
+                        .long("identity")
+                        .help("Identity (JSON) of the cube the stored records come from")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("get or set user.name, user.email, user.signingkey and editor")
+                .arg(
+                    Arg::new("key")
+                        .help("Setting to read or write")
+                        .required_unless_present("list")
+                        .value_parser(config::KEYS)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("value")
+                        .help("New value (prints the current one when omitted)")
+                        .required(false)
+                        .conflicts_with("unset")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("global")
+                        .long("global")
+                        .help("Use the user configuration (~/.config/ak/config.toml) instead of the repository's")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("unset")
+                        .long("unset")
+                        .help("Remove the setting")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("list")
+                        .short('l')
+                        .long("list")
+                        .help("List the effective settings and where they come from")
+                        .required(false)
+                        .conflicts_with_all(["key", "global", "unset"])
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("name a commit, or list and delete tags")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("name")
+                        .help("Tag to create")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("commit")
+                        .long("commit")
+                        .help("Commit to tag (defaults to the head of the current branch)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .help("Move the tag if it already exists")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(Command::new("list").about("list tags"))
+                .subcommand(
+                    Command::new("delete").about("delete a tag").arg(
+                        Arg::new("name")
+                            .help("Tag to delete")
+                            .required(true)
+                            .action(ArgAction::Set),
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("changelog")
+                .about("render the commits between two tags as a Markdown changelog")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .help("Start after this tag or commit (defaults to the previous release)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("End at this tag or commit (defaults to the head of the current branch)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("release")
+                .about("tag the current head as a release and print its changelog")
+                .arg(
+                    Arg::new("version")
+                        .help("Version to release, also the name of its tag")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("revert")
+                .about("undo a commit with a new seal")
+                .arg(
+                    Arg::new("commit")
+                        .help("Commit id or tag to revert")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("cherry-pick")
+                .about("re-apply a commit's changes with a new seal")
+                .arg(
+                    Arg::new("commit")
+                        .help("Commit id or tag to pick")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("binary")
+                        .long("binary")
+                        .value_name("POLICY")
+                        .help("Binary files changed on both sides: keep ours as a conflict, or take ours or theirs")
+                        .required(false)
+                        .value_parser(["conflict", "ours", "theirs"])
+                        .default_value("conflict")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("format-patch")
+                .about("write seals as mailbox patches")
+                .arg(
+                    Arg::new("range")
+                        .help("A commit, or `<a>..<b>` / `<a>..` for the seals after <a>")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("output-directory")
+                        .short('o')
+                        .long("output-directory")
+                        .value_name("DIR")
+                        .help("Directory of the patch files (default: the current directory)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .long("stdout")
+                        .help("Print the patches instead of writing files")
+                        .conflicts_with("output-directory")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("apply")
+                .about("apply mailbox patches or unified diffs to the working directory")
+                .arg(
+                    Arg::new("patches")
+                        .help("Patch files, applied in order")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Only check that the patches apply")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("reverse")
+                        .long("reverse")
+                        .short('R')
+                        .help("Undo the patches, last one first")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("fuzz")
+                        .long("fuzz")
+                        .value_name("N")
+                        .help("Context lines a hunk may leave out at each end to apply")
+                        .required(false)
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(usize))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("merge another branch into the current one")
+                .arg(
+                    Arg::new("branch")
+                        .help("Branch to merge")
+                        .required_unless_present("abort")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .help("Give up the merge in progress and restore the files it changed")
+                        .required(false)
+                        .conflicts_with("branch")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("binary")
+                        .long("binary")
+                        .value_name("POLICY")
+                        .help("Binary files changed on both sides: keep ours as a conflict, or take ours or theirs")
+                        .required(false)
+                        .value_parser(["conflict", "ours", "theirs"])
+                        .default_value("conflict")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(Command::new("verify").about(
+            "check the integrity of cubes, commits and manifests, and commit signatures",
+        ))
+        .subcommand(
+            Command::new("grep")
+                .about("search the sealed files, or every version of them, for a pattern")
+                .arg(
+                    Arg::new("pattern")
+                        .help("Regular expression to look for")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("all-history")
+                        .long("all-history")
+                        .help("Search every version stored in the cubes, not only the branch head")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("ignore-case")
+                        .short('i')
+                        .long("ignore-case")
+                        .help("Match regardless of case")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("blame")
+                .about("show the commit that last changed every line of a file")
+                .arg(
+                    Arg::new("file")
+                        .help("File to annotate")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("commit")
+                        .long("commit")
+                        .help("Annotate the file as of this commit id or tag (defaults to the branch head)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("show a commit in detail")
+                .arg(
+                    Arg::new("commit")
+                        .help("Commit id or tag")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("patch")
+                        .short('p')
+                        .long("patch")
+                        .help("Include the diff against the parent commit")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("show changes since the last seal, or between commits")
+                .arg(
+                    Arg::new("commits")
+                        .help("`<a> <b>`, `<a>..<b>`, or `<a>..` to compare with the working directory; existing paths and globs that follow limit the diff")
+                        .required(false)
+                        .num_args(0..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Only compare these files, directories or globs")
+                        .required(false)
+                        .last(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("normalize-eol")
+                        .long("normalize-eol")
+                        .visible_alias("ignore-cr-at-eol")
+                        .help("Treat CRLF and LF line endings as equal")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("ignore-space-change")
+                        .long("ignore-space-change")
+                        .short('b')
+                        .help("Ignore changes in the amount of whitespace")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("ignore-all-space")
+                        .long("ignore-all-space")
+                        .short('w')
+                        .help("Ignore whitespace when comparing lines")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("binary-similarity")
+                        .long("binary-similarity")
+                        .help("Show the share of bytes modified binary files keep")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("unified")
+                        .short('U')
+                        .long("unified")
+                        .value_name("N")
+                        .help("Lines of context around every change")
+                        .required(false)
+                        .default_value("3")
+                        .value_parser(clap::value_parser!(usize))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("stat")
+                        .long("stat")
+                        .help("Only show the added and removed lines of every file")
+                        .required(false)
+                        .conflicts_with_all(["name-only", "name-status", "json"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("name-only")
+                        .long("name-only")
+                        .help("Only list the changed paths")
+                        .required(false)
+                        .conflicts_with_all(["name-status", "json"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("name-status")
+                        .long("name-status")
+                        .help("List the changed paths with their A, M, D or R code")
+                        .required(false)
+                        .conflicts_with("json")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print every changed file with its hunks as JSON")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("quiet")
+                        .long("quiet")
+                        .short('q')
+                        .help("Print nothing; exit with 1 if there are changes, 0 otherwise, 2 on error")
+                        .required(false)
+                        .conflicts_with_all(["stat", "name-only", "name-status", "json"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-pager")
+                        .long("no-pager")
+                        .help("Print directly instead of through $PAGER")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("branch")
+                .about("list branches, or create one at the current head")
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the branch to create")
+                        .required(false)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("switch")
+                .about("switch the working directory to another branch")
+                .arg(
+                    Arg::new("name")
+                        .help("Branch to switch to")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("bisect")
+                .about("search the history for the commit that introduced a bug")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("start a search from a bad commit and good ones")
+                        .arg(
+                            Arg::new("bad")
+                                .help("Bad commit id or tag (defaults to the branch head)")
+                                .required(false)
+                                .action(ArgAction::Set),
+                        )
+                        .arg(
+                            Arg::new("good")
+                                .help("Good commit ids or tags")
+                                .required(false)
+                                .num_args(1..)
+                                .action(ArgAction::Append),
+                        ),
+                )
+                .subcommand(
+                    Command::new("good")
+                        .about("mark a commit as good")
+                        .arg(Arg::new("commit")
+                            .help("Commit id or tag (defaults to the one being tested)")
+                            .required(false)
+                            .action(ArgAction::Set)),
+                )
+                .subcommand(
+                    Command::new("bad")
+                        .about("mark a commit as bad")
+                        .arg(Arg::new("commit")
+                            .help("Commit id or tag (defaults to the one being tested)")
+                            .required(false)
+                            .action(ArgAction::Set)),
+                )
+                .subcommand(
+                    Command::new("skip")
+                        .about("leave out a commit that can't be tested")
+                        .arg(Arg::new("commit")
+                            .help("Commit id or tag (defaults to the one being tested)")
+                            .required(false)
+                            .action(ArgAction::Set)),
+                )
+                .subcommand(
+                    Command::new("run")
+                        .about("test every candidate with a command: 0 is good, 125 skip, 1-127 bad")
+                        .arg(
+                            Arg::new("command")
+                                .help("Command and arguments, run in the candidate's worktree")
+                                .required(true)
+                                .num_args(1..)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true)
+                                .action(ArgAction::Append),
+                        ),
+                )
+                .subcommand(Command::new("reset").about("end the search and remove its worktree")),
+        )
+        .subcommand(
+            Command::new("note")
+                .about("annotate seals without rewriting them")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("attach a note to a commit")
+                        .arg(
+                            Arg::new("commit")
+                                .help("Commit id or tag")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        )
+                        .arg(
+                            Arg::new("text")
+                                .help("Text of the note")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show").about("list the notes of a commit").arg(
+                        Arg::new("commit")
+                            .help("Commit id or tag")
+                            .required(true)
+                            .action(ArgAction::Set),
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("reflog")
+                .about("list the operations that moved a head, newest first"),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("drop old pending records, superseded commits and unreachable file versions")
+                .arg(
+                    Arg::new("older-than")
+                        .long("older-than")
+                        .value_name("DAYS")
+                        .help("Only drop pending records older than this many days")
+                        .required(false)
+                        .default_value("14")
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Report what would be dropped without rewriting the cubes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("prune")
+                .about("fold old monthly cubes into one and archive the file versions nothing needs")
+                .arg(
+                    Arg::new("before")
+                        .long("before")
+                        .value_name("YYYY-MM")
+                        .help("Prune the cubes of the months before this one")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("archive-dir")
+                        .long("archive-dir")
+                        .value_name("DIR")
+                        .help("Directory of the archive cubes (default: .eikyu/archive)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("compress")
+                        .long("compress")
+                        .help("Gzip the archive cubes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Report what would be pruned without touching the cubes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .help("Do not ask for confirmation")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("serve the appends to your cube from one process, until stopped")
+                .arg(
+                    Arg::new("stop")
+                        .long("stop")
+                        .help("Stop the daemon of your cube")
+                        .required(false)
+                        .conflicts_with("status")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("status")
+                        .long("status")
+                        .help("Tell whether a daemon serves your cube")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("tree")
+                .about("inspect the reference tree")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("verify")
+                        .about("check that the reference tree holds exactly what was sealed")
+                        .arg(
+                            Arg::new("snapshots")
+                                .long("snapshots")
+                                .help("Also check the snapshots kept by [storage] keep-snapshots")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about("remove the reference trees and snapshots nothing reads anymore")
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("List what would be removed without removing it")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("du")
+                        .about("report the disk usage of the reference trees and snapshots"),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshots")
+                .about("list the per-seal snapshots kept by [storage] keep-snapshots, or prune them")
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .value_name("N")
+                        .help("Remove all but the N newest snapshots")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("summarize the history: commits, most changed files, storage")
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .help("Number of most changed files to list")
+                        .required(false)
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the statistics as JSON")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("stash")
+                .about("put unsealed changes aside, or bring them back")
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .long("message")
+                        .help("Describe the stashed changes")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .subcommand(Command::new("list").about("list stashes, latest first"))
+                .subcommand(
+                    Command::new("pop")
+                        .about("restore a stash in the working directory and drop it")
+                        .arg(
+                            Arg::new("id")
+                                .help("Stash to restore (defaults to the latest)")
+                                .required(false)
+                                .value_parser(clap::value_parser!(u64))
+                                .action(ArgAction::Set),
+                        ),
+                )
+                .subcommand(
+                    Command::new("drop").about("discard a stash").arg(
+                        Arg::new("id")
+                            .help("Stash to discard")
+                            .required(true)
+                            .value_parser(clap::value_parser!(u64))
+                            .action(ArgAction::Set),
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("pack the files of a commit into a .tar.gz or .zip")
+                .arg(
+                    Arg::new("commit")
+                        .long("commit")
+                        .help("Commit id or tag to archive (default: the branch head)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("Archive to write: .tar.gz, .tgz or .zip")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("materialize")
+                .about("write the whole tree of a commit into a scratch directory")
+                .arg(
+                    Arg::new("commit")
+                        .long("commit")
+                        .help("Commit id or tag to materialize (default: the branch head)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("into")
+                        .long("into")
+                        .value_name("DIR")
+                        .help("New or empty directory to write to (default: a temporary directory)")
+                        .required(false)
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("worktree")
+                .about("manage working directories linked to this repository")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("check a branch out in a new linked directory")
+                        .arg(
+                            Arg::new("path")
+                                .help("Directory to create")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        )
+                        .arg(
+                            Arg::new("branch")
+                                .help("Branch to check out (not checked out elsewhere)")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("delete a linked directory")
+                        .arg(
+                            Arg::new("path")
+                                .help("Directory of the worktree")
+                                .required(true)
+                                .action(ArgAction::Set),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .help("Remove it even with unsealed changes")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list").about("list the working directories and their branch"),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("restore files as they were at a past commit")
+                .arg(
+                    Arg::new("commit")
+                        .long("commit")
+                        .help("Commit id or tag to restore from")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("into")
+                        .long("into")
+                        .help(
+                            "Write the files below this directory instead of the working directory",
+                        )
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Files or directories to restore (defaults to everything)")
+                        .required(false)
+                        .num_args(0..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("summarize changes since the last seal")
+                .arg(
+                    Arg::new("untracked")
+                        .short('u')
+                        .long("untracked")
+                        .help("Only list files that are not in the last sealed tree")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("porcelain")
+                        .long("porcelain")
+                        .help("Stable, uncolored `<code> <path>` lines for scripts")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .get_matches()
+}
+
+/// Compute the current author's cube file path with year-month bucketing.
+///
+/// Layout:
+/// - .eikyu/cubes/YYYY-MM/<author>.cube
+///
+/// A new month's cube continues the record ids of the author's earlier cubes, so that ids stay
+/// unique across the whole history (see `data::history`). With the shared layout (see
+/// `data::config`) every author uses `.eikyu/cubes/shared.cube` instead.
+pub fn cube_path_for(author: &str) -> String {
+    let layout = Config::load(Path::new("."))
+        .expect("read .eikyu/config.toml failed")
+        .storage
+        .layout;
+    if layout == Layout::Shared {
+        create_dir_all(format!(".eikyu{MAIN_SEPARATOR_STR}cubes"))
+            .expect("create cubes dir failed");
+        return format!(".eikyu{MAIN_SEPARATOR_STR}cubes{MAIN_SEPARATOR_STR}shared.cube");
+    }
+    let ym = chrono::Local::now().format("%Y-%m").to_string();
+    create_dir_all(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}cubes{MAIN_SEPARATOR_STR}{ym}"
+    ))
+    .expect("create cubes dir failed");
+    let path = format!(
+        ".eikyu{MAIN_SEPARATOR_STR}cubes{MAIN_SEPARATOR_STR}{ym}{MAIN_SEPARATOR_STR}{author}.cube"
+    );
+    if !Path::new(&path).exists() {
+        history::next_id(Path::new("."), author)
+            .and_then(|next| Writer::create(&path)?.advance_next_id(next))
+            .expect("create cube failed");
+    }
+    path
+}
+
+/// Every record of `author`, across their monthly cubes.
+pub fn author_events(author: &str) -> std::io::Result<Vec<Event>> {
+    history::author_events(Path::new("."), author)
+}
+
+/// Append a phenomenon/noumenon string pair into the target cube.
+///
+/// Returns the byte offset of the appended record (useful for random access).
+pub fn save_string_in_cube(
+    cube_path: &str,
+    phenomenon: &str,
+    content: &str,
+) -> std::io::Result<u64> {
+    let mut w = Writer::create(cube_path)?;
+    let off = w.append(phenomenon, content)?;
+    Ok(off)
+}
+
+/// Return the commit id branch `name` of `author` points at, if any.
+pub fn branch_head(author: &str, name: &str) -> std::io::Result<Option<u64>> {
+    Ok(branch::heads(&author_events(author)?)
+        .get(name)
+        .copied()
+        .flatten())
+}
+
+/// Message and content source of a seal.
+pub struct SealRequest<'a> {
+    pub ty: &'a str,
+    pub summary: &'a str,
+    pub body: &'a str,
+    pub author: &'a str,
+    pub author_email: &'a str,
+    /// Seal the whole working directory instead of the staged changes.
+    pub all: bool,
+    /// Commit undone by this seal (`ak revert`).
+    pub reverts: Option<u64>,
+    /// Head commit replaced by this seal (`ak seal --amend`).
+    pub amends: Option<u64>,
+    /// Commit whose changes this seal re-applies (`ak cherry-pick`).
+    pub picked_from: Option<u64>,
+    /// Tip of the branch merged by this seal (`ak merge`).
+    pub merge_parent: Option<u64>,
+    /// SSH key signing the commit record (`ak seal --sign`).
+    pub signing_key: Option<&'a Path>,
+}
+
+/// Renames and removals sealed with a commit: the staged ones, or with `all` the deletions of the
+/// working directory, plus the renames detected by content among the other deletions and
+/// additions relative to the sealed state `reference` (see `rename::detect`).
+fn sealed_moves(
+    root: &Path,
+    reference: &tree::Reference,
+    all: bool,
+    staged: &Index,
+) -> std::io::Result<Index> {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    if all {
+        for (change, native) in status::status(root, reference)?.entries {
+            let path = native.replace(MAIN_SEPARATOR_STR, "/");
+            match change {
+                status::Change::Deleted => removed.push(path),
+                status::Change::Added => added.push((path, std::fs::read(root.join(&native))?)),
+                status::Change::Modified => {}
+            }
+        }
+    } else {
+        for (path, content) in &staged.entries {
+            match content {
+                None => removed.push(path.clone()),
+                Some(c) if reference.read(path)?.is_none() => {
+                    added.push((path.clone(), c.clone().into_bytes()))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let mut moves = Index {
+        renames: staged.renames.clone(),
+        ..Index::default()
+    };
+    for path in &removed {
+        moves.stage_content(path, None);
+    }
+    let explicit =
+        |p: &String| moves.renames.contains_key(p) || moves.renames.values().any(|f| f == p);
+    let mut sealed = Vec::new();
+    for path in removed.iter().filter(|p| !explicit(p)) {
+        sealed.push((path.clone(), reference.read(path)?.unwrap_or_default()));
+    }
+    added.retain(|(p, _)| !explicit(p));
+    let removed_view: Vec<(&str, &[u8])> = sealed
+        .iter()
+        .map(|(p, c)| (p.as_str(), c.as_slice()))
+        .collect();
+    let added_view: Vec<(&str, &[u8])> = added
+        .iter()
+        .map(|(p, c)| (p.as_str(), c.as_slice()))
+        .collect();
+    let detected = rename::detect(&removed_view, &added_view);
+    moves.renames.extend(detected);
+    Ok(moves)
+}
+
+/// Seal `staged` (or the whole working directory with `req.all`) on the current branch: store
+/// the contents and their manifest, write the commit record, move the branch head, refresh the
+/// reference tree and clear the index. Returns the new commit id.
+///
+/// With `req.amends`, the new commit takes the place of that (head) commit: it gets its parent,
+/// starts from its manifest, and the old commit is marked superseded.
+pub fn seal(root: &Path, req: &SealRequest, staged: &Index) -> std::io::Result<u64> {
+    // Render the user-facing commit message and reserve an id via a "commit:pending" event.
+    let commit_message = COMMIT_TEMPLATE
+        .replace("%type%", req.ty)
+        .replace("%summary%", req.summary)
+        .replace("%body%", req.body)
+        .replace("%author%", req.author)
+        .replace("%author_email%", req.author_email);
+
+    let cube = cube_path_for(req.author);
+    let branch_name = branch::current(root, req.author);
+    let parent = match req.amends {
+        Some(old) => {
+            let events = author_events(req.author)?;
+            let ev = commit::find(&events, old)
+                .ok_or_else(|| Error::other(format!("no commit #{old}")))?;
+            serde_json::from_str::<serde_json::Value>(&ev.noumenon)
+                .map_err(Error::other)?
+                .get("parent")
+                .and_then(|x| x.as_u64())
+        }
+        None => branch_head(req.author, &branch_name)?,
+    };
+
+    // Renames and removals to record with the commit, from the reference tree's state.
+    let tree_dir = tree::tree_dir(root, req.author);
+    let moves = sealed_moves(
+        root,
+        &tree::Reference::open(root, req.author)?,
+        req.all,
+        staged,
+    )?;
+
+    // Reserve an id by appending a pending record, then read it back to obtain the assigned id.
+    let placeholder_off = save_string_in_cube(&cube, "commit:pending", &commit_message)?;
+    let pending_event = Writer::read_one_at(&cube, placeholder_off)?;
+    let assigned_id = pending_event.id;
+
+    // Record the sealed contents and their manifest ahead of the commit that references them.
+    let manifest_id = {
+        let mut w = Writer::create(&cube)?;
+        let events = author_events(req.author)?;
+        let manifest = if req.all {
+            Manifest::snapshot(&mut w, &events, root)?
+        } else {
+            // Commits from before manifests: the reference tree holds the parent's state.
+            let mut base = match req
+                .amends
+                .or(parent)
+                .and_then(|p| Manifest::of_commit(&events, p))
+            {
+                Some(m) => m,
+                None if tree_dir.exists() => Manifest::snapshot(&mut w, &events, &tree_dir)?,
+                None => Manifest::default(),
+            };
+            base.apply_index(&mut w, staged)?;
+            base
+        };
+        manifest.record(&mut w, assigned_id)?
+    };
+
+    // Durable commit record (wire format), extracted from the "commit:pending" reservation.
+    let record = schema::Commit {
+        v: schema::Commit::VERSION,
+        id: assigned_id,
+        parent,
+        branch: branch_name.clone(),
+        manifest: Some(manifest_id),
+        reverts: req.reverts,
+        amends: req.amends,
+        picked_from: req.picked_from,
+        merge_parent: req.merge_parent,
+        git_commit: None,
+        ty: req.ty.to_string(),
+        summary: req.summary.to_string(),
+        body: req.body.to_string(),
+        author: req.author.to_string(),
+        author_email: req.author_email.to_string(),
+        // Convert internal nanoseconds to milliseconds (bounded).
+        timestamp: u64::try_from(pending_event.timestamp / 1_000_000).unwrap_or(0),
+    };
+    let json = serde_json::to_string_pretty(&record).map_err(Error::other)?;
+
+    // Let user scripts observe (or veto) the commit before it becomes durable.
+    script::on_seal(&json).map_err(|e| Error::other(format!("on_seal hook failed: {e}")))?;
+    let signature = match req.signing_key {
+        Some(key) => Some(sign::sign(key, &json)?),
+        None => None,
+    };
+
+    save_string_in_cube(&cube, "commit", &json)?;
+    let mut w = Writer::create(&cube)?;
+    rename::record(&mut w, assigned_id, &moves)?;
+    if let Some(signature) = signature {
+        sign::record(&mut w, assigned_id, signature)?;
+    }
+    if let Some(old) = req.amends {
+        commit::mark_superseded(&mut w, old, assigned_id)?;
+    }
+    branch::record_head(&mut w, &branch_name, Some(assigned_id))?;
+    let op = if req.merge_parent.is_some() {
+        "merge"
+    } else if req.reverts.is_some() {
+        "revert"
+    } else if req.picked_from.is_some() {
+        "pick"
+    } else if req.amends.is_some() {
+        "amend"
+    } else {
+        "seal"
+    };
+    let state = |head| reflog::State {
+        branch: branch_name.clone(),
+        head,
+    };
+    reflog::record(
+        &mut w,
+        &reflog::Entry {
+            author: req.author.to_string(),
+            op: op.to_string(),
+            before: state(req.amends.or(parent)),
+            after: state(Some(assigned_id)),
+            message: format!("{}: {}", req.ty, req.summary),
+        },
+    )?;
+
+    // Refresh the on-disk reference tree to match the sealed state (or drop it, see `update_tree`).
+    let updated = if req.all || Config::load(root)?.storage.snapshot == config::Snapshot::Cube {
+        tree::update_tree(req.author)
+    } else {
+        staged.apply_to_tree(&tree_dir)
+    };
+    match updated {
+        Ok(_) => println!("Reference tree updated successfully."),
+        Err(e) => eprintln!("Error updating reference tree: {}", e),
+    }
+    if let Err(e) = tree::record_snapshot(root, req.author, assigned_id) {
+        eprintln!("Error keeping a snapshot of the seal: {e}");
+    }
+    if let Err(e) = Index::default().save(root, req.author) {
+        eprintln!("Error clearing the index: {e}");
+    }
+
+    println!(
+        "Sealed on {branch_name}: {} {} (id={} parent={})",
+        req.ty,
+        req.summary,
+        assigned_id,
+        parent
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "none".to_string())
+    );
+    Ok(assigned_id)
+}
+
+/// Pre-commit checks for Rust/Cargo projects.
+/// - fmt --check
+/// - test --no-fail-fast
+/// - clippy with warnings as errors
+pub fn cargo_project_hook(skip: &[String]) -> Result<(), Error> {
+    println!("cargo project detected");
+    PreCommit::new()
+        .add_task("fmt", "cargo", "fmt --check")
+        .add_task("test", "cargo", "test --no-fail-fast")
+        .add_task("lint", "cargo", "clippy -- -D clippy::all")
+        .run(&[], skip)
+}
+
+/// Pre-commit checks for Node.js projects detected via package manager files.
+///
+/// Discovers scripts in package.json and attempts to run a reasonable subset
+/// (format/fmt, lint, test). Defaults to running `test` when nothing is found.
+pub fn npm_project_hook(skip: &[String]) -> Result<(), Error> {
+    println!("npm project detected");
+
+    // Detect package manager and normalize "run" invocation.
+    let (pm_prog, run_args_for): (&str, fn(&str) -> String) =
+        if Path::new("pnpm-lock.yaml").exists() {
+            ("pnpm", |script: &str| format!("run -s {script}"))
+        } else if Path::new("yarn.lock").exists() {
+            // yarn v1: `yarn <script>`
+            ("yarn", |script: &str| script.to_string())
+        } else {
+            // default to npm
+            ("npm", |script: &str| format!("run -s {script}"))
+        };
+
+    // Parse package.json to discover available scripts
+    let mut available: HashMap<String, String> = HashMap::new();
+
+    if let Some(serde_json::Value::Object(obj)) = read_to_string("package.json")
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v.get("scripts").cloned())
+    {
+        for (k, v) in obj {
+            if let Some(cmd) = v.as_str() {
+                available.insert(k, cmd.to_string());
+            }
+        }
+    }
+
+    // Build pre-commit pipeline from discovered scripts
+    let mut pc = PreCommit::new();
+
+    // format/fmt
+    if available.contains_key("format") {
+        pc.add_task("format", pm_prog, &run_args_for("format"));
+    } else if available.contains_key("fmt") {
+        pc.add_task("fmt", pm_prog, &run_args_for("fmt"));
+    }
+
+    // lint
+    if available.contains_key("lint") {
+        pc.add_task("lint", pm_prog, &run_args_for("lint"));
+    }
+
+    // test (present by défaut chez npm, mais on vérifie quand même)
+    if available.contains_key("test") || Path::new("package.json").exists() {
+        pc.add_task("test", pm_prog, &run_args_for("test"));
+    }
+
+    // If nothing was detected, at least try tests to gate commits
+    if pc.tasks.is_empty() {
+        pc.add_task("test", pm_prog, &run_args_for("test"));
+    }
+
+    pc.run(&[], skip)
+}
+
+/// Pre-commit checks for Python projects (pyproject.toml).
+/// - ruff format --check and ruff check, when ruff is configured
+/// - pytest
+pub fn python_project_hook(skip: &[String]) -> Result<(), Error> {
+    println!("python project detected");
+    let pyproject = read_to_string("pyproject.toml").unwrap_or_default();
+    let mut pc = PreCommit::new();
+    if pyproject.contains("[tool.ruff")
+        || Path::new("ruff.toml").exists()
+        || Path::new(".ruff.toml").exists()
+    {
+        pc.add_task("fmt", "ruff", "format --check")
+            .add_task("lint", "ruff", "check");
+    }
+    pc.add_task("test", "pytest", "").run(&[], skip)
+}
+
+/// Pre-commit checks for Go modules (go.mod).
+/// - gofmt lists no file to reformat
+/// - go vet
+/// - go test
+pub fn go_project_hook(skip: &[String]) -> Result<(), Error> {
+    println!("go project detected");
+    let mut pc = PreCommit::new();
+    // `gofmt -l` succeeds even when files need formatting: fail on any listed file.
+    pc.tasks.push(Step {
+        name: "fmt".to_string(),
+        program: "sh".to_string(),
+        args: vec![
+            "-c".to_string(),
+            r#"out=$(gofmt -l .) && test -z "$out""#.to_string(),
+        ],
+        allow_failure: false,
+        only_on_changed: Vec::new(),
+        parallel: false,
+    });
+    pc.add_task("vet", "go", "vet ./...")
+        .add_task("test", "go", "test ./...")
+        .run(&[], skip)
+}
+
+/// Pre-commit checks for projects driven by a justfile: the `check` recipe, or else the
+/// `fmt`, `lint` and `test` recipes it defines.
+pub fn just_project_hook(justfile: &str, skip: &[String]) -> Result<(), Error> {
+    println!("justfile detected");
+    let recipes = hooks::just_recipes(&read_to_string(justfile)?);
+    let mut pc = PreCommit::new();
+    if recipes.iter().any(|r| r == "check") {
+        pc.add_task("check", "just", "check");
+    } else {
+        for recipe in ["fmt", "lint", "test"] {
+            if recipes.iter().any(|r| r == recipe) {
+                pc.add_task(recipe, "just", recipe);
+            }
+        }
+    }
+    pc.run(&[], skip)
+}
+
+/// Pre-commit checks for Make projects: `make check`, when the Makefile has that target.
+pub fn make_project_hook(makefile: &str, skip: &[String]) -> Result<(), Error> {
+    println!("makefile detected");
+    let mut pc = PreCommit::new();
+    if hooks::make_targets(&read_to_string(makefile)?)
+        .iter()
+        .any(|t| t == "check")
+    {
+        pc.add_task("check", "make", "check");
+    }
+    pc.run(&[], skip)
+}
+
+/// Run the pre-commit steps of `.eikyu/hooks.toml` (see `data::hooks`) against the paths
+/// `author` changed since the last seal, staged or not. Without a hooks file, auto-detect the
+/// project type and run the appropriate pre-commit hook. Steps named in `skip` are left out.
+///
+/// No-op for unrecognized projects.
+pub fn hooks(root: &Path, author: &str, skip: &[String]) -> Result<(), Error> {
+    if let Some(steps) = hooks::load(root)? {
+        let mut changed: Vec<String> = Index::load(root, author)?.entries.into_keys().collect();
+        for (_, path) in status::status(root, &tree::Reference::open(root, author)?)?.entries {
+            changed.push(path.replace(MAIN_SEPARATOR_STR, "/"));
+        }
+        changed.sort();
+        changed.dedup();
+        return PreCommit { tasks: steps }.run(&changed, skip);
+    }
+    if Path::new("Cargo.toml").exists() {
+        cargo_project_hook(skip)
+    } else if Path::new("package.json").exists() {
+        npm_project_hook(skip)
+    } else if Path::new("pyproject.toml").exists() {
+        python_project_hook(skip)
+    } else if Path::new("go.mod").exists() {
+        go_project_hook(skip)
+    } else if let Some(justfile) = hooks::JUSTFILES.iter().find(|f| Path::new(f).exists()) {
+        just_project_hook(justfile, skip)
+    } else if let Some(makefile) = hooks::MAKEFILES.iter().find(|f| Path::new(f).exists()) {
+        make_project_hook(makefile, skip)
+    } else {
+        Ok(())
+    }
+}
+
+/// Run this command again with `AK_NO_PAGER` set, its output piped into `$PAGER` (`less -R` by
+/// default, with `LESS=FRX` unless `LESS` is set, as Git does). The arguments are passed on
+/// untouched: a `--no-pager` flag appended to them would land among trailing paths. `None` when
+/// there is no pager (an empty `$PAGER`) or it can't be started: the caller then prints directly.
+fn run_paged() -> Option<ExitCode> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let mut command = std::process::Command::new(words.next()?);
+    command.args(words).stdin(std::process::Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut pager = command.spawn().ok()?;
+    let input = pager.stdin.take()?;
+    let status = {
+        let mut ak = std::process::Command::new(std::env::current_exe().ok()?);
+        ak.args(std::env::args_os().skip(1))
+            .env("AK_NO_PAGER", "1")
+            .stdout(input);
+        // The output is no terminal anymore, but the pager shows colors.
+        if std::env::var_os("NO_COLOR").is_none() {
+            ak.env("CLICOLOR_FORCE", "1");
+        }
+        ak.status()
+    };
+    let _ = pager.wait();
+    Some(match status {
+        Ok(status) => ExitCode::from(status.code().unwrap_or(1) as u8),
+        Err(e) => {
+            eprintln!("diff failed: {e}");
+            ExitCode::FAILURE
+        }
+    })
+}
+
+/// Exit quietly when the pager (or `head`, …) is closed before everything is printed, instead of
+/// panicking on the broken pipe.
+fn quiet_broken_pipe() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<String>()
+            .map_or("", String::as_str);
+        if message.contains("Broken pipe") {
+            std::process::exit(0);
+        }
+        default(info);
+    }));
+}
+
+/// Run the pre-commit steps for `ak check`. With `quiet`, they run in a child `ak hooks run`
+/// whose report is sent to standard error, leaving standard output to the JSON findings.
+fn check_hooks(root: &Path, author: &str, skip: &[String], quiet: bool) -> check::Hooks {
+    let result = if quiet {
+        std::env::current_exe().and_then(|exe| {
+            let out = std::process::Command::new(exe)
+                .args(["hooks", "run"])
+                .args(skip.iter().flat_map(|s| ["--skip", s.as_str()]))
+                .current_dir(root)
+                .stderr(std::process::Stdio::inherit())
+                .output()?;
+            std::io::stderr().write_all(&out.stdout)?;
+            if out.status.success() {
+                Ok(())
+            } else {
+                Err(Error::other(format!(
+                    "pre-commit steps failed ({})",
+                    out.status
+                )))
+            }
+        })
+    } else {
+        hooks(root, author, skip)
+    };
+    check::Hooks {
+        ran: true,
+        passed: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// Print the findings of `ak check`.
+fn print_check(report: &check::Report) {
+    let verdict = |ok: bool| if ok { "ok".green() } else { "failed".red() };
+    if report.hooks.ran {
+        match &report.hooks.error {
+            Some(e) => println!("hooks: {} ({e})", verdict(false)),
+            None => println!("hooks: {}", verdict(true)),
+        }
+    }
+    let head = report.head.map_or("-".to_string(), |h| format!("#{h}"));
+    println!("drift from {head}: {}", verdict(report.drift.is_empty()));
+    for d in &report.drift {
+        println!("  {} {}", d.change, d.path);
+    }
+    println!(
+        "messages of {} commit(s): {}",
+        report.commits,
+        verdict(report.messages.is_empty())
+    );
+    for v in &report.messages {
+        println!("  #{} {}", v.commit, v.summary);
+        for problem in &v.problems {
+            println!("    - {problem}");
+        }
+    }
+}
+
+/// Names given to `--skip`.
+fn skipped_steps(sub: &ArgMatches) -> Vec<String> {
+    sub.get_many::<String>("skip")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Flags bypassing the pre-commit steps, shared by `seal`, `inscribe` and `hooks run`.
+fn verify_args(cmd: Command, no_verify: bool) -> Command {
+    let cmd = cmd.arg(
+        Arg::new("skip")
+            .long("skip")
+            .value_name("STEP")
+            .help("Leave out this pre-commit step (repeatable)")
+            .required(false)
+            .action(ArgAction::Append),
+    );
+    if !no_verify {
+        return cmd;
+    }
+    cmd.arg(
+        Arg::new("no-verify")
+            .long("no-verify")
+            .help("Bypass the pre-commit steps")
+            .required(false)
+            .conflicts_with("skip")
+            .action(ArgAction::SetTrue),
+    )
+}
+
+/// Stage the changes below `targets` into `author`'s index; with `patch`, ask for every hunk
+/// (or whole added/deleted file). Returns the number of paths staged.
+fn add(root: &Path, author: &str, targets: &[&String], patch: bool) -> std::io::Result<usize> {
+    let reference = tree::Reference::open(root, author)?;
+    let mut idx = Index::load(root, author)?;
+    let mut count = 0;
+    for target in targets {
+        if !patch {
+            count += idx.stage(root, &reference, Path::new(target))?.len();
+            continue;
+        }
+        for (change, path) in index::changes_under(root, &reference, Path::new(target))? {
+            let native = root.join(path.replace('/', MAIN_SEPARATOR_STR));
+            let content = match change {
+                status::Change::Added | status::Change::Deleted => {
+                    let question = if change == status::Change::Added {
+                        format!("Stage new file {path}?")
+                    } else {
+                        format!("Stage removal of {path}?")
+                    };
+                    if !Confirm::new(&question)
+                        .with_default(false)
+                        .prompt()
+                        .map_err(Error::other)?
+                    {
+                        continue;
+                    }
+                    match change {
+                        status::Change::Added => Some(read_to_string(&native)?),
+                        _ => None,
+                    }
+                }
+                status::Change::Modified => {
+                    // Hunks are relative to what is already staged, or to the sealed version.
+                    let base = match idx.entries.get(&path) {
+                        Some(Some(staged)) => staged.clone(),
+                        _ => String::from_utf8_lossy(&reference.read(&path)?.unwrap_or_default())
+                            .into_owned(),
+                    };
+                    let new = read_to_string(&native)?;
+                    let mut selected = Vec::new();
+                    for h in index::hunks(&base, &new) {
+                        println!(
+                            "{} {path} @@ -{},{} +{},{} @@",
+                            "diff:".yellow().bold(),
+                            h.old_start,
+                            h.removed.len(),
+                            h.new_start,
+                            h.added.len()
+                        );
+                        for l in &h.removed {
+                            print!("{}", format!("-{l}").red());
+                        }
+                        for l in &h.added {
+                            print!("{}", format!("+{l}").green());
+                        }
+                        println!();
+                        selected.push(
+                            Confirm::new("Stage this hunk?")
+                                .with_default(false)
+                                .prompt()
+                                .map_err(Error::other)?,
+                        );
+                    }
+                    if !selected.contains(&true) {
+                        continue;
+                    }
+                    Some(index::apply_hunks(&base, &new, &selected))
+                }
+            };
+            idx.stage_content(&path, content);
+            count += 1;
+        }
+    }
+    idx.save(root, author)?;
+    Ok(count)
+}
+
+/// Files below portable `target` (itself or its descendants) that `ak mv` and `ak rm` act on:
+/// those of the reference tree `sealed`, and those staged for addition in `idx`.
+fn tracked_below(sealed: &diff::Tree, idx: &Index, target: &str) -> Vec<String> {
+    let below = |p: &String| *p == target || p.starts_with(&format!("{target}/"));
+    let mut files: Vec<String> = sealed.keys().filter(|p| below(p)).cloned().collect();
+    for (path, content) in &idx.entries {
+        if below(path) && content.is_some() && !sealed.contains_key(path) {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files
+}
+
+/// The sealed state of `author` (see `tree::Reference`), empty before the first seal.
+fn sealed_tree(root: &Path, author: &str) -> std::io::Result<diff::Tree> {
+    tree::Reference::open(root, author)?.tree()
+}
+
+/// Move `from` to `to` (into it, when `to` is a directory) and stage the move of every tracked
+/// file below `from` as a rename (see `data::rename`). The staged content is the sealed or
+/// already staged one, edits made before the move stay unstaged. Returns the renamed paths.
+fn mv(root: &Path, author: &str, from: &str, to: &str) -> std::io::Result<Vec<(String, String)>> {
+    let source = root.join(from);
+    let mut dest = root.join(to);
+    if dest.is_dir()
+        && let Some(name) = source.file_name()
+    {
+        dest = dest.join(name);
+    }
+    let outside = |p: &Path| {
+        Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is outside the repository", p.display()),
+        )
+    };
+    let old = paths::normalize(root, &source).ok_or_else(|| outside(&source))?;
+    let new = paths::normalize(root, &dest).ok_or_else(|| outside(&dest))?;
+    if !source.exists() {
+        return Err(Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{from} does not exist"),
+        ));
+    }
+    if dest.exists() {
+        return Err(Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dest.display()),
+        ));
+    }
+    if new == old || new.starts_with(&format!("{old}/")) {
+        return Err(Error::other(format!("cannot move {from} into itself")));
+    }
+
+    let sealed = sealed_tree(root, author)?;
+    let mut idx = Index::load(root, author)?;
+    let files = tracked_below(&sealed, &idx, &old);
+    if files.is_empty() {
+        return Err(Error::other(format!("{from} is not tracked")));
+    }
+    let mut staged = Vec::new();
+    for path in files {
+        let content = match idx.entries.get(&path) {
+            Some(Some(content)) => content.clone(),
+            _ => String::from_utf8(sealed[&path].clone())
+                .map_err(|_| Error::other(format!("{path} is not valid UTF-8")))?,
+        };
+        staged.push((path, content));
+    }
+
+    if let Some(parent) = dest.parent() {
+        create_dir_all(parent)?;
+    }
+    std::fs::rename(&source, &dest)?;
+    let mut renamed = Vec::new();
+    for (path, content) in staged {
+        let target = format!("{new}{}", &path[old.len()..]);
+        idx.stage_rename(&path, &target, content, sealed.contains_key(&path));
+        renamed.push((path, target));
+    }
+    idx.save(root, author)?;
+    Ok(renamed)
+}
+
+/// Delete `targets` from the working directory and stage the removal of every tracked file below
+/// them, sealed as tombstones (see `data::rename`). Returns the removed paths.
+fn rm(root: &Path, author: &str, targets: &[&String]) -> std::io::Result<Vec<String>> {
+    let sealed = sealed_tree(root, author)?;
+    let mut idx = Index::load(root, author)?;
+    let mut removed = Vec::new();
+    for target in targets {
+        let path = root.join(target);
+        let portable = paths::normalize(root, &path).ok_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{target} is outside the repository"),
+            )
+        })?;
+        let files = tracked_below(&sealed, &idx, &portable);
+        if files.is_empty() {
+            return Err(Error::other(format!("{target} is not tracked")));
+        }
+        removed.extend(files);
+    }
+
+    for path in &removed {
+        let native = root.join(path.replace('/', MAIN_SEPARATOR_STR));
+        match std::fs::remove_file(&native) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        // Drop the directories left empty, up to the repository root.
+        let mut dir = native.parent();
+        while let Some(d) = dir.filter(|d| *d != root) {
+            if std::fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+        // Removing a renamed file removes its original.
+        idx.renames.remove(path);
+        if sealed.contains_key(path) {
+            idx.stage_content(path, None);
+        } else {
+            idx.entries.remove(path);
+        }
+    }
+    idx.save(root, author)?;
+    Ok(removed)
+}
+
+/// Branch `author` has checked out in `root` and its head, for the reflog.
+fn head_state(root: &Path, author: &str) -> std::io::Result<reflog::State> {
+    let branch = branch::current(root, author);
+    let head = branch_head(author, &branch)?;
+    Ok(reflog::State { branch, head })
+}
+
+/// Append a reflog entry for `author` (see `data::reflog`).
+fn log_op(
+    author: &str,
+    op: &str,
+    before: reflog::State,
+    after: reflog::State,
+    message: String,
+) -> std::io::Result<()> {
+    let entry = reflog::Entry {
+        author: author.to_string(),
+        op: op.to_string(),
+        before,
+        after,
+        message,
+    };
+    reflog::record(&mut Writer::create(&cube_path_for(author))?, &entry)
+}
+
+/// Format the timestamp of a record (nanoseconds) in local time.
+fn record_when(ts: u128) -> String {
+    i64::try_from(ts / 1_000_000)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Print the notes of a commit, oldest first.
+fn print_notes(notes: &[(u128, note::Note)]) {
+    for (ts, n) in notes {
+        println!("    {} ({}):", n.author.cyan(), record_when(*ts));
+        for line in n.text.lines() {
+            println!("        {line}");
+        }
+    }
+}
+
+/// `bytes` with a binary unit (`12.3 KiB`).
+fn human_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 || unit == "GiB" {
+            return if unit == "B" {
+                format!("{bytes} B")
+            } else {
+                format!("{value:.1} {unit}")
+            };
+        }
+        value /= 1024.0;
+    }
+    unreachable!("the last unit always returns")
+}
+
+/// `ms` as days, hours and minutes (`2d 3h`, `4h 10m`, `12m`).
+fn human_duration(ms: i64) -> String {
+    let minutes = ms / 60_000;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{minutes}m"),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+/// Print `ak stats`, with the `top` most changed files.
+fn print_stats(st: &stats::Stats, top: usize) {
+    let counts = |title: &str, counts: &std::collections::BTreeMap<String, usize>| {
+        println!("{}", title.bold());
+        for (key, n) in counts {
+            println!("  {key:<24} {n}");
+        }
+    };
+    match st.mean_interval_ms {
+        Some(ms) => println!(
+            "{} commit(s), one every {} on average",
+            st.commits,
+            human_duration(ms)
+        ),
+        None => println!("{} commit(s)", st.commits),
+    }
+    counts("By author:", &st.by_author);
+    counts("By type:", &st.by_type);
+    counts("By week:", &st.by_week);
+    println!("{}", "Most changed files:".bold());
+    for (path, n) in st.changed_files.iter().take(top) {
+        println!("  {path:<24} {n}");
+    }
+    println!(
+        "Tracked: {} file(s), {} at the branch head",
+        st.tracked_files,
+        human_bytes(st.tracked_bytes)
+    );
+    let ratio = match st.working_bytes {
+        0 => String::new(),
+        w => format!(" ({:.1}x)", st.cube_bytes as f64 / w as f64),
+    };
+    println!(
+        "Storage: {} of cubes for a {} working tree{ratio}",
+        human_bytes(st.cube_bytes),
+        human_bytes(st.working_bytes)
+    );
+}
+
+/// Print the report of `ak gc`.
+fn print_gc(report: &gc::Report, dry_run: bool) {
+    let verb = if dry_run { "Would drop" } else { "Dropped" };
+    println!(
+        "{verb} {} record(s) from {} cube(s): {} pending, {} superseded commit(s), {} file version(s)",
+        report.records, report.cubes, report.pending, report.superseded, report.files
+    );
+    println!(
+        "{} -> {} ({} freed)",
+        human_bytes(report.bytes_before),
+        human_bytes(report.bytes_after),
+        human_bytes(report.bytes_before - report.bytes_after)
+    );
+}
+
+/// Print what `ak prune` did, or would do with `dry_run`, to every author's cubes.
+fn print_prune(reports: &[prune::Report], dry_run: bool) {
+    let verb = if dry_run { "Would move" } else { "Moved" };
+    for r in reports {
+        println!(
+            "{}: {} month(s) ({} .. {}) into {}",
+            r.author.cyan(),
+            r.months.len(),
+            r.months.first().map_or("", String::as_str),
+            r.months.last().map_or("", String::as_str),
+            r.cube.display()
+        );
+        println!(
+            "  {verb} {} file version(s) to {}, {} record(s) kept",
+            r.moved,
+            r.archive.display(),
+            r.kept
+        );
+        println!(
+            "  {} -> {}",
+            human_bytes(r.bytes_before),
+            human_bytes(r.bytes_after)
+        );
+    }
+}
+
+/// Print `author`'s reflog, newest first: `@{n}` numbers the entries from the latest one.
+fn print_reflog(events: &[Event], author: &str) {
+    for (n, (ts, e)) in reflog::entries(events, author).iter().enumerate() {
+        let when = record_when(*ts);
+        println!(
+            "{} {when} {:<10} {} -> {}  {}",
+            format!("@{{{n}}}").yellow(),
+            e.op.cyan(),
+            e.before,
+            e.after,
+            e.message
+        );
+    }
+}
+
+/// Format the `timestamp` of a commit record for display (`-` when missing or invalid).
+fn commit_when(v: &serde_json::Value, show_utc: bool, show_iso: bool) -> String {
+    let Some(naive) = commit::timestamp_ms(v).and_then(DateTime::from_timestamp_millis) else {
+        return "-".to_string();
+    };
+    if show_utc {
+        if show_iso {
+            naive.to_rfc3339()
+        } else {
+            naive.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+        }
+    } else {
+        let local = naive.with_timezone(&chrono::Local);
+        if show_iso {
+            local.to_rfc3339()
+        } else {
+            local.format("%Y-%m-%d %H:%M:%S").to_string()
+        }
+    }
+}
+
+/// Print `ak timeline --graph`: `shown` (newest first) with their lanes. Commits of `author`
+/// are decorated with the branches and tags (from `events`) pointing at them; `line` renders a
+/// commit after its lanes.
+fn print_graph(
+    events: &[Event],
+    author: &str,
+    shown: &[(history::Commit, serde_json::Value)],
+    line: impl Fn(&history::Commit, &serde_json::Value, &str) -> String,
+) {
+    let mut names: HashMap<u64, Vec<String>> = HashMap::new();
+    for (name, head) in branch::heads(events) {
+        if let Some(id) = head {
+            names.entry(id).or_default().push(name);
+        }
+    }
+    for (name, id) in tag::tags(events) {
+        names.entry(id).or_default().push(format!("tag: {name}"));
+    }
+
+    // Commit ids are only unique per author: lay the graph out on positions.
+    let ids: Vec<(&str, u64, Vec<u64>)> = shown
+        .iter()
+        .map(|(c, v)| {
+            let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(c.event.id);
+            let parents = ["parent", "merge_parent"]
+                .iter()
+                .filter_map(|key| v.get(key).and_then(|x| x.as_u64()))
+                .collect();
+            (c.author.as_str(), id, parents)
+        })
+        .collect();
+    let position: HashMap<(&str, u64), u64> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, (a, id, _))| ((*a, *id), i as u64))
+        .collect();
+    let nodes: Vec<(u64, Vec<u64>)> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, (a, _, parents))| {
+            let parents = parents
+                .iter()
+                .filter_map(|p| position.get(&(*a, *p)).copied())
+                .collect();
+            (i as u64, parents)
+        })
+        .collect();
+
+    for (row, ((a, id, _), (c, v))) in graph::layout(&nodes).into_iter().zip(ids.iter().zip(shown))
+    {
+        let deco = names
+            .get(id)
+            .filter(|_| *a == author)
+            .map(|n| format!(" ({})", n.join(", ")).yellow().to_string())
+            .unwrap_or_default();
+        println!("{}{}", row.prefix, line(c, v, &deco));
+        for connector in row.connectors {
+            println!("{connector}");
+        }
+    }
+}
+
+/// Parse a `--since`/`--until` bound into milliseconds: an RFC 3339 time, or a local
+/// `YYYY-MM-DD` date meaning the start of that day (`end_of_day`: the start of the next one, so
+/// that `--until` includes the whole day).
+fn parse_bound(s: &str, end_of_day: bool) -> Result<i64, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.timestamp_millis());
+    }
+    let day = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("'{s}' is neither YYYY-MM-DD nor an RFC 3339 time"))?;
+    let day = if end_of_day {
+        day.succ_opt().ok_or("date out of range")?
+    } else {
+        day
+    };
+    day.and_time(chrono::NaiveTime::MIN)
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|t| t.timestamp_millis())
+        .ok_or_else(|| format!("'{s}' does not exist in the local time zone"))
+}
+
+/// Files of commit `id` as a diffable tree.
+fn commit_tree(events: &[Event], id: u64) -> std::io::Result<diff::Tree> {
+    Ok(restore::tree_at(events, id)?
+        .into_iter()
+        .map(|(p, c)| (p, c.as_bytes().to_vec()))
+        .collect())
+}
+
+/// Print `ak tree verify`: the reference tree of `author`'s current branch, and with `snapshots`
+/// every kept snapshot, checked against the commit it was derived from (see `tree::verify`).
+/// Returns whether they all match.
+fn verify_trees(root: &Path, author: &str, snapshots: bool) -> std::io::Result<bool> {
+    let events = author_events(author)?;
+    let name = branch::current(root, author);
+    let mut trees = Vec::new();
+    let dir = tree::tree_dir(root, author);
+    if dir.exists() {
+        let head = branch::heads(&events).get(&name).copied().flatten();
+        trees.push((format!("Reference tree of {name}"), dir, head));
+    } else {
+        println!("No reference tree on {name}: its sealed state is read from the cube.");
+    }
+    if snapshots {
+        for id in tree::snapshots(root, author)? {
+            trees.push((
+                "Snapshot".to_string(),
+                tree::snapshot_dir(root, author, id),
+                Some(id),
+            ));
+        }
+    }
+
+    let mut passed = true;
+    for (what, dir, id) in trees {
+        let Some(id) = id else {
+            // Nothing sealed yet: the tree should be empty.
+            let files = diff::read_tree(&dir)?.len();
+            if files == 0 {
+                println!("{what}: empty, nothing sealed yet.");
+            } else {
+                passed = false;
+                println!("{what}: {files} file(s), but nothing was sealed.");
+            }
+            continue;
+        };
+        let mismatches = tree::verify(&dir, &events, id)?;
+        if mismatches.is_empty() {
+            println!("{what} (#{id}): {}", "matches the seal".green());
+            continue;
+        }
+        passed = false;
+        println!(
+            "{what} (#{id}): {} file(s) differ from the seal",
+            mismatches.len()
+        );
+        for m in mismatches {
+            println!("  {} {}", m.change.to_string().red().bold(), m.path);
+        }
+    }
+    Ok(passed)
+}
+
+/// Run `ak tree prune`: remove the stale reference trees and snapshots (see `tree::stale`), or
+/// only list them with `dry_run`.
+fn prune_trees(root: &Path, dry_run: bool) -> std::io::Result<()> {
+    let stale = tree::stale(root)?;
+    if stale.is_empty() {
+        println!("No stale reference tree or snapshot.");
+        return Ok(());
+    }
+    for dir in &stale {
+        let shown = dir.strip_prefix(root).unwrap_or(dir).display();
+        if dry_run {
+            println!("would remove {shown}");
+        } else {
+            std::fs::remove_dir_all(dir)?;
+            println!("removed {shown}");
+        }
+    }
+    Ok(())
+}
+
+/// Print `ak tree du`: files and size of every reference tree and snapshot, and their total.
+fn print_tree_usage(root: &Path) -> std::io::Result<()> {
+    let usage = tree::usage(root)?;
+    if usage.is_empty() {
+        println!("No reference tree or snapshot on disk.");
+        return Ok(());
+    }
+    let (mut files, mut bytes, mut linked) = (0, 0, 0);
+    for u in &usage {
+        let shared = if u.linked > 0 {
+            format!(" ({} hard-linked)", human_bytes(u.linked))
+        } else {
+            String::new()
+        };
+        println!(
+            "{:>7} file(s) {:>10}  {}{shared}",
+            u.files,
+            human_bytes(u.bytes),
+            u.dir.strip_prefix(root).unwrap_or(&u.dir).display()
+        );
+        files += u.files;
+        bytes += u.bytes;
+        linked += u.linked;
+    }
+    print!("Total: {files} file(s), {}", human_bytes(bytes));
+    if linked > 0 {
+        print!(
+            ", {} of it in hard links taking no space of their own",
+            human_bytes(linked)
+        );
+    }
+    println!(".");
+    Ok(())
+}
+
+/// Print `ak blame`: every line of `path` (portable) as of commit `head`, with the commit,
+/// author and date that last changed it. Versions are followed along first parents.
+fn print_blame(events: &[Event], head: u64, path: &str) -> std::io::Result<()> {
+    let mut chain = Vec::new();
+    let mut at = Some(head);
+    while let Some(id) = at {
+        chain.push(id);
+        at = merge::parents(events, id).first().copied();
+    }
+    // Versions since the file last appeared; a deletion starts the history over.
+    let mut versions: Vec<(u64, &str)> = Vec::new();
+    for &id in chain.iter().rev() {
+        match restore::tree_at(events, id)?.get(path) {
+            Some(text) if versions.last().is_some_and(|(_, last)| last == text) => {}
+            Some(text) => versions.push((id, text)),
+            None => versions.clear(),
+        }
+    }
+    if versions.is_empty() {
+        return Err(Error::other(format!("{path} is not part of #{head}")));
+    }
+
+    let mut who: HashMap<u64, (String, String)> = HashMap::new();
+    let lines = blame::blame(&versions);
+    let width = lines.len().to_string().len();
+    let id_width = lines
+        .iter()
+        .map(|(id, _)| id.to_string().len() + 1)
+        .max()
+        .unwrap_or(0);
+    for (n, (id, line)) in lines.iter().enumerate() {
+        let (author, date) = who.entry(*id).or_insert_with(|| {
+            let v = commit::find(events, *id)
+                .and_then(|e| serde_json::from_str::<serde_json::Value>(&e.noumenon).ok())
+                .unwrap_or_default();
+            let author = v.get("author").and_then(|x| x.as_str()).unwrap_or("");
+            let when = commit_when(&v, false, false);
+            (
+                author.to_string(),
+                when.split(' ').next().unwrap_or("-").to_string(),
+            )
+        });
+        println!(
+            "{} {author} {date} {:>width$} | {line}",
+            format!("{:<id_width$}", format!("#{id}")).yellow(),
+            n + 1
+        );
+    }
+    Ok(())
+}
+
+/// Run `ak verify` on the repository at `root`: check the integrity of the cubes, commits and
+/// manifests, then the signature of every commit. Any integrity problem fails the check, as do
+/// bad signatures and, when the repository requires signed seals, commits not signed by an
+/// allowed signer. Returns whether the check passed.
+fn verify(root: &Path) -> std::io::Result<bool> {
+    let report = integrity::check(root)?;
+    for problem in &report.problems {
+        println!("{}", problem.red());
+    }
+    println!(
+        "Cubes: {} checked, {} records. Commits: {}. Manifests: {}.",
+        report.cubes, report.records, report.commits, report.manifests
+    );
+    let signing = Config::load(root)?.signing;
+    let allowed = signing.allowed_signers_path(root);
+    let mut signed: HashMap<String, HashMap<u64, String>> = HashMap::new();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut failed = 0;
+    for c in history::commits(root)? {
+        let signatures = match signed.entry(c.author.clone()) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(sign::signatures(&author_events(&c.author)?))
+            }
+        };
+        let v = sign::verify_commit(&c.event, signatures, &allowed)?;
+        *counts.entry(v.label()).or_default() += 1;
+        if v == sign::Verification::Bad || (signing.required && v != sign::Verification::Good) {
+            failed += 1;
+            println!(
+                "#{} ({}): {}",
+                commit::commit_id(&c.event),
+                c.author,
+                signature_label(&v)
+            );
+        }
+    }
+    let count = |v: sign::Verification| counts.get(v.label()).copied().unwrap_or(0);
+    println!(
+        "Signatures: {} good, {} unverified, {} bad, {} unsigned.",
+        count(sign::Verification::Good),
+        count(sign::Verification::Unverified),
+        count(sign::Verification::Bad),
+        count(sign::Verification::Unsigned)
+    );
+    if signing.required {
+        println!(
+            "Signed seals are required (allowed signers: {}).",
+            allowed.display()
+        );
+    }
+    Ok(report.passed() && failed == 0)
+}
+
+/// Colored description of a signature check.
+fn signature_label(v: &sign::Verification) -> colored::ColoredString {
+    match v {
+        sign::Verification::Good => v.label().green(),
+        sign::Verification::Unverified => v.label().yellow(),
+        sign::Verification::Bad => v.label().red().bold(),
+        sign::Verification::Unsigned => v.label().normal(),
+    }
+}
+
+/// Print `ak show`: the commit's metadata and message, the files it changed relative to its
+/// parent and, with `patch`, the diff itself.
+fn show(events: &[Event], spec: &str, patch: bool, allowed: &Path) -> std::io::Result<()> {
+    let id = resolve_commit(events, spec)?;
+    let ev = commit::find(events, id).expect("resolved commits exist");
+    let v: serde_json::Value = serde_json::from_str(&ev.noumenon).map_err(Error::other)?;
+    let field = |k: &str| v.get(k).and_then(|x| x.as_str()).unwrap_or("");
+    let parent = v.get("parent").and_then(|x| x.as_u64());
+
+    let branch = match field("branch") {
+        "" => String::new(),
+        b => format!(" ({b})"),
+    };
+    println!("{} #{id}{branch}", "commit".yellow().bold());
+    if let Some(p) = parent {
+        println!("Parent: #{p}");
+    }
+    if let Some(m) = v.get("merge_parent").and_then(|x| x.as_u64()) {
+        println!("Merges: #{m}");
+    }
+    if let Some(r) = v.get("reverts").and_then(|x| x.as_u64()) {
+        println!("Reverts: #{r}");
+    }
+    if let Some(a) = v.get("amends").and_then(|x| x.as_u64()) {
+        println!("Amends: #{a}");
+    }
+    if let Some(p) = v.get("picked_from").and_then(|x| x.as_u64()) {
+        println!("Picked from: #{p}");
+    }
+    if let Some(git) = v.get("git_commit").and_then(|x| x.as_str()) {
+        println!("Git commit: {git}");
+    }
+    if let Some(by) = commit::superseded(events).get(&id) {
+        println!("Superseded by: #{by}");
+    }
+    match sign::verify_commit(ev, &sign::signatures(events), allowed)? {
+        sign::Verification::Unsigned => {}
+        v => println!("Signature: {}", signature_label(&v)),
+    }
+    println!("Author: {} <{}>", field("author"), field("author_email"));
+    println!("Date:   {}", commit_when(&v, false, false));
+    println!();
+    println!("    {} {}", field("ty"), field("summary"));
+    if !field("body").trim().is_empty() {
+        println!();
+        for line in field("body").trim_end().lines() {
+            println!("    {line}");
+        }
+    }
+    let notes = note::of_commit(events, id);
+    if !notes.is_empty() {
+        println!();
+        println!("Notes:");
+        print_notes(&notes);
+    }
+
+    let after = commit_tree(events, id)?;
+    let before = match parent {
+        Some(p) => commit_tree(events, p)?,
+        None => diff::Tree::new(),
+    };
+    let sealed = rename::of_commit(events, id)
+        .into_iter()
+        .map(|(from, to)| (to, from))
+        .collect();
+    let renames = diff::renames(&before, &after, &sealed);
+    let renamed = |p: &String| renames.iter().any(|(from, to)| from == p || to == p);
+    let changes = diff::changed_paths(&before, &after);
+    if !changes.is_empty() {
+        println!();
+        for (from, to) in &renames {
+            println!("  R {from} -> {to}");
+        }
+        for (change, path) in changes.iter().filter(|(_, p)| !renamed(p)) {
+            println!("  {} {path}", change.code());
+        }
+    }
+    if patch {
+        println!();
+        let opts = diff::DiffOptions {
+            renames: sealed,
+            ..Default::default()
+        };
+        diff::diff_trees(&before, &after, &opts);
+    }
+    Ok(())
+}
+
+/// Undo commit `spec` in the working directory and seal the result as a "revert" commit
+/// referencing it. History is left untouched.
+fn revert(root: &Path, author: &str, author_email: &str, spec: &str) -> std::io::Result<()> {
+    status::require_clean(root, author)?;
+    let events = author_events(author)?;
+    let id = resolve_commit(&events, spec)?;
+    let ev = commit::find(&events, id).expect("resolved commits exist");
+    let v: serde_json::Value = serde_json::from_str(&ev.noumenon).map_err(Error::other)?;
+    let summary = v.get("summary").and_then(|x| x.as_str()).unwrap_or("");
+
+    let commit = commit_tree(&events, id)?;
+    let parent = match v.get("parent").and_then(|x| x.as_u64()) {
+        Some(p) => commit_tree(&events, p)?,
+        None => diff::Tree::new(),
+    };
+    let undo = pick::pick(
+        &commit,
+        &parent,
+        &diff::read_tree(root)?,
+        merge::Binary::Conflict,
+    );
+    if !undo.conflicts.is_empty() {
+        return Err(Error::other(format!(
+            "changed since #{id}, revert by hand: {}",
+            undo.conflicts.join(", ")
+        )));
+    }
+    if undo.changes.is_empty() {
+        println!("Nothing to revert: the changes of #{id} are already undone.");
+        return Ok(());
+    }
+
+    hooks(root, author, &[])?;
+    pick::apply(root, &undo.changes)?;
+    let mut staged = Index::default();
+    for (path, content) in &undo.changes {
+        let content = match content {
+            Some(bytes) => Some(String::from_utf8(bytes.clone()).map_err(Error::other)?),
+            None => None,
+        };
+        staged.stage_content(path, content);
+    }
+    let summary = format!("Revert \"{summary}\"");
+    let body = format!("This reverts commit #{id}.");
+    let request = SealRequest {
+        ty: "revert",
+        summary: &summary,
+        body: &body,
+        author,
+        author_email,
+        all: false,
+        reverts: Some(id),
+        amends: None,
+        picked_from: None,
+        merge_parent: None,
+        signing_key: None,
+    };
+    seal(root, &request, &staged).map(|_| ())
+}
+
+/// Check commit `id` out into the bisection worktree.
+fn bisect_checkout(events: &[Event], state: &mut Bisect, id: u64) -> std::io::Result<()> {
+    if state.worktree.exists() {
+        std::fs::remove_dir_all(&state.worktree)?;
+    }
+    restore::materialize(events, id, &state.worktree)?;
+    state.current = Some(id);
+    Ok(())
+}
+
+/// Summary line of commit `id`, for bisection reports.
+fn commit_line(events: &[Event], id: u64) -> String {
+    let v = commit::find(events, id)
+        .and_then(|e| serde_json::from_str::<serde_json::Value>(&e.noumenon).ok())
+        .unwrap_or_default();
+    let field = |k: &str| v.get(k).and_then(|x| x.as_str()).unwrap_or("");
+    format!("#{id} {} {}", field("ty"), field("summary"))
+}
+
+/// Move the search one step: check the next candidate out, or report the result. Returns
+/// whether the search is over.
+fn bisect_next(events: &[Event], state: &mut Bisect) -> std::io::Result<bool> {
+    match state.next(events) {
+        bisect::Step::NeedMarks => {
+            let missing = if state.bad.is_none() { "bad" } else { "good" };
+            println!("Mark a {missing} commit to go on.");
+            Ok(false)
+        }
+        bisect::Step::Test(id) => {
+            bisect_checkout(events, state, id)?;
+            println!(
+                "Bisecting: {} candidate(s) left, testing {} in {}",
+                state.candidates(events).len(),
+                commit_line(events, id),
+                state.worktree.display()
+            );
+            Ok(false)
+        }
+        bisect::Step::Found(id) => {
+            println!(
+                "{} is the first bad commit.",
+                commit_line(events, id).bold()
+            );
+            Ok(true)
+        }
+        bisect::Step::Undecided(ids) => {
+            println!("Commits were skipped: the first bad commit is one of");
+            for id in ids {
+                println!("  {}", commit_line(events, id));
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Run `ak bisect <sub>` for `author`.
+fn bisect(root: &Path, author: &str, sub: &ArgMatches) -> std::io::Result<()> {
+    let events = author_events(author)?;
+    let resolve = |m: &ArgMatches, k: &str| -> std::io::Result<Option<u64>> {
+        m.get_one::<String>(k)
+            .map(|spec| resolve_commit(&events, spec))
+            .transpose()
+    };
+    if let Some(("start", start)) = sub.subcommand() {
+        let bad = match resolve(start, "bad")? {
+            Some(id) => Some(id),
+            None => branch_head(author, &branch::current(root, author))?,
+        };
+        let mut good = Vec::new();
+        for spec in start.get_many::<String>("good").into_iter().flatten() {
+            good.push(resolve_commit(&events, spec)?);
+        }
+        let mut state = Bisect {
+            bad,
+            good,
+            worktree: std::env::temp_dir()
+                .join(format!("akasha-bisect-{author}-{}", std::process::id())),
+            ..Bisect::default()
+        };
+        bisect_next(&events, &mut state)?;
+        return state.save(root, author);
+    }
+
+    let mut state = Bisect::load(root, author)?
+        .ok_or_else(|| Error::other("no bisection in progress; run `ak bisect start`"))?;
+    match sub.subcommand() {
+        Some(("reset", _)) => {
+            if state.worktree.exists() {
+                std::fs::remove_dir_all(&state.worktree)?;
+            }
+            println!("Bisection ended.");
+            Bisect::clear(root, author)
+        }
+        Some(("run", run)) => {
+            let command: Vec<&String> = run
+                .get_many("command")
+                .expect("command is required")
+                .collect();
+            if state.next(&events) == bisect::Step::NeedMarks {
+                return Err(Error::other(
+                    "mark a good and a bad commit before `ak bisect run`",
+                ));
+            }
+            while !bisect_next(&events, &mut state)? {
+                let id = state.current.expect("a candidate is checked out");
+                let status = std::process::Command::new(command[0])
+                    .args(&command[1..])
+                    .current_dir(&state.worktree)
+                    .status()
+                    .map_err(|e| Error::other(format!("failed to spawn '{}': {e}", command[0])))?;
+                let verdict = match status.code() {
+                    Some(0) => {
+                        state.good.push(id);
+                        "good"
+                    }
+                    Some(125) => {
+                        state.skipped.push(id);
+                        "skipped"
+                    }
+                    Some(1..=127) => {
+                        state.bad = Some(id);
+                        "bad"
+                    }
+                    _ => {
+                        state.save(root, author)?;
+                        return Err(Error::other(format!("#{id}: command stopped ({status})")));
+                    }
+                };
+                println!(">> #{id} {verdict}");
+            }
+            state.save(root, author)
+        }
+        Some((mark, m)) => {
+            let id = match resolve(m, "commit")? {
+                Some(id) => id,
+                None => state.current.ok_or_else(|| {
+                    Error::other(format!("no commit being tested; name the {mark} one"))
+                })?,
+            };
+            match mark {
+                "good" => state.good.push(id),
+                "bad" => state.bad = Some(id),
+                _ => state.skipped.push(id),
+            }
+            bisect_next(&events, &mut state)?;
+            state.save(root, author)
+        }
+        None => unreachable!("subcommand_required"),
+    }
+}
+
+/// Record the unsealed changes of `author` as a stash and bring the working directory back to
+/// the reference tree. The index is cleared.
+fn stash_push(root: &Path, author: &str, message: Option<&String>) -> std::io::Result<()> {
+    let reference = sealed_tree(root, author)?;
+    let work = diff::read_tree(root)?;
+    let changed = diff::changed_paths(&reference, &work);
+    if changed.is_empty() {
+        println!("No local changes to stash.");
+        return Ok(());
+    }
+    let mut files = std::collections::BTreeMap::new();
+    for (_, path) in &changed {
+        let content = match work.get(path) {
+            Some(bytes) => Some(
+                String::from_utf8(bytes.clone())
+                    .map_err(|_| Error::other(format!("{path}: binary files cannot be stashed")))?,
+            ),
+            None => None,
+        };
+        files.insert(path.clone(), content);
+    }
+    let branch_name = branch::current(root, author);
+    let entry = Stash {
+        base: branch_head(author, &branch_name)?,
+        message: message
+            .cloned()
+            .unwrap_or_else(|| format!("WIP on {branch_name}")),
+        branch: branch_name,
+        files,
+    };
+    let mut w = Writer::create(&cube_path_for(author))?;
+    let id = stash::push(&mut w, &entry)?;
+
+    let reset: Vec<(String, Option<Vec<u8>>)> = changed
+        .into_iter()
+        .map(|(_, path)| {
+            let content = reference.get(&path).cloned();
+            (path, content)
+        })
+        .collect();
+    pick::apply(root, &reset)?;
+    Index::default().save(root, author)?;
+    println!(
+        "Stashed {} file(s) as #{id}: {}",
+        entry.files.len(),
+        entry.message
+    );
+    Ok(())
+}
+
+/// Restore stash `id` (the latest by default) in the working directory and drop it. Files
+/// changed both in the stash and in the working directory stop the pop.
+fn stash_pop(root: &Path, author: &str, id: Option<u64>) -> std::io::Result<()> {
+    let events = author_events(author)?;
+    let live = stash::stashes(&events);
+    let (id, entry) = match id {
+        Some(id) => live.into_iter().find(|(s, _)| *s == id),
+        None => live.into_iter().last(),
+    }
+    .ok_or_else(|| match id {
+        Some(id) => Error::other(format!("no stash #{id}")),
+        None => Error::other("no stash to pop"),
+    })?;
+    let base = match entry.base {
+        Some(b) => commit_tree(&events, b)?,
+        None => diff::Tree::new(),
+    };
+    let picked = stash::unstash(&entry, &base, &diff::read_tree(root)?);
+    if !picked.conflicts.is_empty() {
+        return Err(Error::other(format!(
+            "changed in the working directory, seal or stash them first: {}",
+            picked.conflicts.join(", ")
+        )));
+    }
+    pick::apply(root, &picked.changes)?;
+    stash::drop(&mut Writer::create(&cube_path_for(author))?, id)?;
+    println!("Restored stash #{id}: {}", entry.message);
+    Ok(())
+}
+
+/// Re-apply the changes of commit `spec` to the working directory and seal them on the current
+/// branch with the same message, recording `spec` as `picked_from`.
+fn cherry_pick(
+    root: &Path,
+    author: &str,
+    author_email: &str,
+    spec: &str,
+    binary: merge::Binary,
+) -> std::io::Result<()> {
+    status::require_clean(root, author)?;
+    let events = author_events(author)?;
+    let id = resolve_commit(&events, spec)?;
+    let ev = commit::find(&events, id).expect("resolved commits exist");
+    let v: serde_json::Value = serde_json::from_str(&ev.noumenon).map_err(Error::other)?;
+    let field = |k: &str| v.get(k).and_then(|x| x.as_str()).unwrap_or("");
+
+    let commit = commit_tree(&events, id)?;
+    let parent = match v.get("parent").and_then(|x| x.as_u64()) {
+        Some(p) => commit_tree(&events, p)?,
+        None => diff::Tree::new(),
+    };
+    let picked = pick::pick(&parent, &commit, &diff::read_tree(root)?, binary);
+    if !picked.conflicts.is_empty() {
+        return Err(Error::other(format!(
+            "changed on both sides, pick #{id} by hand: {}",
+            picked.conflicts.join(", ")
+        )));
+    }
+    if picked.changes.is_empty() {
+        println!("Nothing to pick: the changes of #{id} are already here.");
+        return Ok(());
+    }
+
+    hooks(root, author, &[])?;
+    pick::apply(root, &picked.changes)?;
+    let mut staged = Index::default();
+    for (path, content) in &picked.changes {
+        let content = match content {
+            Some(bytes) => Some(String::from_utf8(bytes.clone()).map_err(Error::other)?),
+            None => None,
+        };
+        staged.stage_content(path, content);
+    }
+    let body = match field("body").trim_end() {
+        "" => format!("(cherry picked from commit #{id})"),
+        b => format!("{b}\n\n(cherry picked from commit #{id})"),
+    };
+    let request = SealRequest {
+        ty: field("ty"),
+        summary: field("summary"),
+        body: &body,
+        author,
+        author_email,
+        all: false,
+        reverts: None,
+        amends: None,
+        picked_from: Some(id),
+        merge_parent: None,
+        signing_key: None,
+    };
+    seal(root, &request, &staged).map(|_| ())
+}
+
+/// Pack the files of commit `spec` (default: the head of the current branch) into the archive
+/// `out`, a `.tar.gz` or `.zip` by its extension.
+fn write_archive(root: &Path, author: &str, spec: Option<&str>, out: &Path) -> std::io::Result<()> {
+    let format = archive::Format::of(out)?;
+    let events = author_events(author)?;
+    let id = match spec {
+        Some(spec) => resolve_commit(&events, spec)?,
+        None => branch::heads(&events)
+            .get(&branch::current(root, author))
+            .copied()
+            .flatten()
+            .ok_or_else(|| Error::other("nothing sealed on this branch yet"))?,
+    };
+    let timestamp = commit::find(&events, id)
+        .and_then(|e| serde_json::from_str::<serde_json::Value>(&e.noumenon).ok())
+        .and_then(|v| v.get("timestamp").and_then(|x| x.as_i64()))
+        .unwrap_or(0);
+    let files = restore::tree_at(&events, id)?;
+    let n = archive::write(&files, timestamp, out, format)?;
+    println!("Archived #{id} ({n} file(s)) to {}.", out.display());
+    Ok(())
+}
+
+/// Seals of `range` for `ak format-patch`, oldest first: commit `c` alone, or the commits of
+/// `a..b` (`a..` ends at the current head) along first parents, without those `a` descends from.
+fn patch_range(
+    root: &Path,
+    author: &str,
+    events: &[Event],
+    range: &str,
+) -> std::io::Result<Vec<u64>> {
+    let Some((from, to)) = range.split_once("..") else {
+        return Ok(vec![resolve_commit(events, range)?]);
+    };
+    let to = match to {
+        "" => branch::heads(events)
+            .get(&branch::current(root, author))
+            .copied()
+            .flatten()
+            .ok_or_else(|| Error::other("nothing sealed on the current branch yet"))?,
+        spec => resolve_commit(events, spec)?,
+    };
+    let excluded: std::collections::HashSet<u64> =
+        merge::ancestors(events, resolve_commit(events, from)?)
+            .into_iter()
+            .collect();
+    let mut ids = Vec::new();
+    let mut at = Some(to);
+    while let Some(id) = at.filter(|id| !excluded.contains(id)) {
+        ids.push(id);
+        at = merge::parents(events, id).first().copied();
+    }
+    ids.reverse();
+    Ok(ids)
+}
+
+/// Write the seals of `range` as mailbox patches: one `NNNN-<subject>.patch` file each in
+/// `dir`, or all of them on stdout. Merges are skipped, their changes have no single parent.
+fn format_patch(root: &Path, author: &str, range: &str, dir: Option<&Path>) -> std::io::Result<()> {
+    let events = author_events(author)?;
+    let mut patches = Vec::new();
+    for id in patch_range(root, author, &events, range)? {
+        let parents = merge::parents(&events, id);
+        if parents.len() > 1 {
+            eprintln!("Skipping merge #{id}.");
+            continue;
+        }
+        let ev = commit::find(&events, id).expect("resolved commits exist");
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).map_err(Error::other)?;
+        let field = |k: &str| v.get(k).and_then(|x| x.as_str()).unwrap_or("").to_string();
+        let old = match parents.first() {
+            Some(&p) => restore::tree_at(&events, p)?,
+            None => std::collections::BTreeMap::new(),
+        };
+        let new = restore::tree_at(&events, id)?;
+        let mut paths: std::collections::BTreeSet<&String> = old.keys().collect();
+        paths.extend(new.keys());
+        let files = paths
+            .into_iter()
+            .filter(|p| old.get(*p) != new.get(*p))
+            .map(|p| patch::diff_file(p, old.get(p).copied(), new.get(p).copied()))
+            .collect();
+        let patch = patch::Patch {
+            author: field("author"),
+            email: field("author_email"),
+            timestamp: v.get("timestamp").and_then(|x| x.as_i64()).unwrap_or(0),
+            ty: field("ty"),
+            summary: field("summary"),
+            body: field("body"),
+            files,
+        };
+        patches.push((id, patch));
+    }
+    if patches.is_empty() {
+        println!("No seals in {range}.");
+        return Ok(());
+    }
+
+    let total = patches.len();
+    for (n, (id, patch)) in patches.iter().enumerate() {
+        let mail = patch::render(patch, *id, n + 1, total);
+        let Some(dir) = dir else {
+            print!("{mail}");
+            continue;
+        };
+        let slug: String = patch
+            .summary
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        let mut slug: String = slug.chars().take(52).collect();
+        if slug.is_empty() {
+            slug = format!("seal-{id}");
+        }
+        create_dir_all(dir)?;
+        let path = dir.join(format!("{:04}-{slug}.patch", n + 1));
+        std::fs::write(&path, mail)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Apply the mailbox patches or plain diffs in `files` to the working directory, in order, or
+/// undo them in the opposite order with `reverse`. Hunks may leave out `fuzz` context lines at
+/// each end (see `patch::apply_file`). Nothing is written unless every patch applies; with
+/// `check`, nothing is written at all.
+fn apply_patches(
+    root: &Path,
+    files: &[&String],
+    check: bool,
+    reverse: bool,
+    fuzz: usize,
+) -> std::io::Result<()> {
+    let mut changed: std::collections::BTreeMap<String, Option<String>> =
+        std::collections::BTreeMap::new();
+    let mut queue = Vec::new();
+    for file in files {
+        let patches = read_to_string(file)
+            .and_then(|text| patch::parse(&text))
+            .map_err(|e| Error::new(e.kind(), format!("{file}: {e}")))?;
+        queue.extend(patches.into_iter().map(|p| (*file, p)));
+    }
+    if reverse {
+        queue.reverse();
+    }
+    let mut applied = Vec::new();
+    for (file, p) in queue {
+        for f in &p.files {
+            let reversed;
+            let f = if reverse {
+                reversed = f.reversed();
+                &reversed
+            } else {
+                f
+            };
+            let native = paths::to_native(&f.path).ok_or_else(|| {
+                Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{file}: {}: not a path inside the repository", f.path),
+                )
+            })?;
+            let current = match changed.get(&f.path) {
+                Some(content) => content.clone(),
+                None => match read_to_string(root.join(native)) {
+                    Ok(text) => Some(text),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                },
+            };
+            let new = patch::apply_file(current.as_deref(), f, fuzz)
+                .map_err(|e| Error::new(e.kind(), format!("{file}: {e}")))?;
+            changed.insert(f.path.clone(), new);
+        }
+        applied.push((file, p));
+    }
+
+    // Plain diffs have no message: they go by the name of their file.
+    let subject = |(file, p): &(&String, patch::Patch)| match p.ty.as_str() {
+        _ if p.summary.is_empty() => file.to_string(),
+        "" | "commit" => p.summary.clone(),
+        ty => format!("{ty}: {}", p.summary),
+    };
+    if check {
+        for p in &applied {
+            println!("{} {}", "ok".green(), subject(p));
+        }
+        println!("{} patch(es) apply cleanly.", applied.len());
+        return Ok(());
+    }
+    let mut written = 0;
+    for (path, content) in &changed {
+        let target = root.join(paths::to_native(path).expect("checked above"));
+        match content {
+            Some(text) => {
+                if let Some(parent) = target.parent() {
+                    create_dir_all(parent)?;
+                }
+                std::fs::write(&target, text)?;
+            }
+            None => match std::fs::remove_file(&target) {
+                // Added, then deleted, by the patches.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                r => r?,
+            },
+        }
+        written += 1;
+    }
+    let verb = if reverse { "Reverted" } else { "Applied" };
+    for p in &applied {
+        match p.1.author.as_str() {
+            "" => println!("{verb}: {}", subject(p)),
+            author => println!("{verb}: {} ({author} <{}>)", subject(p), p.1.email),
+        }
+    }
+    println!("{written} file(s) changed; review them with `ak diff` and seal them with `ak seal`.");
+    Ok(())
+}
+
+/// Merge branch `name` into the current branch: apply the three-way merge of both tips to the
+/// working directory and seal it as a "merge" commit whose `merge_parent` is the tip of `name`.
+/// On conflicts, the files are left with conflict markers and the merge waits for `ak seal`.
+fn merge_branch(
+    root: &Path,
+    author: &str,
+    author_email: &str,
+    name: &str,
+    binary: merge::Binary,
+) -> std::io::Result<()> {
+    if let Some(state) = MergeState::load(root, author)? {
+        return Err(Error::other(format!(
+            "a merge of '{}' is in progress; seal it or run `ak merge --abort`",
+            state.branch
+        )));
+    }
+    status::require_clean(root, author)?;
+    let events = author_events(author)?;
+    let heads = branch::heads(&events);
+    let current = branch::current(root, author);
+    if name == current {
+        return Err(Error::other(format!("cannot merge '{name}' into itself")));
+    }
+    let theirs = match heads.get(name) {
+        Some(Some(id)) => *id,
+        Some(None) => return Err(Error::other(format!("nothing sealed on '{name}' yet"))),
+        None => return Err(Error::other(format!("no branch '{name}'"))),
+    };
+    let ours = heads
+        .get(&current)
+        .copied()
+        .flatten()
+        .ok_or_else(|| Error::other(format!("nothing sealed on '{current}' yet")))?;
+    let base = merge::merge_base(&events, ours, theirs);
+    if base == Some(theirs) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    let ours_tree = commit_tree(&events, ours)?;
+    let merged = merge::merge_trees(
+        &match base {
+            Some(b) => commit_tree(&events, b)?,
+            None => diff::Tree::new(),
+        },
+        &ours_tree,
+        &commit_tree(&events, theirs)?,
+        &current,
+        name,
+        binary,
+    );
+    let changes: Vec<(String, Option<Vec<u8>>)> = diff::changed_paths(&ours_tree, &merged.files)
+        .into_iter()
+        .map(|(_, path)| {
+            let content = merged.files.get(&path).cloned();
+            (path, content)
+        })
+        .collect();
+    let mut staged = Index::default();
+    for (path, content) in &changes {
+        if merged.conflicts.contains(path) {
+            continue;
+        }
+        let content = match content {
+            Some(bytes) => Some(String::from_utf8(bytes.clone()).map_err(Error::other)?),
+            None => None,
+        };
+        staged.stage_content(path, content);
+    }
+    pick::apply(root, &changes)?;
+    staged.save(root, author)?;
+    let state = MergeState {
+        branch: name.to_string(),
+        head: theirs,
+        paths: changes.into_iter().map(|(p, _)| p).collect(),
+        conflicts: merged.conflicts,
+    };
+    state.save(root, author)?;
+
+    if !state.conflicts.is_empty() {
+        for path in &state.conflicts {
+            println!("{} {path}", "CONFLICT".red().bold());
+        }
+        return Err(Error::other(
+            "fix the conflicts, then `ak add` them and `ak seal` (or `ak merge --abort`)",
+        ));
+    }
+    if let Err(e) = hooks(root, author, &[]) {
+        return Err(Error::other(format!(
+            "pre-commit hooks failed: {e}; fix and `ak seal`, or `ak merge --abort`"
+        )));
+    }
+    let summary = format!("Merge branch '{name}' into {current}");
+    let body = format!("This merges commit #{theirs}.");
+    let request = SealRequest {
+        ty: "merge",
+        summary: &summary,
+        body: &body,
+        author,
+        author_email,
+        all: false,
+        reverts: None,
+        amends: None,
+        picked_from: None,
+        merge_parent: Some(theirs),
+        signing_key: None,
+    };
+    seal(root, &request, &staged)?;
+    MergeState::clear(root, author)
+}
+
+/// Give up `author`'s merge in progress: restore the files it changed to the current branch
+/// head and clear the index.
+fn merge_abort(root: &Path, author: &str) -> std::io::Result<()> {
+    let state =
+        MergeState::load(root, author)?.ok_or_else(|| Error::other("no merge in progress"))?;
+    let events = author_events(author)?;
+    let ours = match branch_head(author, &branch::current(root, author))? {
+        Some(id) => commit_tree(&events, id)?,
+        None => diff::Tree::new(),
+    };
+    let restored: Vec<(String, Option<Vec<u8>>)> = state
+        .paths
+        .iter()
+        .map(|p| (p.clone(), ours.get(p).cloned()))
+        .collect();
+    pick::apply(root, &restored)?;
+    Index::default().save(root, author)?;
+    MergeState::clear(root, author)?;
+    println!("Merge of '{}' aborted.", state.branch);
+    Ok(())
+}
+
+/// The `--binary` policy of `ak merge` and `ak cherry-pick`.
+fn binary_policy(sub: &ArgMatches) -> merge::Binary {
+    let name = sub
+        .get_one::<String>("binary")
+        .expect("binary has a default");
+    merge::Binary::parse(name).expect("clap checks the policy names")
+}
+
+/// Resolve a commit reference given on the command line to a commit id.
+///
+/// Accepts a commit id (optionally written `#12`) or a tag name.
+fn resolve_commit(events: &[Event], spec: &str) -> std::io::Result<u64> {
+    let id = match spec.trim_start_matches('#').parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => *tag::tags(events).get(spec).ok_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{spec}' is neither a commit id nor a tag"),
+            )
+        })?,
+    };
+    match commit::find(events, id) {
+        Some(_) => Ok(id),
+        None => Err(Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no commit #{id}"),
+        )),
+    }
+}
+
+/// Print `ak status`: a colored list followed by per-kind counts, or `<code> <path>` lines only
+/// with `porcelain`. `untracked_only` restricts the listing to additions.
+fn print_status(st: &status::Status, untracked_only: bool, porcelain: bool) {
+    let entries = st
+        .entries
+        .iter()
+        .filter(|(c, _)| !untracked_only || *c == status::Change::Added);
+    let renamed = st.renamed.iter().filter(|_| !untracked_only);
+    if porcelain {
+        for (from, to) in renamed {
+            println!("R {from} -> {to}");
+        }
+        for (change, path) in entries {
+            println!("{} {path}", change.code());
+        }
+        return;
+    }
+
+    for (from, to) in renamed {
+        println!("{} {from} -> {to}", "R".blue().bold());
+    }
+    let mut shown = 0;
+    for (change, path) in entries {
+        let code = change.code().to_string();
+        let code = match change {
+            status::Change::Added => code.green(),
+            status::Change::Modified => code.yellow(),
+            status::Change::Deleted => code.red(),
+        };
+        println!("{} {path}", code.bold());
+        shown += 1;
+    }
+    if untracked_only {
+        println!("{shown} untracked");
+    } else if st.is_clean() {
+        println!("Nothing changed since the last seal.");
+    } else {
+        println!(
+            "\n{} added, {} modified, {} deleted, {} renamed",
+            st.count(status::Change::Added),
+            st.count(status::Change::Modified),
+            st.count(status::Change::Deleted),
+            st.renamed.len()
+        );
+    }
+}
+
+/// Resolve identity setting `key` (see `config::resolve`). When it is missing and `ak` runs in a
+/// terminal, ask for it once and save it to the user's configuration.
+fn identity(key: &str, env: &str, prompt: &str) -> Result<String, String> {
+    let root = Path::new(".");
+    match config::resolve(root, key, env) {
+        Ok(Some((v, _))) => return Ok(v),
+        Ok(None) => {}
+        Err(e) => return Err(format!("Failed to read the configuration: {e}")),
+    }
+    let hint = format!("Missing {key}: set {env} or run `ak config --global {key} <value>`");
+    if !std::io::stdin().is_terminal() {
+        return Err(hint);
+    }
+    let path = config::user_config_path().ok_or(hint)?;
+    let value = Text::new(prompt)
+        .prompt()
+        .map_err(|e| format!("{key} prompt failed: {e}"))?;
+    let mut user = Config::load_from(&path).map_err(|e| e.to_string())?;
+    user.set(key, Some(value.clone()))
+        .map_err(|e| e.to_string())?;
+    user.save_to(&path)
+        .map_err(|e| format!("saving {} failed: {e}", path.display()))?;
+    println!("Saved {key} to {}.", path.display());
+    Ok(value)
+}
+
+/// Ask for a seal body starting from `initial`: in `editor`, or without one line by line in the
+/// terminal, until an empty line.
+fn edit_body(editor: Option<&str>, initial: &str) -> String {
+    if let Some(editor) = editor {
+        return Editor::new("body:")
+            .with_editor_command(editor.as_ref())
+            .with_predefined_text(initial)
+            .prompt()
+            .expect("body prompt failed");
+    }
+    println!("body (an empty line ends it):");
+    let mut lines = Vec::new();
+    let mut previous = initial.lines();
+    loop {
+        let line = Text::new(">")
+            .with_initial_value(previous.next().unwrap_or(""))
+            .prompt()
+            .expect("body prompt failed");
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Run `ak config`: print, set or unset a setting, or list the effective settings.
+fn config(sub: &ArgMatches) -> std::io::Result<()> {
+    let root = Path::new(".");
+    if sub.get_flag("list") {
+        for (key, env) in config::KEYS
+            .iter()
+            .zip([AK_USERNAME, AK_EMAIL, AK_SIGNING_KEY, EDITOR])
+        {
+            let value = match *key {
+                "editor" => config::editor(root)?,
+                _ => config::resolve(root, key, env)?,
+            };
+            if let Some((v, from)) = value {
+                println!("{key}={v}\t({from})");
+            }
+        }
+        return Ok(());
+    }
+    let key = sub.get_one::<String>("key").expect("key is required");
+    let path = if sub.get_flag("global") {
+        config::user_config_path()
+            .ok_or_else(|| Error::other("no home directory for the user configuration"))?
+    } else {
+        if !root.join(".eikyu").is_dir() {
+            return Err(Error::other(
+                "not in an ak repository (use --global for the user configuration)",
+            ));
+        }
+        config::config_path(root)
+    };
+    let mut file = Config::load_from(&path)?;
+    let value = sub.get_one::<String>("value");
+    if value.is_none() && !sub.get_flag("unset") {
+        return match file.get(key)? {
+            Some(v) => {
+                println!("{v}");
+                Ok(())
+            }
+            None => Err(Error::other(format!(
+                "{key} is not set in {}",
+                path.display()
+            ))),
+        };
+    }
+    file.set(key, value.cloned())?;
+    file.save_to(&path)
+}
+
+/// Arguments of `ak push` and `ak pull`.
+fn sync_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("remote")
+            .help("Remote to sync with (defaults to origin, or the only remote)")
+            .required(false)
+            .action(ArgAction::Set),
+    )
+    .arg(
+        Arg::new("dry-run")
+            .short('n')
+            .long("dry-run")
+            .help("Only report what would be transferred")
+            .required(false)
+            .action(ArgAction::SetTrue),
+    )
+}
+
+/// Run `ak remote`.
+fn remotes(sub: &ArgMatches) -> std::io::Result<()> {
+    let root = Path::new(".");
+    if !root.join(".eikyu").is_dir() {
+        return Err(Error::other("not in an ak repository"));
+    }
+    let mut file = Config::load(root)?;
+    match sub.subcommand() {
+        Some(("add", add)) => {
+            let name = add.get_one::<String>("name").expect("name is required");
+            let url = add.get_one::<String>("url").expect("url is required");
+            if file.remote.contains_key(name) {
+                return Err(Error::other(format!("remote {name} already exists")));
+            }
+            file.remote
+                .insert(name.clone(), config::Remote { url: url.clone() });
+            file.save(root)
+        }
+        Some(("remove", rm)) => {
+            let name = rm.get_one::<String>("name").expect("name is required");
+            if file.remote.remove(name).is_none() {
+                return Err(Error::other(format!("no remote named {name}")));
+            }
+            file.save(root)
+        }
+        _ => {
+            for (name, r) in &file.remote {
+                println!("{name}\t{}", r.url);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run `ak worktree`: add, list or remove the working directories linked to the repository at
+/// `root`.
+fn worktrees(root: &Path, author: &str, sub: &ArgMatches) -> std::io::Result<()> {
+    let events = author_events(author)?;
+    match sub.subcommand() {
+        Some(("add", add)) => {
+            let path = add.get_one::<String>("path").expect("path is required");
+            let name = add.get_one::<String>("branch").expect("branch is required");
+            let files = worktree::add(root, author, Path::new(path), name, &events)?;
+            println!(
+                "Created worktree {path} on branch {name} ({} file(s)).",
+                files.len()
+            );
+            Ok(())
+        }
+        Some(("remove", rm)) => {
+            let path = rm.get_one::<String>("path").expect("path is required");
+            worktree::remove(root, author, Path::new(path), rm.get_flag("force"))?;
+            println!("Removed worktree {path}.");
+            Ok(())
+        }
+        _ => {
+            let heads = branch::heads(&events);
+            let main = worktree::main_root(root)?;
+            let mut dirs = worktree::list(&main)?;
+            dirs.insert(0, main.canonicalize()?);
+            for dir in dirs {
+                if !dir.join(".eikyu").is_dir() {
+                    println!("{} (missing)", dir.display());
+                    continue;
+                }
+                let name = branch::current(&dir, author);
+                let at = match heads.get(&name).copied().flatten() {
+                    Some(h) => format!("#{h}"),
+                    None => "-".to_string(),
+                };
+                println!("{}  {name} {at}", dir.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run `ak push` (`pushing`) or `ak pull`.
+fn sync(sub: &ArgMatches, pushing: bool) -> std::io::Result<()> {
+    let root = Path::new(".");
+    let remotes = Config::load(root)?.remote;
+    let name = match sub.get_one::<String>("remote") {
+        Some(name) => name.clone(),
+        None if remotes.len() == 1 => remotes.keys().next().expect("one remote").clone(),
+        None => "origin".to_string(),
+    };
+    let url = &remotes
+        .get(&name)
+        .ok_or_else(|| Error::other(format!("no remote named {name} (see ak remote add)")))?
+        .url;
+    let mut transport = remote::connect(url);
+    let plan = if pushing {
+        remote::plan_push(root, transport.as_mut())?
+    } else {
+        remote::plan_pull(root, transport.as_mut())?
+    };
+
+    let (here, there) = if pushing {
+        ("here", name.as_str())
+    } else {
+        (name.as_str(), "here")
+    };
+    let mut cubes = 0;
+    let mut records = 0;
+    let mut behind = Vec::new();
+    let mut diverged = Vec::new();
+    let mut unrelated = Vec::new();
+    for (cube, action) in &plan {
+        match action {
+            remote::Action::Copy(events, _) if !events.is_empty() => {
+                println!("{cube}: {} record(s)", events.len());
+                cubes += 1;
+                records += events.len();
+            }
+            remote::Action::Behind => {
+                println!("{cube}: {there} has newer records than {here}");
+                behind.push(cube.as_str());
+            }
+            remote::Action::Diverged => {
+                println!("{cube}: diverged");
+                diverged.push(cube.as_str());
+            }
+            remote::Action::Unrelated => {
+                println!("{cube}: unrelated (another cube with the same name)");
+                unrelated.push(cube.as_str());
+            }
+            _ => {}
+        }
+    }
+    if sub.get_flag("dry-run") {
+        println!("Would transfer {records} record(s) in {cubes} cube(s).");
+        return Ok(());
+    }
+    if records > 0 {
+        let direction = if pushing { "push" } else { "pull" };
+        script::on_sync(
+            &serde_json::json!({
+                "direction": direction,
+                "remote": name,
+                "url": url,
+                "cubes": cubes,
+                "records": records,
+            })
+            .to_string(),
+        )?;
+        if pushing {
+            remote::push(&plan, transport.as_mut())?;
+        } else {
+            remote::pull(root, &plan)?;
+        }
+    }
+    match (records, pushing) {
+        (0, _) if behind.is_empty() && diverged.is_empty() && unrelated.is_empty() => {
+            println!("Everything up to date.")
+        }
+        (0, _) => {}
+        (_, true) => println!("Pushed {records} record(s) in {cubes} cube(s) to {name}."),
+        (_, false) => println!("Pulled {records} record(s) in {cubes} cube(s) from {name}."),
+    }
+    if !diverged.is_empty() {
+        return Err(Error::other(format!(
+            "{} diverged: both sides appended records (left untouched)",
+            diverged.join(", ")
+        )));
+    }
+    if !unrelated.is_empty() {
+        return Err(Error::other(format!(
+            "{} unrelated: both sides have a cube with that name but different UUIDs (left untouched)",
+            unrelated.join(", ")
+        )));
+    }
+    if pushing && !behind.is_empty() {
+        return Err(Error::other(format!(
+            "{} behind {name}: run ak pull {name} first",
+            behind.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Run `ak import-git`: seal, as `author`, the commits of the Git repository `dir` that were not
+/// imported yet, then point branches and tags at them and refresh their reference trees. Branches
+/// and tags naming a commit sealed with ak are left alone; the working directory is not touched.
+fn import_git(root: &Path, author: &str, dir: &Path, first_parent: bool) -> std::io::Result<()> {
+    let cube = cube_path_for(author);
+    let events = author_events(author)?;
+    // Seals of earlier imports, by Git commit.
+    let mut imported: HashMap<String, u64> = HashMap::new();
+    for ev in commit::history(&events) {
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).unwrap_or_default();
+        if let Some(git) = v.get("git_commit").and_then(|x| x.as_str()) {
+            imported.insert(git.to_string(), commit::commit_id(ev));
+        }
+    }
+    let known = imported.keys().cloned().collect();
+    let mut manifests: HashMap<u64, Manifest> = HashMap::new();
+    let (mut count, mut skipped) = (0, 0);
+    let mut w = Writer::create(&cube)?;
+    w.begin_bulk()?;
+    let walked = git::walk(dir, first_parent, &known, |c| {
+        let parent = c.parents.first().and_then(|p| imported.get(p)).copied();
+        let mut manifest = parent
+            .and_then(|p| {
+                manifests
+                    .get(&p)
+                    .cloned()
+                    .or_else(|| Manifest::of_commit(&events, p))
+            })
+            .unwrap_or_default();
+        let id = w.append_record("commit:pending", &c.message)?.id;
+        let mut staged = Index::default();
+        for (path, content) in &c.changes {
+            staged.stage_content(path, content.clone());
+        }
+        manifest.apply_index(&mut w, &staged)?;
+        for path in &c.skipped {
+            eprintln!("skip {path} in {}: not a UTF-8 text file", &c.id[..7]);
+        }
+        skipped += c.skipped.len();
+
+        let (ty, summary, body) = git::split_message(&c.message);
+        let record = schema::Commit {
+            v: schema::Commit::VERSION,
+            id,
+            parent,
+            branch: git::ref_name(&c.branch),
+            manifest: Some(manifest.record(&mut w, id)?),
+            reverts: None,
+            amends: None,
+            picked_from: None,
+            merge_parent: c.parents.get(1).and_then(|p| imported.get(p)).copied(),
+            git_commit: Some(c.id.clone()),
+            ty,
+            summary,
+            body,
+            author: c.author.clone(),
+            author_email: c.email.clone(),
+            timestamp: c.timestamp,
+        };
+        let json = serde_json::to_string_pretty(&record).map_err(Error::other)?;
+        w.append("commit", &json)?;
+        imported.insert(c.id, id);
+        manifests.insert(id, manifest);
+        count += 1;
+        Ok(())
+    });
+    w.finish_bulk()?;
+    let refs = walked?;
+
+    // Move what only ever named imported commits.
+    let ours: std::collections::HashSet<u64> = imported.values().copied().collect();
+    let heads = branch::heads(&events);
+    let tags = tag::tags(&events);
+    let mut moved = Vec::new();
+    for (git_name, tip) in &refs.branches {
+        let (name, Some(&id)) = (git::ref_name(git_name), imported.get(tip)) else {
+            continue;
+        };
+        let head = heads.get(&name).copied().flatten();
+        if head == Some(id) {
+            continue;
+        }
+        if head.is_some_and(|h| !ours.contains(&h)) {
+            eprintln!("Branch {name} kept: it has seals of its own.");
+            continue;
+        }
+        branch::record_head(&mut w, &name, Some(id))?;
+        let entry = reflog::Entry {
+            author: author.to_string(),
+            op: "import-git".to_string(),
+            before: reflog::State {
+                branch: name.clone(),
+                head,
+            },
+            after: reflog::State {
+                branch: name.clone(),
+                head: Some(id),
+            },
+            message: format!("{git_name} from {}", dir.display()),
+        };
+        reflog::record(&mut w, &entry)?;
+        moved.push((name, id));
+    }
+    let mut tagged = 0;
+    for (git_name, target) in &refs.tags {
+        let (name, Some(&id)) = (git::ref_name(git_name), imported.get(target)) else {
+            continue;
+        };
+        match tags.get(&name) {
+            Some(&t) if t == id => {}
+            Some(t) if !ours.contains(t) => eprintln!("Tag {name} kept: it names #{t}."),
+            _ => {
+                tag::create(&mut w, &events, &name, id, true)?;
+                tagged += 1;
+            }
+        }
+    }
+
+    // Without a head of its own, `main` would stand for the latest imported commit.
+    if !moved.is_empty()
+        && heads
+            .get(branch::DEFAULT_BRANCH)
+            .copied()
+            .flatten()
+            .is_none()
+        && !moved.iter().any(|(name, _)| name == branch::DEFAULT_BRANCH)
+    {
+        branch::record_head(&mut w, branch::DEFAULT_BRANCH, None)?;
+    }
+
+    let events = author_events(author)?;
+    for (name, id) in &moved {
+        let tree = tree::branch_tree_dir(root, author, name);
+        if tree.exists() {
+            std::fs::remove_dir_all(&tree)?;
+        }
+        restore::restore(&events, *id, &[], &tree)?;
+    }
+    // A repository without seals follows Git's checked out branch.
+    let current = branch::current(root, author);
+    if let Some(head) = refs.head.map(|h| git::ref_name(&h))
+        && head != current
+        && heads.get(&current).copied().flatten().is_none()
+        && moved.iter().any(|(name, _)| *name == head)
+    {
+        branch::set_current(root, author, &head)?;
+        println!("Switched to branch {head}.");
+    }
+    println!(
+        "Imported {count} commit(s) from {}: {} branch(es) and {tagged} tag(s) updated.",
+        dir.display(),
+        moved.len()
+    );
+    if skipped > 0 {
+        println!("{skipped} file version(s) skipped: binary, symbolic links or submodules.");
+    }
+    Ok(())
+}
+
+/// Run `ak clone`: create `dir` with `url` as its `origin` remote, pull every cube, then check
+/// out the head of `author`'s main branch, or else the latest commit of the repository.
+fn clone(url: &str, dir: Option<&String>, author: &str) -> std::io::Result<()> {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let path = match remote::parse_url(url) {
+                remote::Location::Path(path) => path,
+                remote::Location::Ssh(ssh) => PathBuf::from(ssh.path),
+            };
+            PathBuf::from(path.file_name().ok_or_else(|| {
+                Error::other(format!("can't name a directory after {url}, give one"))
+            })?)
+        }
+    };
+    if dir.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(Error::other(format!(
+            "{} already exists and is not empty",
+            dir.display()
+        )));
+    }
+    // A relative path is relative to where clone runs, not to the new repository.
+    let url = match remote::parse_url(url) {
+        remote::Location::Path(path) => path.canonicalize()?.display().to_string(),
+        remote::Location::Ssh(_) => url.to_string(),
+    };
+    let mut transport = remote::connect(&url);
+    for sub in ["cubes", "branches"] {
+        create_dir_all(dir.join(".eikyu").join(sub))?;
+    }
+    let mut file = Config::default();
+    file.remote
+        .insert("origin".to_string(), config::Remote { url: url.clone() });
+    file.save(&dir)?;
+
+    let plan = remote::plan_pull(&dir, transport.as_mut())?;
+    remote::pull(&dir, &plan)?;
+    let records: usize = plan.values().map(remote::Action::records).sum();
+    println!("Cloned {url} into {}: {records} record(s).", dir.display());
+
+    let own = history::author_events(&dir, author)?;
+    let (events, id) = match branch::heads(&own).get(branch::DEFAULT_BRANCH) {
+        Some(Some(head)) => (own, *head),
+        _ => match history::commits(&dir)?.pop() {
+            Some(latest) => {
+                let id = serde_json::from_str::<serde_json::Value>(&latest.event.noumenon)
+                    .ok()
+                    .and_then(|v| v.get("id")?.as_u64())
+                    .ok_or_else(|| Error::other("malformed commit record"))?;
+                (history::author_events(&dir, &latest.author)?, id)
+            }
+            None => {
+                create_dir_all(tree::tree_dir(&dir, author))?;
+                println!("The repository has no commits yet.");
+                return Ok(());
+            }
+        },
+    };
+    let files = restore::restore(&events, id, &[], &dir)?;
+    // The checked out files are the reference tree: a fresh clone has no changes.
+    restore::restore(&events, id, &[], &tree::tree_dir(&dir, author))?;
+    println!("Checked out #{id}: {} file(s).", files.len());
+    Ok(())
+}
+
+/// Run `ak remote-helper` for an `ak push` or `ak pull` over SSH.
+fn remote_helper(sub: &ArgMatches) -> std::io::Result<()> {
+    let root = Path::new(sub.get_one::<String>("path").expect("path is required"));
+    let cube = || {
+        sub.get_one::<String>("cube")
+            .ok_or_else(|| Error::other("missing cube"))
+    };
+    match sub.get_one::<String>("op").map(String::as_str) {
+        Some("tips") => {
+            serde_json::to_writer(std::io::stdout(), &remote::tips(root)?)?;
+            Ok(())
+        }
+        Some("fetch") => {
+            let from = sub.get_one::<u64>("from").copied().unwrap_or(0);
+            let events = remote::fetch(root, cube()?, from)?;
+            remote::write_events(std::io::stdout().lock(), &events)
+        }
+        _ => {
+            let identity: Option<Identity> = sub
+                .get_one::<String>("identity")
+                .map(|json| serde_json::from_str(json))
+                .transpose()?;
+            let events = remote::read_events(std::io::stdin().lock())?;
+            remote::store(root, cube()?, identity.as_ref(), &events)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = apps();
+    // `auto` is the default of `colored`: standard output is a terminal and NO_COLOR is unset
+    // (CLICOLOR and CLICOLOR_FORCE are honored as well).
+    match args.get_one::<String>("color").map(String::as_str) {
+        Some("always") => colored::control::set_override(true),
+        Some("never") => colored::control::set_override(false),
+        _ => {}
+    }
+
+    let standalone = match args.subcommand() {
+        Some(("remote", sub)) => Some(("remote", remotes(sub))),
+        Some(("push", sub)) => Some(("push", sync(sub, true))),
+        Some(("pull", sub)) => Some(("pull", sync(sub, false))),
+        Some(("remote-helper", sub)) => Some(("remote-helper", remote_helper(sub))),
+        _ => None,
+    };
+    if let Some((command, result)) = standalone {
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{command} failed: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if let Some(("config", sub)) = args.subcommand() {
+        return match config(sub) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("config failed: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // Resolve the author identity required for commit metadata.
+    let author = match identity("user.name", AK_USERNAME, "Your name:") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let author_email = match identity("user.email", AK_EMAIL, "Your email:") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match args.subcommand() {
+        Some(("init", sub)) => {
+            // Layout:
+            // - .eikyu/
+            //   - config.toml
+            //   - cubes/<YYYY-MM>/<author>.cube (or cubes/shared.cube)
+            //   - branches/<author>
+            //   - tree/<author>
+            create_dir_all("./.eikyu").expect("create dir failed");
+            if let Some(layout) = sub.get_one::<String>("layout") {
+                let layout = if layout == "shared" {
+                    Layout::Shared
+                } else {
+                    Layout::PerAuthor
+                };
+                let root = Path::new(".");
+                let mut config = Config::load(root).expect("read .eikyu/config.toml failed");
+                let has_cubes = std::fs::read_dir(format!(".eikyu{MAIN_SEPARATOR_STR}cubes"))
+                    .map(|mut d| d.next().is_some())
+                    .unwrap_or(false);
+                if config.storage.layout != layout && has_cubes {
+                    eprintln!("init failed: the repository already has cubes in another layout");
+                    return ExitCode::FAILURE;
+                }
+                config.storage.layout = layout;
+                config.save(root).expect("write .eikyu/config.toml failed");
+            }
+            create_dir_all(format!(".eikyu{MAIN_SEPARATOR_STR}cubes"))
+                .expect("create cubes dir failed");
+            create_dir_all(format!(".eikyu{MAIN_SEPARATOR_STR}branches"))
+                .expect("create branches dir failed");
+            let tree_path = format!(".eikyu{MAIN_SEPARATOR_STR}tree{MAIN_SEPARATOR_STR}{author}");
+            create_dir_all(&tree_path).expect("create tree dir failed");
+
+            // Ensure the current cube file exists for this author/month.
+            let cube = cube_path_for(&author);
+            let _ = Writer::create(&cube).expect("failed to initialize cube");
+            println!("Initialized repository. Cube: {cube}");
+            println!("Reference tree: {tree_path}");
+            ExitCode::SUCCESS
+        }
 
         Some(("inscribe", sub)) => {
             // Gate the operation through pre-commit hooks. If hooks fail, abort inscription.
-            if let Err(e) = hooks() {
+            if sub.get_flag("no-verify") {
+                println!("Pre-commit hooks skipped (--no-verify).");
+            } else if let Err(e) = hooks(Path::new("."), &author, &skipped_steps(sub)) {
+                eprintln!("Pre-commit hooks failed: {e}");
+                return ExitCode::FAILURE;
+            }
+
+            let mut targets: Vec<&str> = sub
+                .get_many::<String>("paths")
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            if targets.is_empty() {
+                targets.push(".");
+            }
+            let mut exclude = GlobSetBuilder::new();
+            for pattern in sub.get_many::<String>("exclude").into_iter().flatten() {
+                match Glob::new(pattern) {
+                    Ok(glob) => exclude.add(glob),
+                    Err(e) => {
+                        eprintln!("inscribe failed: --exclude {pattern}: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
+            let exclude = exclude.build().expect("globs are valid");
+            let cube = cube_path_for(&author);
+            let mut w = Writer::create(&cube)
+                .and_then(script::attach)
+                .expect("open cube failed");
+            match w.store_paths_at(".", &targets, &exclude) {
+                Ok(stored) => {
+                    let referenced = match stored.referenced {
+                        0 => String::new(),
+                        n => format!(" ({n} stored as references to identical contents)"),
+                    };
+                    println!(
+                        "Inscribed {}: {} new, {} updated{referenced}, {} unchanged, {} byte(s) \
+                         written.",
+                        targets.join(", "),
+                        stored.new,
+                        stored.updated,
+                        stored.unchanged,
+                        stored.bytes
+                    );
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("inscribe failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("seal", sub)) => {
+            // Without --all, only the staged changes are sealed.
+            let seal_all = sub.get_flag("all");
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let staged = match Index::load(&root, &author) {
+                Ok(idx) => idx,
+                Err(e) => {
+                    eprintln!("Failed to read the index: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            // --amend replaces the head commit, keeping its message unless new values are given.
+            let amended = if sub.get_flag("amend") {
+                let events = author_events(&author).expect("read cubes failed");
+                let head = branch::heads(&events)
+                    .get(&branch::current(&root, &author))
+                    .copied()
+                    .flatten();
+                let Some(head) = head else {
+                    eprintln!("Nothing to amend: no commit on this branch yet.");
+                    return ExitCode::FAILURE;
+                };
+                let v = commit::find(&events, head)
+                    .and_then(|e| serde_json::from_str::<serde_json::Value>(&e.noumenon).ok())
+                    .unwrap_or_default();
+                let field = |k: &str| v.get(k).and_then(|x| x.as_str()).map(str::to_string);
+                Some((head, field("ty"), field("summary"), field("body")))
+            } else {
+                None
+            };
+            // A merge stopped by conflicts is sealed once every conflict is resolved and staged.
+            let merging = match MergeState::load(&root, &author) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Failed to read the merge state: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            if let Some(state) = &merging {
+                let unresolved: Vec<&String> = state
+                    .conflicts
+                    .iter()
+                    .filter(|p| match staged.entries.get(*p) {
+                        Some(Some(text)) => merge::has_conflict_markers(text),
+                        Some(None) => false,
+                        None => !seal_all,
+                    })
+                    .collect();
+                if !unresolved.is_empty() {
+                    eprintln!(
+                        "Unresolved merge conflicts (fix and `ak add` them): {}",
+                        unresolved
+                            .iter()
+                            .map(|p| p.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+            // Amends and merges are sealed as they are; other seals must change the tree.
+            if !sub.get_flag("allow-empty") && amended.is_none() && merging.is_none() {
+                if staged.is_empty() && !seal_all {
+                    eprintln!("Nothing staged. Use `ak add <paths>` or `ak seal --all`.");
+                    return ExitCode::FAILURE;
+                }
+                let changed = tree::Reference::open(&root, &author).and_then(|reference| {
+                    if seal_all {
+                        status::status(&root, &reference).map(|st| !st.is_clean())
+                    } else {
+                        staged.changes(&reference)
+                    }
+                });
+                match changed {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        eprintln!(
+                            "Nothing to seal: no change since the last seal (use --allow-empty to seal anyway)."
+                        );
+                        return ExitCode::FAILURE;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to compare with the reference tree: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            let signing_key = if sub.get_flag("sign") {
+                match config::resolve(&root, "user.signingkey", AK_SIGNING_KEY) {
+                    Ok(Some((key, _))) => Some(expand_home(&key)),
+                    Ok(None) => {
+                        eprintln!(
+                            "Missing signing key: set {AK_SIGNING_KEY} or run `ak config user.signingkey <path>`"
+                        );
+                        return ExitCode::FAILURE;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read the configuration: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Gate the operation through pre-commit hooks. If hooks fail, abort the commit.
+            if sub.get_flag("no-verify") {
+                println!("Pre-commit hooks skipped (--no-verify).");
+            } else if let Err(e) = hooks(&root, &author, &skipped_steps(sub)) {
                 eprintln!("Pre-commit hooks failed: {e}");
                 return ExitCode::FAILURE;
             }
 
-            let target = sub
-                .get_one::<String>("path")
+            // Resolve editor for interactive body capture (`None`: read it in the terminal).
+            let editor = match config::editor(Path::new(".")) {
+                Ok(found) => found.map(|(v, _)| v),
+                Err(e) => {
+                    eprintln!("Failed to read the configuration: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let (amends, old_ty, old_summary, old_body) = match amended {
+                Some((id, ty, summary, body)) => (Some(id), ty, summary, body),
+                None => (None, None, None, None),
+            };
+
+            // Rules the message must follow, and the types offered (the allowed ones, if set).
+            let rules = match Config::load(&root) {
+                Ok(c) => c.message,
+                Err(e) => {
+                    eprintln!("Failed to read the configuration: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let types: Vec<&str> = if rules.types.is_empty() {
+                vec!["feat", "fix", "refactor", "docs", "test", "chore"]
+            } else {
+                rules.types.iter().map(String::as_str).collect()
+            };
+
+            // Commit type (amended commit, then interactive fallback).
+            let mut ty = if let Some(t) = sub.get_one::<String>("type").cloned().or(old_ty) {
+                t
+            } else {
+                Select::new("type:", types.clone())
+                    .prompt()
+                    .expect("type prompt failed")
+                    .to_string()
+            };
+
+            // Commit summary (interactive fallback).
+            let mut summary =
+                if let Some(s) = sub.get_one::<String>("summary").cloned().or(old_summary) {
+                    s
+                } else {
+                    Text::new("summary:")
+                        .prompt()
+                        .expect("summary prompt failed")
+                };
+
+            // Commit body (interactive editor fallback).
+            let mut body = if let Some(b) = sub.get_one::<String>("body").cloned().or(old_body) {
+                b
+            } else {
+                edit_body(editor.as_deref(), "")
+            };
+
+            // Check the message before anything is written; in a terminal, fix it until it passes.
+            while !sub.get_flag("no-verify") {
+                let violations = match lint::check(&rules, &ty, &summary, &body) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("Failed to check the message: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                };
+                if violations.is_empty() {
+                    break;
+                }
+                eprintln!("The message breaks the repository's rules:");
+                for v in &violations {
+                    eprintln!("  - {v}");
+                }
+                if !std::io::stdin().is_terminal() {
+                    eprintln!("Fix the message, or skip the checks with --no-verify.");
+                    return ExitCode::FAILURE;
+                }
+                match Select::new("fix:", vec!["type", "summary", "body", "abort"])
+                    .prompt()
+                    .expect("fix prompt failed")
+                {
+                    "type" => {
+                        ty = Select::new("type:", types.clone())
+                            .prompt()
+                            .expect("type prompt failed")
+                            .to_string()
+                    }
+                    "summary" => {
+                        summary = Text::new("summary:")
+                            .with_initial_value(&summary)
+                            .prompt()
+                            .expect("summary prompt failed")
+                    }
+                    "body" => body = edit_body(editor.as_deref(), &body),
+                    _ => {
+                        eprintln!("Seal aborted.");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+
+            let request = SealRequest {
+                ty: &ty,
+                summary: &summary,
+                body: &body,
+                author: &author,
+                author_email: &author_email,
+                all: seal_all,
+                reverts: None,
+                amends,
+                picked_from: None,
+                merge_parent: merging.as_ref().map(|m| m.head),
+                signing_key: signing_key.as_deref(),
+            };
+            match seal(&root, &request, &staged) {
+                Ok(_) => {
+                    if merging.is_some()
+                        && let Err(e) = MergeState::clear(&root, &author)
+                    {
+                        eprintln!("Error clearing the merge state: {e}");
+                    }
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("seal failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("timeline", sub)) => {
+            let show_utc = sub.get_flag("utc");
+            let show_iso = sub.get_flag("iso");
+            let json = sub.get_flag("json");
+            // Every month and every author, in the order commits were sealed.
+            let commits = history::commits(Path::new(".")).expect("read commits failed");
+            let several_authors = commits.iter().any(|c| c.author != commits[0].author);
+            if commits.is_empty() && !json {
+                println!("No commits.");
+                return ExitCode::SUCCESS;
+            }
+            let filter = commit::Filter {
+                since: sub.get_one::<i64>("since").copied(),
+                until: sub.get_one::<i64>("until").copied(),
+                ty: sub.get_one::<String>("type").cloned(),
+                grep: sub.get_one::<String>("grep").cloned(),
+                author: sub.get_one::<String>("author").cloned(),
+            };
+
+            // Parse the commit JSON payloads; tolerate errors by skipping malformed entries.
+            let mut shown: Vec<(history::Commit, serde_json::Value)> = Vec::new();
+            // Notes live with the commits they annotate, in their author's cubes.
+            let noted = sub.get_one::<String>("note");
+            let mut notes: HashMap<String, HashMap<u64, Vec<(u128, note::Note)>>> = HashMap::new();
+            for c in commits {
+                if let Some(text) = noted {
+                    let of_author = notes.entry(c.author.clone()).or_insert_with(|| {
+                        note::notes(&author_events(&c.author).expect("read cubes failed"))
+                    });
+                    let id = commit::commit_id(&c.event);
+                    if !of_author.get(&id).is_some_and(|n| note::mention(n, text)) {
+                        continue;
+                    }
+                }
+                match serde_json::from_str::<serde_json::Value>(&c.event.noumenon) {
+                    Ok(v) if filter.matches(&v) => shown.push((c, v)),
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!(
+                            "warning: failed to parse commit #{} of {}, reason: {e}",
+                            c.event.id, c.author
+                        );
+                    }
+                }
+            }
+            if let Some(&limit) = sub.get_one::<usize>("limit") {
+                shown.drain(..shown.len().saturating_sub(limit));
+            }
+            // The graph is drawn newest first, like `--reverse`.
+            if sub.get_flag("reverse") || sub.get_flag("graph") {
+                shown.reverse();
+            }
+            if json {
+                let out: Vec<&serde_json::Value> = shown.iter().map(|(_, v)| v).collect();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&out).expect("serialize commits")
+                );
+                return ExitCode::SUCCESS;
+            }
+            if shown.is_empty() {
+                println!("No matching commits.");
+                return ExitCode::SUCCESS;
+            }
+            // Signed commits show how their signature checks out.
+            let allowed = Config::load(Path::new("."))
+                .expect("read configuration failed")
+                .signing
+                .allowed_signers_path(Path::new("."));
+            let mut checked: HashMap<(String, u64), sign::Verification> = HashMap::new();
+            let mut signed: HashMap<String, HashMap<u64, String>> = HashMap::new();
+            for (c, _) in &shown {
+                let signatures = signed.entry(c.author.clone()).or_insert_with(|| {
+                    sign::signatures(&author_events(&c.author).expect("read cubes failed"))
+                });
+                match sign::verify_commit(&c.event, signatures, &allowed) {
+                    Ok(sign::Verification::Unsigned) => {}
+                    Ok(v) => {
+                        checked.insert((c.author.clone(), c.event.id), v);
+                    }
+                    Err(e) => eprintln!("warning: checking the signature of #{}: {e}", c.event.id),
+                }
+            }
+            let line = |c: &history::Commit, v: &serde_json::Value, deco: &str| {
+                let sig = match checked.get(&(c.author.clone(), c.event.id)) {
+                    Some(v) => format!(" [{}]", signature_label(v)),
+                    None => String::new(),
+                };
+                let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(c.event.id);
+                let ty = v.get("ty").and_then(|x| x.as_str()).unwrap_or("commit");
+                let summary = v.get("summary").and_then(|x| x.as_str()).unwrap_or("");
+                let when = commit_when(v, show_utc, show_iso);
+                // Ids are per author: name them once several authors share the timeline.
+                let by = if several_authors {
+                    format!(" ({})", c.author)
+                } else {
+                    String::new()
+                };
+                format!("#{id}{deco} [{ty}] {summary} @ {when}{by}{sig}")
+            };
+            if sub.get_flag("graph") {
+                let events = author_events(&author).expect("read cubes failed");
+                print_graph(&events, &author, &shown, line);
+                return ExitCode::SUCCESS;
+            }
+
+            for (c, v) in &shown {
+                println!("{}", line(c, v, ""));
+            }
+            ExitCode::SUCCESS
+        }
+
+        Some(("verify", _)) => match verify(Path::new(".")) {
+            Ok(true) => {
+                println!("{}", "verify: passed".green());
+                ExitCode::SUCCESS
+            }
+            Ok(false) => {
+                println!("{}", "verify: failed".red().bold());
+                ExitCode::FAILURE
+            }
+            Err(e) => {
+                eprintln!("verify failed: {e}");
+                ExitCode::FAILURE
+            }
+        },
+
+        Some(("grep", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let events = author_events(&author).expect("read cubes failed");
+            let pattern = sub
+                .get_one::<String>("pattern")
+                .expect("pattern is required");
+            let re = match regex::RegexBuilder::new(pattern)
+                .case_insensitive(sub.get_flag("ignore-case"))
+                .build()
+            {
+                Ok(re) => re,
+                Err(e) => {
+                    eprintln!("grep failed: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let found = if sub.get_flag("all-history") {
+                grep::in_history(&events, &re)
+            } else {
+                match branch_head(&author, &branch::current(&root, &author)) {
+                    Ok(Some(head)) => grep::in_commit(&events, head, &re),
+                    Ok(None) => Err(Error::other("nothing sealed on this branch yet")),
+                    Err(e) => Err(e),
+                }
+            };
+            match found {
+                Ok(matches) => {
+                    for m in &matches {
+                        println!(
+                            "{} {}:{}: {}",
+                            format!("#{}", m.commit).yellow(),
+                            m.path.magenta(),
+                            m.line.to_string().green(),
+                            m.text
+                        );
+                    }
+                    // Like grep(1): no match is a failure.
+                    if matches.is_empty() {
+                        ExitCode::FAILURE
+                    } else {
+                        ExitCode::SUCCESS
+                    }
+                }
+                Err(e) => {
+                    eprintln!("grep failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("blame", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let events = author_events(&author).expect("read cubes failed");
+            let file = sub.get_one::<String>("file").expect("file is required");
+            let Some(path) = paths::normalize(&root, &root.join(file)) else {
+                eprintln!("{file} is outside the repository");
+                return ExitCode::FAILURE;
+            };
+            let head = match sub.get_one::<String>("commit") {
+                Some(spec) => resolve_commit(&events, spec).map(Some),
+                None => branch_head(&author, &branch::current(&root, &author)),
+            };
+            let result = match head {
+                Ok(Some(head)) => print_blame(&events, head, &path),
+                Ok(None) => Err(Error::other("nothing sealed on this branch yet")),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("blame failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("show", sub)) => {
+            let events = author_events(&author).expect("read cubes failed");
+            let spec = sub.get_one::<String>("commit").expect("commit is required");
+            let root = Path::new(".");
+            let allowed = match Config::load(root) {
+                Ok(c) => c.signing.allowed_signers_path(root),
+                Err(e) => {
+                    eprintln!("Failed to read the configuration: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            match show(&events, spec, sub.get_flag("patch"), &allowed) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("show failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("cherry-pick", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let spec = sub.get_one::<String>("commit").expect("commit is required");
+            let binary = binary_policy(sub);
+            match cherry_pick(&root, &author, &author_email, spec, binary) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("cherry-pick failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("format-patch", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let range = sub.get_one::<String>("range").expect("range is required");
+            let dir = match sub.get_one::<String>("output-directory") {
+                Some(dir) => Some(PathBuf::from(dir)),
+                None if sub.get_flag("stdout") => None,
+                None => Some(root.clone()),
+            };
+            match format_patch(&root, &author, range, dir.as_deref()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("format-patch failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("apply", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let files: Vec<&String> = sub
+                .get_many::<String>("patches")
+                .expect("patches are required")
+                .collect();
+            let fuzz = *sub.get_one::<usize>("fuzz").expect("fuzz has a default");
+            match apply_patches(
+                &root,
+                &files,
+                sub.get_flag("check"),
+                sub.get_flag("reverse"),
+                fuzz,
+            ) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("apply failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("merge", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let result = match sub.get_one::<String>("branch") {
+                Some(name) => merge_branch(&root, &author, &author_email, name, binary_policy(sub)),
+                None => merge_abort(&root, &author),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("merge failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("revert", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let spec = sub.get_one::<String>("commit").expect("commit is required");
+            match revert(&root, &author, &author_email, spec) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("revert failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("tag", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let mut w = Writer::create(&cube_path_for(&author)).expect("open cube failed");
+            let events = author_events(&author).expect("read cubes failed");
+            let result = match sub.subcommand() {
+                Some(("list", _)) => {
+                    for (name, id) in tag::tags(&events) {
+                        println!("{name} #{id}");
+                    }
+                    Ok(())
+                }
+                Some(("delete", del)) => {
+                    let name = del.get_one::<String>("name").expect("name is required");
+                    tag::delete(&mut w, &events, name).map(|()| println!("Deleted tag {name}."))
+                }
+                _ => {
+                    let name = sub.get_one::<String>("name").expect("name is required");
+                    // Defaults to the head of the current branch.
+                    let target = match sub.get_one::<String>("commit") {
+                        Some(spec) => resolve_commit(&events, spec).map(Some),
+                        None => Ok(branch::heads(&events)
+                            .get(&branch::current(&root, &author))
+                            .copied()
+                            .flatten()),
+                    };
+                    match target {
+                        Ok(Some(id)) => {
+                            tag::create(&mut w, &events, name, id, sub.get_flag("force"))
+                                .map(|()| println!("Tagged #{id} as {name}."))
+                        }
+                        Ok(None) => Err(Error::other("no commit to tag on this branch yet")),
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("tag failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("changelog", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let result = author_events(&author).and_then(|events| {
+                let to = match sub.get_one::<String>("to") {
+                    Some(spec) => resolve_commit(&events, spec)?,
+                    None => branch_head(&author, &branch::current(&root, &author))?
+                        .ok_or_else(|| Error::other("no commit on this branch yet"))?,
+                };
+                let from = match sub.get_one::<String>("from") {
+                    Some(spec) => Some(resolve_commit(&events, spec)?),
+                    None => changelog::previous(&events, to).map(|r| r.commit),
+                };
+                let title = sub.get_one::<String>("to").map_or("Unreleased", |s| s);
+                print!(
+                    "{}",
+                    changelog::render(title, &changelog::commits(&events, from, to))
+                );
+                Ok(())
+            });
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("changelog failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("release", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let version = sub
+                .get_one::<String>("version")
+                .expect("version is required");
+            let result = author_events(&author).and_then(|events| {
+                let head = branch_head(&author, &branch::current(&root, &author))?
+                    .ok_or_else(|| Error::other("no commit to release on this branch yet"))?;
+                let from = changelog::previous(&events, head).map(|r| r.commit);
+                let mut w = Writer::create(&cube_path_for(&author))?;
+                tag::create(&mut w, &events, version, head, false)?;
+                changelog::record(
+                    &mut w,
+                    &Release {
+                        version: version.clone(),
+                        commit: head,
+                        author: author.clone(),
+                    },
+                )?;
+                println!("Released {version} at #{head}.");
+                println!();
+                print!(
+                    "{}",
+                    changelog::render(version, &changelog::commits(&events, from, head))
+                );
+                Ok(())
+            });
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("release failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("hooks", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let result = match sub.subcommand() {
+                Some(("list", _)) => hooks::load(&root).map(|steps| match steps {
+                    Some(steps) => {
+                        for step in steps {
+                            let mut notes = Vec::new();
+                            if step.allow_failure {
+                                notes.push("allow-failure".to_string());
+                            }
+                            if step.parallel {
+                                notes.push("parallel".to_string());
+                            }
+                            if !step.only_on_changed.is_empty() {
+                                notes.push(format!("on {}", step.only_on_changed.join(", ")));
+                            }
+                            let notes = if notes.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" ({})", notes.join("; "))
+                            };
+                            println!(
+                                "{}: {}{notes}",
+                                step.name,
+                                [vec![step.program.clone()], step.args].concat().join(" ")
+                            );
+                        }
+                    }
+                    None => {
+                        println!("No .eikyu/hooks.toml: steps are detected from the project type.")
+                    }
+                }),
+                Some(("run", run)) => hooks(&root, &author, &skipped_steps(run)),
+                _ => unreachable!("clap requires a hooks subcommand"),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Pre-commit hooks failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("check", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let json = sub.get_flag("json");
+            let result = (|| -> std::io::Result<check::Report> {
+                let mut report = check::Report::default();
+                let events = author_events(&author)?;
+                report.head = branch_head(&author, &branch::current(&root, &author))?;
+                // Resolve the range first: a typo shouldn't cost a run of the steps.
+                let to = match sub.get_one::<String>("to") {
+                    Some(spec) => Some(resolve_commit(&events, spec)?),
+                    None => report.head,
+                };
+                let from = match (sub.get_one::<String>("from"), to) {
+                    (Some(spec), _) => Some(resolve_commit(&events, spec)?),
+                    (None, Some(to)) => changelog::previous(&events, to).map(|r| r.commit),
+                    (None, None) => None,
+                };
+                let rules = Config::load(&root)?.message;
+
+                if !sub.get_flag("no-hooks") {
+                    report.hooks = check_hooks(&root, &author, &skipped_steps(sub), json);
+                }
+                report.drift = check::drift(&root, &events, report.head)?;
+                if let Some(to) = to {
+                    let commits = changelog::commits(&events, from, to);
+                    report.commits = commits.len();
+                    report.messages = check::messages(&commits, &rules)?;
+                }
+                Ok(report)
+            })();
+            match result {
+                Ok(report) => {
+                    if json {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&report).expect("serialize check report")
+                        );
+                    } else {
+                        print_check(&report);
+                    }
+                    if report.passed() {
+                        ExitCode::SUCCESS
+                    } else {
+                        ExitCode::FAILURE
+                    }
+                }
+                Err(e) => {
+                    eprintln!("check failed: {e}");
+                    ExitCode::from(2)
+                }
+            }
+        }
+
+        Some(("check-ignore", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let mut status = ExitCode::SUCCESS;
+            for p in sub.get_many::<String>("paths").into_iter().flatten() {
+                let Some(rel) =
+                    paths::normalize(&root, &root.join(p)).and_then(|r| paths::to_native(&r))
+                else {
+                    eprintln!("{p} is outside the repository");
+                    status = ExitCode::FAILURE;
+                    continue;
+                };
+                match akignore::check(&root, &rel) {
+                    Ok(akignore::Verdict::Ignored { source, pattern }) => {
+                        println!("{p}: ignored by {source}: {pattern}")
+                    }
+                    Ok(akignore::Verdict::Included { source, pattern }) => {
+                        println!("{p}: included by {source}: {pattern}")
+                    }
+                    Ok(akignore::Verdict::Hidden) => println!("{p}: ignored (hidden)"),
+                    Ok(akignore::Verdict::NotIgnored) => println!("{p}: not ignored"),
+                    Err(e) => {
+                        eprintln!("check-ignore failed: {e}");
+                        status = ExitCode::FAILURE;
+                    }
+                }
+            }
+            status
+        }
+
+        Some(("view", _)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let head = branch_head(&author, &branch::current(&root, &author))
+                .expect("read branch head failed");
+            let events = author_events(&author).expect("read cubes failed");
+            let commits = commit::history(&events);
+            if let Some(ev) = commits.iter().rfind(|e| Some(commit::commit_id(e)) == head) {
+                match serde_json::from_str::<serde_json::Value>(&ev.noumenon) {
+                    Ok(v) => {
+                        let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(ev.id);
+                        let ty = v.get("ty").and_then(|x| x.as_str()).unwrap_or("commit");
+                        let summary = v.get("summary").and_then(|x| x.as_str()).unwrap_or("");
+                        println!("#{id} [{ty}] {summary}");
+                        ExitCode::SUCCESS
+                    }
+                    Err(_) => {
+                        println!("#{} [commit]", ev.id);
+                        ExitCode::FAILURE
+                    }
+                }
+            } else {
+                println!("No commits.");
+                ExitCode::SUCCESS
+            }
+        }
+
+        Some(("diff", sub)) => {
+            if !sub.get_flag("no-pager")
+                && std::env::var_os("AK_NO_PAGER").is_none()
+                && !sub.get_flag("quiet")
+                && std::io::stdout().is_terminal()
+                && let Some(code) = run_paged()
+            {
+                return code;
+            }
+            quiet_broken_pipe();
+            let root = Path::new(".");
+            let mut opts = diff::DiffOptions {
+                normalize_eol: sub.get_flag("normalize-eol"),
+                renames: std::collections::BTreeMap::new(),
+                context: *sub
+                    .get_one::<usize>("unified")
+                    .expect("unified has a default"),
+                whitespace: if sub.get_flag("ignore-all-space") {
+                    diff::Whitespace::IgnoreAll
+                } else if sub.get_flag("ignore-space-change") {
+                    diff::Whitespace::IgnoreChange
+                } else {
+                    diff::Whitespace::Exact
+                },
+                binary_similarity: sub.get_flag("binary-similarity"),
+                paths: None,
+                format: if sub.get_flag("stat") {
+                    diff::Format::Stat
+                } else if sub.get_flag("name-only") {
+                    diff::Format::NameOnly
+                } else if sub.get_flag("name-status") {
+                    diff::Format::NameStatus
+                } else if sub.get_flag("json") {
+                    diff::Format::Json
+                } else if sub.get_flag("quiet") {
+                    diff::Format::Quiet
+                } else {
+                    diff::Format::Patch
+                },
+            };
+            let staged = match Index::load(root, &author) {
+                Ok(idx) => idx.renames,
+                Err(e) => {
+                    eprintln!("diff failed: {e}");
+                    return opts.error_code();
+                }
+            };
+            let mut specs: Vec<&String> = sub
+                .get_many::<String>("commits")
+                .into_iter()
+                .flatten()
+                .collect();
+            // Without `--`, the first argument that names an existing path or holds a glob, and
+            // everything after it, are paths.
+            let mut patterns: Vec<&str> = sub
+                .get_many::<String>("paths")
+                .into_iter()
+                .flatten()
                 .map(String::as_str)
-                .unwrap_or(".");
+                .collect();
+            if let Some(at) = specs.iter().position(|s| {
+                !s.contains("..") && (Path::new(s).exists() || s.contains(['*', '?', '[', '{']))
+            }) {
+                patterns.splice(0..0, specs.split_off(at).into_iter().map(String::as_str));
+            }
+            if specs.len() > 2 {
+                eprintln!("diff failed: at most two commits, got {}", specs.len());
+                return opts.error_code();
+            }
+            if !patterns.is_empty() {
+                match diff::pathspec(&patterns) {
+                    Ok(set) => opts.paths = Some(set),
+                    Err(e) => {
+                        eprintln!("diff failed: {e}");
+                        return opts.error_code();
+                    }
+                }
+            }
+            // Show changes between working directory and the last sealed reference tree.
+            if specs.is_empty() {
+                opts.renames = staged;
+                return diff::diff(&opts);
+            }
+            // `a b`, `a..b`, `a..` and `a` (the last two against the working directory).
+            let (from, to) = match specs[..] {
+                [a, b] => (a.as_str(), Some(b.as_str())),
+                [a] => match a.split_once("..") {
+                    Some((a, "")) => (a, None),
+                    Some((a, b)) => (a, Some(b)),
+                    None => (a.as_str(), None),
+                },
+                _ => unreachable!("at most two commits"),
+            };
+            let events = match author_events(&author) {
+                Ok(events) => events,
+                Err(e) => {
+                    eprintln!("diff failed: {e}");
+                    return opts.error_code();
+                }
+            };
+            // Commits kept as snapshots (`[storage] keep-snapshots`) are read from them.
+            let commit_tree = |events: &[Event], id| tree::commit_files(root, &author, events, id);
+            let tree_of =
+                |spec: &str| resolve_commit(&events, spec).and_then(|id| commit_tree(&events, id));
+            // Follow the renames sealed in between, and those staged for the working directory.
+            let sides = tree_of(from).and_then(|left| {
+                let from = resolve_commit(&events, from)?;
+                let (right, renames) = match to {
+                    Some(spec) => {
+                        let to = resolve_commit(&events, spec)?;
+                        (
+                            commit_tree(&events, to)?,
+                            rename::between(&events, from, to),
+                        )
+                    }
+                    None => {
+                        let head = branch::heads(&events)
+                            .get(&branch::current(root, &author))
+                            .copied()
+                            .flatten();
+                        let sealed = head
+                            .map(|head| rename::between(&events, from, head))
+                            .unwrap_or_default();
+                        (
+                            diff::read_tree(&std::env::current_dir()?)?,
+                            rename::compose(sealed, &staged),
+                        )
+                    }
+                };
+                Ok((left, right, renames))
+            });
+            match sides {
+                Ok((left, right, renames)) => {
+                    opts.renames = renames;
+                    diff::diff_trees(&left, &right, &opts)
+                }
+                Err(e) => {
+                    eprintln!("diff failed: {e}");
+                    opts.error_code()
+                }
+            }
+        }
+
+        Some(("add", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let targets: Vec<&String> = sub.get_many::<String>("paths").unwrap().collect();
+            match add(&root, &author, &targets, sub.get_flag("patch")) {
+                Ok(0) => {
+                    println!("Nothing to stage.");
+                    ExitCode::SUCCESS
+                }
+                Ok(n) => {
+                    println!("Staged {n} path(s).");
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("add failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("mv", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let from = sub.get_one::<String>("from").expect("from is required");
+            let to = sub.get_one::<String>("to").expect("to is required");
+            match mv(&root, &author, from, to) {
+                Ok(renamed) => {
+                    for (from, to) in &renamed {
+                        println!("renamed {from} -> {to}");
+                    }
+                    println!("Staged {} rename(s).", renamed.len());
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("mv failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("rm", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let targets: Vec<&String> = sub.get_many::<String>("paths").unwrap().collect();
+            match rm(&root, &author, &targets) {
+                Ok(removed) => {
+                    for path in &removed {
+                        println!("removed {path}");
+                    }
+                    println!("Staged {} removal(s).", removed.len());
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("rm failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("branch", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
             let cube = cube_path_for(&author);
             let mut w = Writer::create(&cube).expect("open cube failed");
-            w.store_directory(target).expect("store directory failed");
-            println!("Inscribed: {target}");
+            let events = author_events(&author).expect("read cubes failed");
+            if let Some(name) = sub.get_one::<String>("name") {
+                return match branch::create(&root, &author, name, &events, &mut w) {
+                    Ok(head) => {
+                        let at = head.map(|h| format!("#{h}")).unwrap_or("no commit".into());
+                        println!("Created branch {name} at {at}.");
+                        ExitCode::SUCCESS
+                    }
+                    Err(e) => {
+                        eprintln!("branch failed: {e}");
+                        ExitCode::FAILURE
+                    }
+                };
+            }
+            let current = branch::current(&root, &author);
+            for (name, head) in branch::heads(&events) {
+                let mark = if name == current { "*" } else { " " };
+                let at = head.map(|h| format!("#{h}")).unwrap_or("-".into());
+                println!("{mark} {name} {at}");
+            }
             ExitCode::SUCCESS
         }
 
-        Some(("seal", sub)) => {
-            // Gate the operation through pre-commit hooks. If hooks fail, abort the commit.
-            if let Err(e) = hooks() {
-                eprintln!("Pre-commit hooks failed: {e}");
-                return ExitCode::FAILURE;
+        Some(("clone", sub)) => {
+            let url = sub.get_one::<String>("url").expect("url is required");
+            match clone(url, sub.get_one::<String>("dir"), &author) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("clone failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("import-git", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let repo = sub.get_one::<String>("repo").expect("repo is required");
+            match import_git(
+                &root,
+                &author,
+                Path::new(repo),
+                sub.get_flag("first-parent"),
+            ) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("import-git failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("export-git", sub)) => {
+            let dir = sub.get_one::<String>("dir").expect("dir is required");
+            match git::export(Path::new("."), Path::new(dir), &author) {
+                Ok(e) => {
+                    println!(
+                        "{} commit(s), {} branch(es) and {} tag(s) exported to {dir}.",
+                        e.commits, e.branches, e.tags
+                    );
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("export-git failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("bisect", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            match bisect(&root, &author, sub) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("bisect failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("note", sub)) => {
+            let result = author_events(&author).and_then(|events| match sub.subcommand() {
+                Some(("add", add)) => {
+                    let spec = add.get_one::<String>("commit").expect("commit is required");
+                    let text = add.get_one::<String>("text").expect("text is required");
+                    let id = resolve_commit(&events, spec)?;
+                    let mut w = Writer::create(&cube_path_for(&author))?;
+                    note::add(&mut w, id, &author, text)?;
+                    println!("Noted #{id}.");
+                    Ok(())
+                }
+                Some(("show", show)) => {
+                    let spec = show
+                        .get_one::<String>("commit")
+                        .expect("commit is required");
+                    let id = resolve_commit(&events, spec)?;
+                    let notes = note::of_commit(&events, id);
+                    if notes.is_empty() {
+                        println!("No notes on #{id}.");
+                    }
+                    print_notes(&notes);
+                    Ok(())
+                }
+                _ => unreachable!("clap requires a note subcommand"),
+            });
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("note failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("reflog", _)) => match author_events(&author) {
+            Ok(events) => {
+                print_reflog(&events, &author);
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("reflog failed: {e}");
+                ExitCode::FAILURE
+            }
+        },
+
+        Some(("gc", sub)) => {
+            let days = *sub
+                .get_one::<u64>("older-than")
+                .expect("older-than has a default");
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("system time before UNIX epoch")
+                .as_nanos();
+            let cutoff = now.saturating_sub(days as u128 * 86_400 * 1_000_000_000);
+            let dry_run = sub.get_flag("dry-run");
+            match gc::collect(Path::new("."), cutoff, dry_run) {
+                Ok(report) => {
+                    print_gc(&report, dry_run);
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("gc failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+
+        Some(("tree", sub)) => match sub.subcommand() {
+            Some(("verify", verify)) => {
+                match verify_trees(Path::new("."), &author, verify.get_flag("snapshots")) {
+                    Ok(true) => ExitCode::SUCCESS,
+                    Ok(false) => ExitCode::FAILURE,
+                    Err(e) => {
+                        eprintln!("tree verify failed: {e}");
+                        ExitCode::FAILURE
+                    }
+                }
+            }
+            Some(("prune", prune)) => {
+                match prune_trees(Path::new("."), prune.get_flag("dry-run")) {
+                    Ok(()) => ExitCode::SUCCESS,
+                    Err(e) => {
+                        eprintln!("tree prune failed: {e}");
+                        ExitCode::FAILURE
+                    }
+                }
             }
+            Some(("du", _)) => match print_tree_usage(Path::new(".")) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("tree du failed: {e}");
+                    ExitCode::FAILURE
+                }
+            },
+            _ => unreachable!("subcommand_required"),
+        },
 
-            // Resolve editor for interactive body capture.
-            let editor = match var(EDITOR) {
-                Ok(v) => v,
+        Some(("snapshots", sub)) => {
+            let root = Path::new(".");
+            if let Some(&keep) = sub.get_one::<usize>("keep") {
+                return match tree::prune_snapshots(root, &author, keep) {
+                    Ok(gone) => {
+                        println!("{} snapshot(s) removed.", gone.len());
+                        ExitCode::SUCCESS
+                    }
+                    Err(e) => {
+                        eprintln!("snapshots failed: {e}");
+                        ExitCode::FAILURE
+                    }
+                };
+            }
+            let listed =
+                tree::snapshots(root, &author).and_then(|ids| Ok((ids, author_events(&author)?)));
+            match listed {
+                Ok((ids, _)) if ids.is_empty() => {
+                    println!("No snapshots kept (see keep-snapshots in [storage]).");
+                }
+                Ok((ids, events)) => {
+                    for id in ids {
+                        let v: serde_json::Value = commit::find(&events, id)
+                            .and_then(|ev| serde_json::from_str(&ev.noumenon).ok())
+                            .unwrap_or_default();
+                        let summary = v.get("summary").and_then(|x| x.as_str()).unwrap_or("");
+                        let dir = tree::snapshot_dir(Path::new(""), &author, id);
+                        println!("#{id} {summary}  {}", dir.display());
+                    }
+                }
                 Err(e) => {
-                    eprintln!("Missing {EDITOR}: {e}");
+                    eprintln!("snapshots failed: {e}");
                     return ExitCode::FAILURE;
                 }
-            };
+            }
+            ExitCode::SUCCESS
+        }
 
-            // Commit type (interactive fallback).
-            let ty = if let Some(t) = sub.get_one::<String>("type") {
-                t.to_owned()
-            } else {
-                let types = ["feat", "fix", "refactor", "docs", "test", "chore"];
-                Select::new("type:", types.to_vec())
-                    .prompt()
-                    .expect("type prompt failed")
-                    .to_string()
+        Some(("prune", sub)) => {
+            let root = Path::new(".");
+            let before = sub.get_one::<String>("before").expect("before is required");
+            let current = chrono::Local::now().format("%Y-%m").to_string();
+            if let Err(e) = prune::parse_month(before, &current) {
+                eprintln!("prune failed: {e}");
+                return ExitCode::FAILURE;
+            }
+            let archive_dir = sub
+                .get_one::<String>("archive-dir")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| root.join(".eikyu").join("archive"));
+            let compress = sub.get_flag("compress");
+            let plan = match prune::prune(root, before, &archive_dir, compress, true) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    eprintln!("prune failed: {e}");
+                    return ExitCode::FAILURE;
+                }
             };
-
-            // Commit summary (interactive fallback).
-            let summary = if let Some(s) = sub.get_one::<String>("summary") {
-                s.to_owned()
-            } else {
-                Text::new("summary:")
+            if plan.is_empty() {
+                println!("Nothing to prune before {before}.");
+                return ExitCode::SUCCESS;
+            }
+            print_prune(&plan, true);
+            if sub.get_flag("dry-run") {
+                return ExitCode::SUCCESS;
+            }
+            eprintln!(
+                "{} the pruned cubes are deleted, and commits sealed before {before} can no longer \
+                 be restored from the cubes: their files only remain in the archives. Copies of \
+                 the repository that are not pruned bring the old cubes back on `ak pull`.",
+                "Warning:".yellow()
+            );
+            if !sub.get_flag("yes") {
+                if !std::io::stdin().is_terminal() {
+                    eprintln!("Refusing to prune without confirmation: pass --yes.");
+                    return ExitCode::FAILURE;
+                }
+                let go = Confirm::new("Prune these cubes?")
+                    .with_default(false)
                     .prompt()
-                    .expect("summary prompt failed")
-            };
+                    .expect("confirm prompt failed");
+                if !go {
+                    println!("Nothing pruned.");
+                    return ExitCode::SUCCESS;
+                }
+            }
+            match prune::prune(root, before, &archive_dir, compress, false) {
+                Ok(reports) => {
+                    print_prune(&reports, false);
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("prune failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
 
-            // Commit body (interactive editor fallback).
-            let body = if let Some(b) = sub.get_one::<String>("body") {
-                b.to_owned()
+        Some(("daemon", sub)) => {
+            let cube = cube_path_for(&author);
+            let result = if sub.get_flag("stop") {
+                daemon::stop(Path::new(&cube)).map(|stopped| match stopped {
+                    true => println!("Stopped the daemon of {cube}."),
+                    false => println!("No daemon serves {cube}."),
+                })
+            } else if sub.get_flag("status") {
+                daemon::status(Path::new(&cube)).map(|status| match status {
+                    Some(daemon::Response::Status { pid, appended, .. }) => println!(
+                        "Daemon {pid} serves {cube}: {appended} record(s) appended so far."
+                    ),
+                    _ => println!("No daemon serves {cube}."),
+                })
             } else {
-                Editor::new("body:")
-                    .with_editor_command(editor.as_ref())
-                    .prompt()
-                    .expect("body prompt failed")
+                println!(
+                    "Serving the appends to {cube} on {} (ak daemon --stop to stop).",
+                    daemon::socket_path(Path::new(&cube)).display()
+                );
+                daemon::serve(&cube)
             };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("daemon failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
 
-            // Render the user-facing commit message and reserve an id via a "commit:pending" event.
-            let commit_message = COMMIT_TEMPLATE
-                .replace("%type%", &ty)
-                .replace("%summary%", &summary)
-                .replace("%body%", &body)
-                .replace("%author%", &author)
-                .replace("%author_email%", &author_email);
+        Some(("stats", sub)) => match stats::compute(Path::new("."), &author) {
+            Ok(st) if sub.get_flag("json") => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&st).expect("serialize statistics")
+                );
+                ExitCode::SUCCESS
+            }
+            Ok(st) => {
+                print_stats(
+                    &st,
+                    *sub.get_one::<usize>("top").expect("top has a default"),
+                );
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("stats failed: {e}");
+                ExitCode::FAILURE
+            }
+        },
 
-            let cube = cube_path_for(&author);
-            let parent = last_commit_id(&cube).expect("read last commit failed");
-
-            // Reserve an id by appending a pending record, then read it back to obtain the assigned id.
-            let placeholder_off = save_string_in_cube(&cube, "commit:pending", &commit_message)
-                .expect("failed to reserve commit id");
-            let pending_event =
-                Writer::read_one_at(&cube, placeholder_off).expect("failed to read back pending");
-            let assigned_id = pending_event.id;
-
-            // Durable commit record (wire format).
-            let record = CommitRecord {
-                id: assigned_id,
-                parent,
-                ty: &ty,
-                summary: &summary,
-                body: &body,
-                author: &author,
-                author_email: &author_email,
-                // Convert internal nanoseconds to milliseconds (bounded).
-                timestamp: u64::try_from(pending_event.timestamp / 1_000_000).unwrap_or(0),
+        Some(("stash", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let result = match sub.subcommand() {
+                Some(("list", _)) => author_events(&author).map(|events| {
+                    for (id, s) in stash::stashes(&events).iter().rev() {
+                        println!(
+                            "#{id} on {}: {} ({} file(s))",
+                            s.branch,
+                            s.message,
+                            s.files.len()
+                        );
+                    }
+                }),
+                Some(("pop", pop)) => stash_pop(&root, &author, pop.get_one::<u64>("id").copied()),
+                Some(("drop", drop)) => {
+                    let id = *drop.get_one::<u64>("id").expect("id is required");
+                    author_events(&author).and_then(|events| {
+                        if !stash::stashes(&events).iter().any(|(s, _)| *s == id) {
+                            return Err(Error::other(format!("no stash #{id}")));
+                        }
+                        stash::drop(&mut Writer::create(&cube_path_for(&author))?, id)
+                            .map(|()| println!("Dropped stash #{id}."))
+                    })
+                }
+                _ => stash_push(&root, &author, sub.get_one::<String>("message")),
             };
-            let json = serde_json::to_string_pretty(&record).expect("serialize commit failed");
-
-            save_string_in_cube(&cube, "commit", &json).expect("failed to save commit record");
-
-            // Refresh the on-disk reference tree to match the sealed state.
-            match tree::update_tree(&author) {
-                Ok(_) => println!("Reference tree updated successfully."),
-                Err(e) => eprintln!("Error updating reference tree: {}", e),
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("stash failed: {e}");
+                    ExitCode::FAILURE
+                }
             }
+        }
 
-            println!(
-                "Sealed: {} {} (id={} parent={})",
-                ty,
-                summary,
-                assigned_id,
-                parent
-                    .map(|p| p.to_string())
-                    .unwrap_or_else(|| "none".to_string())
-            );
-            ExitCode::SUCCESS
+        Some(("worktree", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            match worktrees(&root, &author, sub) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("worktree failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
         }
 
-        Some(("timeline", sub)) => {
-            let cube = cube_path_for(&author);
-            let show_utc = sub.get_flag("utc");
-            let show_iso = sub.get_flag("iso");
-            let commits = read_commits_from_cube(&cube).expect("read commits failed");
-            if commits.is_empty() {
-                println!("No commits.");
-                return ExitCode::SUCCESS;
+        Some(("switch", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let name = sub.get_one::<String>("name").expect("name is required");
+            let events = author_events(&author).expect("read cubes failed");
+            let result = match worktree::checked_out_elsewhere(&root, &author, name) {
+                Ok(Some(dir)) => Err(Error::other(format!(
+                    "'{name}' is checked out in {}",
+                    dir.display()
+                ))),
+                Ok(None) => head_state(&root, &author).and_then(|before| {
+                    branch::switch(&root, &author, name, &events)?;
+                    let message = format!("{} -> {name}", before.branch);
+                    log_op(
+                        &author,
+                        "switch",
+                        before,
+                        head_state(&root, &author)?,
+                        message,
+                    )
+                }),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    println!("Switched to branch {name}.");
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("switch failed: {e}");
+                    ExitCode::FAILURE
+                }
             }
+        }
 
-            for ev in commits {
-                // Parse the commit JSON payload; tolerate errors by skipping malformed entries.
-                match serde_json::from_str::<serde_json::Value>(&ev.noumenon) {
-                    Ok(v) => {
-                        let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(ev.id);
+        Some(("archive", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let out = sub.get_one::<String>("out").expect("out is required");
+            let spec = sub.get_one::<String>("commit").map(String::as_str);
+            match write_archive(&root, &author, spec, Path::new(out)) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("archive failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
 
-                        // Robust timestamp handling: accept number or string; allow ns or ms.
-                        let ts_raw_u128: Option<u128> = match v.get("timestamp") {
-                            Some(serde_json::Value::Number(n)) => n.as_u64().map(|u| u as u128),
-                            Some(serde_json::Value::String(s)) => s.parse::<u128>().ok(),
-                            _ => None,
-                        };
-
-                        let ty = v
-                            .get("ty")
-                            .and_then(|x| x.as_str())
-                            .unwrap_or("commit")
-                            .to_string();
-                        let summary = v
-                            .get("summary")
-                            .and_then(|x| x.as_str())
-                            .unwrap_or("")
-                            .to_string();
-
-                        // Normalize to milliseconds and format according to flags.
-                        let when = if let Some(ts_raw) = ts_raw_u128 {
-                            let mut ts_ms_i128: i128 = ts_raw as i128;
-                            // Heuristic: treat very large values as nanoseconds and convert to ms.
-                            if ts_ms_i128 > 1_000_000_000_000_000_i128 {
-                                ts_ms_i128 /= 1_000_000;
-                            }
-                            if let Ok(ts_ms) = i64::try_from(ts_ms_i128) {
-                                if let Some(naive) = DateTime::from_timestamp_millis(ts_ms) {
-                                    if show_utc {
-                                        if show_iso {
-                                            naive.to_rfc3339()
-                                        } else {
-                                            naive.format("%Y-%m-%d %H:%M:%S UTC").to_string()
-                                        }
-                                    } else {
-                                        let local = naive.with_timezone(&chrono::Local);
-                                        if show_iso {
-                                            local.to_rfc3339()
-                                        } else {
-                                            local.format("%Y-%m-%d %H:%M:%S").to_string()
-                                        }
-                                    }
-                                } else {
-                                    "-".to_string()
-                                }
-                            } else {
-                                "-".to_string()
-                            }
-                        } else {
-                            "-".to_string()
-                        };
+        Some(("materialize", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let result = author_events(&author).and_then(|events| {
+                let id = match sub.get_one::<String>("commit") {
+                    Some(spec) => resolve_commit(&events, spec)?,
+                    None => branch_head(&author, &branch::current(&root, &author))?
+                        .ok_or_else(|| Error::other("nothing sealed on this branch yet"))?,
+                };
+                let into = match sub.get_one::<String>("into") {
+                    Some(dir) => root.join(dir),
+                    None => std::env::temp_dir()
+                        .join(format!("akasha-materialize-{id}-{}", std::process::id())),
+                };
+                let files = restore::materialize(&events, id, &into)?;
+                // Only the directory goes to standard output, for `cd "$(ak materialize …)"`.
+                eprintln!("Materialized #{id} ({} file(s)).", files.len());
+                println!("{}", into.display());
+                Ok(())
+            });
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("materialize failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
 
-                        println!("#{id} [{ty}] {summary} @ {when}");
+        Some(("restore", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let spec = sub.get_one::<String>("commit").expect("commit is required");
+            let into = sub
+                .get_one::<String>("into")
+                .map(|d| root.join(d))
+                .unwrap_or_else(|| root.clone());
+            let mut filters = Vec::new();
+            let mut everything = false;
+            for p in sub.get_many::<String>("paths").into_iter().flatten() {
+                match paths::normalize(&root, &root.join(p)) {
+                    Some(f) => filters.push(f),
+                    // The repository root itself: restore everything.
+                    None if root.join(p).canonicalize().ok() == root.canonicalize().ok() => {
+                        everything = true;
                     }
-                    Err(e) => {
-                        eprintln!("warning: failed to parse commit #{}, reason: {e}", ev.id);
-                        // Skip malformed entries but keep showing the rest of the timeline.
+                    None => {
+                        eprintln!("{p} is outside the repository");
+                        return ExitCode::FAILURE;
                     }
                 }
             }
-            ExitCode::SUCCESS
-        }
-
-        Some(("view", _)) => {
-            let cube = cube_path_for(&author);
-            let commits = read_commits_from_cube(&cube).expect("read commits failed");
-            if let Some(ev) = commits.last() {
-                match serde_json::from_str::<serde_json::Value>(&ev.noumenon) {
-                    Ok(v) => {
-                        let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(ev.id);
-                        let ty = v.get("ty").and_then(|x| x.as_str()).unwrap_or("commit");
-                        let summary = v.get("summary").and_then(|x| x.as_str()).unwrap_or("");
-                        println!("#{id} [{ty}] {summary}");
-                        ExitCode::SUCCESS
+            if everything {
+                filters.clear();
+            }
+            let events = author_events(&author).expect("read cubes failed");
+            let id = match resolve_commit(&events, spec) {
+                Ok(id) => id,
+                Err(e) => {
+                    eprintln!("restore failed: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            // Paths name files as they are now: follow renames since `id` along the branch.
+            let head = branch::heads(&events)
+                .get(&branch::current(&root, &author))
+                .copied()
+                .flatten();
+            let renames = head
+                .map(|head| rename::between(&events, id, head))
+                .unwrap_or_default();
+            match restore::restore_renamed(&events, id, &filters, &renames, &into) {
+                Ok(restored) => {
+                    let message = format!("{} file(s) from #{id}", restored.len());
+                    let logged = head_state(&root, &author).and_then(|state| {
+                        log_op(&author, "restore", state.clone(), state, message)
+                    });
+                    if let Err(e) = logged {
+                        eprintln!("warning: reflog not updated: {e}");
                     }
-                    Err(_) => {
-                        println!("#{} [commit]", ev.id);
-                        ExitCode::FAILURE
+                    for path in &restored {
+                        println!("restored {path}");
                     }
+                    println!("{} file(s) restored from #{id}.", restored.len());
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("restore failed: {e}");
+                    ExitCode::FAILURE
                 }
-            } else {
-                println!("No commits.");
-                ExitCode::SUCCESS
             }
         }
 
-        // Show changes between working directory and the last sealed reference tree.
-        Some(("diff", _)) => diff::diff(),
+        Some(("status", sub)) => {
+            let root = std::env::current_dir().expect("Failed to get current directory");
+            let found = tree::Reference::open(&root, &author)
+                .and_then(|reference| Ok((status::status(&root, &reference)?, reference)));
+            let (mut st, reference) = match found {
+                Ok(found) => found,
+                Err(e) => {
+                    eprintln!("Failed to compute status: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let idx = Index::load(&root, &author).unwrap_or_else(|e| {
+                eprintln!("warning: {e}");
+                Index::default()
+            });
+            st.pair_renames(&idx.renames);
+            if let Err(e) = st.detect_renames(&root, &reference) {
+                eprintln!("warning: rename detection failed: {e}");
+            }
+            let porcelain = sub.get_flag("porcelain");
+            if !porcelain && !idx.is_empty() {
+                println!("Staged for the next seal:");
+                for (to, from) in &idx.renames {
+                    println!("  {} {from} -> {to}", "R".cyan().bold());
+                }
+                let renamed = |p: &String| {
+                    idx.renames.contains_key(p) || idx.renames.values().any(|f| f == p)
+                };
+                for (path, content) in idx.entries.iter().filter(|(p, _)| !renamed(p)) {
+                    let code = if content.is_some() { "+" } else { "-" };
+                    println!("  {} {path}", code.cyan().bold());
+                }
+                println!();
+            }
+            print_status(&st, sub.get_flag("untracked"), porcelain);
+            ExitCode::SUCCESS
+        }
 
         _ => {
             println!("unknown command");
@@ -0,0 +1,177 @@
+//! `.akignore`: paths `ak` leaves alone.
+//!
+//! `.akignore` files use the gitignore syntax, including negation (`!keep.log` re-includes a
+//! path an earlier pattern excluded) and anchoring (`/build/` only matches the `build`
+//! directory next to the `.akignore`, `build/` matches one at any depth). A file applies to its
+//! directory and everything below it; deeper files take precedence over shallower ones.
+//!
+//! They come on top of `.ignore` and, inside a git repository, `.gitignore`: in the same
+//! directory `.akignore` wins over `.ignore`, which wins over `.gitignore`. Hidden files and
+//! directories are always skipped, and `walker` never enters `.eikyu` and `.git` (see `INTERNAL`)
+//! even when a caller shows hidden files. Every command enumerating the working directory (`inscribe`,
+//! `diff`, `status` and the reference tree refresh) walks it through `walker`, and `check`
+//! explains the decision for one path (`ak check-ignore`).
+
+use ignore::WalkBuilder;
+use ignore::gitignore::Gitignore;
+use std::io;
+use std::path::{Component, Path};
+
+/// Name of `ak`'s own ignore files.
+pub const AKIGNORE: &str = ".akignore";
+
+/// Directories holding the state of `ak` (cubes, reference trees, snapshots) and Git.
+pub const INTERNAL: [&str; 2] = [".eikyu", ".git"];
+
+/// Walker over `root` applying `ak`'s ignore rules, never entering an `INTERNAL` directory below
+/// `root`.
+pub fn walker(root: &Path) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .add_custom_ignore_filename(".ignore")
+        .add_custom_ignore_filename(AKIGNORE)
+        .filter_entry(|e| e.depth() == 0 || !INTERNAL.iter().any(|d| e.file_name() == *d));
+    builder
+}
+
+/// Why `check` keeps or skips a path.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    /// Excluded by `pattern` from ignore file `source`.
+    Ignored { source: String, pattern: String },
+    /// Re-included by the negated `pattern` from `source`.
+    Included { source: String, pattern: String },
+    /// A hidden file or directory.
+    Hidden,
+    /// No rule applies.
+    NotIgnored,
+}
+
+/// Explain whether `path` (relative to `root`) is ignored, and by which rule.
+pub fn check(root: &Path, path: &Path) -> io::Result<Verdict> {
+    let mut dirs = vec![root.to_path_buf()];
+    let mut components = path.components().peekable();
+    while let Some(c) = components.next() {
+        let Component::Normal(name) = c else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: expected a path inside the repository", path.display()),
+            ));
+        };
+        if name.to_string_lossy().starts_with('.') {
+            return Ok(Verdict::Hidden);
+        }
+        if components.peek().is_some() {
+            let below = dirs.last().expect("starts with root").join(name);
+            dirs.push(below);
+        }
+    }
+
+    let target = root.join(path);
+    let is_dir = target.is_dir();
+    let in_git = root.ancestors().any(|d| d.join(".git").exists());
+    let mut names = vec![".ignore", AKIGNORE];
+    if in_git {
+        names.insert(0, ".gitignore");
+    }
+    // Later (deeper, then higher precedence) matches override earlier ones.
+    let mut verdict = Verdict::NotIgnored;
+    for dir in &dirs {
+        for name in &names {
+            let file = dir.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            let (rules, err) = Gitignore::new(&file);
+            if let Some(err) = err {
+                eprintln!("warning: {}: {err}", file.display());
+            }
+            let m = rules.matched_path_or_any_parents(&target, is_dir);
+            if let Some(glob) = m.inner() {
+                let source = file
+                    .strip_prefix(root)
+                    .unwrap_or(&file)
+                    .display()
+                    .to_string();
+                let pattern = glob.original().to_string();
+                verdict = if m.is_whitelist() {
+                    Verdict::Included { source, pattern }
+                } else {
+                    Verdict::Ignored { source, pattern }
+                };
+            }
+        }
+    }
+    Ok(verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_negation_and_anchoring() {
+        let root = std::env::temp_dir().join(format!("akasha-akignore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::create_dir_all(root.join("src").join("build")).unwrap();
+        fs::write(root.join(AKIGNORE), "*.log\n!keep.log\n/build/\n").unwrap();
+        for f in [
+            "a.log",
+            "keep.log",
+            "a.txt",
+            "build/out",
+            "src/build/gen.rs",
+        ] {
+            fs::write(root.join(f), "x").unwrap();
+        }
+
+        let mut seen: Vec<String> = walker(&root)
+            .build()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+            .map(|e| {
+                let rel = e.path().strip_prefix(&root).unwrap();
+                rel.to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+        seen.sort();
+        assert_eq!(seen, ["a.txt", "keep.log", "src/build/gen.rs"]);
+
+        assert_eq!(
+            check(&root, Path::new("a.log")).unwrap(),
+            Verdict::Ignored {
+                source: AKIGNORE.into(),
+                pattern: "*.log".into()
+            }
+        );
+        assert!(matches!(
+            check(&root, Path::new("keep.log")).unwrap(),
+            Verdict::Included { .. }
+        ));
+        assert!(matches!(
+            check(&root, &Path::new("build").join("out")).unwrap(),
+            Verdict::Ignored { .. }
+        ));
+        assert_eq!(
+            check(&root, &Path::new("src").join("build")).unwrap(),
+            Verdict::NotIgnored
+        );
+        assert_eq!(check(&root, Path::new(".env")).unwrap(), Verdict::Hidden);
+
+        // Showing hidden files still leaves the internal state out.
+        fs::create_dir_all(root.join(".eikyu").join("tree")).unwrap();
+        fs::write(root.join(".eikyu").join("tree").join("a.txt"), "x").unwrap();
+        fs::write(root.join(".env"), "x").unwrap();
+        let hidden: Vec<_> = walker(&root)
+            .hidden(false)
+            .build()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(hidden.contains(&".env".to_string()));
+        assert!(!hidden.iter().any(|n| n == ".eikyu" || n == "tree"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Release archives: the files of a seal as a `.tar.gz` or `.zip`, the engine behind
+//! `ak archive`.
+//!
+//! Entries are regular files (mode 0644) in path order, dated with the seal's timestamp, so the
+//! same seal always gives the same archive. Repository metadata (`.eikyu`) is never included.
+
+use chrono::{DateTime, Datelike, Timelike};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Kind of archive, chosen by the extension of the output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    /// Format of an archive named `path`: `.tar.gz` or `.tgz`, or `.zip`.
+    pub fn of(path: &Path) -> io::Result<Format> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Format::TarGz)
+        } else if name.ends_with(".zip") {
+            Ok(Format::Zip)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unknown archive format, use .tar.gz, .tgz or .zip",
+                    path.display()
+                ),
+            ))
+        }
+    }
+}
+
+/// Whether portable `path` belongs to the repository metadata.
+fn is_metadata(path: &str) -> bool {
+    path == ".eikyu" || path.starts_with(".eikyu/")
+}
+
+/// Write `files` (portable path to content) to the archive `out`, dated `timestamp` (milliseconds
+/// since the epoch). Returns the number of files written.
+pub fn write(
+    files: &BTreeMap<String, &str>,
+    timestamp: i64,
+    out: &Path,
+    format: Format,
+) -> io::Result<usize> {
+    let files: Vec<(&String, &&str)> = files.iter().filter(|(p, _)| !is_metadata(p)).collect();
+    let file = File::create(out)?;
+    match format {
+        Format::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            for (path, content) in &files {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(content.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(timestamp.max(0) as u64 / 1000);
+                tar.append_data(&mut header, path.as_str(), content.as_bytes())?;
+            }
+            tar.into_inner()?.finish()?.sync_all()?;
+        }
+        Format::Zip => {
+            // Zip dates have no time zone (UTC here) and span 1980 to 2107; others keep 1980.
+            let date = DateTime::from_timestamp_millis(timestamp)
+                .map(|t| t.naive_utc())
+                .and_then(|t| {
+                    zip::DateTime::from_date_and_time(
+                        u16::try_from(t.year()).ok()?,
+                        t.month() as u8,
+                        t.day() as u8,
+                        t.hour() as u8,
+                        t.minute() as u8,
+                        t.second() as u8,
+                    )
+                    .ok()
+                })
+                .unwrap_or_default();
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .last_modified_time(date)
+                .unix_permissions(0o644);
+            let mut zip = zip::ZipWriter::new(file);
+            for (path, content) in &files {
+                zip.start_file(path.as_str(), options)
+                    .map_err(io::Error::other)?;
+                zip.write_all(content.as_bytes())?;
+            }
+            zip.finish().map_err(io::Error::other)?.sync_all()?;
+        }
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_write_tar_gz_and_zip() {
+        let dir = std::env::temp_dir().join(format!("akasha-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = BTreeMap::from([
+            ("src/main.rs".to_string(), "fn main() {}\n"),
+            ("README".to_string(), "hi"),
+            (".eikyu/config.toml".to_string(), "secret"),
+        ]);
+        let timestamp = 1_700_000_000_000;
+
+        let tgz = dir.join("out.tar.gz");
+        assert_eq!(Format::of(&tgz).unwrap(), Format::TarGz);
+        assert_eq!(write(&files, timestamp, &tgz, Format::TarGz).unwrap(), 2);
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&tgz).unwrap()));
+        let mut read = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            assert_eq!(entry.header().mtime().unwrap(), 1_700_000_000);
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            read.push((path, content));
+        }
+        assert_eq!(
+            read,
+            [
+                ("README".to_string(), "hi".to_string()),
+                ("src/main.rs".to_string(), "fn main() {}\n".to_string()),
+            ]
+        );
+
+        let zip_path = dir.join("out.zip");
+        assert_eq!(Format::of(&zip_path).unwrap(), Format::Zip);
+        assert_eq!(write(&files, timestamp, &zip_path, Format::Zip).unwrap(), 2);
+        let mut zip = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(zip.len(), 2);
+        let mut content = String::new();
+        zip.by_name("src/main.rs")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "fn main() {}\n");
+
+        assert!(Format::of(Path::new("out.rar")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
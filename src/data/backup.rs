@@ -0,0 +1,247 @@
+//! Cube backups: verified point-in-time snapshots with a bounded number of generations.
+//!
+//! A snapshot is taken under a shared file lock, so it never contains a half-written record even
+//! while other processes append (appends take the exclusive lock). Defaults can be configured per
+//! cube through a JSON sidecar named `<cube>.backup.json`:
+//!
+//! ```json
+//! { "to": "/mnt/backups/akasha", "keep": 14 }
+//! ```
+//!
+//! Destinations are either a directory or an `s3://bucket/prefix` URL; S3 transfers go through the
+//! `aws` command line tool, which must be installed and configured. Snapshots are named
+//! `<stem>-YYYYMMDDTHHMMSS.cube`, so lexical order is chronological order.
+
+use crate::data::paths;
+use crate::data::write::Writer;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Generations kept when nothing else is configured.
+pub const DEFAULT_KEEP: usize = 7;
+
+/// Backup settings for a cube.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    /// Directory or `s3://` URL; defaults to `backups/` next to the cube.
+    pub to: Option<String>,
+    /// Number of snapshots retained at the destination (at least 1).
+    pub keep: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            to: None,
+            keep: DEFAULT_KEEP,
+        }
+    }
+}
+
+/// Outcome of a backup.
+#[derive(Debug)]
+pub struct Report {
+    /// Where the snapshot was stored (path or URL).
+    pub location: String,
+    pub bytes: u64,
+    pub records: usize,
+    /// Older snapshots removed to honor `keep`.
+    pub pruned: Vec<String>,
+}
+
+/// Load the backup settings configured for the cube at `cube_path`, or the defaults.
+pub fn load_for_cube(cube_path: &str) -> io::Result<Options> {
+    let config_path = format!("{cube_path}.backup.json");
+    let Ok(raw) = fs::read_to_string(&config_path) else {
+        return Ok(Options::default());
+    };
+    serde_json::from_str(&raw)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{config_path}: {e}")))
+}
+
+/// Snapshot the cube at `cube`, verify the copy, store it at the configured destination and prune
+/// old generations. `now` names the snapshot.
+pub fn backup(
+    cube: &str,
+    opts: &Options,
+    now: chrono::DateTime<chrono::Local>,
+) -> io::Result<Report> {
+    let path = Path::new(cube);
+    let bytes = snapshot(path)?;
+    let records = Writer::decode(&bytes)?.len();
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("cube");
+    let name = format!("{stem}-{}.cube", now.format("%Y%m%dT%H%M%S"));
+    let keep = opts.keep.max(1);
+
+    let dest = opts.to.clone().unwrap_or_else(|| {
+        path.parent()
+            .unwrap_or(Path::new("."))
+            .join("backups")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let (location, pruned) = match dest.strip_prefix("s3://") {
+        Some(_) => {
+            let staging = std::env::temp_dir().join(&name);
+            store(&staging, &bytes)?;
+            let url = format!("{}/{name}", dest.trim_end_matches('/'));
+            let uploaded = aws(&["s3", "cp", &staging.to_string_lossy(), &url]);
+            let _ = fs::remove_file(&staging);
+            uploaded?;
+            (url, prune_s3(&dest, stem, keep)?)
+        }
+        None => {
+            let dir = PathBuf::from(&dest);
+            fs::create_dir_all(&dir)?;
+            let target = dir.join(&name);
+            store(&target, &bytes)?;
+            (
+                target.to_string_lossy().into_owned(),
+                prune_dir(&dir, stem, keep)?,
+            )
+        }
+    };
+    Ok(Report {
+        location,
+        bytes: bytes.len() as u64,
+        records,
+        pruned,
+    })
+}
+
+/// Read the whole cube under a shared lock, so that no record is half-written.
+pub fn snapshot(path: &Path) -> io::Result<Vec<u8>> {
+    let f = File::open(path)?;
+    f.lock_shared()?;
+    let bytes = fs::read(path);
+    f.unlock()?;
+    bytes
+}
+
+/// Write `bytes` to `target` through a temporary file, then read it back and compare.
+fn store(target: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp = target.as_os_str().to_owned();
+    tmp.push(".partial");
+    let tmp = PathBuf::from(tmp);
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+    }
+    let copy = fs::read(&tmp)?;
+    if blake3::hash(&copy) != blake3::hash(bytes) || Writer::decode(&copy).is_err() {
+        let _ = fs::remove_file(&tmp);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("verification of {} failed", target.display()),
+        ));
+    }
+    fs::rename(&tmp, target)?;
+    paths::sync_dir(target.parent().unwrap_or(Path::new(".")))
+}
+
+/// Whether `file` is a snapshot of the cube named `stem`.
+fn is_generation(file: &str, stem: &str) -> bool {
+    file.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.strip_suffix(".cube"))
+        .is_some_and(|ts| ts.len() == 15 && ts.as_bytes()[8] == b'T')
+}
+
+/// Names to delete so that only the newest `keep` of `names` remain.
+fn expired(mut names: Vec<String>, keep: usize) -> Vec<String> {
+    names.sort();
+    let excess = names.len().saturating_sub(keep);
+    names.truncate(excess);
+    names
+}
+
+fn prune_dir(dir: &Path, stem: &str, keep: usize) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if is_generation(&name, stem) {
+            names.push(name);
+        }
+    }
+    let old = expired(names, keep);
+    for name in &old {
+        fs::remove_file(dir.join(name))?;
+    }
+    Ok(old)
+}
+
+fn prune_s3(dest: &str, stem: &str, keep: usize) -> io::Result<Vec<String>> {
+    let prefix = format!("{}/", dest.trim_end_matches('/'));
+    let listing = aws(&["s3", "ls", &prefix])?;
+    // Lines look like "2025-08-01 12:00:00      1234 name.cube".
+    let names = listing
+        .lines()
+        .filter_map(|l| l.split_whitespace().last())
+        .filter(|n| is_generation(n, stem))
+        .map(str::to_string)
+        .collect();
+    let old = expired(names, keep);
+    for name in &old {
+        aws(&["s3", "rm", &format!("{prefix}{name}")])?;
+    }
+    Ok(old.into_iter().map(|n| format!("{prefix}{n}")).collect())
+}
+
+/// Run the `aws` CLI and return its stdout.
+fn aws(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("aws")
+        .args(args)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run `aws` for S3 backups: {e}")))?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!(
+            "aws {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_backup_keeps_generations() {
+        let dir = std::env::temp_dir().join(format!("akasha-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("notes.cube");
+        let cube = cube.to_str().unwrap();
+        Writer::create(cube).unwrap().append("a", "1").unwrap();
+
+        let opts = Options {
+            to: Some(dir.join("bk").to_string_lossy().into_owned()),
+            keep: 2,
+        };
+        for sec in 0..3 {
+            let now = chrono::Local
+                .with_ymd_and_hms(2025, 8, 1, 12, 0, sec)
+                .unwrap();
+            let report = backup(cube, &opts, now).unwrap();
+            assert_eq!(report.records, 1);
+        }
+        let mut left: Vec<_> = fs::read_dir(dir.join("bk"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            ["notes-20250801T120001.cube", "notes-20250801T120002.cube"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
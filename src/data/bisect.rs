@@ -0,0 +1,176 @@
+//! Bisection: the search behind `ak bisect` for the first bad commit.
+//!
+//! The candidates are the commits reachable from the bad commit through first parents, down to
+//! (excluding) anything reachable from a good commit. Every step tests the candidate halfway
+//! along that line until a single commit is left: the first bad one. Commits that can't be
+//! tested are skipped, which may leave several candidates.
+//!
+//! The session is kept in `.eikyu/bisect/<author>.json` (`Bisect`) until `ak bisect reset`;
+//! candidates are checked out into a worktree outside the repository, so the working directory
+//! is never touched.
+
+use crate::data::merge;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
+
+/// A bisection session.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Bisect {
+    /// Known bad commit, the newest end of the search.
+    pub bad: Option<u64>,
+    /// Known good commits.
+    pub good: Vec<u64>,
+    /// Commits that could not be tested.
+    pub skipped: Vec<u64>,
+    /// Where candidates are checked out.
+    pub worktree: PathBuf,
+    /// Candidate checked out in the worktree.
+    pub current: Option<u64>,
+}
+
+/// Where a search stands.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// A bad and a good commit are needed first.
+    NeedMarks,
+    /// Test this commit next.
+    Test(u64),
+    /// The first bad commit.
+    Found(u64),
+    /// Only skipped commits are left: the first bad one is among these.
+    Undecided(Vec<u64>),
+}
+
+/// Location of `author`'s bisection under `root`.
+pub fn state_path(root: &Path, author: &str) -> PathBuf {
+    root.join(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}bisect{MAIN_SEPARATOR_STR}{author}.json"
+    ))
+}
+
+impl Bisect {
+    /// `author`'s bisection in progress, if any.
+    pub fn load(root: &Path, author: &str) -> io::Result<Option<Self>> {
+        let path = state_path(root, author);
+        match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map(Some).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {e}", path.display()),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remember the bisection.
+    pub fn save(&self, root: &Path, author: &str) -> io::Result<()> {
+        let path = state_path(root, author);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )
+    }
+
+    /// End `author`'s bisection.
+    pub fn clear(root: &Path, author: &str) -> io::Result<()> {
+        match fs::remove_file(state_path(root, author)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Commits that may still be the first bad one, newest (the bad commit) first.
+    pub fn candidates(&self, events: &[Event]) -> Vec<u64> {
+        let Some(bad) = self.bad else {
+            return Vec::new();
+        };
+        let good: HashSet<u64> = self
+            .good
+            .iter()
+            .flat_map(|&g| merge::ancestors(events, g))
+            .collect();
+        let mut out = Vec::new();
+        let mut at = Some(bad);
+        while let Some(c) = at.filter(|c| !good.contains(c)) {
+            out.push(c);
+            at = merge::parents(events, c).first().copied();
+        }
+        out
+    }
+
+    /// The next step of the search.
+    pub fn next(&self, events: &[Event]) -> Step {
+        if self.bad.is_none() || self.good.is_empty() {
+            return Step::NeedMarks;
+        }
+        let candidates = self.candidates(events);
+        let testable: Vec<u64> = candidates
+            .iter()
+            .skip(1)
+            .copied()
+            .filter(|c| !self.skipped.contains(c))
+            .collect();
+        match (testable.is_empty(), candidates.len()) {
+            (false, _) => Step::Test(testable[testable.len() / 2]),
+            (true, 1) => Step::Found(candidates[0]),
+            (true, _) => Step::Undecided(candidates),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bisect_finds_first_bad_commit() {
+        // A line of ten commits: 1 <- 2 <- ... <- 10; #7 is the first bad one.
+        let events: Vec<Event> = (1..=10)
+            .map(|id: u64| {
+                let noumenon = match id {
+                    1 => r#"{"id":1}"#.to_string(),
+                    _ => format!(r#"{{"id":{id},"parent":{}}}"#, id - 1),
+                };
+                Event::builder()
+                    .id(id)
+                    .phenomenon("commit")
+                    .noumenon(noumenon)
+                    .timestamp(0)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut b = Bisect {
+            bad: Some(10),
+            ..Bisect::default()
+        };
+        assert_eq!(b.next(&events), Step::NeedMarks);
+        b.good.push(1);
+        assert_eq!(b.candidates(&events), (2..=10).rev().collect::<Vec<_>>());
+        let mut tests = 0;
+        let found = loop {
+            match b.next(&events) {
+                Step::Test(c) if c >= 7 => b.bad = Some(c),
+                Step::Test(c) => b.good.push(c),
+                Step::Found(c) => break c,
+                other => panic!("{other:?}"),
+            }
+            tests += 1;
+        };
+        assert_eq!(found, 7);
+        assert!(tests <= 4);
+
+        b.skipped.push(6);
+        b.good = vec![5];
+        assert_eq!(b.next(&events), Step::Undecided(vec![7, 6]));
+    }
+}
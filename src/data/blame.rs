@@ -0,0 +1,47 @@
+//! Line provenance: the engine behind `ak blame`.
+//!
+//! The successive versions of a file are compared line by line with the `diff` machinery of
+//! `ak diff`: lines kept from one version to the next keep their commit, lines added by a
+//! version are attributed to its commit.
+
+/// Attribute every line of the last of `versions` (commit id and content, oldest first) to the
+/// commit that last changed it.
+pub fn blame<'a>(versions: &[(u64, &'a str)]) -> Vec<(u64, &'a str)> {
+    let mut out: Vec<(u64, &str)> = Vec::new();
+    for &(commit, text) in versions {
+        let old: Vec<&str> = out.iter().map(|(_, line)| *line).collect();
+        let new: Vec<&str> = text.lines().collect();
+        let mut kept = out.iter().map(|(c, _)| *c);
+        let mut next = Vec::with_capacity(new.len());
+        for d in diff::slice(&old, &new) {
+            match d {
+                diff::Result::Both(_, line) => {
+                    next.push((kept.next().expect("kept line"), *line));
+                }
+                diff::Result::Left(_) => {
+                    kept.next();
+                }
+                diff::Result::Right(line) => next.push((commit, *line)),
+            }
+        }
+        out = next;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blame_keeps_unchanged_lines() {
+        let versions = [
+            (1, "a\nb\nc\n"),
+            (2, "a\nB\nc\n"),
+            (3, "a\nB\nc\nd\n"),
+            (4, "B\nc\nd\n"),
+        ];
+        assert_eq!(blame(&versions), [(2, "B"), (1, "c"), (3, "d")]);
+        assert!(blame(&[]).is_empty());
+    }
+}
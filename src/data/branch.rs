@@ -0,0 +1,221 @@
+//! Named branches for `ak`.
+//!
+//! The branch an author works on is stored in `.eikyu/branches/<author>` (`main` when absent).
+//! Branch heads live in the cube: every `ak branch` and every seal appends a `branch:<name>`
+//! record whose noumenon is `{"head": <commit id or null>}`, and the latest record of a branch
+//! wins. Cubes written before branches existed have no such record; `main` then points at the
+//! last commit.
+//!
+//! Each branch has its own reference tree (see `tree::branch_tree_dir`), so `status`, `diff` and
+//! `seal` compare against the state sealed on the current branch.
+
+use crate::data::config::Snapshot;
+use crate::data::write::Writer;
+use crate::data::{commit, status, tree};
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
+
+/// Branch used when none was selected.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// Noumenon of a `branch:<name>` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Head {
+    /// Commit id the branch points at, `None` before its first seal.
+    pub head: Option<u64>,
+}
+
+fn current_file(root: &Path, author: &str) -> PathBuf {
+    root.join(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}branches{MAIN_SEPARATOR_STR}{author}"
+    ))
+}
+
+/// Phenomenon of the head records of `name`.
+pub fn phenomenon(name: &str) -> String {
+    format!("branch:{name}")
+}
+
+/// Branch `author` currently works on.
+pub fn current(root: &Path, author: &str) -> String {
+    fs::read_to_string(current_file(root, author))
+        .map(|s| s.trim().to_string())
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| DEFAULT_BRANCH.to_string())
+}
+
+/// Make `name` the branch `author` works on.
+pub fn set_current(root: &Path, author: &str, name: &str) -> io::Result<()> {
+    let path = current_file(root, author);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, name)
+}
+
+/// Reject names that can't be used as a single path component of the reference tree.
+pub fn validate(name: &str) -> io::Result<()> {
+    check_name("branch", name)
+}
+
+/// Shared rule for branch and tag names (`kind` names which one in errors).
+pub(crate) fn check_name(kind: &str, name: &str) -> io::Result<()> {
+    let ok = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if ok {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid {kind} name '{name}' (use letters, digits, '-', '_' and '.')"),
+        ))
+    }
+}
+
+/// Head of every branch found in `events`.
+pub fn heads(events: &[Event]) -> BTreeMap<String, Option<u64>> {
+    let mut out = BTreeMap::new();
+    let mut last_commit = None;
+    for ev in events {
+        if ev.phenomenon == "commit" {
+            last_commit = Some(commit::commit_id(ev));
+        } else if let Some(name) = ev.phenomenon.strip_prefix("branch:")
+            && let Ok(h) = serde_json::from_str::<Head>(&ev.noumenon)
+        {
+            out.insert(name.to_string(), h.head);
+        }
+    }
+    out.entry(DEFAULT_BRANCH.to_string()).or_insert(last_commit);
+    out
+}
+
+/// Record that branch `name` now points at `head`.
+pub fn record_head(w: &mut Writer, name: &str, head: Option<u64>) -> io::Result<u64> {
+    let json = serde_json::to_string(&Head { head }).map_err(io::Error::other)?;
+    w.append(&phenomenon(name), &json)
+}
+
+/// Create branch `name` at the head of the current branch (as recorded in `events`), with a copy
+/// of its reference tree. Returns the new branch's head.
+pub fn create(
+    root: &Path,
+    author: &str,
+    name: &str,
+    events: &[Event],
+    w: &mut Writer,
+) -> io::Result<Option<u64>> {
+    validate(name)?;
+    let heads = heads(events);
+    if heads.contains_key(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("branch '{name}' already exists"),
+        ));
+    }
+    let from = current(root, author);
+    let head = heads.get(&from).copied().flatten();
+    let src = tree::branch_tree_dir(root, author, &from);
+    if src.exists() {
+        tree::copy_dir(
+            &src,
+            &tree::branch_tree_dir(root, author, name),
+            Snapshot::Reflink,
+        )?;
+    }
+    record_head(w, name, head)?;
+    Ok(head)
+}
+
+/// Switch to branch `name`: the working directory is updated from the current branch's sealed
+/// state to `name`'s. Refuses to run with staged or unsealed changes, which would be lost.
+pub fn switch(root: &Path, author: &str, name: &str, events: &[Event]) -> io::Result<()> {
+    if !heads(events).contains_key(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no branch named '{name}'"),
+        ));
+    }
+    status::require_clean(root, author)?;
+    let from = tree::Reference::of_branch(root, author, &current(root, author), events)?;
+    let to = tree::Reference::of_branch(root, author, name, events)?;
+
+    // The working directory matches `from`: drop what `to` doesn't have, then write `to` over.
+    let keep = to.files()?;
+    for path in from.files()? {
+        if !keep.contains(&path) {
+            fs::remove_file(root.join(&path))?;
+        }
+    }
+    for path in &keep {
+        let dest = root.join(path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        to.checkout(path, &dest)?;
+    }
+    set_current(root, author, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heads_and_switch() {
+        let dir = std::env::temp_dir().join(format!("akasha-branch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+
+        // A cube from before branches: main follows the last commit.
+        w.append("commit", r#"{"id":7}"#).unwrap();
+        assert_eq!(heads(&w.events().unwrap())[DEFAULT_BRANCH], Some(7));
+
+        let work = dir.join("work");
+        fs::create_dir_all(&work).unwrap();
+        fs::write(work.join("a.txt"), "a").unwrap();
+        fs::create_dir_all(tree::branch_tree_dir(&work, "u", DEFAULT_BRANCH)).unwrap();
+        fs::write(
+            tree::branch_tree_dir(&work, "u", DEFAULT_BRANCH).join("a.txt"),
+            "a",
+        )
+        .unwrap();
+
+        let events = w.events().unwrap();
+        assert_eq!(
+            create(&work, "u", "topic", &events, &mut w).unwrap(),
+            Some(7)
+        );
+        let events = w.events().unwrap();
+        assert!(create(&work, "u", "topic", &events, &mut w).is_err());
+        assert!(validate("../x").is_err());
+
+        // Diverge `topic`'s reference tree, then switch to it.
+        fs::write(
+            tree::branch_tree_dir(&work, "u", "topic").join("b.txt"),
+            "b",
+        )
+        .unwrap();
+        record_head(&mut w, "topic", Some(9)).unwrap();
+        let events = w.events().unwrap();
+        assert_eq!(heads(&events)["topic"], Some(9));
+        switch(&work, "u", "topic", &events).unwrap();
+        assert_eq!(current(&work, "u"), "topic");
+        assert_eq!(fs::read_to_string(work.join("b.txt")).unwrap(), "b");
+
+        // Back to main: `b.txt` isn't part of it.
+        switch(&work, "u", DEFAULT_BRANCH, &events).unwrap();
+        assert!(!work.join("b.txt").exists());
+        assert!(switch(&work, "u", "nope", &events).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
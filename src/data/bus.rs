@@ -0,0 +1,102 @@
+//! In-process notification of appended events.
+//!
+//! Two levels are available:
+//! - `Writer::subscribe()`: events appended through that particular writer.
+//! - [`subscribe_cube`]: events appended to a cube by *any* writer of this process, keyed by the
+//!   canonical cube path. This is what long-lived services sharing a cube between several writers
+//!   (e.g. the daemon) use to fan out notifications.
+//!
+//! Delivery uses `std::sync::mpsc` channels: receivers never block writers, and dropped receivers
+//! are pruned on the next publish.
+
+use crate::event::Event;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Mutex, OnceLock};
+
+type Registry = Mutex<HashMap<PathBuf, Vec<Sender<Event>>>>;
+
+fn registry() -> &'static Registry {
+    static BUS: OnceLock<Registry> = OnceLock::new();
+    BUS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Normalize a cube path into a registry key.
+///
+/// The parent directory is canonicalized and the file name joined back, so the key is the same
+/// before and after the cube file is created. Falls back to the absolute path when the directory
+/// does not exist either.
+pub fn key_for(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    match (parent.canonicalize(), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+/// Receive every event appended to the cube at `path` by any writer in this process.
+pub fn subscribe_cube<P: AsRef<Path>>(path: P) -> Receiver<Event> {
+    let (tx, rx) = channel();
+    registry()
+        .lock()
+        .expect("event bus poisoned")
+        .entry(key_for(path.as_ref()))
+        .or_default()
+        .push(tx);
+    rx
+}
+
+/// Whether anyone subscribed to the cube identified by `key` (possibly since gone).
+pub fn has_subscribers(key: &Path) -> bool {
+    registry()
+        .lock()
+        .expect("event bus poisoned")
+        .contains_key(key)
+}
+
+/// Deliver `ev` to the process-wide subscribers of the cube identified by `key`.
+pub fn publish(key: &Path, ev: &Event) {
+    let mut reg = registry().lock().expect("event bus poisoned");
+    if let Some(subs) = reg.get_mut(key) {
+        subs.retain(|tx| tx.send(ev.clone()).is_ok());
+        if subs.is_empty() {
+            reg.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_subscribers_receive_appends() {
+        let path = std::env::temp_dir().join(format!("akasha-bus-{}.cube", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let cube = path.to_str().unwrap();
+
+        // Subscribing before the cube exists must still match the writers' key.
+        let shared = subscribe_cube(&path);
+        let mut w = Writer::create(cube).unwrap();
+        let local = w.subscribe();
+        w.append("a.txt", "hello").unwrap();
+
+        let mut other = Writer::create(cube).unwrap();
+        other.append("b.txt", "world").unwrap();
+
+        assert_eq!(local.try_recv().unwrap().phenomenon, "a.txt");
+        assert!(local.try_recv().is_err());
+        let seen: Vec<_> = shared.try_iter().map(|e| e.id).collect();
+        assert_eq!(seen, vec![1, 2]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Changelogs and releases: `ak changelog` and `ak release`.
+//!
+//! Every seal has a conventional type (`feat`, `fix`, …), so the commits between two points of
+//! the history render as a Markdown changelog with one section per type:
+//!
+//! ```markdown
+//! ## 1.2.0 (2025-08-14)
+//!
+//! ### Features
+//!
+//! - export to Git (#42)
+//! ```
+//!
+//! `ak release <version>` tags the head of the current branch with the version and appends a
+//! `release:entry` record `{"version": "1.2.0", "commit": <id>, "author": "ana"}`. A changelog
+//! starts, by default, after the latest release reachable from where it ends.
+
+use crate::data::commit;
+use crate::data::merge;
+use crate::data::write::Writer;
+use crate::event::Event;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+/// Phenomenon of release records.
+pub const RELEASE: &str = "release:entry";
+
+/// Section titles of the known commit types, in changelog order. Other types follow, in
+/// alphabetical order, under their own name.
+pub const SECTIONS: [(&str, &str); 6] = [
+    ("feat", "Features"),
+    ("fix", "Bug fixes"),
+    ("refactor", "Refactoring"),
+    ("docs", "Documentation"),
+    ("test", "Tests"),
+    ("chore", "Chores"),
+];
+
+/// Noumenon of a `release:entry` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Release {
+    pub version: String,
+    pub commit: u64,
+    pub author: String,
+}
+
+/// Record `release`.
+pub fn record(w: &mut Writer, release: &Release) -> io::Result<u64> {
+    let json = serde_json::to_string(release).map_err(io::Error::other)?;
+    w.append(RELEASE, &json)
+}
+
+/// Every release, oldest first.
+pub fn releases(events: &[Event]) -> Vec<Release> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == RELEASE)
+        .filter_map(|e| serde_json::from_str(&e.noumenon).ok())
+        .collect()
+}
+
+/// Latest release of a commit reachable from `to`, other than `to` itself.
+pub fn previous(events: &[Event], to: u64) -> Option<Release> {
+    let reachable: HashSet<u64> = merge::ancestors(events, to).into_iter().collect();
+    releases(events)
+        .into_iter()
+        .rev()
+        .find(|r| r.commit != to && reachable.contains(&r.commit))
+}
+
+/// Commit records reachable from `to` but not from `from`, newest first.
+pub fn commits(events: &[Event], from: Option<u64>, to: u64) -> Vec<&Event> {
+    let before: HashSet<u64> = from
+        .map(|f| merge::ancestors(events, f).into_iter().collect())
+        .unwrap_or_default();
+    let wanted: HashSet<u64> = merge::ancestors(events, to)
+        .into_iter()
+        .filter(|id| !before.contains(id))
+        .collect();
+    let mut out: Vec<&Event> = commit::history(events)
+        .into_iter()
+        .filter(|ev| wanted.contains(&commit::commit_id(ev)))
+        .collect();
+    out.reverse();
+    out
+}
+
+/// Markdown changelog of `commits` (newest first) under the heading `title`, dated with the
+/// newest commit.
+pub fn render(title: &str, commits: &[&Event]) -> String {
+    let mut by_type: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut date = None;
+    for ev in commits {
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).unwrap_or_default();
+        let field = |key: &str| v.get(key).and_then(|x| x.as_str()).unwrap_or("").trim();
+        date = date.or_else(|| {
+            let t = DateTime::from_timestamp_millis(commit::timestamp_ms(&v)?)?;
+            Some(
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d")
+                    .to_string(),
+            )
+        });
+        let ty = match field("ty") {
+            "" => "commit",
+            ty => ty,
+        };
+        by_type.entry(ty.to_string()).or_default().push(format!(
+            "- {} (#{})",
+            field("summary"),
+            commit::commit_id(ev)
+        ));
+    }
+
+    let mut out = match date {
+        Some(date) => format!("## {title} ({date})\n"),
+        None => format!("## {title}\n"),
+    };
+    if by_type.is_empty() {
+        out.push_str("\nNo changes.\n");
+        return out;
+    }
+    let mut sections: Vec<(String, Vec<String>)> = SECTIONS
+        .iter()
+        .filter_map(|(ty, heading)| Some((heading.to_string(), by_type.remove(*ty)?)))
+        .collect();
+    sections.extend(by_type);
+    for (heading, lines) in sections {
+        out.push_str(&format!("\n### {heading}\n\n"));
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changelog_since_the_previous_release() {
+        let dir = std::env::temp_dir().join(format!("akasha-changelog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let mut seal = |id: u64, parent: &str, ty: &str, summary: &str| {
+            w.append(
+                "commit",
+                &format!(r#"{{"id":{id},"parent":{parent},"ty":"{ty}","summary":"{summary}"}}"#),
+            )
+            .unwrap();
+        };
+        seal(1, "null", "feat", "first");
+        seal(2, "1", "fix", "crash");
+        seal(3, "2", "feat", "export");
+        seal(4, "3", "perf", "faster");
+        seal(5, "4", "fix", "typo");
+        let release = Release {
+            version: "1.0.0".to_string(),
+            commit: 2,
+            author: "u".to_string(),
+        };
+        record(&mut w, &release).unwrap();
+
+        let events = w.events().unwrap();
+        assert_eq!(previous(&events, 5), Some(release));
+        assert_eq!(previous(&events, 2), None);
+        let shown = commits(&events, Some(2), 5);
+        assert_eq!(
+            render("1.1.0", &shown),
+            "## 1.1.0\n\
+             \n### Features\n\n- export (#3)\n\
+             \n### Bug fixes\n\n- typo (#5)\n\
+             \n### perf\n\n- faster (#4)\n"
+        );
+        assert_eq!(commits(&events, None, 2).len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
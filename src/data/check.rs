@@ -0,0 +1,171 @@
+//! Repository checks for continuous integration: the engine behind `ak check`.
+//!
+//! Along with the pre-commit steps (see `data::hooks`), a check looks for:
+//! - drift: files of the working directory that differ from the head of the current branch, as
+//!   read from the cube (the reference tree of a fresh checkout can't be trusted to exist);
+//! - seal messages of a range of commits that break the `[message]` rules of the repository
+//!   (see `data::lint`).
+//!
+//! The outcome serializes to the JSON printed by `ak check --json`.
+
+use crate::data::commit;
+use crate::data::config::Message;
+use crate::data::diff::collect_files;
+use crate::data::lint;
+use crate::data::paths;
+use crate::data::restore;
+use crate::data::status::Change;
+use crate::event::Event;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path};
+
+/// Outcome of the pre-commit steps.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Hooks {
+    /// Whether the steps ran; `false` with `--no-hooks`.
+    pub ran: bool,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A path of the working directory that differs from the branch head.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Drift {
+    /// `A`, `M` or `D`, as in `ak status`.
+    pub change: char,
+    /// Portable path.
+    pub path: String,
+}
+
+/// A commit whose message breaks the rules.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Violation {
+    pub commit: u64,
+    pub summary: String,
+    pub problems: Vec<String>,
+}
+
+/// Findings of `ak check`.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Report {
+    pub hooks: Hooks,
+    /// Branch head the working directory was compared with, `None` before the first seal.
+    pub head: Option<u64>,
+    pub drift: Vec<Drift>,
+    /// Number of commits whose message was checked.
+    pub commits: usize,
+    pub messages: Vec<Violation>,
+}
+
+impl Report {
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        (!self.hooks.ran || self.hooks.passed) && self.drift.is_empty() && self.messages.is_empty()
+    }
+}
+
+/// Differences between the working directory at `root` and commit `head` of `events` (nothing
+/// sealed yet with `None`), sorted by path.
+pub fn drift(root: &Path, events: &[Event], head: Option<u64>) -> io::Result<Vec<Drift>> {
+    let sealed = match head {
+        Some(id) => restore::tree_at(events, id)?,
+        None => Default::default(),
+    };
+    let work: BTreeSet<String> = collect_files(root)?
+        .into_iter()
+        .map(|p| p.replace(MAIN_SEPARATOR_STR, "/"))
+        .collect();
+    let mut out = Vec::new();
+    for path in work.iter().chain(sealed.keys()).collect::<BTreeSet<_>>() {
+        let change = match sealed.get(path) {
+            None => Change::Added,
+            Some(_) if !work.contains(path) => Change::Deleted,
+            Some(content) => {
+                let native = path.replace('/', MAIN_SEPARATOR_STR);
+                if fs::read(paths::long(&root.join(native)))? == content.as_bytes() {
+                    continue;
+                }
+                Change::Modified
+            }
+        };
+        out.push(Drift {
+            change: change.code(),
+            path: path.clone(),
+        });
+    }
+    Ok(out)
+}
+
+/// Messages of `commits` (commit records) that break `rules`, in the order given.
+pub fn messages(commits: &[&Event], rules: &Message) -> io::Result<Vec<Violation>> {
+    let mut out = Vec::new();
+    for ev in commits {
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).unwrap_or_default();
+        let field = |key: &str| {
+            v.get(key)
+                .and_then(|x| x.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        let summary = field("summary");
+        let problems = lint::check(rules, &field("ty"), &summary, &field("body"))?;
+        if !problems.is_empty() {
+            out.push(Violation {
+                commit: commit::commit_id(ev),
+                summary,
+                problems,
+            });
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_drift_and_messages() {
+        let dir = std::env::temp_dir().join(format!("akasha-check-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let work = dir.join("work");
+        fs::create_dir_all(&work).unwrap();
+        let mut w = Writer::create(dir.join("u.cube").to_str().unwrap()).unwrap();
+        w.append("kept.txt", "same").unwrap();
+        w.append("edited.txt", "old").unwrap();
+        w.append("gone.txt", "g").unwrap();
+        w.append(
+            "commit",
+            r#"{"id":9,"ty":"wip","summary":"Stuff","body":""}"#,
+        )
+        .unwrap();
+        fs::write(work.join("kept.txt"), "same").unwrap();
+        fs::write(work.join("edited.txt"), "new").unwrap();
+        fs::write(work.join("new.txt"), "n").unwrap();
+
+        let events = w.events().unwrap();
+        let found: Vec<String> = drift(&work, &events, Some(9))
+            .unwrap()
+            .iter()
+            .map(|d| format!("{} {}", d.change, d.path))
+            .collect();
+        assert_eq!(found, ["M edited.txt", "D gone.txt", "A new.txt"]);
+        assert_eq!(drift(&work, &events, None).unwrap().len(), 3);
+
+        let rules = Message {
+            types: vec!["feat".to_string()],
+            ..Message::default()
+        };
+        let commits: Vec<&Event> = events.iter().filter(|e| e.phenomenon == "commit").collect();
+        let found = messages(&commits, &rules).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].commit, found[0].problems.len()), (9, 1));
+        assert!(messages(&commits, &Message::default()).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+//! Scan checkpoints: remember how far a cube has been scanned so that opening it does not need a
+//! full scan.
+//!
+//! A checkpoint is stored in a JSON sidecar named `<cube>.checkpoint.json` and holds the offset
+//! right after the last scanned record, the maximum id seen so far and the deduplication state of
+//! `store_directory` (canonical phenomenon -> BLAKE3 of its latest content, and BLAKE3 of every
+//! file content -> the record holding it). NEXT_ID recovery and deduplication then only scan the
+//! records written after that offset.
+//!
+//! The checkpoint also records the offset and CRC of the last scanned record. It is only trusted
+//! when that record is still there, intact, and ends exactly at the checkpoint offset; a cube that
+//! was rewritten or truncated since falls back to a full scan. Checkpoints are refreshed by
+//! `Writer::checkpoint`, which `store_directory` and `finish_bulk` call.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Last record covered by a checkpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    /// Offset of the record's length prefix.
+    pub offset: u64,
+    /// CRC32 of the record's payload.
+    pub crc: u32,
+}
+
+/// Scan state of a cube up to `offset`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// Offset right after the last scanned record (the header length for an empty scan).
+    pub offset: u64,
+    /// Last scanned record, `None` when nothing was scanned yet.
+    pub anchor: Option<Anchor>,
+    /// Highest record id seen.
+    pub max_id: Option<u64>,
+    /// Canonical phenomenon -> BLAKE3 (hex) of its latest noumenon.
+    pub seen: HashMap<String, String>,
+    /// BLAKE3 (hex) of file contents -> id of the first record holding them (see
+    /// `Writer::find_by_hash`). Checkpoints from before it are unreadable, hence rescanned.
+    pub contents: HashMap<String, u64>,
+}
+
+impl Checkpoint {
+    /// Checkpoint of a scan that has not read any record yet.
+    pub fn start(header_len: u64) -> Self {
+        Self {
+            offset: header_len,
+            anchor: None,
+            max_id: None,
+            seen: HashMap::new(),
+            contents: HashMap::new(),
+        }
+    }
+}
+
+fn sidecar(cube: &Path) -> PathBuf {
+    let mut p = cube.as_os_str().to_owned();
+    p.push(".checkpoint.json");
+    PathBuf::from(p)
+}
+
+/// Load the checkpoint stored next to `cube`, if any. Unreadable checkpoints are ignored: they
+/// only speed things up.
+pub fn load(cube: &Path) -> Option<Checkpoint> {
+    let raw = fs::read(sidecar(cube)).ok()?;
+    serde_json::from_slice(&raw).ok()
+}
+
+/// Store `cp` next to `cube`, replacing the previous checkpoint atomically.
+pub fn save(cube: &Path, cp: &Checkpoint) -> io::Result<()> {
+    let path = sidecar(cube);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, serde_json::to_vec(cp).map_err(io::Error::other)?)?;
+    fs::rename(&tmp, &path)
+}
+
+/// Forget the checkpoint of `cube` (after rewriting it).
+pub fn remove(cube: &Path) -> io::Result<()> {
+    match fs::remove_file(sidecar(cube)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
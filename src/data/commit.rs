@@ -0,0 +1,197 @@
+//! Commit records.
+//!
+//! A seal writes a `commit` record whose noumenon is the JSON commit (id, parent, branch,
+//! manifest, message, author, timestamp). Amending a commit writes a new commit record plus a
+//! `commit:superseded` record `{"id": <old id>, "by": <new id>}`: the superseded commit stays
+//! readable by id but is left out of the history.
+
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+
+/// Phenomenon of the records marking a commit as superseded.
+pub const SUPERSEDED: &str = "commit:superseded";
+
+/// Noumenon of a `commit:superseded` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Superseded {
+    /// The amended commit.
+    pub id: u64,
+    /// The commit replacing it.
+    pub by: u64,
+}
+
+/// Id of a commit record: the `id` of its JSON noumenon, or the record id.
+pub fn commit_id(ev: &Event) -> u64 {
+    serde_json::from_str::<serde_json::Value>(&ev.noumenon)
+        .ok()
+        .and_then(|v| v.get("id").and_then(|x| x.as_u64()))
+        .unwrap_or(ev.id)
+}
+
+/// The `commit` record of commit `id`.
+pub fn find(events: &[Event], id: u64) -> Option<&Event> {
+    events
+        .iter()
+        .find(|e| e.phenomenon == "commit" && commit_id(e) == id)
+}
+
+/// Superseded commit id -> id of the commit replacing it.
+pub fn superseded(events: &[Event]) -> HashMap<u64, u64> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == SUPERSEDED)
+        .filter_map(|e| serde_json::from_str::<Superseded>(&e.noumenon).ok())
+        .map(|s| (s.id, s.by))
+        .collect()
+}
+
+/// Record that commit `id` was replaced by commit `by`.
+pub fn mark_superseded(w: &mut Writer, id: u64, by: u64) -> io::Result<u64> {
+    let json = serde_json::to_string(&Superseded { id, by }).map_err(io::Error::other)?;
+    w.append(SUPERSEDED, &json)
+}
+
+/// Seal time of a JSON commit in milliseconds since the epoch. Accepts a number or a string,
+/// in milliseconds or (older records) nanoseconds.
+pub fn timestamp_ms(v: &serde_json::Value) -> Option<i64> {
+    let raw: u128 = match v.get("timestamp")? {
+        serde_json::Value::Number(n) => n.as_u64()? as u128,
+        serde_json::Value::String(s) => s.parse().ok()?,
+        _ => return None,
+    };
+    // Heuristic: treat very large values as nanoseconds.
+    let ms = if raw > 1_000_000_000_000_000 {
+        raw / 1_000_000
+    } else {
+        raw
+    };
+    i64::try_from(ms).ok()
+}
+
+/// Criteria selecting commits of the history; empty criteria select everything.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Sealed at or after this time (ms).
+    pub since: Option<i64>,
+    /// Sealed before this time (ms).
+    pub until: Option<i64>,
+    /// Exact commit type.
+    pub ty: Option<String>,
+    /// Case-insensitive substring of the summary or body.
+    pub grep: Option<String>,
+    /// Case-insensitive substring of the author name or email.
+    pub author: Option<String>,
+}
+
+impl Filter {
+    /// Whether the JSON commit `v` meets every criterion. Commits without a readable timestamp
+    /// never match a date bound.
+    pub fn matches(&self, v: &serde_json::Value) -> bool {
+        let text = |key: &str| {
+            v.get(key)
+                .and_then(|x| x.as_str())
+                .unwrap_or("")
+                .to_lowercase()
+        };
+        if self.since.is_some() || self.until.is_some() {
+            let Some(ts) = timestamp_ms(v) else {
+                return false;
+            };
+            if self.since.is_some_and(|s| ts < s) || self.until.is_some_and(|u| ts >= u) {
+                return false;
+            }
+        }
+        if let Some(ty) = &self.ty
+            && v.get("ty").and_then(|x| x.as_str()) != Some(ty.as_str())
+        {
+            return false;
+        }
+        if let Some(pat) = &self.grep {
+            let pat = pat.to_lowercase();
+            if !text("summary").contains(&pat) && !text("body").contains(&pat) {
+                return false;
+            }
+        }
+        if let Some(who) = &self.author {
+            let who = who.to_lowercase();
+            if !text("author").contains(&who) && !text("author_email").contains(&who) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Commit records that were not superseded, in on-disk order.
+pub fn history(events: &[Event]) -> Vec<&Event> {
+    let gone = superseded(events);
+    events
+        .iter()
+        .filter(|e| e.phenomenon == "commit" && !gone.contains_key(&commit_id(e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_superseded_commits_leave_the_history() {
+        let ev = |id: u64, ph: &str, no: &str| {
+            Event::builder()
+                .id(id)
+                .phenomenon(ph)
+                .noumenon(no)
+                .timestamp(0)
+                .build()
+                .unwrap()
+        };
+        let events = [
+            ev(2, "commit", r#"{"id":1}"#),
+            ev(4, "commit", r#"{"id":3}"#),
+            ev(5, SUPERSEDED, r#"{"id":1,"by":3}"#),
+        ];
+        assert_eq!(find(&events, 1).map(|e| e.id), Some(2));
+        assert_eq!(superseded(&events)[&1], 3);
+        assert_eq!(
+            history(&events).iter().map(|e| e.id).collect::<Vec<_>>(),
+            [4]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let v: serde_json::Value = serde_json::from_str(
+            r#"{"ty":"feat","summary":"Add Timeline","body":"","author":"Ana","author_email":"ana@x","timestamp":1000}"#,
+        )
+        .unwrap();
+        assert!(Filter::default().matches(&v));
+        let f = |f: Filter| f.matches(&v);
+        assert!(f(Filter {
+            ty: Some("feat".into()),
+            grep: Some("timeline".into()),
+            author: Some("ANA@".into()),
+            ..Filter::default()
+        }));
+        assert!(!f(Filter {
+            ty: Some("fix".into()),
+            ..Filter::default()
+        }));
+        assert!(f(Filter {
+            since: Some(1000),
+            until: Some(1001),
+            ..Filter::default()
+        }));
+        assert!(!f(Filter {
+            until: Some(1000),
+            ..Filter::default()
+        }));
+        assert!(!f(Filter {
+            grep: Some("branch".into()),
+            ..Filter::default()
+        }));
+    }
+}
@@ -0,0 +1,373 @@
+//! Configuration for `ak`: the repository's `.eikyu/config.toml` and the user's
+//! `~/.config/ak/config.toml` (`$XDG_CONFIG_HOME/ak`, `%APPDATA%\ak` on Windows).
+//!
+//! ```toml
+//! editor = "vim"
+//!
+//! [user]
+//! name = "ana"
+//! email = "ana@example.com"
+//!
+//! [storage]
+//! layout = "shared"
+//! snapshot = "hardlink"
+//! keep-snapshots = 10
+//!
+//! [remote.origin]
+//! url = "ana@host:/srv/project"
+//!
+//! [signing]
+//! required = true
+//! allowed-signers = ".eikyu/allowed_signers"
+//!
+//! [message]
+//! max-summary = 72
+//! types = ["feat", "fix", "docs"]
+//! scope = "^[a-z-]+: "
+//! body-width = 72
+//! ```
+//!
+//! Identity is looked up in the environment first (`AK_USERNAME`, `AK_EMAIL`), then in the
+//! repository file, then in the user file; see `resolve`. The editor comes from the files first,
+//! then from `VISUAL` and `EDITOR`; see `editor`.
+//!
+//! The storage layout only applies to the repository file:
+//! - `layout = "per-author"` (the default, also used when the file is absent): every author
+//!   writes to `.eikyu/cubes/YYYY-MM/<author>.cube`.
+//! - `layout = "shared"`: the whole team writes to one repository cube,
+//!   `.eikyu/cubes/shared.cube`. Each commit still records its author, and branches and tags are
+//!   shared; the current branch, the index and the reference trees stay per author.
+//!
+//! The layout is chosen by `ak init --layout` and can't change once the repository has cubes.
+//!
+//! `snapshot` says how the reference tree (see `data::tree`) gets its files:
+//! - `"reflink"` (the default): copy-on-write clones where the filesystem supports them (Btrfs,
+//!   XFS, …), byte copies elsewhere.
+//! - `"hardlink"`: clones, else hard links to the working files, else copies. Hard links take no
+//!   space, but a file edited in place (rather than replaced) changes its reference copy too, so
+//!   only use them with tools that write files anew.
+//! - `"copy"`: always byte copies.
+//! - `"cube"`: no reference tree at all. Commits already store manifests of content-addressed
+//!   records, so `status`, `diff` and `add` read the sealed state back from the cube (see
+//!   `tree::Reference`) and each seal removes the tree instead of refreshing it.
+//!
+//! `keep-snapshots` (0 by default) keeps a snapshot directory of each of that many latest seals
+//! next to the reference tree, placed the `snapshot` way; `ak diff` reads a commit from its
+//! snapshot rather than from the cube, even once `ak prune` archived its file versions (see
+//! `tree::record_snapshot`).
+//!
+//! Remotes (`ak remote add`, used by `ak push` and `ak pull`, see `data::remote`) also live in
+//! the repository file, as do the signing policy (see `data::sign`) and the rules seal messages
+//! are checked against (see `data::lint`). The key used by `ak seal --sign` is `user.signingkey`
+//! (or `AK_SIGNING_KEY`).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Keys accepted by `ak config`.
+pub const KEYS: [&str; 4] = ["user.name", "user.email", "user.signingkey", "editor"];
+
+/// Where `ak` stores its records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// One cube per author and month.
+    #[default]
+    PerAuthor,
+    /// One cube for the whole repository.
+    Shared,
+}
+
+/// How the reference tree gets its files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Snapshot {
+    /// Byte copies.
+    Copy,
+    /// Copy-on-write clones, or copies.
+    #[default]
+    Reflink,
+    /// Clones, or hard links, or copies.
+    Hardlink,
+    /// No reference tree: the sealed state is read from the cube.
+    Cube,
+}
+
+/// `[storage]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Storage {
+    pub layout: Layout,
+    #[serde(skip_serializing_if = "is_default")]
+    pub snapshot: Snapshot,
+    /// Number of per-seal snapshots kept by every author.
+    #[serde(skip_serializing_if = "is_default")]
+    pub keep_snapshots: usize,
+}
+
+/// `[user]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct User {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// SSH key signing seals.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signingkey: Option<String>,
+}
+
+/// `[signing]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Signing {
+    /// Make `ak verify` fail on commits not signed by an allowed signer.
+    pub required: bool,
+    /// Allowed signers file, relative to the repository root.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_signers: Option<String>,
+}
+
+impl Signing {
+    /// Allowed signers file of the repository at `root`.
+    pub fn allowed_signers_path(&self, root: &Path) -> PathBuf {
+        match &self.allowed_signers {
+            Some(p) => root.join(p),
+            None => root.join(".eikyu").join("allowed_signers"),
+        }
+    }
+}
+
+/// `[message]` table: rules of `ak seal` messages, all optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Message {
+    /// Longest summary, in characters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_summary: Option<usize>,
+    /// Allowed commit types; any type when empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<String>,
+    /// Regular expression the summary must match, e.g. a `scope: ` prefix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Longest body line, in characters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_width: Option<usize>,
+}
+
+/// `[remote.<name>]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Remote {
+    /// Path of another repository, or an SSH location (`[user@]host:path`, `ssh://`).
+    pub url: String,
+}
+
+/// Contents of a configuration file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Command used to edit commit bodies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+    #[serde(skip_serializing_if = "is_default")]
+    pub user: User,
+    #[serde(skip_serializing_if = "is_default")]
+    pub storage: Storage,
+    /// Remotes by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote: BTreeMap<String, Remote>,
+    #[serde(skip_serializing_if = "is_default")]
+    pub signing: Signing,
+    #[serde(skip_serializing_if = "is_default")]
+    pub message: Message,
+}
+
+fn is_default<T: Default + PartialEq>(v: &T) -> bool {
+    *v == T::default()
+}
+
+/// Path of the configuration file of the repository at `root`.
+pub fn config_path(root: &Path) -> PathBuf {
+    root.join(".eikyu").join("config.toml")
+}
+
+/// Path of the user's configuration file, `None` when no home directory is known.
+pub fn user_config_path() -> Option<PathBuf> {
+    let var = |k: &str| {
+        std::env::var_os(k)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if cfg!(windows) {
+        var("APPDATA")?
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|h| h.join(".config")))?
+    };
+    Some(base.join("ak").join("config.toml"))
+}
+
+/// Path of the repository cube used by the shared layout.
+pub fn shared_cube(root: &Path) -> PathBuf {
+    root.join(".eikyu").join("cubes").join("shared.cube")
+}
+
+fn unknown_key(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unknown key '{key}' (expected one of {})", KEYS.join(", ")),
+    )
+}
+
+impl Config {
+    /// Configuration of the repository at `root`, or the defaults without a config file.
+    pub fn load(root: &Path) -> io::Result<Config> {
+        Self::load_from(&config_path(root))
+    }
+
+    /// Configuration stored at `path`, or the defaults when it doesn't exist.
+    pub fn load_from(path: &Path) -> io::Result<Config> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&raw).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
+    /// Write the configuration of the repository at `root`.
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        self.save_to(&config_path(root))
+    }
+
+    /// Write the configuration to `path`, creating its directory.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let raw = toml::to_string(self).map_err(io::Error::other)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, raw)
+    }
+
+    /// Value of `key` (one of `KEYS`), if set.
+    pub fn get(&self, key: &str) -> io::Result<Option<&str>> {
+        Ok(match key {
+            "user.name" => self.user.name.as_deref(),
+            "user.email" => self.user.email.as_deref(),
+            "user.signingkey" => self.user.signingkey.as_deref(),
+            "editor" => self.editor.as_deref(),
+            _ => return Err(unknown_key(key)),
+        })
+    }
+
+    /// Set `key` (one of `KEYS`) to `value`, or remove it with `None`.
+    pub fn set(&mut self, key: &str, value: Option<String>) -> io::Result<()> {
+        let slot = match key {
+            "user.name" => &mut self.user.name,
+            "user.email" => &mut self.user.email,
+            "user.signingkey" => &mut self.user.signingkey,
+            "editor" => &mut self.editor,
+            _ => return Err(unknown_key(key)),
+        };
+        *slot = value;
+        Ok(())
+    }
+}
+
+/// Command editing seal bodies and where it comes from: `editor` of the repository at `root`,
+/// then of the user's file, then `$VISUAL`, then `$EDITOR`. `None` when none is set: `ak` then
+/// reads the body in the terminal.
+pub fn editor(root: &Path) -> io::Result<Option<(String, String)>> {
+    let mut files = vec![config_path(root)];
+    files.extend(user_config_path());
+    for path in files {
+        if let Some(v) = Config::load_from(&path)?.editor {
+            return Ok(Some((v, path.display().to_string())));
+        }
+    }
+    for env in ["VISUAL", "EDITOR"] {
+        if let Ok(v) = std::env::var(env)
+            && !v.is_empty()
+        {
+            return Ok(Some((v, format!("${env}"))));
+        }
+    }
+    Ok(None)
+}
+
+/// Effective value of `key` and where it comes from: environment variable `env`, then the
+/// repository at `root`, then the user's file.
+pub fn resolve(root: &Path, key: &str, env: &str) -> io::Result<Option<(String, String)>> {
+    if let Ok(v) = std::env::var(env)
+        && !v.is_empty()
+    {
+        return Ok(Some((v, format!("${env}"))));
+    }
+    let mut files = vec![config_path(root)];
+    files.extend(user_config_path());
+    for path in files {
+        if let Some(v) = Config::load_from(&path)?.get(key)? {
+            return Ok(Some((v.to_string(), path.display().to_string())));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_round_trip() {
+        let root = std::env::temp_dir().join(format!("akasha-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".eikyu")).unwrap();
+        assert_eq!(
+            Config::load(&root).unwrap().storage.layout,
+            Layout::PerAuthor
+        );
+
+        let shared = Config {
+            storage: Storage {
+                layout: Layout::Shared,
+                ..Storage::default()
+            },
+            ..Config::default()
+        };
+        shared.save(&root).unwrap();
+        assert!(
+            fs::read_to_string(config_path(&root))
+                .unwrap()
+                .contains(r#"layout = "shared""#)
+        );
+        assert_eq!(Config::load(&root).unwrap(), shared);
+
+        fs::write(config_path(&root), "[storage]\nlayout = \"weekly\"\n").unwrap();
+        assert!(Config::load(&root).is_err());
+
+        // Identity keys, and what a file holding only them looks like.
+        let mut user = Config::default();
+        user.set("user.name", Some("ana".into())).unwrap();
+        user.set("editor", Some("vim".into())).unwrap();
+        assert!(user.set("user.phone", None).is_err());
+        assert_eq!(user.get("user.name").unwrap(), Some("ana"));
+        let path = root.join("user.toml");
+        user.save_to(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "editor = \"vim\"\n\n[user]\nname = \"ana\"\n"
+        );
+        assert_eq!(Config::load_from(&path).unwrap(), user);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Writer daemon: one process owning the write handle of a cube (`ak daemon`).
+//!
+//! Every `Writer` appends under the cube's exclusive file lock, after opening the cube, loading
+//! its processors and reading `next_id` back from the header. When many short-lived processes
+//! append to one cube (hooks, scripts, editors), a daemon instead keeps a single `Writer` open
+//! and serves appends over a Unix socket next to the cube, `<cube>.sock`, one at a time in the
+//! order they arrive.
+//!
+//! `Writer::create` notices the socket and sends its appends there (`append`, `append_record`,
+//! `append_typed` and `append_events`, outside bulk mode). Everything else, reads included, still
+//! uses the file, which the daemon has made durable by the time it replies. Without a daemon, or
+//! once it stopped (a socket left behind refuses connections), appends go to the file as before;
+//! the file lock keeps direct writers and the daemon from interleaving records.
+//!
+//! The protocol is one JSON `Request` line per connection, answered by one JSON `Response` line.
+//! Processors configured for the cube run in the daemon, and subscribers of the client's writer
+//! are notified with the records as stored. Only Unix has the daemon: elsewhere writers always
+//! write directly.
+//!
+//! Records appended for a client carry the provenance it sends along (see `data::provenance`),
+//! and the ones the daemon appends itself its own.
+//!
+//! Limits configured in `<cube>.quota.json` apply to the appends the daemon serves, by the token
+//! each request carries (see `data::quota`); `Writer` sends none.
+
+use crate::data::quota::{self, Exceeded, Limiter, QUOTA, Refusal};
+use crate::data::write::{Appended, Writer};
+use crate::event::{Event, RecordType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A request to the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// `Writer::append_typed`, or `append_record` without a kind.
+    Append {
+        /// Token the client identifies with, for rate limits.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        kind: Option<RecordType>,
+        phenomenon: String,
+        noumenon: String,
+        /// Provenance of the client, stamped on the record (see `data::provenance`).
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        attributes: BTreeMap<String, String>,
+    },
+    /// `Writer::append_events`.
+    AppendEvents {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        events: Vec<Event>,
+    },
+    /// Whether the daemon is up, and what it serves.
+    Status,
+    /// Stop serving once the reply is sent.
+    Stop,
+}
+
+/// The daemon's answer to a `Request`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    /// The records appended, as stored, and the offset of each.
+    Appended {
+        offsets: Vec<u64>,
+        events: Vec<Event>,
+    },
+    Status {
+        /// Path of the cube, as given to `serve`.
+        cube: String,
+        pid: u32,
+        /// Records appended since the daemon started.
+        appended: u64,
+    },
+    Stopping,
+    /// The append was refused by a limit of the cube; nothing was written.
+    Refused {
+        exceeded: Exceeded,
+    },
+    /// The request failed; `invalid_input` for `io::ErrorKind::InvalidInput` (bad records).
+    Error {
+        message: String,
+        invalid_input: bool,
+    },
+}
+
+impl Response {
+    fn error(e: &io::Error) -> Self {
+        Response::Error {
+            message: e.to_string(),
+            invalid_input: e.kind() == io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Socket of the daemon of the cube at `cube`.
+pub fn socket_path(cube: &Path) -> PathBuf {
+    let mut name = cube.as_os_str().to_owned();
+    name.push(".sock");
+    PathBuf::from(name)
+}
+
+/// Socket of the cube at `cube` if it exists, for `Writer::create`; always `None` where there is
+/// no daemon.
+pub fn socket_of(cube: &Path) -> Option<PathBuf> {
+    let socket = socket_path(cube);
+    (cfg!(unix) && socket.exists()).then_some(socket)
+}
+
+/// Send `req` to the daemon listening on `socket`. `None` when no daemon answers there.
+#[cfg(unix)]
+pub fn call(socket: &Path, req: &Request) -> io::Result<Option<Response>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(s) => s,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    let mut line = serde_json::to_vec(req).map_err(io::Error::other)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    serde_json::from_str(&reply)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("daemon reply: {e}")))
+}
+
+/// Send `req` to the daemon listening on `socket`. `None` when no daemon answers there.
+#[cfg(not(unix))]
+pub fn call(_socket: &Path, _req: &Request) -> io::Result<Option<Response>> {
+    Ok(None)
+}
+
+/// The daemon's `Response::Status` for the cube at `cube`, `None` when none runs.
+pub fn status(cube: &Path) -> io::Result<Option<Response>> {
+    call(&socket_path(cube), &Request::Status)
+}
+
+/// Ask the daemon of the cube at `cube` to stop. Whether one was running.
+pub fn stop(cube: &Path) -> io::Result<bool> {
+    Ok(call(&socket_path(cube), &Request::Stop)?.is_some())
+}
+
+/// Serve appends to the cube at `cube` until a `Request::Stop`.
+///
+/// Fails with `AlreadyExists` when another daemon serves the cube; a socket left behind by a
+/// daemon that did not stop cleanly is replaced. Connections are handled one at a time, each
+/// allowed a few seconds to send its request, so a stuck client can't hold the cube for long.
+#[cfg(unix)]
+pub fn serve(cube: &str) -> io::Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::time::Duration;
+
+    let socket = socket_path(Path::new(cube));
+    if status(Path::new(cube))?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("a daemon already serves {cube}"),
+        ));
+    }
+    if socket.exists() {
+        std::fs::remove_file(&socket)?;
+    }
+    let mut limiter = Limiter::new(quota::load_for_cube(cube)?.unwrap_or_default());
+    // Opened before binding, so that this writer appends to the file itself.
+    let mut w = Writer::create(cube)?;
+    let stored = w.subscribe();
+    let listener = UnixListener::bind(&socket)?;
+    let mut appended = 0u64;
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+        let mut line = String::new();
+        let req = BufReader::new(&stream).read_line(&mut line).and_then(|_| {
+            serde_json::from_str::<Request>(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        });
+        let stopping = matches!(req, Ok(Request::Stop));
+        let reply = match req {
+            Err(e) => Response::error(&e),
+            Ok(Request::Status) => Response::Status {
+                cube: cube.to_string(),
+                pid: std::process::id(),
+                appended,
+            },
+            Ok(Request::Stop) => Response::Stopping,
+            Ok(Request::Append {
+                token,
+                kind,
+                phenomenon,
+                noumenon,
+                attributes,
+            }) => {
+                let token = token.unwrap_or_default();
+                let result = Event::builder()
+                    .phenomenon(phenomenon)
+                    .noumenon(noumenon)
+                    .timestamp(0)
+                    .attributes(attributes)
+                    .build()
+                    .map_err(|e| Response::error(&e))
+                    .and_then(|ev| {
+                        admit(
+                            &mut w,
+                            &mut limiter,
+                            cube,
+                            &token,
+                            std::slice::from_ref(&ev),
+                        )?;
+                        // The client's provenance for its record, the daemon's for the others.
+                        let own = w.provenance().clone();
+                        w.set_provenance(ev.attributes.clone());
+                        let appended = match kind {
+                            Some(kind) => w.append_typed(kind, &ev.phenomenon, &ev.noumenon),
+                            None => w.append_record(&ev.phenomenon, &ev.noumenon),
+                        };
+                        w.set_provenance(own);
+                        appended.map(|a| vec![a]).map_err(|e| Response::error(&e))
+                    });
+                drain(result, &stored)
+            }
+            Ok(Request::AppendEvents { token, events }) => {
+                let token = token.unwrap_or_default();
+                let result = admit(&mut w, &mut limiter, cube, &token, &events)
+                    .and_then(|()| w.append_events(&events).map_err(|e| Response::error(&e)));
+                drain(result, &stored)
+            }
+        };
+        if let Response::Appended { events, .. } = &reply {
+            appended += events.len() as u64;
+        }
+        let mut out = serde_json::to_vec(&reply).map_err(io::Error::other)?;
+        out.push(b'\n');
+        // A client gone before the reply already lost interest in it.
+        let _ = (&stream).write_all(&out);
+        if stopping {
+            break;
+        }
+    }
+    std::fs::remove_file(&socket)
+}
+
+/// Check the appends of `events` by `token` against the limits of the cube at `cube`, recording
+/// the first refusal of the token by each limit in the cube (see `data::quota`).
+#[cfg(unix)]
+fn admit(
+    w: &mut Writer,
+    limiter: &mut Limiter,
+    cube: &str,
+    token: &str,
+    events: &[Event],
+) -> Result<(), Response> {
+    let bytes = events
+        .iter()
+        .map(|e| Writer::record_len(w.version(), e))
+        .sum();
+    let cube_len = std::fs::metadata(cube).map_or(0, |m| m.len());
+    let now = std::time::Instant::now();
+    let Err(exceeded) = limiter.admit(token, events.len(), bytes, cube_len, now) else {
+        return Ok(());
+    };
+    if limiter.first_refusal(token, &exceeded) {
+        let refusal = Refusal {
+            token: token.to_string(),
+            exceeded: exceeded.clone(),
+            records: events.len(),
+            timestamp: chrono::Utc::now().timestamp_millis().max(0) as u64,
+        };
+        let recorded = serde_json::to_string(&refusal)
+            .map_err(io::Error::other)
+            .and_then(|json| w.append_typed(RecordType::Meta, QUOTA, &json));
+        if let Err(e) = recorded {
+            eprintln!("daemon: recording the refusal failed: {e}");
+        }
+    }
+    Err(Response::Refused { exceeded })
+}
+
+/// Reply to an append: the records it stored, taken from the daemon's subscription so that
+/// they are the ones processors produced, or its failure.
+#[cfg(unix)]
+fn drain(
+    result: Result<Vec<Appended>, Response>,
+    stored: &std::sync::mpsc::Receiver<Event>,
+) -> Response {
+    // A refusal leaves its own record in there, which is nobody's reply.
+    let events: Vec<Event> = stored.try_iter().collect();
+    match result {
+        Ok(a) => Response::Appended {
+            offsets: a.iter().map(|a| a.offset).collect(),
+            events,
+        },
+        Err(reply) => reply,
+    }
+}
+
+/// Serve appends to the cube at `cube`: not supported on this platform.
+#[cfg(not(unix))]
+pub fn serve(cube: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{cube}: the writer daemon needs Unix sockets"),
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_appends_go_through_the_daemon() {
+        let dir = std::env::temp_dir().join(format!("akasha-daemon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("d.cube");
+        let path = cube.to_str().unwrap().to_string();
+        let server = std::thread::spawn({
+            let path = path.clone();
+            move || serve(&path)
+        });
+        while status(&cube).unwrap().is_none() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(serve(&path).is_err());
+
+        let mut a = Writer::create(&path).unwrap();
+        let mut b = Writer::create(&path).unwrap();
+        let seen = a.subscribe();
+        assert_eq!(a.append_record("a.txt", "1").unwrap().id, 1);
+        assert_eq!(b.append_record("b.txt", "2").unwrap().id, 2);
+        assert_eq!(a.append_record("a.txt", "3").unwrap().id, 3);
+        assert_eq!(a.next_id(), 4);
+        assert_eq!(seen.try_iter().map(|e| e.id).collect::<Vec<_>>(), [1, 3]);
+        let custom = b.append_typed(RecordType::Custom(0x90), "x", "y").unwrap();
+        assert_eq!(custom.id, 4);
+        let err = b.append_events(&a.events().unwrap()[..1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        match status(&cube).unwrap() {
+            Some(Response::Status { appended, .. }) => assert_eq!(appended, 4),
+            other => panic!("unexpected status {other:?}"),
+        }
+
+        assert!(stop(&cube).unwrap());
+        server.join().unwrap().unwrap();
+        assert!(!socket_path(&cube).exists());
+        // Without the daemon, the same writer appends directly.
+        assert_eq!(a.append_record("a.txt", "4").unwrap().id, 5);
+        let ids: Vec<u64> = a.events().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quota_refusals_are_recorded_once() {
+        let dir = std::env::temp_dir().join(format!("akasha-daemon-quota-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("q.cube");
+        let path = cube.to_str().unwrap().to_string();
+        fs::write(format!("{path}.quota.json"), r#"{"max_bytes":200}"#).unwrap();
+        let server = std::thread::spawn({
+            let path = path.clone();
+            move || serve(&path)
+        });
+        while status(&cube).unwrap().is_none() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let mut w = Writer::create(&path).unwrap();
+        w.append("a.txt", "small").unwrap();
+        let big = "x".repeat(200);
+        for _ in 0..2 {
+            let err = w.append("b.txt", &big).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::QuotaExceeded);
+        }
+        assert!(stop(&cube).unwrap());
+        server.join().unwrap().unwrap();
+
+        let events = w.events().unwrap();
+        assert_eq!(events.len(), 2);
+        let refusal: Refusal = serde_json::from_str(&events[1].noumenon).unwrap();
+        assert_eq!((events[1].phenomenon.as_str(), refusal.records), (QUOTA, 1));
+        assert!(matches!(
+            refusal.exceeded,
+            Exceeded::Size { max_bytes: 200, .. }
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
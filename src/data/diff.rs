@@ -4,146 +4,1047 @@
 //! stored under `.eikyu/tree/<AK_USERNAME>` and prints a concise, colorized summary:
 //! - Green “+” for files added in the repository (not present in the stored tree)
 //! - Red “-” for files removed from the repository (present only in the stored tree)
-//! - Yellow “~” for files modified. For UTF‑8 text files, a unified line diff is shown;
-//!   for binaries or invalid UTF‑8, a single “(modified binary)” marker is printed.
+//! - Yellow “~” for files modified. For UTF‑8 text files, a unified diff is shown: `--- a/…` and
+//!   `+++ b/…` file headers, then hunks introduced by `@@ -a,b +c,d @@` with
+//!   `DiffOptions::context` unchanged lines around the changes (`ak diff -U <n>`); for binaries
+//!   or invalid UTF‑8, the old and new sizes and hashes are printed, with the dimensions of
+//!   recognized images and, on request, the share of bytes in common (`BinaryChange`). Within a
+//!   run of changed lines, every removed line is paired with the added line at the same
+//!   position, and the words that differ between them are shown in inverse colors (see
+//!   `word_spans`).
+//!
+//! `DiffOptions::format` trades the patch for a summary: `--stat` counts the added and removed
+//! lines of every file, `--name-only` and `--name-status` list the changed paths, and `--json`
+//! prints every changed file with its hunks for other programs.
+//!
+//! Lines can be compared ignoring whitespace changes (`--ignore-space-change`, `-b`) or all
+//! whitespace (`--ignore-all-space`, `-w`), and CRLF line endings as LF (`--ignore-cr-at-eol`).
+//!
+//! Files are read, compared and diffed on a pool of threads, one per core (see `in_order`); the
+//! output keeps the order of a serial run and starts as soon as the first files are done.
+//!
+//! `ak diff -- <path>...` only compares the files below the given paths or matching the given
+//! globs (see `pathspec`).
+//!
+//! The same report is available between any two trees (`diff_trees`), e.g. two commits rebuilt
+//! from their manifests.
 //!
 //! This command is read‑only and does not modify the repository or the stored tree.
 
-use colored::Colorize;
+use crate::data::rename;
+use crate::data::status::Change;
+use crate::data::{akignore, paths, tree};
+use colored::{ColoredString, Colorize};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::env::{current_dir, var};
+use std::fs;
 use std::io;
 use std::path::{MAIN_SEPARATOR_STR, Path};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+
+/// Unchanged lines shown around changes by default, as in `diff -u`.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// Options controlling how `diff` compares files.
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    /// Treat CRLF and LF line endings as equal for text files (cross-platform checkouts).
+    pub normalize_eol: bool,
+    /// Renamed files, new path -> old path (see `data::rename`).
+    pub renames: BTreeMap<String, String>,
+    /// Unchanged lines shown before and after every change.
+    pub context: usize,
+    /// Whitespace changes that don't count.
+    pub whitespace: Whitespace,
+    /// Compute the share of bytes modified binary files keep (see `byte_similarity`).
+    pub binary_similarity: bool,
+    /// Only compare the paths matching this set (see `pathspec`), every path with `None`.
+    pub paths: Option<GlobSet>,
+    pub format: Format,
+}
+
+/// How whitespace counts when comparing lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Whitespace {
+    #[default]
+    Exact,
+    /// `--ignore-space-change`: runs of whitespace are equal whatever their length, and
+    /// whitespace at both ends of a line is ignored.
+    IgnoreChange,
+    /// `--ignore-all-space`: whitespace is ignored altogether.
+    IgnoreAll,
+}
+
+/// What `diff_trees` prints.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    /// Changed paths, then the unified diff of every modified text file.
+    #[default]
+    Patch,
+    /// `--stat`: added and removed lines of every file, with a bar chart and a total.
+    Stat,
+    /// `--name-only`: changed paths.
+    NameOnly,
+    /// `--name-status`: changed paths with their `A`, `M`, `D` or `R` code.
+    NameStatus,
+    /// `--json`: every changed file with its hunks, see `json`.
+    Json,
+    /// `--quiet`: nothing, the exit code tells whether the trees differ.
+    Quiet,
+}
+
+/// One changed file, for the summary and JSON formats.
+#[derive(Debug, PartialEq)]
+pub struct FileStat {
+    /// `A`, `M`, `D` or `R`.
+    pub code: char,
+    /// Path on the left side, the same as `path` unless renamed.
+    pub old: String,
+    pub path: String,
+    /// Added and removed lines, `None` for binary files.
+    pub lines: Option<(usize, usize)>,
+    /// Hunks of a text file, with `DiffOptions::context` lines of context.
+    pub hunks: Vec<Hunk>,
+    /// Sizes, hashes and image dimensions of a binary file.
+    pub binary: Option<BinaryChange>,
+}
+
+/// What changed in a binary file. The sides absent (an added or removed file) have no size or
+/// hash.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct BinaryChange {
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    /// BLAKE3 hashes, shortened to 12 hex digits.
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+    /// Share of bytes in common, in percent, with `DiffOptions::binary_similarity`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<u32>,
+    /// Image format (`png`, `gif`, `jpeg` or `bmp`) and dimensions of each side, when recognized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_image: Option<Image>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_image: Option<Image>,
+}
+
+/// Format and dimensions of an image.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Image {
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Format and dimensions of the image `bytes`, read from their header: PNG, GIF, JPEG or BMP.
+pub fn image(bytes: &[u8]) -> Option<Image> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le32 = |at: usize| Some(i32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let (format, width, height) = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        ("png", be32(16)?, be32(20)?)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        ("gif", le16(6)?, le16(8)?)
+    } else if bytes.starts_with(b"BM") {
+        ("bmp", le32(18)?.unsigned_abs(), le32(22)?.unsigned_abs())
+    } else if bytes.starts_with(&[0xff, 0xd8]) {
+        // Walk the segments up to a start of frame (SOF0 to SOF15 but DHT, JPG and DAC).
+        let mut at = 2;
+        loop {
+            while *bytes.get(at)? == 0xff && *bytes.get(at + 1)? == 0xff {
+                at += 1;
+            }
+            if *bytes.get(at)? != 0xff {
+                return None;
+            }
+            let marker = *bytes.get(at + 1)?;
+            if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+                break ("jpeg", be16(at + 7)?, be16(at + 5)?);
+            }
+            at += 2 + be16(at + 2)? as usize;
+        }
+    } else {
+        return None;
+    };
+    Some(Image {
+        format,
+        width,
+        height,
+    })
+}
+
+/// Share of bytes `a` and `b` have in common, in percent. Both are cut into chunks ending at a
+/// newline or after 64 bytes, as Git does for rename detection, so that an insertion only shifts
+/// the chunks up to the next newline.
+pub fn byte_similarity(a: &[u8], b: &[u8]) -> u32 {
+    if a.is_empty() && b.is_empty() {
+        return 100;
+    }
+    let chunks = |bytes: &[u8]| {
+        let mut out: HashMap<u64, usize> = HashMap::new();
+        let mut start = 0;
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == b'\n' || i + 1 - start == 64 || i + 1 == bytes.len() {
+                let chunk = &bytes[start..=i];
+                let mut h = std::hash::DefaultHasher::new();
+                std::hash::Hash::hash(chunk, &mut h);
+                *out.entry(std::hash::Hasher::finish(&h)).or_default() += chunk.len();
+                start = i + 1;
+            }
+        }
+        out
+    };
+    let (ca, cb) = (chunks(a), chunks(b));
+    let common: usize = ca.iter().map(|(k, n)| n.min(cb.get(k).unwrap_or(&0))).sum();
+    (200 * common / (a.len() + b.len())) as u32
+}
+
+/// Details of a binary file changing from `old` to `new` (`None` for an absent side).
+pub fn binary_change(old: Option<&[u8]>, new: Option<&[u8]>, similarity: bool) -> BinaryChange {
+    let hash = |b: &[u8]| blake3::hash(b).to_hex()[..12].to_string();
+    BinaryChange {
+        old_size: old.map(|b| b.len() as u64),
+        new_size: new.map(|b| b.len() as u64),
+        old_hash: old.map(hash),
+        new_hash: new.map(hash),
+        similarity: similarity.then(|| byte_similarity(old.unwrap_or(&[]), new.unwrap_or(&[]))),
+        old_image: old.and_then(image),
+        new_image: new.and_then(image),
+    }
+}
+
+impl BinaryChange {
+    /// One-line description: sizes, hashes, images and similarity.
+    pub fn describe(&self) -> String {
+        let side = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+        let size = |n: Option<u64>| side(n.map(|n| format!("{n} bytes")));
+        let mut out = format!(
+            "{} -> {}, {} -> {}",
+            size(self.old_size),
+            size(self.new_size),
+            side(self.old_hash.clone()),
+            side(self.new_hash.clone())
+        );
+        let shown =
+            |i: Option<Image>| side(i.map(|i| format!("{} {}x{}", i.format, i.width, i.height)));
+        if self.old_image.is_some() || self.new_image.is_some() {
+            out.push_str(&format!(
+                ", {} -> {}",
+                shown(self.old_image),
+                shown(self.new_image)
+            ));
+        }
+        if let Some(similarity) = self.similarity {
+            out.push_str(&format!(", {similarity}% similar"));
+        }
+        out
+    }
+}
+
+impl FileStat {
+    /// `hunks` is `None` for a binary file.
+    fn new(code: char, old: &str, path: &str, hunks: Option<Vec<Hunk>>) -> FileStat {
+        let lines = hunks.as_ref().map(|hunks| {
+            let lines = hunks.iter().flat_map(|h| &h.lines);
+            let added = lines
+                .clone()
+                .filter(|l| matches!(l, Line::Added(_)))
+                .count();
+            (
+                added,
+                lines.filter(|l| matches!(l, Line::Removed(_))).count(),
+            )
+        });
+        FileStat {
+            code,
+            old: old.to_string(),
+            path: path.to_string(),
+            lines,
+            hunks: hunks.unwrap_or_default(),
+            binary: None,
+        }
+    }
+}
+
+/// A file of `ak diff --json`.
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    /// `added`, `removed`, `modified` or `renamed`.
+    change: &'static str,
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_path: Option<&'a str>,
+    binary: bool,
+    added: usize,
+    removed: usize,
+    hunks: &'a [Hunk],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary_change: Option<&'a BinaryChange>,
+}
+
+/// Widest bar of `--stat`.
+const STAT_WIDTH: usize = 40;
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            normalize_eol: false,
+            renames: BTreeMap::new(),
+            context: DEFAULT_CONTEXT,
+            whitespace: Whitespace::default(),
+            binary_similarity: false,
+            paths: None,
+            format: Format::default(),
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Exit code of a failed diff: 2 with `Format::Quiet`, where 1 means the trees differ.
+    pub fn error_code(&self) -> ExitCode {
+        match self.format {
+            Format::Quiet => ExitCode::from(2),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+/// A line of a hunk, with its line ending (none for a last line without one).
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", content = "text", rename_all = "lowercase")]
+pub enum Line {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// A run of changes and their context.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// First line in the old version, 1-based (the line before for an empty range).
+    pub old_start: usize,
+    pub old_len: usize,
+    /// First line in the new version, 1-based (the line before for an empty range).
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<Line>,
+}
+
+impl Hunk {
+    /// `@@ -a,b +c,d @@` header (a length of one is left out).
+    pub fn header(&self) -> String {
+        let range = |start: usize, len: usize| match len {
+            1 => start.to_string(),
+            _ => format!("{start},{len}"),
+        };
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )
+    }
+}
+
+impl Line {
+    /// The line, with its line ending.
+    pub fn text(&self) -> &str {
+        match self {
+            Line::Context(t) | Line::Removed(t) | Line::Added(t) => t,
+        }
+    }
+}
+
+/// A piece of a modified line, and whether it changed.
+pub type Span = (bool, String);
+
+/// Split `line` into words, runs of whitespace and single other characters.
+fn words(line: &str) -> Vec<&str> {
+    let class = |c: char| match c {
+        c if c.is_alphanumeric() || c == '_' => 0,
+        c if c.is_whitespace() => 1,
+        _ => 2,
+    };
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends = match chars.peek() {
+            None => true,
+            Some(&(_, next)) => class(c) == 2 || class(next) != class(c),
+        };
+        if ends {
+            let end = i + c.len_utf8();
+            out.push(&line[start..end]);
+            start = end;
+        }
+    }
+    out
+}
+
+/// Spans of `old` and `new`, two versions of a line, with the words that differ marked as
+/// changed. `None` when the lines share less than half of the longer one: highlighting nearly
+/// everything would not help.
+pub fn word_spans(old: &str, new: &str) -> Option<(Vec<Span>, Vec<Span>)> {
+    let (a, b) = (words(old), words(new));
+    let (mut left, mut right): (Vec<Span>, Vec<Span>) = (Vec::new(), Vec::new());
+    let push = |spans: &mut Vec<Span>, changed: bool, word: &str| match spans.last_mut() {
+        Some((c, text)) if *c == changed => text.push_str(word),
+        _ => spans.push((changed, word.to_string())),
+    };
+    let mut shared = 0;
+    for d in diff::slice(&a, &b) {
+        match d {
+            diff::Result::Both(w, _) => {
+                shared += w.len();
+                push(&mut left, false, w);
+                push(&mut right, false, w);
+            }
+            diff::Result::Left(w) => push(&mut left, true, w),
+            diff::Result::Right(w) => push(&mut right, true, w),
+        }
+    }
+    if shared * 2 < old.len().max(new.len()) {
+        return None;
+    }
+    Some((left, right))
+}
+
+/// Print hunk `h`, highlighting the changed words of every removed line paired with an added one.
+fn print_hunk(h: &Hunk) {
+    println!("{}", h.header().cyan());
+    let mut k = 0;
+    while k < h.lines.len() {
+        let count = |from: usize, removed: bool| {
+            h.lines[from..]
+                .iter()
+                .take_while(|l| match l {
+                    Line::Removed(_) => removed,
+                    Line::Added(_) => !removed,
+                    Line::Context(_) => false,
+                })
+                .count()
+        };
+        let removed = count(k, true);
+        let added = count(k + removed, false);
+        if removed + added == 0 {
+            print_line(&h.lines[k], None);
+            k += 1;
+            continue;
+        }
+        // The n-th removed line of a run pairs with its n-th added line.
+        let run = &h.lines[k..k + removed + added];
+        let pairs: Vec<Option<(Vec<Span>, Vec<Span>)>> = (0..removed.min(added))
+            .map(|n| {
+                word_spans(
+                    run[n].text().trim_end_matches('\n'),
+                    run[removed + n].text().trim_end_matches('\n'),
+                )
+            })
+            .collect();
+        for (n, line) in run.iter().enumerate() {
+            let spans = match n.checked_sub(removed) {
+                None => pairs.get(n).and_then(|p| p.as_ref()).map(|p| &p.0),
+                Some(m) => pairs.get(m).and_then(|p| p.as_ref()).map(|p| &p.1),
+            };
+            print_line(line, spans.map(Vec::as_slice));
+        }
+        k += removed + added;
+    }
+}
+
+/// Print one line of a hunk, its changed `spans` in inverse colors.
+fn print_line(line: &Line, spans: Option<&[Span]>) {
+    let text = line.text().trim_end_matches('\n');
+    let (prefix, paint): (&str, fn(&str) -> ColoredString) = match line {
+        Line::Context(_) => (" ", |t| t.normal()),
+        Line::Removed(_) => ("-", |t| t.red()),
+        Line::Added(_) => ("+", |t| t.green()),
+    };
+    let mut out = paint(prefix).to_string();
+    match spans {
+        Some(spans) => {
+            for (changed, span) in spans {
+                let span = paint(span);
+                out.push_str(&if *changed { span.reversed() } else { span }.to_string());
+            }
+        }
+        None => out.push_str(&paint(text).to_string()),
+    }
+    println!("{out}");
+    if !line.text().ends_with('\n') {
+        println!("\\ No newline at end of file");
+    }
+}
+
+impl Whitespace {
+    /// What a line is compared by.
+    fn key(self, line: &str) -> std::borrow::Cow<'_, str> {
+        match self {
+            Whitespace::Exact => line.into(),
+            Whitespace::IgnoreChange => {
+                line.split_whitespace().collect::<Vec<_>>().join(" ").into()
+            }
+            Whitespace::IgnoreAll => line.split_whitespace().collect::<String>().into(),
+        }
+    }
+}
+
+/// Hunks turning `old` into `new`, with `context` unchanged lines around every change. Changes
+/// less than two contexts apart share a hunk.
+pub fn hunks(old: &str, new: &str, context: usize) -> Vec<Hunk> {
+    hunks_ignoring(old, new, context, Whitespace::Exact)
+}
+
+/// `hunks`, comparing lines as `whitespace` says. Lines equal under it are context, shown as they
+/// are in `new`.
+pub fn hunks_ignoring(old: &str, new: &str, context: usize, whitespace: Whitespace) -> Vec<Hunk> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let ka: Vec<_> = a.iter().map(|l| whitespace.key(l)).collect();
+    let kb: Vec<_> = b.iter().map(|l| whitespace.key(l)).collect();
+    // Every line with the number of old and new lines before it.
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for d in diff::slice(&ka, &kb) {
+        match d {
+            diff::Result::Both(..) => {
+                lines.push((Line::Context(b[j].to_string()), i, j));
+                i += 1;
+                j += 1;
+            }
+            diff::Result::Left(_) => {
+                lines.push((Line::Removed(a[i].to_string()), i, j));
+                i += 1;
+            }
+            diff::Result::Right(_) => {
+                lines.push((Line::Added(b[j].to_string()), i, j));
+                j += 1;
+            }
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&k| !matches!(lines[k].0, Line::Context(_)))
+        .collect();
+    let mut out = Vec::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(context);
+        while k + 1 < changed.len() && changed[k + 1] - changed[k] <= 2 * context + 1 {
+            k += 1;
+        }
+        let end = (changed[k] + context + 1).min(lines.len());
+        let run = &lines[start..end];
+        let old_len = run
+            .iter()
+            .filter(|(l, ..)| !matches!(l, Line::Added(_)))
+            .count();
+        let new_len = run
+            .iter()
+            .filter(|(l, ..)| !matches!(l, Line::Removed(_)))
+            .count();
+        let (_, i, j) = run[0];
+        out.push(Hunk {
+            old_start: if old_len == 0 { i } else { i + 1 },
+            old_len,
+            new_start: if new_len == 0 { j } else { j + 1 },
+            new_len,
+            lines: run.iter().map(|(l, ..)| l.clone()).collect(),
+        });
+        k += 1;
+    }
+    out
+}
+
+/// Replace CRLF line endings by LF when `enabled`, borrowing the input otherwise.
+fn normalize_eol(text: &str, enabled: bool) -> std::borrow::Cow<'_, str> {
+    if enabled && text.contains("\r\n") {
+        std::borrow::Cow::Owned(text.replace("\r\n", "\n"))
+    } else {
+        std::borrow::Cow::Borrowed(text)
+    }
+}
+
+/// A tree to compare: portable path (forward slashes) -> file bytes.
+pub type Tree = BTreeMap<String, Vec<u8>>;
 
 /// Compare the current repository state against the last stored tree snapshot and print differences.
 ///
 /// Flow:
-/// 1) Locate repository root (current_dir) and resolve the tree snapshot path using `AK_USERNAME`.
-/// 2) Enumerate files (relative paths) for both the repository and the stored tree,
-///    excluding `.eikyu/` from the repository listing and applying `.ignore` rules.
-/// 3) Compute set differences:
-///    - Added: present in repo only
-///    - Removed: present in tree only
-///    - Modified: present in both but with different content
-/// 4) For modified files:
-///    - If both sides are valid UTF‑8, print a line-by-line diff
-///    - Otherwise, print a “modified binary” marker
+/// 1) Locate repository root (current_dir) and resolve the sealed state of `AK_USERNAME` (see
+///    `tree::Reference`: the tree snapshot, or the branch head read back from the cube).
+/// 2) Read the files of both the repository and the sealed state, excluding `.eikyu/` from the
+///    repository listing and applying `.ignore` rules.
+/// 3) Print the differences with `diff_trees`.
 ///
-/// Returns:
-/// - ExitCode::SUCCESS on success
-/// - ExitCode::FAILURE if the snapshot is missing or enumeration fails
-pub fn diff() -> ExitCode {
+/// Returns the code of `diff_trees`, or `DiffOptions::error_code` if nothing was sealed yet or
+/// enumeration fails.
+pub fn diff(opts: &DiffOptions) -> ExitCode {
     // Determine repository root and author (used to address the stored tree).
     let repository_root = current_dir().expect("Failed to get current directory");
     let auteur = var("AK_USERNAME").expect("Failed to get auteur");
 
-    // Stored tree layout: .eikyu/tree/<AK_USERNAME>
-    let tree_dir = repository_root.join(format!(
-        ".eikyu{MAIN_SEPARATOR_STR}tree{MAIN_SEPARATOR_STR}{auteur}"
-    ));
+    // Stored tree layout: .eikyu/tree/<AK_USERNAME>, or the branch head in the cube.
+    let tree_dir = tree::tree_dir(&repository_root, &auteur);
+    let stored = match tree::Reference::open(&repository_root, &auteur).and_then(|r| r.tree()) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to read the sealed state: {e}");
+            return opts.error_code();
+        }
+    };
 
-    // Early exit if there is no stored snapshot yet.
-    if !tree_dir.exists() {
+    // Early exit if nothing was sealed yet.
+    if stored.is_empty() && !tree_dir.exists() {
         eprintln!("No stored tree found at: {}", tree_dir.to_string_lossy());
         eprintln!("Tip: run a command that creates the tree snapshot first.");
-        return ExitCode::FAILURE;
+        return opts.error_code();
     }
 
-    // Enumerate repo files as relative paths, sorted for stable output.
-    let repo_list = match collect_files(&repository_root) {
-        Ok(mut v) => {
-            v.sort();
-            v
-        }
+    let repo = match read_tree(&repository_root) {
+        Ok(t) => t,
         Err(e) => {
             eprintln!("Failed to enumerate repository files: {e}");
-            return ExitCode::FAILURE;
+            return opts.error_code();
         }
     };
+    diff_trees(&stored, &repo, opts)
+}
 
-    // Enumerate stored tree files as relative paths, sorted for stable output.
-    let tree_list = match collect_files(&tree_dir) {
-        Ok(mut v) => {
-            v.sort();
-            v
+/// Paths matching `patterns`, relative to the repository root: a file, every file below a
+/// directory, or a glob (`*` also matches `/`, so `*.rs` matches at any depth). `.` matches
+/// everything. Fails on an invalid glob.
+pub fn pathspec(patterns: &[&str]) -> io::Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let portable = pattern.replace('\\', "/");
+        let trimmed = portable.trim_start_matches("./").trim_end_matches('/');
+        let trimmed = if trimmed.is_empty() { "." } else { trimmed };
+        if trimmed == "." {
+            set.add(Glob::new("**").expect("valid glob"));
+            continue;
         }
-        Err(e) => {
-            eprintln!("Failed to enumerate stored tree files: {e}");
-            return ExitCode::FAILURE;
+        for glob in [trimmed.to_string(), format!("{trimmed}/**")] {
+            set.add(Glob::new(&glob).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{pattern}: {e}"))
+            })?);
         }
-    };
+    }
+    set.build().map_err(io::Error::other)
+}
 
-    // Instead of comparing only manifests, compute a 3-way classification:
-    // - additions (repo only)
-    // - deletions (tree only)
-    // - modifications (present on both sides but content differs)
-    use std::collections::HashSet;
-    use std::fs;
+/// Read every file below `root` (same enumeration as `diff`) into a `Tree`. Unreadable files are
+/// skipped; the report is best-effort.
+pub fn read_tree(root: &Path) -> io::Result<Tree> {
+    let mut out = Tree::new();
+    let files = collect_files(root)?;
+    in_order(
+        &files,
+        |rel| fs::read(paths::long(&root.join(rel))).ok(),
+        |(rel, bytes)| {
+            if let Some(bytes) = bytes {
+                out.insert(rel.replace(MAIN_SEPARATOR_STR, "/"), bytes);
+            }
+        },
+    );
+    Ok(out)
+}
 
-    let repo_set: HashSet<_> = repo_list.iter().cloned().collect();
-    let tree_set: HashSet<_> = tree_list.iter().cloned().collect();
+/// Apply `f` to every item of `items` on a pool of threads, one per core, and hand each item with
+/// its result to `emit` in the order of `items`, as soon as the results before it are in.
+fn in_order<'a, T: Sync, R: Send>(
+    items: &'a [T],
+    f: impl Fn(&T) -> R + Sync,
+    mut emit: impl FnMut((&'a T, R)),
+) {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if workers <= 1 {
+        items.iter().for_each(|item| emit((item, f(item))));
+        return;
+    }
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = channel();
+    std::thread::scope(|s| {
+        for _ in 0..workers {
+            let (tx, next, f) = (tx.clone(), &next, &f);
+            s.spawn(move || {
+                loop {
+                    let at = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(at) else { break };
+                    if tx.send((at, f(item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        // Results that came in before those of earlier items wait here.
+        let mut early = BTreeMap::new();
+        let mut due = 0;
+        for (at, result) in rx {
+            early.insert(at, result);
+            while let Some(result) = early.remove(&due) {
+                emit((&items[due], result));
+                due += 1;
+            }
+        }
+    });
+}
 
-    // Added files (present in repo, absent in tree).
-    for path in repo_set.difference(&tree_set) {
-        println!("{} {} {}", "+".green().bold(), path, "".normal());
+/// Paths that differ from `left` to `right`, sorted by path.
+pub fn changed_paths(left: &Tree, right: &Tree) -> Vec<(Change, String)> {
+    let mut out: Vec<(Change, String)> = Vec::new();
+    for (path, bytes) in right {
+        match left.get(path) {
+            None => out.push((Change::Added, path.clone())),
+            Some(old) if old != bytes => out.push((Change::Modified, path.clone())),
+            Some(_) => {}
+        }
     }
-    // Removed files (present in tree, absent in repo).
-    for path in tree_set.difference(&repo_set) {
-        println!("{} {} {}", "-".red().bold(), path, "".normal());
+    for path in left.keys().filter(|p| !right.contains_key(*p)) {
+        out.push((Change::Deleted, path.clone()));
     }
+    out.sort_by(|a, b| a.1.cmp(&b.1));
+    out
+}
 
-    // Potentially modified files (present on both sides).
-    for path in repo_set.intersection(&tree_set) {
-        let repo_p = repository_root.join(path);
-        let tree_p = tree_dir.join(path);
+/// Files renamed from `left` to `right`, as `(old, new)`: the `explicit` ones (new path -> old
+/// path) with `old` on the left only and `new` on the right only, then those detected by content
+/// among the other deletions and additions (see `rename::detect`).
+pub fn renames(
+    left: &Tree,
+    right: &Tree,
+    explicit: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = explicit
+        .iter()
+        .filter(|(new, old)| {
+            left.contains_key(*old)
+                && !right.contains_key(*old)
+                && right.contains_key(*new)
+                && !left.contains_key(*new)
+        })
+        .map(|(new, old)| (old.clone(), new.clone()))
+        .collect();
+    let paired = |p: &String| pairs.iter().any(|(old, new)| old == p || new == p);
+    let removed: Vec<(&str, &[u8])> = left
+        .iter()
+        .filter(|(p, _)| !right.contains_key(*p) && !paired(p))
+        .map(|(p, c)| (p.as_str(), c.as_slice()))
+        .collect();
+    let added: Vec<(&str, &[u8])> = right
+        .iter()
+        .filter(|(p, _)| !left.contains_key(*p) && !paired(p))
+        .map(|(p, c)| (p.as_str(), c.as_slice()))
+        .collect();
+    let detected = rename::detect(&removed, &added);
+    pairs.extend(detected.into_iter().map(|(new, old)| (old, new)));
+    pairs.sort();
+    pairs
+}
 
-        // Compare raw bytes first; if different, attempt a line-oriented diff for UTF‑8 text.
-        let repo_bytes = match fs::read(&repo_p) {
-            Ok(b) => b,
-            Err(_) => continue, // Skip unreadable files; report is best-effort
+/// Print the differences from `left` (the older side) to `right` in `opts.format`.
+///
+/// Returns ExitCode::SUCCESS, except with `Format::Quiet` where it is 1 if the trees differ (files
+/// left out by `opts.normalize_eol` or `opts.whitespace` don't count).
+pub fn diff_trees(left: &Tree, right: &Tree, opts: &DiffOptions) -> ExitCode {
+    if let Some(paths) = &opts.paths {
+        let only = |tree: &Tree| -> Tree {
+            tree.iter()
+                .filter(|(path, _)| paths.is_match(path))
+                .map(|(path, bytes)| (path.clone(), bytes.clone()))
+                .collect()
         };
-        let tree_bytes = match fs::read(&tree_p) {
-            Ok(b) => b,
-            Err(_) => continue,
+        let opts = DiffOptions {
+            paths: None,
+            ..opts.clone()
         };
+        return diff_trees(&only(left), &only(right), &opts);
+    }
+    match opts.format {
+        Format::Quiet if !file_stats(left, right, opts).is_empty() => return ExitCode::FAILURE,
+        Format::Quiet => {}
+        Format::Patch => print_patch(left, right, opts),
+        Format::Stat => print_stat(&file_stats(left, right, opts)),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&json(&file_stats(left, right, opts)))
+                .expect("serialize diff")
+        ),
+        Format::NameOnly => {
+            for f in file_stats(left, right, opts) {
+                println!("{}", f.path);
+            }
+        }
+        Format::NameStatus => {
+            for f in file_stats(left, right, opts) {
+                match f.code {
+                    'R' => println!("R\t{}\t{}", f.old, f.path),
+                    code => println!("{code}\t{}", f.path),
+                }
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
 
-        if repo_bytes != tree_bytes {
-            match (
-                std::str::from_utf8(&tree_bytes),
-                std::str::from_utf8(&repo_bytes),
-            ) {
-                // Text diff for UTF‑8 on both sides.
-                (Ok(left), Ok(right)) => {
-                    println!("\n{} {}", "diff:".yellow().bold(), path);
-                    for d in diff::lines(left, right) {
-                        match d {
-                            diff::Result::Left(line) => {
-                                println!("{} {}", "-".red().bold(), line.red());
-                            }
-                            diff::Result::Right(line) => {
-                                println!("{} {}", "+".green().bold(), line.green());
-                            }
-                            diff::Result::Both(line, _) => {
-                                // Optionally display unchanged context as dimmed text.
-                                // Keeping output concise by default.
-                                let _ = line;
-                            }
-                        }
-                    }
+/// Files changed from `left` to `right`, sorted by path, with their line counts. Like the patch
+/// format, files that only differ by line endings are left out with `opts.normalize_eol`.
+pub fn file_stats(left: &Tree, right: &Tree, opts: &DiffOptions) -> Vec<FileStat> {
+    let renamed = renames(left, right, &opts.renames);
+    let is_renamed = |path: &String| renamed.iter().any(|(old, new)| old == path || new == path);
+
+    let mut files: Vec<(char, &str, &str)> = renamed
+        .iter()
+        .map(|(old, new)| ('R', old.as_str(), new.as_str()))
+        .collect();
+    let changed = changed_paths(left, right);
+    files.extend(
+        changed
+            .iter()
+            .filter(|(_, path)| !is_renamed(path))
+            .map(|(change, path)| (change.code(), path.as_str(), path.as_str())),
+    );
+
+    let mut out = Vec::new();
+    let stat = |&(code, old, new): &(char, &str, &str)| {
+        let (before, after) = (left.get(old), right.get(new));
+        let hunks = match text_changes(before, after, opts) {
+            Some(hunks) => hunks,
+            // Renamed files are listed even when they only differ by line endings.
+            None if code == 'R' => Some(Vec::new()),
+            None => return None,
+        };
+        let mut f = FileStat::new(code, old, new, hunks);
+        if f.lines.is_none() {
+            f.binary = Some(binary_change(
+                before.map(Vec::as_slice),
+                after.map(Vec::as_slice),
+                opts.binary_similarity,
+            ));
+        }
+        Some(f)
+    };
+    in_order(&files, stat, |(_, f)| out.extend(f));
+    out.sort_by(|a, b| a.path.cmp(&b.path));
+    out
+}
+
+/// Hunks from `old` to `new` (`None` for an absent side), `Some(None)` when either side is binary
+/// and `None` when they only differ by line endings with `opts.normalize_eol`.
+fn text_changes(
+    old: Option<&Vec<u8>>,
+    new: Option<&Vec<u8>>,
+    opts: &DiffOptions,
+) -> Option<Option<Vec<Hunk>>> {
+    fn text(bytes: Option<&Vec<u8>>) -> Option<&str> {
+        match bytes {
+            Some(b) => std::str::from_utf8(b).ok(),
+            None => Some(""),
+        }
+    }
+    let (Some(a), Some(b)) = (text(old), text(new)) else {
+        return Some(None);
+    };
+    match text_hunks(a, b, opts) {
+        Some(hunks) => Some(Some(hunks)),
+        // Equal texts: an empty file added or removed, a file renamed without changes, or one
+        // that only differs by line endings.
+        None => (old == new || old.is_none() || new.is_none()).then_some(Some(Vec::new())),
+    }
+}
+
+/// The document printed by `ak diff --json`.
+pub fn json(stats: &[FileStat]) -> serde_json::Value {
+    let files: Vec<JsonFile> = stats
+        .iter()
+        .map(|f| JsonFile {
+            change: match f.code {
+                'A' => "added",
+                'D' => "removed",
+                'R' => "renamed",
+                _ => "modified",
+            },
+            path: &f.path,
+            old_path: (f.old != f.path).then_some(f.old.as_str()),
+            binary: f.lines.is_none(),
+            added: f.lines.map_or(0, |l| l.0),
+            removed: f.lines.map_or(0, |l| l.1),
+            hunks: &f.hunks,
+            binary_change: f.binary.as_ref(),
+        })
+        .collect();
+    serde_json::json!({ "files": files })
+}
+
+/// Lengths of the `+` and `-` bars of a file with `added` and `removed` lines, when the largest
+/// change of the listing is `max` lines: scaled down to `width`, a change keeps at least one mark.
+fn bars(added: usize, removed: usize, max: usize, width: usize) -> (usize, usize) {
+    if max <= width {
+        return (added, removed);
+    }
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => (n * width / max).max(1),
+    };
+    (scale(added), scale(removed))
+}
+
+/// Print `ak diff --stat`: one line per file, then the totals.
+fn print_stat(stats: &[FileStat]) {
+    let label = |f: &FileStat| match f.code {
+        'R' => format!("{} => {}", f.old, f.path),
+        _ => f.path.clone(),
+    };
+    let name_width = stats
+        .iter()
+        .map(|f| label(f).chars().count())
+        .max()
+        .unwrap_or(0);
+    let max = stats
+        .iter()
+        .filter_map(|f| f.lines.map(|(a, r)| a + r))
+        .max()
+        .unwrap_or(0);
+    let count_width = max.to_string().len().max(3);
+    let (mut added, mut removed) = (0, 0);
+    for f in stats {
+        let shown = match f.lines {
+            Some((a, r)) => {
+                added += a;
+                removed += r;
+                let (plus, minus) = bars(a, r, max, STAT_WIDTH);
+                let mut bar = String::new();
+                if plus > 0 {
+                    bar.push_str(&"+".repeat(plus).green().to_string());
                 }
-                // Non-text or invalid UTF‑8: mark as modified binary.
-                _ => {
-                    println!(
-                        "{} {} {}",
-                        "~".yellow().bold(),
-                        path,
-                        "(modified binary)".yellow()
-                    );
+                if minus > 0 {
+                    bar.push_str(&"-".repeat(minus).red().to_string());
                 }
+                format!("{:>count_width$} {bar}", a + r)
+                    .trim_end()
+                    .to_string()
+            }
+            None => {
+                let size = |n: Option<u64>| n.unwrap_or(0);
+                let b = f.binary.as_ref();
+                format!(
+                    "{:>count_width$} {} -> {} bytes",
+                    "Bin",
+                    size(b.and_then(|b| b.old_size)),
+                    size(b.and_then(|b| b.new_size))
+                )
             }
+        };
+        println!(" {:<name_width$} | {shown}", label(f));
+    }
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    println!(
+        " {}, {}(+), {}(-)",
+        plural(stats.len(), "file changed", "files changed"),
+        plural(added, "insertion", "insertions"),
+        plural(removed, "deletion", "deletions")
+    );
+}
+
+/// Print the differences from `left` to `right` as a patch:
+/// - Renamed: see `renames`, followed by the line diff when the content changed too
+/// - Added: present in `right` only
+/// - Removed: present in `left` only
+/// - Modified: present on both sides with different content. If both sides are valid UTF‑8, a
+///   line-by-line diff is printed (after CRLF → LF normalization when `opts.normalize_eol` is
+///   set; files differing only by line endings are then skipped). Otherwise the binary change is
+///   described (see `BinaryChange::describe`).
+fn print_patch(left: &Tree, right: &Tree, opts: &DiffOptions) {
+    let renamed = renames(left, right, &opts.renames);
+    for (old, new) in &renamed {
+        println!("{} {old} -> {new}", "R".blue().bold());
+    }
+    let is_renamed = |path: &String| renamed.iter().any(|(old, new)| old == path || new == path);
+    // Added files (present on the right, absent on the left).
+    for path in right
+        .keys()
+        .filter(|p| !left.contains_key(*p) && !is_renamed(p))
+    {
+        println!("{} {} {}", "+".green().bold(), path, "".normal());
+    }
+    // Removed files (present on the left, absent on the right).
+    for path in left
+        .keys()
+        .filter(|p| !right.contains_key(*p) && !is_renamed(p))
+    {
+        println!("{} {} {}", "-".red().bold(), path, "".normal());
+    }
+
+    // Potentially modified files: present on both sides, or renamed.
+    let pairs: Vec<(&String, &String)> = left
+        .keys()
+        .filter(|p| right.contains_key(*p))
+        .map(|p| (p, p))
+        .chain(renamed.iter().map(|(old, new)| (old, new)))
+        .collect();
+    let compare = |&(old, new): &(&String, &String)| {
+        let (left_bytes, right_bytes) = (&left[old], &right[new]);
+        if left_bytes == right_bytes {
+            return None;
+        }
+        match (
+            std::str::from_utf8(left_bytes),
+            std::str::from_utf8(right_bytes),
+        ) {
+            // Text diff for UTF‑8 on both sides.
+            (Ok(left), Ok(right)) => text_hunks(left, right, opts).map(Ok),
+            // Non-text or invalid UTF‑8: describe the binary change.
+            _ => Some(Err(binary_change(
+                Some(left_bytes),
+                Some(right_bytes),
+                opts.binary_similarity,
+            ))),
         }
+    };
+    in_order(&pairs, compare, |(&(old, new), change)| match change {
+        None => {}
+        Some(Ok(hunks)) => {
+            println!();
+            println!("{}", format!("--- a/{old}").bold());
+            println!("{}", format!("+++ b/{new}").bold());
+            for h in &hunks {
+                print_hunk(h);
+            }
+        }
+        Some(Err(change)) => {
+            let label = if old == new {
+                old.clone()
+            } else {
+                format!("{old} -> {new}")
+            };
+            println!(
+                "{} {} {}",
+                "~".yellow().bold(),
+                label,
+                format!("(binary: {})", change.describe()).yellow()
+            );
+        }
+    });
+}
+
+/// Hunks between two texts, honoring `opts`; `None` when they are equivalent.
+fn text_hunks(left: &str, right: &str, opts: &DiffOptions) -> Option<Vec<Hunk>> {
+    let left = normalize_eol(left, opts.normalize_eol);
+    let right = normalize_eol(right, opts.normalize_eol);
+    if left == right {
+        return None;
     }
-    ExitCode::SUCCESS
+    let found = hunks_ignoring(&left, &right, opts.context, opts.whitespace);
+    (!found.is_empty()).then_some(found)
 }
 
 /// Recursively collect all file paths under `root` and return them as relative strings.
@@ -158,13 +1059,12 @@ pub fn diff() -> ExitCode {
 /// Returns:
 /// - Ok(`Vec<String>`) sorted by the caller for stable output
 /// - Err(`io::Error`) if traversal cannot be constructed or read
-fn collect_files(root: &Path) -> io::Result<Vec<String>> {
+pub(crate) fn collect_files(root: &Path) -> io::Result<Vec<String>> {
     // Precompute a path prefix that will be stripped to create relative paths.
     let dir = format!("{}{}", root.display(), MAIN_SEPARATOR_STR);
     let mut out = Vec::new();
 
-    ignore::WalkBuilder::new(root)
-        .add_custom_ignore_filename(".ignore")
+    akignore::walker(root)
         .build()
         .filter(Result::is_ok)
         .filter(|f| {
@@ -192,3 +1092,264 @@ fn collect_files(root: &Path) -> io::Result<Vec<String>> {
 
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_eol_skips_crlf_only_changes() {
+        let eol = DiffOptions {
+            normalize_eol: true,
+            ..Default::default()
+        };
+        assert!(text_hunks("a\nb\n", "a\r\nb\r\n", &eol).is_none());
+        assert!(text_hunks("a\nb\n", "a\r\nb\r\n", &DiffOptions::default()).is_some());
+
+        let found = text_hunks("a\nb\n", "a\r\nc\r\n", &eol).unwrap();
+        assert_eq!(
+            found[0].lines,
+            [
+                Line::Context("a\n".to_string()),
+                Line::Removed("b\n".to_string()),
+                Line::Added("c\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hunks_with_context() {
+        let old: String = (1..=20).map(|n| format!("l{n}\n")).collect();
+        let new = old
+            .replace("l3\n", "three\n")
+            .replace("l18\n", "eighteen\n");
+        let found = hunks(&old, &new, 2);
+        let headers: Vec<String> = found.iter().map(Hunk::header).collect();
+        assert_eq!(headers, ["@@ -1,5 +1,5 @@", "@@ -16,5 +16,5 @@"]);
+        // Changes closer than two contexts share a hunk.
+        assert_eq!(hunks(&old, &new, 7).len(), 1);
+        let headers: Vec<String> = hunks(&old, &new, 0).iter().map(Hunk::header).collect();
+        assert_eq!(headers, ["@@ -3 +3 @@", "@@ -18 +18 @@"]);
+        assert_eq!(hunks("", "x", 3)[0].header(), "@@ -0,0 +1 @@");
+    }
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let old = "fn main() {\n    call(a, b);\n}\n";
+        let new = "fn main() {\n\tcall(a,  b);   \n}\n";
+        let opts = |whitespace| DiffOptions {
+            whitespace,
+            ..Default::default()
+        };
+        assert_eq!(
+            text_hunks(old, new, &opts(Whitespace::Exact))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(text_hunks(old, new, &opts(Whitespace::IgnoreChange)).is_none());
+        let squeezed = "fn main() {\n    call(a,b);\n}\n";
+        assert!(text_hunks(old, squeezed, &opts(Whitespace::IgnoreChange)).is_some());
+        assert!(text_hunks(old, squeezed, &opts(Whitespace::IgnoreAll)).is_none());
+        // Lines that only changed in whitespace stay as context next to real changes.
+        let edited = "fn main() {\n\tcall(a, b);\n}\nmore\n";
+        let found = text_hunks(old, edited, &opts(Whitespace::IgnoreChange)).unwrap();
+        assert_eq!(
+            found[0].lines[1],
+            Line::Context("\tcall(a, b);\n".to_string())
+        );
+        assert_eq!(found[0].header(), "@@ -1,3 +1,4 @@");
+    }
+
+    #[test]
+    fn test_quiet_exit_code() {
+        let tree = |text: &str| Tree::from([("a.txt".to_string(), text.as_bytes().to_vec())]);
+        let quiet = |whitespace| DiffOptions {
+            format: Format::Quiet,
+            whitespace,
+            ..Default::default()
+        };
+        let (old, new) = (tree("a b\n"), tree("a  b\n"));
+        assert_eq!(
+            diff_trees(&old, &old, &quiet(Whitespace::Exact)),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(
+            diff_trees(&old, &new, &quiet(Whitespace::Exact)),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            diff_trees(&old, &new, &quiet(Whitespace::IgnoreChange)),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(quiet(Whitespace::Exact).error_code(), ExitCode::from(2));
+    }
+
+    #[test]
+    fn test_in_order() {
+        let items: Vec<u64> = (0..200).collect();
+        let mut seen = Vec::new();
+        in_order(
+            &items,
+            |&n| {
+                // Make early items finish last.
+                std::thread::sleep(std::time::Duration::from_micros(200 - n));
+                n * 2
+            },
+            |(&n, double)| seen.push((n, double)),
+        );
+        assert_eq!(seen, items.iter().map(|&n| (n, n * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pathspec() {
+        let spec = pathspec(&["./src/", "*.md", "Cargo.toml"]).unwrap();
+        let matched: Vec<&str> = [
+            "src/a.rs",
+            "src/data/b.rs",
+            "srcs/c.rs",
+            "docs/x.md",
+            "Cargo.toml",
+            "Cargo.lock",
+        ]
+        .into_iter()
+        .filter(|p| spec.is_match(p))
+        .collect();
+        assert_eq!(
+            matched,
+            ["src/a.rs", "src/data/b.rs", "docs/x.md", "Cargo.toml"]
+        );
+        assert!(pathspec(&["."]).unwrap().is_match("any/file"));
+        assert!(pathspec(&["a[b"]).is_err());
+
+        let tree = Tree::from([
+            ("src/a.rs".to_string(), b"a\n".to_vec()),
+            ("b.txt".to_string(), b"b\n".to_vec()),
+        ]);
+        let mut changed = tree.clone();
+        changed.insert("b.txt".to_string(), b"c\n".to_vec());
+        let opts = DiffOptions {
+            format: Format::Quiet,
+            paths: Some(pathspec(&["src"]).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(diff_trees(&tree, &changed, &opts), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_binary_change() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(64u32.to_be_bytes());
+        png.extend(32u32.to_be_bytes());
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend([10, 0, 20, 0]);
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc0, 0, 11, 8, 0, 48, 0, 96,
+        ];
+        let dims = |b: &[u8]| image(b).map(|i| (i.format, i.width, i.height));
+        assert_eq!(dims(&png), Some(("png", 64, 32)));
+        assert_eq!(dims(&gif), Some(("gif", 10, 20)));
+        assert_eq!(dims(&jpeg), Some(("jpeg", 96, 48)));
+        assert_eq!(dims(b"\0\x01"), None);
+
+        let change = binary_change(Some(&png), Some(&gif), true);
+        assert_eq!((change.old_size, change.new_size), (Some(24), Some(10)));
+        assert_eq!(change.old_hash.as_ref().map(String::len), Some(12));
+        assert!(
+            change
+                .describe()
+                .ends_with("png 64x32 -> gif 10x20, 0% similar")
+        );
+        let added = binary_change(None, Some(&gif), false);
+        assert_eq!(
+            added.describe(),
+            format!(
+                "- -> 10 bytes, - -> {}, - -> gif 10x20",
+                added.new_hash.clone().unwrap()
+            )
+        );
+
+        let old: Vec<u8> = (0..20)
+            .flat_map(|n| format!("l{n}\n").into_bytes())
+            .collect();
+        let mut new = old.clone();
+        new.insert(30, 0xff);
+        assert_eq!(byte_similarity(&old, &old), 100);
+        assert!((70..100).contains(&byte_similarity(&old, &new)));
+    }
+
+    #[test]
+    fn test_word_spans() {
+        let (old, new) = word_spans(
+            r#"{"name": "ak", "size": 12}"#,
+            r#"{"name": "ak", "size": 14}"#,
+        )
+        .unwrap();
+        let changed = |spans: &[Span]| -> Vec<String> {
+            spans.iter().filter(|s| s.0).map(|s| s.1.clone()).collect()
+        };
+        assert_eq!(
+            (changed(&old), changed(&new)),
+            (vec!["12".to_string()], vec!["14".to_string()])
+        );
+        let whole: String = new.iter().map(|s| s.1.as_str()).collect();
+        assert_eq!(whole, r#"{"name": "ak", "size": 14}"#);
+        assert!(word_spans("one two three", "four five six").is_none());
+        assert_eq!(words("a_b, c"), ["a_b", ",", " ", "c"]);
+    }
+
+    #[test]
+    fn test_file_stats() {
+        let tree = |files: &[(&str, &[u8])]| -> Tree {
+            files
+                .iter()
+                .map(|(p, c)| (p.to_string(), c.to_vec()))
+                .collect()
+        };
+        let before = tree(&[("a", b"1\n2\n3\n"), ("bin", &[0xff, 1]), ("gone", b"x\n")]);
+        let after = tree(&[
+            ("a", b"1\ntwo\n3\n4\n"),
+            ("bin", &[0xff, 2]),
+            ("new", b"n\n"),
+        ]);
+        let found: Vec<String> = file_stats(&before, &after, &DiffOptions::default())
+            .iter()
+            .map(|f| format!("{} {} {:?}", f.code, f.path, f.lines))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "M a Some((2, 1))",
+                "M bin None",
+                "D gone Some((0, 1))",
+                "A new Some((1, 0))",
+            ]
+        );
+        let doc = json(&file_stats(&before, &after, &DiffOptions::default()));
+        assert_eq!(doc["files"][0]["hunks"][0]["lines"][1]["kind"], "removed");
+        assert_eq!(doc["files"][1]["binary"], true);
+        assert_eq!(doc["files"][2]["change"], "removed");
+        assert_eq!(bars(3, 1, 10, 40), (3, 1));
+        assert_eq!(bars(100, 1, 200, 40), (20, 1));
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let tree = |files: &[(&str, &str)]| -> Tree {
+            files
+                .iter()
+                .map(|(p, c)| (p.to_string(), c.as_bytes().to_vec()))
+                .collect()
+        };
+        let before = tree(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        let after = tree(&[("a", "1"), ("b", "2"), ("d", "1")]);
+        assert_eq!(
+            changed_paths(&before, &after),
+            [
+                (Change::Modified, "b".to_string()),
+                (Change::Deleted, "c".to_string()),
+                (Change::Added, "d".to_string()),
+            ]
+        );
+    }
+}
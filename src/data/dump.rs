@@ -0,0 +1,218 @@
+//! Annotated hex dumps of on-disk records, for debugging format issues and corruption.
+//!
+//! The dump follows the record layout documented in `data::write` field by field, so a reader
+//! can see exactly where a length, checksum or string goes wrong. It never trusts the bytes:
+//! truncated or inconsistent records are dumped as far as they go and flagged.
+
+use crate::event::RecordType;
+use crc32fast::Hasher;
+use std::fmt::Write;
+
+/// Bytes shown per hex line.
+const WIDTH: usize = 16;
+
+/// Render `raw` (a record as read from disk, starting with its LEN_TOTAL prefix) located at file
+/// offset `offset` of a cube of format `version` as an annotated hexdump.
+pub fn annotate(offset: u64, raw: &[u8], version: u16) -> String {
+    let mut out = String::new();
+    let mut fields = Fields {
+        raw,
+        pos: 0,
+        offset,
+        out: &mut out,
+    };
+
+    let Some(len) = fields.take(4, "LEN_TOTAL", |b| {
+        u32::from_le_bytes(b.try_into().unwrap()).to_string()
+    }) else {
+        return out;
+    };
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    fields.take(16, "TS", |b| {
+        format!("{} ns", u128::from_le_bytes(b.try_into().unwrap()))
+    });
+    fields.take(8, "ID", |b| {
+        u64::from_le_bytes(b.try_into().unwrap()).to_string()
+    });
+    let typed = (version > 1) as usize;
+    if typed == 1 {
+        fields.take(1, "TYPE", |b| format!("{:?}", RecordType::from_byte(b[0])));
+    }
+    let ph_len = fields.take(2, "PH_LEN", |b| {
+        u16::from_le_bytes(b.try_into().unwrap()).to_string()
+    });
+    let no_len = fields.take(2, "NO_LEN", |b| {
+        u16::from_le_bytes(b.try_into().unwrap()).to_string()
+    });
+    let at_len = match version > 3 {
+        true => fields.take(2, "AT_LEN", |b| {
+            u16::from_le_bytes(b.try_into().unwrap()).to_string()
+        }),
+        false => None,
+    };
+    let str_len = |b: Option<&[u8]>| b.map_or(0, |b| u16::from_le_bytes(b.try_into().unwrap()));
+    let (ph_len, no_len) = (str_len(ph_len) as usize, str_len(no_len) as usize);
+    let at_len = str_len(at_len) as usize;
+    let fixed = 28 + typed + 2 * (version > 3) as usize;
+
+    // The CRC sits at the end of the entry as declared by LEN_TOTAL, wherever the strings end:
+    // prefix (4) + LEN_TOTAL - CRC (4).
+    let payload_end = len;
+    if 4 + fixed + ph_len + no_len + at_len != payload_end {
+        let _ = writeln!(
+            fields.out,
+            "!! LEN_TOTAL {len} disagrees with string lengths (expected {})",
+            fixed + ph_len + no_len + at_len + 4
+        );
+    }
+    fields.take(
+        ph_len.min(payload_end.saturating_sub(fields.pos)),
+        "PHENOMENON",
+        utf8,
+    );
+    fields.take(
+        no_len.min(payload_end.saturating_sub(fields.pos)),
+        "NOUMENON",
+        utf8,
+    );
+    if version > 3 {
+        fields.take(
+            at_len.min(payload_end.saturating_sub(fields.pos)),
+            "ATTRIBUTES",
+            |b| format!("{} bytes", b.len()),
+        );
+    }
+    let extra = payload_end.min(raw.len()).saturating_sub(fields.pos);
+    if extra > 0 {
+        fields.take(extra, "UNACCOUNTED", |b| format!("{} bytes", b.len()));
+    }
+    let payload = raw.get(4..payload_end).map(|p| {
+        let mut h = Hasher::new();
+        h.update(p);
+        h.finalize()
+    });
+    fields.take(4, "CRC32", |b| {
+        let got = u32::from_le_bytes(b.try_into().unwrap());
+        match payload {
+            Some(expected) if expected == got => format!("{got:#010x} ok"),
+            Some(expected) => format!("{got:#010x} MISMATCH (payload hashes to {expected:#010x})"),
+            None => format!("{got:#010x} (payload truncated)"),
+        }
+    });
+    out
+}
+
+fn utf8(b: &[u8]) -> String {
+    match std::str::from_utf8(b) {
+        Ok(s) => format!("{} bytes, UTF-8", s.len()),
+        Err(e) => format!("{} bytes, INVALID UTF-8 at {}", b.len(), e.valid_up_to()),
+    }
+}
+
+/// Cursor that prints one record field at a time.
+struct Fields<'a> {
+    raw: &'a [u8],
+    pos: usize,
+    offset: u64,
+    out: &'a mut String,
+}
+
+impl<'a> Fields<'a> {
+    /// Dump the next `n` bytes as field `name`, described by `describe`; returns them, or `None`
+    /// when the record ends early (the bytes that are present are still dumped, then flagged).
+    fn take(
+        &mut self,
+        n: usize,
+        name: &str,
+        describe: impl FnOnce(&[u8]) -> String,
+    ) -> Option<&'a [u8]> {
+        let end = self.raw.len().min(self.pos.saturating_add(n));
+        let bytes = &self.raw[self.pos.min(end)..end];
+        let complete = bytes.len() == n;
+        let mut note = Some(if complete {
+            format!("{name} = {}", describe(bytes))
+        } else {
+            format!(
+                "!! {name}: truncated ({} of {n} bytes present)",
+                bytes.len()
+            )
+        });
+        if bytes.is_empty() {
+            let blank = format!("{:<w$}", "", w = WIDTH * 4 + 3);
+            self.line(self.at(), &blank, note.take());
+        }
+        for (i, chunk) in bytes.chunks(WIDTH).enumerate() {
+            let hex: String = chunk.iter().map(|b| format!("{b:02x} ")).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let cols = format!("{hex:<w$} |{ascii:<WIDTH$}|", w = WIDTH * 3);
+            self.line(self.at() + (i * WIDTH) as u64, &cols, note.take());
+        }
+        self.pos = end;
+        complete.then_some(bytes)
+    }
+
+    fn line(&mut self, at: u64, cols: &str, note: Option<String>) {
+        let line = format!("{at:08x}  {cols}  {}", note.unwrap_or_default());
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+
+    fn at(&self) -> u64 {
+        self.offset + self.pos as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_annotate_flags_crc_mismatch() {
+        let mut payload = vec![0u8; 16];
+        payload.extend_from_slice(&7u64.to_le_bytes());
+        payload.extend_from_slice(&1u16.to_le_bytes());
+        payload.extend_from_slice(&2u16.to_le_bytes());
+        payload.extend_from_slice(b"ahi");
+        let mut raw = ((payload.len() + 4) as u32).to_le_bytes().to_vec();
+        raw.extend_from_slice(&payload);
+        raw.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+
+        let dump = annotate(16, &raw, 1);
+        assert!(dump.starts_with("00000010  "));
+        assert!(dump.contains("ID = 7"));
+        assert!(dump.contains("CRC32 = ") && dump.contains(" ok"));
+        assert!(!dump.contains("TYPE") && !dump.contains("disagrees"));
+        // Read as version 2, the low byte of PH_LEN is taken for the type.
+        assert!(annotate(16, &raw, 2).contains("TYPE = Commit"));
+
+        *raw.last_mut().unwrap() ^= 1;
+        assert!(annotate(16, &raw, 1).contains("MISMATCH"));
+        assert!(annotate(16, &raw[..10], 1).contains("TS: truncated"));
+
+        // Version 4 records end with their attribute block.
+        let attrs = Writer::encode_attributes(&[("pid".into(), "42".into())].into()).unwrap();
+        let mut payload = vec![0u8; 16];
+        payload.extend_from_slice(&7u64.to_le_bytes());
+        payload.push(0);
+        for n in [1, 2, attrs.len()] {
+            payload.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        payload.extend_from_slice(b"ahi");
+        payload.extend_from_slice(&attrs);
+        let mut raw = ((payload.len() + 4) as u32).to_le_bytes().to_vec();
+        raw.extend_from_slice(&payload);
+        raw.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        let dump = annotate(40, &raw, 4);
+        assert!(dump.contains("AT_LEN = 8") && dump.contains("ATTRIBUTES = 8 bytes"));
+        assert!(dump.contains(" ok") && !dump.contains("disagrees"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Cube export to external formats for ad-hoc analysis.
+//!
+//! Supported formats:
+//! - `sqlite`: a single `records` table indexed by phenomenon and timestamp, plus an
+//!   FTS5 virtual table (`records_fts`) over phenomenon and noumenon for full-text search.
+//! - `parquet` (feature `parquet`): a columnar file with one row per record
+//!   (timestamp, id, phenomenon, size, hash and optionally the content), readable by DuckDB/Spark.
+//!
+//! Exports are read-only with respect to the cube: records are scanned once and copied
+//! verbatim, so the exported database can be thrown away and regenerated at any time.
+
+use crate::data::write::Writer;
+use rusqlite::{Connection, params};
+use std::io;
+use std::path::Path;
+
+/// Schema of the SQLite export.
+///
+/// - `records`: one row per valid record, `id` is the cube record id.
+/// - `records_fts`: external-content FTS5 table mirroring `records`, queried with `MATCH`.
+const SQLITE_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS records (
+    id          INTEGER PRIMARY KEY,
+    ts          INTEGER NOT NULL,
+    phenomenon  TEXT    NOT NULL,
+    noumenon    TEXT    NOT NULL,
+    size        INTEGER NOT NULL,
+    hash        TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_phenomenon ON records (phenomenon);
+CREATE INDEX IF NOT EXISTS records_ts ON records (ts);
+CREATE VIRTUAL TABLE IF NOT EXISTS records_fts USING fts5 (
+    phenomenon,
+    noumenon,
+    content = 'records',
+    content_rowid = 'id'
+);
+"#;
+
+/// Export every valid record of the cube at `cube_path` into the SQLite database at `out`.
+///
+/// Behavior:
+/// - The database is created if missing; existing rows with the same id are replaced,
+///   so re-exporting the same cube is idempotent.
+/// - All inserts run in a single transaction.
+/// - `ts` is stored in nanoseconds since UNIX epoch; `hash` is the BLAKE3 hex of the noumenon.
+/// - The cube is opened read-only; a missing cube is an error.
+///
+/// Returns the number of exported records.
+pub fn to_sqlite(cube_path: &str, out: &Path) -> io::Result<usize> {
+    let mut reader = Writer::open(cube_path)?;
+    let events = reader.events()?;
+
+    let mut conn = Connection::open(out).map_err(io::Error::other)?;
+    conn.execute_batch(SQLITE_SCHEMA)
+        .map_err(io::Error::other)?;
+
+    let tx = conn.transaction().map_err(io::Error::other)?;
+    {
+        let mut insert = tx
+            .prepare(
+                "INSERT OR REPLACE INTO records (id, ts, phenomenon, noumenon, size, hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .map_err(io::Error::other)?;
+        for ev in &events {
+            // SQLite integers are signed 64-bit; nanosecond timestamps fit until year 2262.
+            let ts = i64::try_from(ev.timestamp).unwrap_or(i64::MAX);
+            let hash = blake3::hash(ev.noumenon.as_bytes()).to_hex().to_string();
+            insert
+                .execute(params![
+                    ev.id as i64,
+                    ts,
+                    ev.phenomenon,
+                    ev.noumenon,
+                    ev.noumenon.len() as i64,
+                    hash
+                ])
+                .map_err(io::Error::other)?;
+        }
+    }
+    // Rebuild the full-text index from the content table in one pass.
+    tx.execute(
+        "INSERT INTO records_fts (records_fts) VALUES ('rebuild')",
+        [],
+    )
+    .map_err(io::Error::other)?;
+    tx.commit().map_err(io::Error::other)?;
+
+    Ok(events.len())
+}
+
+/// Export every valid record of the cube at `cube_path` as a Parquet file at `out`.
+///
+/// Columns:
+/// - `timestamp`: nanoseconds since UNIX epoch (Arrow `Timestamp(Nanosecond, UTC)`)
+/// - `id`, `phenomenon`, `size` (noumenon bytes), `hash` (BLAKE3 hex of the noumenon)
+/// - `content`: the noumenon itself, only when `with_content` is set (null otherwise)
+///
+/// Rows are written in a single Snappy-compressed row group. Returns the number of exported records.
+#[cfg(feature = "parquet")]
+pub fn to_parquet(cube_path: &str, out: &Path, with_content: bool) -> io::Result<usize> {
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampNanosecondArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+    use std::sync::Arc;
+
+    let mut reader = Writer::open(cube_path)?;
+    let events = reader.events()?;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("id", DataType::UInt64, false),
+        Field::new("phenomenon", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("hash", DataType::Utf8, false),
+        Field::new("content", DataType::Utf8, true),
+    ]));
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampNanosecondArray::from_iter_values(
+                events
+                    .iter()
+                    .map(|e| i64::try_from(e.timestamp).unwrap_or(i64::MAX)),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(UInt64Array::from_iter_values(events.iter().map(|e| e.id))),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| e.phenomenon.as_str()),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            events.iter().map(|e| e.noumenon.len() as u64),
+        )),
+        Arc::new(StringArray::from_iter_values(events.iter().map(|e| {
+            blake3::hash(e.noumenon.as_bytes()).to_hex().to_string()
+        }))),
+        Arc::new(StringArray::from_iter(events.iter().map(|e| {
+            if with_content {
+                Some(e.noumenon.as_str())
+            } else {
+                None
+            }
+        }))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)?;
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let file = std::fs::File::create(out)?;
+    let mut writer = ArrowWriter::try_new(file, schema, Some(props)).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+
+    Ok(events.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A temp directory holding a cube with three records.
+    fn sample(tag: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("akasha-export-{tag}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut w = Writer::create(dir.join("c.cube").to_str().unwrap()).unwrap();
+        w.append("notes/a.md", "the quick brown fox").unwrap();
+        w.append("notes/b.md", "lazy dog").unwrap();
+        w.append("notes/a.md", "the quick red fox").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_to_sqlite_rows_and_fts() {
+        let dir = sample("sqlite");
+        let cube = dir.join("c.cube");
+        let db = dir.join("c.db");
+        assert_eq!(to_sqlite(cube.to_str().unwrap(), &db).unwrap(), 3);
+        // Re-exporting is idempotent.
+        assert_eq!(to_sqlite(cube.to_str().unwrap(), &db).unwrap(), 3);
+
+        let conn = Connection::open(&db).unwrap();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM records", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(rows, 3);
+        let mut stmt = conn
+            .prepare("SELECT rowid FROM records_fts WHERE records_fts MATCH 'fox' ORDER BY rowid")
+            .unwrap();
+        let ids: Vec<i64> = stmt
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(ids, [1, 3]);
+
+        let missing = dir.join("missing.cube");
+        assert!(to_sqlite(missing.to_str().unwrap(), &dir.join("m.db")).is_err());
+        assert!(!missing.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_to_parquet_schema_and_rows() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let dir = sample("parquet");
+        let out = dir.join("c.parquet");
+        assert_eq!(
+            to_parquet(dir.join("c.cube").to_str().unwrap(), &out, true).unwrap(),
+            3
+        );
+
+        let reader = SerializedFileReader::new(fs::File::open(&out).unwrap()).unwrap();
+        let meta = reader.metadata().file_metadata();
+        assert_eq!(meta.num_rows(), 3);
+        let columns: Vec<&str> = meta
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.name())
+            .collect();
+        assert_eq!(
+            columns,
+            ["timestamp", "id", "phenomenon", "size", "hash", "content"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
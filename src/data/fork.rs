@@ -0,0 +1,209 @@
+//! Cube forks: `eikyu cube fork <src> <dst>`.
+//!
+//! A fork is a copy of a cube, taken under its shared lock like a backup, after which both cubes
+//! grow on their own. Unlike a backup, the fork is a new cube with an identity of its own (see
+//! `data::write::Identity`). Each side then records the other in a metadata record along with the fork
+//! point, the last record they have in common: the source gets a `fork:to` record naming the
+//! fork, and the fork a `fork:from` record naming the source. Both are appended after the copy,
+//! so neither belongs to the common history.
+//!
+//! Cubes are named by their canonical path, and by their UUID when they have one.
+//! `merge_base` finds the fork point of two cubes from
+//! these links, so that tools combining them (`connect`, `merge`) only need to look at the
+//! records past it. A fork point is only trusted while both cubes still hold that record
+//! unchanged, as told by its `remote::digest`: rewriting a cube (`gc`, `rotate`) may drop it.
+//! A link is ignored altogether when the cube now at its path has another UUID.
+
+use crate::data::backup;
+use crate::data::remote;
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Phenomenon of the link from a fork to its source.
+pub const FORK_FROM: &str = "fork:from";
+/// Phenomenon of the link from a source to one of its forks.
+pub const FORK_TO: &str = "fork:to";
+
+/// Noumenon of `fork:from` and `fork:to` records.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Link {
+    /// Canonical path of the other cube.
+    pub cube: String,
+    /// Id of the last record common to both cubes, 0 for a fork of an empty cube.
+    pub fork_point: u64,
+    /// `remote::digest` of that record, empty without one.
+    pub digest: String,
+    /// UUID of the other cube, `None` before format version 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    /// Milliseconds since Unix epoch (UTC).
+    pub timestamp: u64,
+}
+
+/// Outcome of `fork`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forked {
+    /// Records copied into the fork.
+    pub records: usize,
+    pub fork_point: u64,
+}
+
+/// Name of the cube at `path` in links.
+fn name_of(path: &Path) -> io::Result<String> {
+    Ok(path.canonicalize()?.to_string_lossy().into_owned())
+}
+
+/// Copy the cube at `src` into a new cube at `dst` and link them (see the module documentation).
+///
+/// The fork gets the current format version and a fresh identity; bytes past the last valid
+/// record of `src` are left out of the copy. Fails with `AlreadyExists` when `dst` exists.
+pub fn fork(src: &Path, dst: &Path) -> io::Result<Forked> {
+    if dst.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{}: already exists", dst.display()),
+        ));
+    }
+    let bytes = backup::snapshot(src)?;
+    let events = Writer::decode(&bytes)?;
+    let next_id = events.iter().map(|e| e.id + 1).max().unwrap_or(1);
+    Writer::write_image(&mut File::create_new(dst)?, &events, next_id, None)?;
+
+    let (fork_point, digest) = events
+        .last()
+        .map_or((0, String::new()), |ev| (ev.id, remote::digest(ev)));
+    let timestamp = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    let link = |cube: &Path| {
+        serde_json::to_string(&Link {
+            cube: name_of(cube)?,
+            fork_point,
+            digest: digest.clone(),
+            uuid: Writer::identity_of(cube)?.map(|i| i.uuid),
+            timestamp,
+        })
+        .map_err(io::Error::other)
+    };
+    let (to_src, to_dst) = (link(src)?, link(dst)?);
+    Writer::create(&dst.to_string_lossy())?.append(FORK_FROM, &to_src)?;
+    Writer::create(&src.to_string_lossy())?.append(FORK_TO, &to_dst)?;
+    Ok(Forked {
+        records: events.len(),
+        fork_point,
+    })
+}
+
+/// Links recorded by the cube of `events`, as (phenomenon, link), in order.
+pub fn links(events: &[Event]) -> Vec<(&str, Link)> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == FORK_FROM || e.phenomenon == FORK_TO)
+        .filter_map(|e| {
+            Some((
+                e.phenomenon.as_str(),
+                serde_json::from_str(&e.noumenon).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Fork point of the cubes at `a` and `b`, whose records are `a_events` and `b_events`: the
+/// latest fork point linking them that both still hold. `None` when neither was forked from the
+/// other.
+pub fn merge_base(
+    a: &Path,
+    a_events: &[Event],
+    b: &Path,
+    b_events: &[Event],
+) -> io::Result<Option<u64>> {
+    let (a_name, b_name) = (name_of(a)?, name_of(b)?);
+    let uuid = |path: &Path| Ok::<_, io::Error>(Writer::identity_of(path)?.map(|i| i.uuid));
+    let (a_uuid, b_uuid) = (uuid(a)?, uuid(b)?);
+    // A link names the cube at a path, as long as it is the same cube.
+    let names = |link: &Link, name: &str, uuid: &Option<String>| {
+        link.cube == name && (link.uuid.is_none() || uuid.is_none() || link.uuid == *uuid)
+    };
+    let holds = |events: &[Event], link: &Link| {
+        link.fork_point == 0
+            || events
+                .iter()
+                .any(|e| e.id == link.fork_point && remote::digest(e) == link.digest)
+    };
+    Ok(links(a_events)
+        .into_iter()
+        .filter(|(_, l)| names(l, &b_name, &b_uuid))
+        .chain(
+            links(b_events)
+                .into_iter()
+                .filter(|(_, l)| names(l, &a_name, &a_uuid)),
+        )
+        .filter(|(_, l)| holds(a_events, l) && holds(b_events, l))
+        .map(|(_, l)| l.fork_point)
+        .max())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fork_links_both_cubes() {
+        let dir = std::env::temp_dir().join(format!("akasha-fork-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (src, dst, other) = (dir.join("a.cube"), dir.join("b.cube"), dir.join("c.cube"));
+        let mut w = Writer::create(src.to_str().unwrap()).unwrap();
+        w.append("a.txt", "1").unwrap();
+        w.append("a.txt", "2").unwrap();
+
+        let forked = fork(&src, &dst).unwrap();
+        assert_eq!(
+            forked,
+            Forked {
+                records: 2,
+                fork_point: 2
+            }
+        );
+        assert_eq!(
+            fork(&src, &dst).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        let mut f = Writer::create(dst.to_str().unwrap()).unwrap();
+        f.append("b.txt", "fork").unwrap();
+        w.append("a.txt", "3").unwrap();
+
+        let (a, b) = (w.events().unwrap(), f.events().unwrap());
+        assert_eq!(links(&a)[0].0, FORK_TO);
+        assert_eq!(links(&b)[0].1.cube, name_of(&src).unwrap());
+        // The fork is a cube of its own.
+        let src_uuid = w.identity().unwrap().unwrap().uuid;
+        assert_ne!(f.identity().unwrap().unwrap().uuid, src_uuid);
+        assert_eq!(links(&b)[0].1.uuid, Some(src_uuid));
+        // Ids go on from the fork point on both sides.
+        assert_eq!((a[3].id, b[3].id), (4, 4));
+        assert_eq!(merge_base(&src, &a, &dst, &b).unwrap(), Some(2));
+        assert_eq!(merge_base(&dst, &b, &src, &a).unwrap(), Some(2));
+
+        // A fork of the fork inherits its link to the source, and its fork point.
+        fork(&dst, &other).unwrap();
+        let c = Writer::open(other.to_str().unwrap())
+            .unwrap()
+            .events()
+            .unwrap();
+        assert_eq!(merge_base(&src, &a, &other, &c).unwrap(), Some(2));
+        assert_eq!(merge_base(&dst, &b, &other, &c).unwrap(), Some(4));
+        // Losing the fork point record unlinks the cubes.
+        assert_eq!(merge_base(&src, &a[1..], &dst, &b).unwrap(), Some(2));
+        assert_eq!(merge_base(&src, &a[2..], &dst, &b).unwrap(), None);
+        // Another cube in place of the source is not linked to the fork, even with its records.
+        drop(w);
+        fs::remove_file(&src).unwrap();
+        Writer::write_image(&mut File::create(&src).unwrap(), &a[..2], 3, None).unwrap();
+        assert_eq!(merge_base(&src, &a[..2], &dst, &b).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+//! Garbage collection of cube records: `ak gc`.
+//!
+//! Cubes only grow. Every seal leaves a `commit:pending` placeholder behind (it reserved the
+//! commit id), amending leaves the superseded commit with its manifest, and file versions stay
+//! in the cube after every commit referencing them is gone. `collect` rewrites the cubes without:
+//! - `commit:pending` records older than the given age;
+//! - superseded commits (see `data::commit`) that no live commit, branch, tag or stash still
+//!   reaches, along with their manifest, signature, renames, removals and notes;
+//! - file versions that no remaining commit contains. Commits sealed before manifests hold the
+//!   latest version of every path written before them, which stays, and so does everything
+//!   written after the last commit: it may belong to a seal in progress.
+//!
+//! Kept records keep their ids and timestamps, so commit ids, parents and manifest entries stay
+//! valid, and the last record of every cube is kept so that `ak pull` still finds where copies
+//! diverge. Like `retention::rotate`, every cube is rewritten in place under its lock through a
+//! `<cube>.gc-tmp` image, so writers opened before a collection keep working.
+
+use crate::data::branch;
+use crate::data::checkpoint;
+use crate::data::commit;
+use crate::data::config::{self, Config, Layout};
+use crate::data::history;
+use crate::data::manifest::Manifest;
+use crate::data::note::NOTE;
+use crate::data::paths;
+use crate::data::rename::{REMOVE, RENAME};
+use crate::data::restore::is_file_record;
+use crate::data::sign::SIGNATURE;
+use crate::data::stash;
+use crate::data::tag;
+use crate::data::write::Writer;
+use crate::event::Event;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Why a record is collected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Garbage {
+    /// A `commit:pending` placeholder.
+    Pending,
+    /// A superseded commit, or a record attached to one.
+    Superseded,
+    /// A file version no commit contains.
+    File,
+}
+
+/// What `collect` dropped, or would drop with `dry_run`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub cubes: usize,
+    pub pending: usize,
+    /// Superseded commits; `records` also counts their manifests, signatures and notes.
+    pub superseded: usize,
+    pub files: usize,
+    pub records: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Records of `events` (every record of one author) to drop, by id. Pending placeholders are
+/// dropped when written before `cutoff` (nanoseconds).
+pub fn select(events: &[Event], cutoff: u128) -> BTreeMap<u64, Garbage> {
+    let mut out = BTreeMap::new();
+    for ev in events.iter().filter(|e| e.phenomenon == "commit:pending") {
+        if ev.timestamp < cutoff {
+            out.insert(ev.id, Garbage::Pending);
+        }
+    }
+
+    // Commits still reachable from a live commit, a branch, a tag or a stash.
+    let gone = commit::superseded(events);
+    let mut parents: HashMap<u64, Vec<u64>> = HashMap::new();
+    for ev in events.iter().filter(|e| e.phenomenon == "commit") {
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).unwrap_or_default();
+        parents.entry(commit::commit_id(ev)).or_insert_with(|| {
+            ["parent", "merge_parent"]
+                .iter()
+                .filter_map(|k| v.get(k).and_then(|x| x.as_u64()))
+                .collect()
+        });
+    }
+    let mut queue: VecDeque<u64> = parents
+        .keys()
+        .copied()
+        .filter(|id| !gone.contains_key(id))
+        .collect();
+    queue.extend(branch::heads(events).into_values().flatten());
+    queue.extend(tag::tags(events).into_values());
+    queue.extend(
+        stash::stashes(events)
+            .into_iter()
+            .filter_map(|(_, s)| s.base),
+    );
+    let mut reached = HashSet::new();
+    while let Some(id) = queue.pop_front() {
+        if reached.insert(id) {
+            queue.extend(parents.get(&id).into_iter().flatten());
+        }
+    }
+    let dropped: HashSet<u64> = gone
+        .keys()
+        .copied()
+        .filter(|id| !reached.contains(id))
+        .collect();
+
+    let attached = |ev: &Event| {
+        serde_json::from_str::<serde_json::Value>(&ev.noumenon)
+            .ok()
+            .and_then(|v| v.get("commit")?.as_u64())
+    };
+    for ev in events {
+        let id = match ev.phenomenon.as_str() {
+            "commit" => commit::commit_id(ev),
+            SIGNATURE | RENAME | REMOVE | NOTE => match attached(ev) {
+                Some(id) => id,
+                None => continue,
+            },
+            ph => match ph.strip_prefix("manifest:").and_then(|c| c.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            },
+        };
+        if dropped.contains(&id) {
+            out.insert(ev.id, Garbage::Superseded);
+        }
+    }
+
+    // File versions of the remaining commits.
+    let mut needed = HashSet::new();
+    let mut latest: HashMap<String, u64> = HashMap::new();
+    let mut kept_commits = events
+        .iter()
+        .filter(|e| e.phenomenon == "commit" && !dropped.contains(&commit::commit_id(e)))
+        .peekable();
+    for ev in events {
+        // Pre-manifest commits hold the latest version of every path written before them.
+        while let Some(c) = kept_commits.next_if(|c| c.id <= ev.id) {
+            if Manifest::of_commit(events, commit::commit_id(c)).is_none() {
+                needed.extend(latest.values().copied());
+            }
+        }
+        if is_file_record(ev) {
+            latest.insert(paths::canonical(&ev.phenomenon), ev.id);
+        }
+    }
+    for c in events
+        .iter()
+        .filter(|e| e.phenomenon == "commit" && !dropped.contains(&commit::commit_id(e)))
+    {
+        if let Some(m) = Manifest::of_commit(events, commit::commit_id(c)) {
+            needed.extend(m.files.values().map(|e| e.record));
+        }
+    }
+    let last_commit = events
+        .iter()
+        .filter(|e| e.phenomenon == "commit")
+        .map(|e| e.id)
+        .max()
+        .unwrap_or(0);
+    for ev in events.iter().filter(|e| is_file_record(e)) {
+        if ev.id < last_commit && !needed.contains(&ev.id) {
+            out.insert(ev.id, Garbage::File);
+        }
+    }
+    out
+}
+
+/// Collect the garbage of every cube of the repository at `root`, dropping pending placeholders
+/// written before `cutoff` (nanoseconds). With `dry_run`, only computes the report.
+pub fn collect(root: &Path, cutoff: u128, dry_run: bool) -> io::Result<Report> {
+    // Records are selected over each author's cubes as a whole: a commit references records of
+    // earlier months.
+    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+    if Config::load(root)?.storage.layout == Layout::Shared {
+        let path = config::shared_cube(root);
+        if path.exists() {
+            groups.push(vec![path]);
+        }
+    } else {
+        let cubes = history::cube_files(root)?;
+        for author in history::authors(root)? {
+            groups.push(
+                cubes
+                    .iter()
+                    .filter(|c| c.author == author)
+                    .map(|c| c.path.clone())
+                    .collect(),
+            );
+        }
+    }
+
+    let mut report = Report::default();
+    for cubes in groups {
+        let mut events = Vec::new();
+        for cube in &cubes {
+            events.extend(Writer::open(&cube.to_string_lossy())?.events()?);
+        }
+        let mut ids = HashSet::new();
+        if let Some(ev) = events.iter().find(|e| !ids.insert(e.id)) {
+            return Err(io::Error::other(format!(
+                "{}: record id {} is used twice; cubes written before ids continued across \
+                 months can't be collected",
+                cubes[0].display(),
+                ev.id
+            )));
+        }
+        let garbage = select(&events, cutoff);
+        for cube in &cubes {
+            sweep(cube, &garbage, dry_run, &mut report)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Drop the `garbage` records of the cube at `path`, except its last record, adding them to
+/// `report`.
+fn sweep(
+    path: &Path,
+    garbage: &BTreeMap<u64, Garbage>,
+    dry_run: bool,
+    report: &mut Report,
+) -> io::Result<()> {
+    let mut f = File::options().read(true).write(true).open(path)?;
+    f.lock()?;
+    let result = sweep_locked(path, &mut f, garbage, dry_run, report);
+    f.unlock()?;
+    result
+}
+
+fn sweep_locked(
+    path: &Path,
+    f: &mut File,
+    garbage: &BTreeMap<u64, Garbage>,
+    dry_run: bool,
+    report: &mut Report,
+) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    f.read_to_end(&mut bytes)?;
+    let events = Writer::decode(&bytes)?;
+    let version = Writer::image_version(&bytes)?;
+    let valid = Writer::header_len(version)
+        + events
+            .iter()
+            .map(|e| Writer::record_len(version, e))
+            .sum::<u64>();
+    if valid != bytes.len() as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: {} trailing bytes are not valid records; refusing to compact",
+                path.display(),
+                bytes.len() as u64 - valid
+            ),
+        ));
+    }
+    let next_id = events.iter().map(|e| e.id + 1).max().unwrap_or(1);
+    let last = events.last().map(|e| e.id);
+    let (mut kept, mut dropped) = (Vec::new(), Vec::new());
+    for ev in events {
+        match garbage.get(&ev.id) {
+            Some(kind) if Some(ev.id) != last => {
+                match kind {
+                    Garbage::Pending => report.pending += 1,
+                    Garbage::Superseded if ev.phenomenon == "commit" => report.superseded += 1,
+                    Garbage::Superseded => {}
+                    Garbage::File => report.files += 1,
+                }
+                report.records += 1;
+                dropped.push(ev);
+            }
+            _ => kept.push(ev),
+        }
+    }
+    Writer::detach_refs(&mut kept, &mut dropped);
+    let after = Writer::header_len(version)
+        + kept
+            .iter()
+            .map(|e| Writer::record_len(version, e))
+            .sum::<u64>();
+    report.cubes += 1;
+    report.bytes_before += bytes.len() as u64;
+    report.bytes_after += after;
+    if after == bytes.len() as u64 || dry_run {
+        return Ok(());
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".gc-tmp");
+    let tmp = PathBuf::from(tmp);
+    // The recovery image carries the identity the collected cube is about to get.
+    let identity = Writer::image_identity(&bytes)?.map(|i| i.bumped());
+    Writer::write_image(&mut File::create(&tmp)?, &kept, next_id, identity.as_ref())?;
+    Writer::write_image(f, &kept, next_id, identity.as_ref())?;
+    checkpoint::remove(path)?;
+    fs::remove_file(&tmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::index::Index;
+
+    #[test]
+    fn test_select_keeps_reachable_records() {
+        let dir = std::env::temp_dir().join(format!("akasha-gc-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let seal = |w: &mut Writer, base: &Manifest, files: &[(&str, &str)], parent: &str| {
+            let id = w.append("commit:pending", "msg").unwrap();
+            let mut index = Index::default();
+            for (path, content) in files {
+                index.stage_content(path, Some(content.to_string()));
+            }
+            let mut manifest = base.clone();
+            manifest.apply_index(w, &index).unwrap();
+            let m = manifest.record(w, id).unwrap();
+            w.append(
+                "commit",
+                &format!(r#"{{"id":{id},"manifest":{m},"parent":{parent}}}"#),
+            )
+            .unwrap();
+            (id, manifest)
+        };
+        let (first, base) = seal(&mut w, &Manifest::default(), &[("a", "1")], "null");
+        let (amended, _) = seal(
+            &mut w,
+            &base,
+            &[("b", "draft"), ("c", "1")],
+            &first.to_string(),
+        );
+        let (second, _) = seal(&mut w, &base, &[("b", "final")], &first.to_string());
+        commit::mark_superseded(&mut w, amended, second).unwrap();
+        crate::data::note::add(&mut w, amended, "u", "wip").unwrap();
+        branch::record_head(&mut w, branch::DEFAULT_BRANCH, Some(second)).unwrap();
+
+        let events = w.events().unwrap();
+        let garbage = select(&events, u128::MAX);
+        let dropped: Vec<(String, Garbage)> = events
+            .iter()
+            .filter_map(|e| Some((e.phenomenon.clone(), *garbage.get(&e.id)?)))
+            .collect();
+        assert_eq!(
+            dropped,
+            [
+                ("commit:pending".to_string(), Garbage::Pending),
+                ("commit:pending".to_string(), Garbage::Pending),
+                // Versions only the amended commit contains.
+                ("b".to_string(), Garbage::File),
+                ("c".to_string(), Garbage::File),
+                (format!("manifest:{amended}"), Garbage::Superseded),
+                ("commit".to_string(), Garbage::Superseded),
+                ("commit:pending".to_string(), Garbage::Pending),
+                ("commit:note".to_string(), Garbage::Superseded),
+            ]
+        );
+        assert!(select(&events, 0).values().all(|g| *g != Garbage::Pending));
+
+        // A tag keeps the amended commit and its files.
+        tag::create(&mut w, &events, "draft", amended, false).unwrap();
+        let garbage = select(&w.events().unwrap(), 0);
+        assert!(garbage.is_empty(), "{garbage:?}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
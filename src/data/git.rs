@@ -0,0 +1,535 @@
+//! Git interoperability (feature `git`): the engine behind `ak export-git` and `ak import-git`.
+//!
+//! Export: every seal that was not superseded becomes a Git commit with the same author, email
+//! and date. Its message is `<type>: <summary>` followed by the body (see `message`), its tree
+//! holds the files of the seal's manifest, and its parents are the seal's parent and merge
+//! parent. Branch heads and tags become `refs/heads/*` and `refs/tags/*`; when several authors
+//! have cubes (per-author layout) their references are prefixed with the author, as in
+//! `refs/heads/ana/main`. Git objects only depend on their content, so exporting again into the
+//! same repository recreates the same commits and only moves the references forward.
+//!
+//! Import: `walk` reads the commits of every local branch, parents first, with the files each
+//! one changed since its first parent; the caller turns them into seals. Conventional subjects
+//! (`fix(cli): …`) give the seal its type, other subjects get the type `commit`.
+
+#[cfg(feature = "git")]
+use crate::data::config::{Config, Layout};
+#[cfg(feature = "git")]
+use crate::data::{branch, commit, history, restore, tag};
+#[cfg(feature = "git")]
+use crate::event::Event;
+use std::collections::BTreeMap;
+#[cfg(feature = "git")]
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+/// Type given to imported commits whose subject doesn't name one.
+pub const DEFAULT_TYPE: &str = "commit";
+
+/// A Git commit read by `walk`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitCommit {
+    /// Hex object id.
+    pub id: String,
+    /// Parent ids, only the first one when walking first parents.
+    pub parents: Vec<String>,
+    /// Branch the commit was first reached from.
+    pub branch: String,
+    pub author: String,
+    pub email: String,
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    pub message: String,
+    /// Files changed since the first parent (portable path, content or `None` when deleted).
+    pub changes: Vec<(String, Option<String>)>,
+    /// Changed files a cube can't hold: binary, symbolic links and submodules.
+    pub skipped: Vec<String>,
+}
+
+/// Local branches and tags of a Git repository, with the commit they point at.
+#[derive(Debug, Default, PartialEq)]
+pub struct GitRefs {
+    pub branches: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+    /// Checked out branch.
+    pub head: Option<String>,
+}
+
+/// Name of a Git branch or tag as an ak one: characters ak doesn't allow become `-`, as in
+/// `feature-login` for `feature/login`.
+pub fn ref_name(git: &str) -> String {
+    let name: String = git
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') => c,
+            _ => '-',
+        })
+        .collect();
+    match name.strip_prefix('.') {
+        Some(rest) => format!("-{rest}"),
+        None => name,
+    }
+}
+
+/// Git commit message of a seal.
+pub fn message(ty: &str, summary: &str, body: &str) -> String {
+    let mut out = match ty {
+        "" | DEFAULT_TYPE => summary.to_string(),
+        ty => format!("{ty}: {summary}"),
+    };
+    if !body.trim().is_empty() {
+        out = format!("{out}\n\n{}", body.trim());
+    }
+    out.push('\n');
+    out
+}
+
+/// Type, summary and body of a Git commit message, the reverse of `message`.
+pub fn split_message(message: &str) -> (String, String, String) {
+    let (subject, body) = message
+        .trim()
+        .split_once('\n')
+        .unwrap_or((message.trim(), ""));
+    let typed = subject.split_once(": ").filter(|(ty, _)| {
+        !ty.is_empty()
+            && ty
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "()!-_/.".contains(c))
+    });
+    let (ty, summary) = typed.unwrap_or((DEFAULT_TYPE, subject));
+    (
+        ty.to_string(),
+        summary.trim().to_string(),
+        body.trim().to_string(),
+    )
+}
+
+/// What `export` wrote.
+#[derive(Debug, Default, PartialEq)]
+pub struct Exported {
+    pub commits: usize,
+    pub branches: usize,
+    pub tags: usize,
+}
+
+/// Export the history of the repository at `root` into the Git repository `dir`, created if
+/// needed. A new repository gets `author`'s current branch checked out.
+#[cfg(feature = "git")]
+pub fn export(root: &Path, dir: &Path, author: &str) -> io::Result<Exported> {
+    let (repo, created) = match git2::Repository::open(dir) {
+        Ok(repo) => (repo, false),
+        Err(_) => (git2::Repository::init(dir).map_err(io::Error::other)?, true),
+    };
+    let authors = if Config::load(root)?.storage.layout == Layout::Shared {
+        vec![String::new()]
+    } else {
+        history::authors(root)?
+    };
+    let prefix = |name: &str| match authors.len() {
+        1 => String::new(),
+        _ => format!("{name}/"),
+    };
+    let mut exported = Exported::default();
+    for name in &authors {
+        let events = history::author_events(root, name)?;
+        let oids = export_commits(&repo, &events, name)?;
+        exported.commits += oids.len();
+        let refs = branch::heads(&events)
+            .into_iter()
+            .filter_map(|(b, head)| Some((format!("refs/heads/{}{b}", prefix(name)), head?)))
+            .chain(
+                tag::tags(&events)
+                    .into_iter()
+                    .map(|(t, id)| (format!("refs/tags/{}{t}", prefix(name)), id)),
+            );
+        for (reference, id) in refs {
+            let Some(oid) = oids.get(&id) else {
+                continue;
+            };
+            repo.reference(&reference, *oid, true, "ak export-git")
+                .map_err(io::Error::other)?;
+            if reference.starts_with("refs/heads/") {
+                exported.branches += 1;
+            } else {
+                exported.tags += 1;
+            }
+        }
+    }
+
+    if created {
+        let name = if authors.len() == 1 {
+            &authors[0]
+        } else {
+            author
+        };
+        let head = format!(
+            "refs/heads/{}{}",
+            prefix(name),
+            branch::current(root, author)
+        );
+        if repo.find_reference(&head).is_ok() {
+            repo.set_head(&head).map_err(io::Error::other)?;
+            if !repo.is_bare() {
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+                    .map_err(io::Error::other)?;
+            }
+        }
+    }
+    Ok(exported)
+}
+
+/// Without the `git` feature there is no Git support.
+#[cfg(not(feature = "git"))]
+pub fn export(_root: &Path, _dir: &Path, _author: &str) -> io::Result<Exported> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Git export requires building with `--features git`",
+    ))
+}
+
+/// Write a Git commit for every live commit of `events` (the records of `author`), parents
+/// first. Returns the Git commit of every commit id.
+#[cfg(feature = "git")]
+fn export_commits(
+    repo: &git2::Repository,
+    events: &[Event],
+    author: &str,
+) -> io::Result<HashMap<u64, git2::Oid>> {
+    let mut oids = HashMap::new();
+    for ev in commit::history(events) {
+        let v: serde_json::Value = serde_json::from_str(&ev.noumenon).map_err(io::Error::other)?;
+        let text = |key: &str| v.get(key).and_then(|x| x.as_str()).unwrap_or("");
+        let id = commit::commit_id(ev);
+
+        let tree = write_tree(repo, &restore::tree_at(events, id)?).map_err(io::Error::other)?;
+        let tree = repo.find_tree(tree).map_err(io::Error::other)?;
+        let seconds = commit::timestamp_ms(&v)
+            .map(|ms| ms / 1000)
+            .unwrap_or((ev.timestamp / 1_000_000_000) as i64);
+        let name = [text("author"), author, "unknown"]
+            .into_iter()
+            .find(|n| !n.is_empty())
+            .expect("fallback name");
+        let signature =
+            git2::Signature::new(name, text("author_email"), &git2::Time::new(seconds, 0))
+                .map_err(io::Error::other)?;
+        let message = message(text("ty"), text("summary"), text("body"));
+
+        let parents = ["parent", "merge_parent"]
+            .into_iter()
+            .filter_map(|key| oids.get(&v.get(key)?.as_u64()?))
+            .map(|oid| repo.find_commit(*oid))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io::Error::other)?;
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let oid = repo
+            .commit(None, &signature, &signature, &message, &tree, &parents)
+            .map_err(io::Error::other)?;
+        oids.insert(id, oid);
+    }
+    Ok(oids)
+}
+
+/// Read the commits of every local branch of the Git repository `dir`, parents first, and pass
+/// the ones not in `known` to `each`. The checked out branch is walked first, so that history
+/// shared with other branches is attributed to it. With `first_parent`, merged branches are left
+/// out and merges keep their first parent only.
+#[cfg(feature = "git")]
+pub fn walk(
+    dir: &Path,
+    first_parent: bool,
+    known: &HashSet<String>,
+    mut each: impl FnMut(GitCommit) -> io::Result<()>,
+) -> io::Result<GitRefs> {
+    let repo = git2::Repository::open(dir).map_err(io::Error::other)?;
+    let mut refs = GitRefs::default();
+    for b in repo
+        .branches(Some(git2::BranchType::Local))
+        .map_err(io::Error::other)?
+    {
+        let (b, _) = b.map_err(io::Error::other)?;
+        if let (Ok(Some(name)), Ok(tip)) = (b.name(), b.get().peel_to_commit()) {
+            refs.branches.insert(name.to_string(), tip.id().to_string());
+        }
+    }
+    for name in repo
+        .tag_names(None)
+        .map_err(io::Error::other)?
+        .iter()
+        .flatten()
+    {
+        if let Ok(c) = repo
+            .revparse_single(&format!("refs/tags/{name}"))
+            .and_then(|o| o.peel_to_commit())
+        {
+            refs.tags.insert(name.to_string(), c.id().to_string());
+        }
+    }
+
+    refs.head = repo
+        .head()
+        .ok()
+        .filter(|h| h.is_branch())
+        .and_then(|h| h.shorthand().map(str::to_string));
+    let mut order: Vec<&String> = refs.branches.keys().collect();
+    order.sort_by_key(|b| Some(*b) != refs.head.as_ref());
+    let mut seen = HashSet::new();
+    for name in order {
+        let mut revwalk = repo.revwalk().map_err(io::Error::other)?;
+        revwalk
+            .set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+            .map_err(io::Error::other)?;
+        if first_parent {
+            revwalk.simplify_first_parent().map_err(io::Error::other)?;
+        }
+        let tip = git2::Oid::from_str(&refs.branches[name]).map_err(io::Error::other)?;
+        revwalk.push(tip).map_err(io::Error::other)?;
+        for oid in revwalk {
+            let oid = oid.map_err(io::Error::other)?;
+            if !seen.insert(oid) || known.contains(&oid.to_string()) {
+                continue;
+            }
+            each(read_commit(&repo, oid, name, first_parent).map_err(io::Error::other)?)?;
+        }
+    }
+    Ok(refs)
+}
+
+/// Without the `git` feature there is no Git support.
+#[cfg(not(feature = "git"))]
+pub fn walk(
+    _dir: &Path,
+    _first_parent: bool,
+    _known: &std::collections::HashSet<String>,
+    _each: impl FnMut(GitCommit) -> io::Result<()>,
+) -> io::Result<GitRefs> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Git import requires building with `--features git`",
+    ))
+}
+
+/// Read commit `oid` and the files it changed since its first parent.
+#[cfg(feature = "git")]
+fn read_commit(
+    repo: &git2::Repository,
+    oid: git2::Oid,
+    branch: &str,
+    first_parent: bool,
+) -> Result<GitCommit, git2::Error> {
+    let c = repo.find_commit(oid)?;
+    let parent_tree = match c.parent(0) {
+        Ok(p) => Some(p.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&c.tree()?), None)?;
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+    for delta in diff.deltas() {
+        let file = match delta.status() {
+            git2::Delta::Deleted => delta.old_file(),
+            _ => delta.new_file(),
+        };
+        // Git paths are `/`-separated bytes, like portable paths.
+        let bytes = file.path_bytes().unwrap_or_default();
+        let Ok(path) = String::from_utf8(bytes.to_vec()) else {
+            skipped.push(String::from_utf8_lossy(bytes).into_owned());
+            continue;
+        };
+        if delta.status() == git2::Delta::Deleted {
+            changes.push((path, None));
+            continue;
+        }
+        let text = match file.mode() {
+            git2::FileMode::Blob | git2::FileMode::BlobExecutable => {
+                String::from_utf8(repo.find_blob(file.id())?.content().to_vec()).ok()
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => changes.push((path, Some(text))),
+            None => skipped.push(path),
+        }
+    }
+    let parents = c.parent_ids().map(|p| p.to_string());
+    let author = c.author();
+    Ok(GitCommit {
+        id: oid.to_string(),
+        parents: parents
+            .take(if first_parent { 1 } else { usize::MAX })
+            .collect(),
+        branch: branch.to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        email: author.email().unwrap_or_default().to_string(),
+        timestamp: u64::try_from(author.when().seconds()).unwrap_or(0) * 1000,
+        message: String::from_utf8_lossy(c.message_bytes()).into_owned(),
+        changes,
+        skipped,
+    })
+}
+
+/// Write `files` (portable path to content) as a Git tree.
+#[cfg(feature = "git")]
+fn write_tree(
+    repo: &git2::Repository,
+    files: &BTreeMap<String, &str>,
+) -> Result<git2::Oid, git2::Error> {
+    let mut builder = repo.treebuilder(None)?;
+    let mut dirs: BTreeMap<&str, BTreeMap<String, &str>> = BTreeMap::new();
+    for (path, content) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                dirs.entry(dir)
+                    .or_default()
+                    .insert(rest.to_string(), content);
+            }
+            None => {
+                let blob = repo.blob(content.as_bytes())?;
+                builder.insert(path, blob, 0o100644)?;
+            }
+        }
+    }
+    for (dir, files) in dirs {
+        builder.insert(dir, write_tree(repo, &files)?, 0o040000)?;
+    }
+    builder.write()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        for (ty, summary, body) in [
+            ("feat", "Add export", ""),
+            ("fix(cli)", "Exit with 1", "Scripts rely on it.\n\nSee #12."),
+            (DEFAULT_TYPE, "Initial import: old tree", ""),
+        ] {
+            let split = split_message(&message(ty, summary, body));
+            assert_eq!(split, (ty.into(), summary.into(), body.into()));
+        }
+        assert_eq!(
+            split_message("Merge branch 'dev'\n"),
+            (DEFAULT_TYPE.into(), "Merge branch 'dev'".into(), "".into())
+        );
+        assert_eq!(ref_name("feature/login"), "feature-login");
+        assert_eq!(ref_name(".hidden"), "-hidden");
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn test_export_commits_trees_and_branches() {
+        use crate::data::manifest;
+        use crate::data::write::Writer;
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("akasha-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let cube = root.join(".eikyu/cubes/2026-01/ana.cube");
+        fs::create_dir_all(cube.parent().unwrap()).unwrap();
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let entry = |record: u64, content: &str| {
+            format!(
+                r#"{{"record":{record},"hash":"{}"}}"#,
+                manifest::hash(content)
+            )
+        };
+        w.append("a.txt", "one\n").unwrap();
+        w.append("src/b.rs", "fn b() {}\n").unwrap();
+        let files = format!(
+            r#"{{"files":{{"a.txt":{},"src/b.rs":{}}}}}"#,
+            entry(1, "one\n"),
+            entry(2, "fn b() {}\n")
+        );
+        w.append("manifest:4", &files).unwrap();
+        w.append(
+            "commit",
+            r#"{"id":4,"branch":"main","manifest":3,"ty":"feat","summary":"first","body":"","author":"ana","author_email":"ana@example.com","timestamp":1700000000000}"#,
+        )
+        .unwrap();
+        w.append("a.txt", "two\n").unwrap();
+        let files = format!(r#"{{"files":{{"a.txt":{}}}}}"#, entry(5, "two\n"));
+        w.append("manifest:7", &files).unwrap();
+        w.append(
+            "commit",
+            r#"{"id":7,"parent":4,"branch":"main","manifest":6,"ty":"fix","summary":"second","body":"Why.","author":"ana","author_email":"ana@example.com","timestamp":1700000060000}"#,
+        )
+        .unwrap();
+        w.append("tag:v1", r#"{"commit":4}"#).unwrap();
+
+        let dir = root.join("git");
+        let exported = export(&root, &dir, "ana").unwrap();
+        assert_eq!(
+            exported,
+            Exported {
+                commits: 2,
+                branches: 1,
+                tags: 1
+            }
+        );
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "two\n");
+        assert!(!dir.join("src").exists());
+
+        let repo = git2::Repository::open(&dir).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.message(), Some("fix: second\n\nWhy.\n"));
+        assert_eq!(head.author().email(), Some("ana@example.com"));
+        assert_eq!(head.time().seconds(), 1700000060);
+        let first = head.parent(0).unwrap();
+        assert_eq!(first.message(), Some("feat: first\n"));
+        assert!(
+            first
+                .tree()
+                .unwrap()
+                .get_path(Path::new("src/b.rs"))
+                .is_ok()
+        );
+        let tagged = repo
+            .revparse_single("v1")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(tagged.id(), first.id());
+
+        // Exporting again recreates the same commits.
+        assert_eq!(export(&root, &dir, "ana").unwrap(), exported);
+        assert_eq!(
+            repo.head().unwrap().peel_to_commit().unwrap().id(),
+            head.id()
+        );
+
+        // Walking the export gives the commits back, with what each one changed.
+        let mut walked = Vec::new();
+        let refs = walk(&dir, false, &HashSet::new(), |c| {
+            walked.push(c);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(refs.branches["main"], head.id().to_string());
+        assert_eq!(refs.tags["v1"], first.id().to_string());
+        assert_eq!(walked.len(), 2);
+        assert_eq!(walked[1].parents, [walked[0].id.clone()]);
+        assert_eq!(
+            (walked[1].author.as_str(), walked[1].timestamp),
+            ("ana", 1700000060000)
+        );
+        assert_eq!(
+            walked[1].changes,
+            [
+                ("a.txt".to_string(), Some("two\n".to_string())),
+                ("src/b.rs".to_string(), None)
+            ]
+        );
+        let known = HashSet::from([walked[0].id.clone()]);
+        let mut again = 0;
+        walk(&dir, true, &known, |_| {
+            again += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(again, 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! ASCII commit graph for `ak timeline --graph`.
+//!
+//! Commits are laid out newest first, one lane per line of work, in the spirit of
+//! `git log --graph`: `*` marks the commit, `|` a lane passing by, and a `/` row joins a lane
+//! into another once both reach the same parent (the point a branch was created). Below a merge,
+//! a `\` row opens a lane for each of its other parents (the merged branches).
+
+/// One commit of the graph: its prefix (e.g. `| * `) and the connector rows drawn below it.
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub prefix: String,
+    pub connectors: Vec<String>,
+}
+
+fn lane_chars(lanes: &[Option<u64>], col: usize) -> String {
+    let mut out = String::new();
+    for (i, lane) in lanes.iter().enumerate() {
+        out.push(match lane {
+            _ if i == col => '*',
+            Some(_) => '|',
+            None => ' ',
+        });
+        out.push(' ');
+    }
+    out
+}
+
+/// Row drawing a line from lane `from` down to lane `to`: `/` leftwards, `\` rightwards.
+fn link(lanes: &[Option<u64>], from: usize, to: usize) -> String {
+    let mut out: Vec<char> = lane_chars(lanes, usize::MAX).chars().collect();
+    let (left, right) = (from.min(to), from.max(to));
+    for c in &mut out[2 * left + 1..2 * right - 1] {
+        if *c == ' ' {
+            *c = '_';
+        }
+    }
+    out[2 * right - 1] = if from > to { '/' } else { '\\' };
+    out.into_iter().collect::<String>().trim_end().to_string()
+}
+
+/// Row drawing lane `from` joining lane `into` (`into < from`), which ends lane `from`.
+fn join(lanes: &mut [Option<u64>], from: usize, into: usize) -> String {
+    lanes[from] = None;
+    link(lanes, from, into)
+}
+
+/// Lay out `commits`, given newest first as `(id, parents)`, the first parent first. Parents
+/// that are not part of `commits` (filtered out, or never recorded) end their lane.
+pub fn layout(commits: &[(u64, Vec<u64>)]) -> Vec<Row> {
+    let shown: std::collections::HashSet<u64> = commits.iter().map(|(id, _)| *id).collect();
+    let mut lanes: Vec<Option<u64>> = Vec::new();
+    let mut rows = Vec::new();
+    for (id, parents) in commits {
+        let id = *id;
+        let mut parents = parents.iter().copied().filter(|p| shown.contains(p));
+        let col = match lanes.iter().position(|l| *l == Some(id)) {
+            Some(col) => col,
+            None => match lanes.iter().position(|l| l.is_none()) {
+                Some(free) => free,
+                None => {
+                    lanes.push(None);
+                    lanes.len() - 1
+                }
+            },
+        };
+        lanes[col] = Some(id);
+        let prefix = lane_chars(&lanes, col);
+
+        // Other lanes waiting for this commit end here: it was their parent too.
+        let mut connectors = Vec::new();
+        for j in (col + 1..lanes.len()).rev() {
+            if lanes[j] == Some(id) {
+                connectors.push(join(&mut lanes, j, col));
+            }
+        }
+        lanes[col] = parents.next();
+
+        // Two lanes now waiting for the same parent: the rightmost one joins the other.
+        if let Some(p) = lanes[col]
+            && let Some(k) = (0..lanes.len()).find(|&k| k != col && lanes[k] == Some(p))
+        {
+            connectors.push(join(&mut lanes, col.max(k), col.min(k)));
+        }
+
+        // The other parents of a merge: a line to the lane already waiting for one, or to a new
+        // lane right of this one.
+        for p in parents {
+            if lanes[col] == Some(p) {
+                continue;
+            }
+            if let Some(k) = lanes.iter().position(|l| *l == Some(p)) {
+                connectors.push(link(&lanes, col, k));
+                continue;
+            }
+            let k = match (col + 1..lanes.len()).find(|&k| lanes[k].is_none()) {
+                Some(free) => free,
+                None => {
+                    lanes.push(None);
+                    lanes.len() - 1
+                }
+            };
+            connectors.push(link(&lanes, col, k));
+            lanes[k] = Some(p);
+        }
+        while lanes.last() == Some(&None) {
+            lanes.pop();
+        }
+        rows.push(Row { prefix, connectors });
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_joins_branches() {
+        // 1 <- 2 <- 4 on main, 1 <- 3 on a branch; newest first.
+        let rows = layout(&[(4, vec![2]), (3, vec![1]), (2, vec![1]), (1, vec![])]);
+        assert_eq!(
+            drawn(&rows),
+            [
+                ("* ", vec![]),
+                ("| * ", vec![]),
+                ("* | ", vec!["|/"]),
+                ("* ", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_joins_merges() {
+        // 1 <- 2 <- 4 on main, 1 <- 3 on a branch, 5 merges 3 into 4; newest first.
+        let rows = layout(&[
+            (5, vec![4, 3]),
+            (4, vec![2]),
+            (3, vec![1]),
+            (2, vec![1]),
+            (1, vec![]),
+        ]);
+        assert_eq!(
+            drawn(&rows),
+            [
+                ("* ", vec!["|\\"]),
+                ("* | ", vec![]),
+                ("| * ", vec![]),
+                ("* | ", vec!["|/"]),
+                ("* ", vec![]),
+            ]
+        );
+        // A merged parent some lane already waits for: the line goes to that lane.
+        let rows = layout(&[(4, vec![2]), (3, vec![1, 2]), (2, vec![1]), (1, vec![])]);
+        assert_eq!(drawn(&rows)[1], ("| * ", vec!["|/|"]));
+    }
+
+    fn drawn(rows: &[Row]) -> Vec<(&str, Vec<&str>)> {
+        rows.iter()
+            .map(|r| {
+                (
+                    r.prefix.as_str(),
+                    r.connectors.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect()
+    }
+}
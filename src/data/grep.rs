@@ -0,0 +1,119 @@
+//! Searching sealed contents: `ak grep`.
+//!
+//! By default the files of one seal (the branch head) are searched. `--all-history` searches every
+//! version stored in the cubes instead, each reported with the first seal that contains it.
+
+use crate::data::commit;
+use crate::data::restore;
+use crate::event::Event;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+/// A line matching the pattern.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    /// Seal containing this version of the file.
+    pub commit: u64,
+    pub path: String,
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub text: &'a str,
+}
+
+fn search<'a>(commit: u64, path: &str, content: &'a str, re: &Regex, out: &mut Vec<Match<'a>>) {
+    for (k, text) in content.lines().enumerate() {
+        if re.is_match(text) {
+            out.push(Match {
+                commit,
+                path: path.to_string(),
+                line: k + 1,
+                text,
+            });
+        }
+    }
+}
+
+/// Lines of the files of commit `id` matching `re`, in path order.
+pub fn in_commit<'a>(events: &'a [Event], id: u64, re: &Regex) -> io::Result<Vec<Match<'a>>> {
+    let mut out = Vec::new();
+    for (path, content) in restore::tree_at(events, id)? {
+        search(id, &path, content, re, &mut out);
+    }
+    Ok(out)
+}
+
+/// Lines matching `re` in every version of every file sealed in `events`, each version searched
+/// once, under the first seal that contains it.
+pub fn in_history<'a>(events: &'a [Event], re: &Regex) -> io::Result<Vec<Match<'a>>> {
+    // Unchanged files point to the same stored record from one seal to the next.
+    let mut seen: HashSet<(String, *const u8)> = HashSet::new();
+    let mut out = Vec::new();
+    for ev in commit::history(events) {
+        let id = commit::commit_id(ev);
+        let files: BTreeMap<String, &str> = restore::tree_at(events, id)?;
+        for (path, content) in files {
+            if seen.insert((path.clone(), content.as_ptr())) {
+                search(id, &path, content, re, &mut out);
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::manifest::Manifest;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_grep_head_and_history() {
+        let dir = std::env::temp_dir().join(format!("akasha-grep-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+
+        let mut manifest = Manifest::default();
+        let mut index = crate::data::index::Index::default();
+        index.stage_content("a.txt", Some("TODO one\nfine\n".to_string()));
+        index.stage_content("b.txt", Some("nothing\n".to_string()));
+        manifest.apply_index(&mut w, &index).unwrap();
+        let m = manifest.record(&mut w, 10).unwrap();
+        w.append("commit", &format!(r#"{{"id":10,"manifest":{m}}}"#))
+            .unwrap();
+
+        let mut index = crate::data::index::Index::default();
+        index.stage_content("a.txt", Some("fine\n".to_string()));
+        manifest.apply_index(&mut w, &index).unwrap();
+        let m = manifest.record(&mut w, 20).unwrap();
+        w.append(
+            "commit",
+            &format!(r#"{{"id":20,"manifest":{m},"parent":10}}"#),
+        )
+        .unwrap();
+
+        let events = w.events().unwrap();
+        let re = Regex::new("TODO|nothing").unwrap();
+        let head: Vec<(u64, String, usize)> = in_commit(&events, 20, &re)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.commit, m.path, m.line))
+            .collect();
+        assert_eq!(head, [(20, "b.txt".to_string(), 1)]);
+        let all: Vec<(u64, String, &str)> = in_history(&events, &re)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.commit, m.path, m.text))
+            .collect();
+        assert_eq!(
+            all,
+            [
+                (10, "a.txt".to_string(), "TODO one"),
+                (10, "b.txt".to_string(), "nothing")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
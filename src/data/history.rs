@@ -0,0 +1,186 @@
+//! History spread over several cubes.
+//!
+//! `ak` writes to `.eikyu/cubes/YYYY-MM/<author>.cube`, so a repository's history is split by
+//! month and by author. This module reads it back as a whole: every cube of an author, oldest
+//! month first, for the records `ak` resolves commits, manifests, branches and tags from, and
+//! the commits of every author merged into one timeline.
+//!
+//! A new monthly cube continues the record ids of the author's earlier cubes (see `next_id`),
+//! so commit ids, parents and manifest entries stay unambiguous across months. Cubes written
+//! before that restarted at 1 every month; their colliding ids resolve to the oldest record.
+//!
+//! With the shared layout (see `data::config`) there is a single repository cube: every author
+//! reads all of it, and a commit's author comes from its record.
+
+use crate::data::commit;
+use crate::data::config::{self, Config, Layout};
+use crate::data::write::Writer;
+use crate::event::Event;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One cube file of the repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeFile {
+    /// `YYYY-MM` bucket.
+    pub month: String,
+    pub author: String,
+    pub path: PathBuf,
+}
+
+/// A commit record and its author.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub author: String,
+    pub event: Event,
+}
+
+/// Every cube under `<root>/.eikyu/cubes`, by month then author.
+pub fn cube_files(root: &Path) -> io::Result<Vec<CubeFile>> {
+    let dir = root.join(".eikyu").join("cubes");
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut out = Vec::new();
+    for month in fs::read_dir(&dir)? {
+        let month = month?;
+        if !month.file_type()?.is_dir() {
+            continue;
+        }
+        for cube in fs::read_dir(month.path())? {
+            let path = cube?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("cube") {
+                continue;
+            }
+            let Some(author) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            out.push(CubeFile {
+                month: month.file_name().to_string_lossy().into_owned(),
+                author: author.to_string(),
+                path: path.clone(),
+            });
+        }
+    }
+    out.sort_by(|a, b| (&a.month, &a.author).cmp(&(&b.month, &b.author)));
+    Ok(out)
+}
+
+fn read(path: &Path) -> io::Result<Writer> {
+    let path = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: not a UTF-8 path", path.display()),
+        )
+    })?;
+    Writer::open(path)
+}
+
+/// Records of the shared repository cube, `None` with the per-author layout.
+fn shared_events(root: &Path) -> io::Result<Option<Vec<Event>>> {
+    if Config::load(root)?.storage.layout != Layout::Shared {
+        return Ok(None);
+    }
+    let path = config::shared_cube(root);
+    if !path.exists() {
+        return Ok(Some(Vec::new()));
+    }
+    Ok(Some(read(&path)?.events()?))
+}
+
+/// First id a new cube of `author` should use: past every id of their existing cubes.
+pub fn next_id(root: &Path, author: &str) -> io::Result<u64> {
+    let mut next = 1;
+    for cube in cube_files(root)?.iter().filter(|c| c.author == author) {
+        next = next.max(read(&cube.path)?.next_id());
+    }
+    Ok(next)
+}
+
+/// Records of every cube of `author`, oldest month first (the repository cube with the shared
+/// layout).
+pub fn author_events(root: &Path, author: &str) -> io::Result<Vec<Event>> {
+    if let Some(events) = shared_events(root)? {
+        return Ok(events);
+    }
+    let mut out = Vec::new();
+    for cube in cube_files(root)?.iter().filter(|c| c.author == author) {
+        out.extend(read(&cube.path)?.events()?);
+    }
+    Ok(out)
+}
+
+/// Authors with a cube under `root`, sorted.
+pub fn authors(root: &Path) -> io::Result<Vec<String>> {
+    let mut out: Vec<String> = cube_files(root)?.into_iter().map(|c| c.author).collect();
+    out.sort();
+    out.dedup();
+    Ok(out)
+}
+
+/// Commits of every author that were not superseded, in the order they were sealed.
+pub fn commits(root: &Path) -> io::Result<Vec<Commit>> {
+    if let Some(events) = shared_events(root)? {
+        return Ok(commit::history(&events)
+            .into_iter()
+            .map(|ev| Commit {
+                author: serde_json::from_str::<serde_json::Value>(&ev.noumenon)
+                    .ok()
+                    .and_then(|v| v.get("author")?.as_str().map(str::to_string))
+                    .unwrap_or_default(),
+                event: ev.clone(),
+            })
+            .collect());
+    }
+    let mut out = Vec::new();
+    for author in authors(root)? {
+        let events = author_events(root, &author)?;
+        out.extend(commit::history(&events).into_iter().map(|ev| Commit {
+            author: author.clone(),
+            event: ev.clone(),
+        }));
+    }
+    out.sort_by_key(|c| c.event.timestamp);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_across_months_and_authors() {
+        let root = std::env::temp_dir().join(format!("akasha-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let cube = |month: &str, author: &str| {
+            let dir = root.join(".eikyu").join("cubes").join(month);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(format!("{author}.cube"));
+            Writer::create(path.to_str().unwrap()).unwrap()
+        };
+
+        let mut w = cube("2025-07", "ana");
+        w.append("commit", r#"{"id":1}"#).unwrap();
+        let mut w = cube("2025-07", "bo");
+        w.append("commit", r#"{"id":1}"#).unwrap();
+
+        // August continues July's ids for `ana`.
+        let next = next_id(&root, "ana").unwrap();
+        assert_eq!(next, 2);
+        let mut w = cube("2025-08", "ana");
+        w.advance_next_id(next).unwrap();
+        w.append("commit", r#"{"id":2,"parent":1}"#).unwrap();
+
+        let events = author_events(&root, "ana").unwrap();
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2]);
+        let all = commits(&root).unwrap();
+        assert_eq!(
+            all.iter()
+                .map(|c| (c.author.as_str(), c.event.id))
+                .collect::<Vec<_>>(),
+            [("ana", 1), ("bo", 1), ("ana", 2)]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+//! Pre-commit steps configured in `.eikyu/hooks.toml`.
+//!
+//! ```toml
+//! [[step]]
+//! name = "fmt"
+//! program = "cargo"
+//! args = ["fmt", "--check"]
+//!
+//! [[step]]
+//! name = "docs"
+//! program = "mdbook"
+//! args = ["build"]
+//! allow-failure = true
+//! only-on-changed = ["docs/**"]
+//! parallel = true
+//! ```
+//!
+//! Steps run in file order. A failing step stops the pipeline unless it has `allow-failure`;
+//! a step with `only-on-changed` globs only runs when a changed path (portable, relative to the
+//! repository root) matches one of them. Consecutive `parallel` steps don't depend on each other
+//! and run at the same time (see `batches`). Without a hooks file, `ak` falls back to detecting
+//! the project type (Cargo, npm, Python, Go, justfile, Makefile).
+//!
+//! A step's output is captured and only shown when it fails.
+
+use globset::{Glob, GlobSetBuilder};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::time::{Duration, Instant};
+
+/// One pre-commit step.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Step {
+    pub name: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Report a failure without stopping the pipeline.
+    #[serde(default)]
+    pub allow_failure: bool,
+    /// Only run when a changed path matches one of these globs (always run when empty).
+    #[serde(default)]
+    pub only_on_changed: Vec<String>,
+    /// May run alongside the neighbouring `parallel` steps.
+    #[serde(default)]
+    pub parallel: bool,
+}
+
+/// What running a step produced.
+#[derive(Debug)]
+pub struct Run {
+    pub status: ExitStatus,
+    /// Captured standard output.
+    pub stdout: Vec<u8>,
+    /// Captured standard error.
+    pub stderr: Vec<u8>,
+    pub elapsed: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HooksFile {
+    #[serde(default)]
+    step: Vec<Step>,
+}
+
+/// Names `just` looks for, in its order.
+pub const JUSTFILES: [&str; 3] = ["justfile", "Justfile", ".justfile"];
+
+/// Names `make` looks for, in its order.
+pub const MAKEFILES: [&str; 3] = ["GNUmakefile", "makefile", "Makefile"];
+
+/// Path of the hooks file of the repository at `root`.
+pub fn hooks_path(root: &Path) -> PathBuf {
+    root.join(".eikyu").join("hooks.toml")
+}
+
+/// Steps configured for the repository at `root`, `None` without a hooks file.
+pub fn load(root: &Path) -> io::Result<Option<Vec<Step>>> {
+    let path = hooks_path(root);
+    let raw = match fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let file: HooksFile = toml::from_str(&raw).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+    Ok(Some(file.step))
+}
+
+impl Step {
+    /// Whether the step runs given the `changed` paths.
+    pub fn applies(&self, changed: &[String]) -> io::Result<bool> {
+        if self.only_on_changed.is_empty() {
+            return Ok(true);
+        }
+        let mut set = GlobSetBuilder::new();
+        for pattern in &self.only_on_changed {
+            let glob = Glob::new(pattern).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("step {}: {e}", self.name),
+                )
+            })?;
+            set.add(glob);
+        }
+        let set = set.build().map_err(io::Error::other)?;
+        Ok(changed.iter().any(|p| set.is_match(p)))
+    }
+
+    /// Run the step in `dir`, capturing its output.
+    pub fn execute(&self, dir: &Path) -> io::Result<Run> {
+        let start = Instant::now();
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = std::process::Command::new(&self.program)
+            .args(&self.args)
+            .current_dir(dir)
+            .output()
+            .map_err(|e| io::Error::other(format!("failed to spawn '{}': {e}", self.program)))?;
+        Ok(Run {
+            status,
+            stdout,
+            stderr,
+            elapsed: start.elapsed(),
+        })
+    }
+}
+
+/// Split `steps` into batches run one after the other: each run of consecutive `parallel`
+/// steps forms one batch, every other step is a batch of its own.
+pub fn batches<'a>(steps: &[&'a Step]) -> Vec<Vec<&'a Step>> {
+    let mut out: Vec<Vec<&Step>> = Vec::new();
+    for &step in steps {
+        match out.last_mut() {
+            Some(last) if step.parallel && last.iter().all(|s| s.parallel) => last.push(step),
+            _ => out.push(vec![step]),
+        }
+    }
+    out
+}
+
+/// Recipes defined by a justfile: unindented `name params...:` lines, skipping settings,
+/// variables (`:=`) and comments.
+pub fn just_recipes(justfile: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for line in justfile.lines() {
+        if line.starts_with([' ', '\t', '#', '[']) || line.contains(":=") {
+            continue;
+        }
+        let Some((head, _)) = line.split_once(':') else {
+            continue;
+        };
+        let name = head.trim_start_matches('@').split_whitespace().next();
+        if let Some(name) = name
+            && !["set", "alias", "export", "import", "mod"].contains(&name)
+        {
+            out.push(name.to_string());
+        }
+    }
+    out
+}
+
+/// Targets defined by a Makefile: unindented `a b: deps` rules, skipping variable assignments
+/// and special targets such as `.PHONY`.
+pub fn make_targets(makefile: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for line in makefile.lines() {
+        if line.starts_with([' ', '\t', '#', '.']) {
+            continue;
+        }
+        let Some((head, rest)) = line.split_once(':') else {
+            continue;
+        };
+        if head.contains('=') || rest.starts_with('=') {
+            continue;
+        }
+        out.extend(head.split_whitespace().map(str::to_string));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_in_order_and_filter_on_changes() {
+        let root = std::env::temp_dir().join(format!("akasha-hooks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".eikyu")).unwrap();
+        assert_eq!(load(&root).unwrap(), None);
+
+        fs::write(
+            hooks_path(&root),
+            r#"
+[[step]]
+name = "test"
+program = "cargo"
+args = ["test"]
+
+[[step]]
+name = "docs"
+program = "mdbook"
+allow-failure = true
+only-on-changed = ["docs/**", "*.md"]
+"#,
+        )
+        .unwrap();
+        let steps = load(&root).unwrap().unwrap();
+        assert_eq!(
+            steps.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            ["test", "docs"]
+        );
+        assert!(steps[1].allow_failure && steps[1].args.is_empty());
+        assert!(steps[0].applies(&[]).unwrap());
+        assert!(!steps[1].applies(&["src/lib.rs".into()]).unwrap());
+        assert!(steps[1].applies(&["docs/guide/intro.txt".into()]).unwrap());
+
+        fs::write(hooks_path(&root), "[[step]]\nname = \"x\"\n").unwrap();
+        assert!(load(&root).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_batches_group_consecutive_parallel_steps() {
+        let step = |name: &str, parallel| Step {
+            name: name.into(),
+            program: "true".into(),
+            args: Vec::new(),
+            allow_failure: false,
+            only_on_changed: Vec::new(),
+            parallel,
+        };
+        let steps = [
+            step("fmt", false),
+            step("lint", true),
+            step("docs", true),
+            step("test", false),
+            step("bench", true),
+        ];
+        let refs: Vec<&Step> = steps.iter().collect();
+        let names: Vec<Vec<&str>> = batches(&refs)
+            .iter()
+            .map(|b| b.iter().map(|s| s.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                vec!["fmt"],
+                vec!["lint", "docs"],
+                vec!["test"],
+                vec!["bench"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_just_recipes_and_make_targets() {
+        let justfile = "set shell := [\"bash\", \"-c\"]\nversion := \"1\"\n\n# run the tests\n[group('ci')]\ntest filter='': build\n    cargo test {{filter}}\n@lint:\n    cargo clippy\nalias t := test\n";
+        assert_eq!(just_recipes(justfile), ["test", "lint"]);
+        let makefile =
+            ".PHONY: check\nCC := gcc\nall check: build\n\t$(CC) -o x x.c\nbuild:\n\ttrue\n";
+        assert_eq!(make_targets(makefile), ["all", "check", "build"]);
+    }
+}
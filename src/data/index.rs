@@ -0,0 +1,323 @@
+//! Staging area for `ak add` / `ak seal`.
+//!
+//! The index lives in `.eikyu/index/<author>.json` and maps a portable path (forward-slash
+//! separated, relative to the repository root) to the content staged for the next seal, or to
+//! `null` for a staged deletion. Content is captured when it is staged, so a file edited after
+//! `ak add` is sealed as it was when added; `ak add -p` stages a version of the file containing
+//! only the selected hunks. `ak mv` also records the rename itself (see `data::rename`).
+//!
+//! Sealing writes the staged contents into the cube, applies the index to the reference tree and
+//! clears it.
+
+use crate::data::paths;
+use crate::data::status::{self, Change};
+use crate::data::tree::Reference;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
+
+/// Staged changes, keyed by portable path.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Index {
+    /// Staged content, `None` for a deletion.
+    pub entries: BTreeMap<String, Option<String>>,
+    /// Staged renames: new path -> path in the reference tree.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub renames: BTreeMap<String, String>,
+}
+
+/// Location of `author`'s index under `root`.
+pub fn index_path(root: &Path, author: &str) -> PathBuf {
+    root.join(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}index{MAIN_SEPARATOR_STR}{author}.json"
+    ))
+}
+
+/// Native form of a portable path, relative to `root`.
+fn native(root: &Path, path: &str) -> PathBuf {
+    path.split('/').fold(root.to_path_buf(), |p, c| p.join(c))
+}
+
+impl Index {
+    /// Load `author`'s index; a missing index is empty.
+    pub fn load(root: &Path, author: &str) -> io::Result<Self> {
+        let path = index_path(root, author);
+        match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {e}", path.display()),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store the index atomically; an empty index removes the file.
+    pub fn save(&self, root: &Path, author: &str) -> io::Result<()> {
+        let path = index_path(root, author);
+        if self.entries.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(
+            &tmp,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )?;
+        fs::rename(&tmp, &path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Stage `content` for `path` (`None` stages a deletion).
+    pub fn stage_content(&mut self, path: &str, content: Option<String>) {
+        self.entries.insert(path.to_string(), content);
+    }
+
+    /// Stage the move of `from` to `to`, whose content is `content`; `tracked` tells whether
+    /// `from` is part of the reference tree. Moving a file twice keeps its original path, moving
+    /// it back drops the rename.
+    pub fn stage_rename(&mut self, from: &str, to: &str, content: String, tracked: bool) {
+        let origin = self
+            .renames
+            .remove(from)
+            .or_else(|| tracked.then(|| from.to_string()));
+        if tracked {
+            self.entries.insert(from.to_string(), None);
+        } else {
+            self.entries.remove(from);
+        }
+        self.entries.insert(to.to_string(), Some(content));
+        if let Some(origin) = origin.filter(|o| o != to) {
+            self.renames.insert(to.to_string(), origin);
+        }
+    }
+
+    /// Stage every change below `target` (a file or directory of the working tree, possibly
+    /// deleted) relative to the sealed state `reference`. Returns the staged paths.
+    pub fn stage(
+        &mut self,
+        root: &Path,
+        reference: &Reference,
+        target: &Path,
+    ) -> io::Result<Vec<String>> {
+        let mut staged = Vec::new();
+        for (change, path) in changes_under(root, reference, target)? {
+            let content = match change {
+                Change::Deleted => None,
+                _ => Some(fs::read_to_string(paths::long(&native(root, &path)))?),
+            };
+            self.stage_content(&path, content);
+            staged.push(path);
+        }
+        Ok(staged)
+    }
+
+    /// Whether sealing the index would change the sealed state `reference`.
+    pub fn changes(&self, reference: &Reference) -> io::Result<bool> {
+        for (path, content) in &self.entries {
+            let sealed = reference.read(path)?;
+            if content.as_ref().map(|c| c.as_bytes()) != sealed.as_deref() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Apply the staged changes to the reference tree at `tree_dir`.
+    pub fn apply_to_tree(&self, tree_dir: &Path) -> io::Result<()> {
+        for (path, content) in &self.entries {
+            let dest = native(tree_dir, path);
+            match content {
+                Some(content) => {
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(paths::long(parent))?;
+                    }
+                    fs::write(paths::long(&dest), content)?;
+                }
+                None => match fs::remove_file(paths::long(&dest)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changes relative to the sealed state `reference` at or below `target`, with portable paths.
+pub fn changes_under(
+    root: &Path,
+    reference: &Reference,
+    target: &Path,
+) -> io::Result<Vec<(Change, String)>> {
+    // `None` is the root itself: everything matches.
+    let prefix = paths::normalize(root, &root.join(target));
+    let st = status::status(root, reference)?;
+    Ok(st
+        .entries
+        .into_iter()
+        .map(|(c, p)| (c, p.replace(MAIN_SEPARATOR_STR, "/")))
+        .filter(|(_, p)| match &prefix {
+            None => true,
+            Some(pre) => p == pre || p.starts_with(&format!("{pre}/")),
+        })
+        .collect())
+}
+
+/// A run of changed lines between two versions of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// 1-based first line of the hunk in the old version.
+    pub old_start: usize,
+    /// 1-based first line of the hunk in the new version.
+    pub new_start: usize,
+    /// Removed lines, with their line endings.
+    pub removed: Vec<String>,
+    /// Added lines, with their line endings.
+    pub added: Vec<String>,
+}
+
+/// Split `old` -> `new` into hunks (maximal runs of changed lines).
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let (mut i, mut j) = (1, 1);
+    let mut out: Vec<Hunk> = Vec::new();
+    let mut open = false;
+    for d in diff::slice(&a, &b) {
+        match d {
+            diff::Result::Both(..) => {
+                open = false;
+                i += 1;
+                j += 1;
+            }
+            d => {
+                if !open {
+                    out.push(Hunk {
+                        old_start: i,
+                        new_start: j,
+                        removed: Vec::new(),
+                        added: Vec::new(),
+                    });
+                    open = true;
+                }
+                let h = out.last_mut().expect("hunk opened above");
+                match d {
+                    diff::Result::Left(l) => {
+                        h.removed.push(l.to_string());
+                        i += 1;
+                    }
+                    diff::Result::Right(r) => {
+                        h.added.push(r.to_string());
+                        j += 1;
+                    }
+                    diff::Result::Both(..) => unreachable!(),
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Rebuild `old` with only the hunks of `hunks(old, new)` whose flag in `selected` is set.
+pub fn apply_hunks(old: &str, new: &str, selected: &[bool]) -> String {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(old.len().max(new.len()));
+    let mut hunk = 0;
+    let mut open = false;
+    for d in diff::slice(&a, &b) {
+        match d {
+            diff::Result::Both(l, _) => {
+                if open {
+                    hunk += 1;
+                    open = false;
+                }
+                out.push_str(l);
+            }
+            diff::Result::Left(l) => {
+                open = true;
+                if !selected.get(hunk).copied().unwrap_or(false) {
+                    out.push_str(l);
+                }
+            }
+            diff::Result::Right(r) => {
+                open = true;
+                if selected.get(hunk).copied().unwrap_or(false) {
+                    out.push_str(r);
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_hunk_selection() {
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "a\nB\nc\nd\nE\nf";
+        let hs = hunks(old, new);
+        assert_eq!(hs.len(), 2);
+        assert_eq!((hs[0].old_start, hs[0].new_start), (2, 2));
+        assert_eq!(hs[1].removed, ["e\n"]);
+        assert_eq!(hs[1].added, ["E\n", "f"]);
+
+        assert_eq!(apply_hunks(old, new, &[true, false]), "a\nB\nc\nd\ne\n");
+        assert_eq!(apply_hunks(old, new, &[false, true]), "a\nb\nc\nd\nE\nf");
+        assert_eq!(apply_hunks(old, new, &[true, true]), new);
+        assert_eq!(apply_hunks(old, new, &[]), old);
+    }
+
+    #[test]
+    fn test_stage_and_apply_to_tree() {
+        let dir = std::env::temp_dir().join(format!("akasha-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (work, tree) = (dir.join("work"), dir.join("tree"));
+        let reference = Reference::Dir(tree.clone());
+        fs::create_dir_all(work.join("src")).unwrap();
+        fs::create_dir_all(&tree).unwrap();
+        fs::write(work.join("src").join("a.rs"), "a").unwrap();
+        fs::write(work.join("b.txt"), "b").unwrap();
+        fs::write(tree.join("gone.txt"), "g").unwrap();
+
+        let mut idx = Index::default();
+        assert_eq!(
+            idx.stage(&work, &reference, Path::new("src")).unwrap(),
+            ["src/a.rs"]
+        );
+        idx.stage(&work, &reference, Path::new("gone.txt")).unwrap();
+        idx.save(&dir, "u").unwrap();
+        let idx = Index::load(&dir, "u").unwrap();
+        assert_eq!(idx.entries["gone.txt"], None);
+        assert!(idx.changes(&reference).unwrap());
+
+        idx.apply_to_tree(&tree).unwrap();
+        assert!(!idx.changes(&reference).unwrap());
+        assert_eq!(
+            fs::read_to_string(tree.join("src").join("a.rs")).unwrap(),
+            "a"
+        );
+        assert!(!tree.join("gone.txt").exists() && !tree.join("b.txt").exists());
+        Index::default().save(&dir, "u").unwrap();
+        assert!(!index_path(&dir, "u").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
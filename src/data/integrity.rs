@@ -0,0 +1,226 @@
+//! Repository integrity checks: the engine behind `ak verify`.
+//!
+//! - Cubes: every record passes its CRC and nothing follows the last valid record (a reader
+//!   silently stops there, so anything after it is lost history).
+//! - Commits: ids are unique, every parent (and merge parent) exists, and no parent chain loops.
+//! - Manifests: the manifest record of every commit exists, and every file it lists points to an
+//!   existing content record whose BLAKE3 hash matches. Content records `ak prune` moved to an
+//!   archive (see `data::prune`) are not reported.
+//!
+//! Commits reference records of their own author's cubes (the repository cube with the shared
+//! layout), so the commit and manifest checks run on each author's records separately.
+
+use crate::data::config::{self, Config, Layout};
+use crate::data::history;
+use crate::data::manifest::{self, Manifest};
+use crate::data::paths;
+use crate::data::prune;
+use crate::data::restore::is_file_record;
+use crate::data::write::Writer;
+use crate::event::Event;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Findings of `check`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub cubes: usize,
+    pub records: usize,
+    pub commits: usize,
+    pub manifests: usize,
+    /// One line per problem, prefixed with the check that found it.
+    pub problems: Vec<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check the cubes, commits and manifests of the repository at `root`.
+pub fn check(root: &Path) -> io::Result<Report> {
+    let mut report = Report::default();
+    let shared = Config::load(root)?.storage.layout == Layout::Shared;
+    let mut cubes: Vec<_> = history::cube_files(root)?
+        .into_iter()
+        .map(|c| c.path)
+        .collect();
+    if shared && config::shared_cube(root).exists() {
+        cubes.push(config::shared_cube(root));
+    }
+    for path in &cubes {
+        let bytes = fs::read(path)?;
+        match Writer::check(&bytes) {
+            Ok(c) => {
+                report.cubes += 1;
+                report.records += c.records;
+                if c.valid_len < c.len {
+                    report.problems.push(format!(
+                        "cube {}: {} byte(s) after record {} are corrupt or truncated",
+                        path.display(),
+                        c.len - c.valid_len,
+                        c.records
+                    ));
+                }
+            }
+            Err(e) => report
+                .problems
+                .push(format!("cube {}: {e}", path.display())),
+        }
+    }
+
+    let authors = if shared {
+        vec![String::new()]
+    } else {
+        history::authors(root)?
+    };
+    for author in authors {
+        let events = history::author_events(root, &author)?;
+        let found = check_commits(&events, &mut report);
+        let label = |p: String| match author.as_str() {
+            "" => p,
+            a => format!("{p} ({a})"),
+        };
+        report.problems.extend(found.into_iter().map(label));
+    }
+    Ok(report)
+}
+
+/// Check the commits and manifests of one set of records, counting them in `report`; returns
+/// the problems found.
+pub fn check_commits(events: &[Event], report: &mut Report) -> Vec<String> {
+    let mut problems = Vec::new();
+    let by_record: HashMap<u64, &Event> = events.iter().map(|e| (e.id, e)).collect();
+    let pruned = prune::pruned_through(events);
+    let mut commits: HashMap<u64, serde_json::Value> = HashMap::new();
+    for ev in events.iter().filter(|e| e.phenomenon == "commit") {
+        let v: serde_json::Value = match serde_json::from_str(&ev.noumenon) {
+            Ok(v) => v,
+            Err(e) => {
+                problems.push(format!("commit record #{}: {e}", ev.id));
+                continue;
+            }
+        };
+        let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(ev.id);
+        if commits.insert(id, v).is_some() {
+            problems.push(format!("commit #{id}: recorded more than once"));
+        }
+    }
+    report.commits += commits.len();
+
+    let mut ids: Vec<u64> = commits.keys().copied().collect();
+    ids.sort();
+    let parent = |id: u64| commits[&id].get("parent").and_then(|x| x.as_u64());
+    let mut walked: HashSet<u64> = HashSet::new();
+    for &id in &ids {
+        for key in ["parent", "merge_parent"] {
+            if let Some(p) = commits[&id].get(key).and_then(|x| x.as_u64())
+                && !commits.contains_key(&p)
+            {
+                problems.push(format!(
+                    "commit #{id}: {} #{p} is missing",
+                    key.replace('_', " ")
+                ));
+            }
+        }
+        // Follow the parent chain down to the root or to a commit already walked.
+        let mut chain = vec![id];
+        let mut seen = HashSet::from([id]);
+        let mut at = parent(id).filter(|p| commits.contains_key(p));
+        while let Some(p) = at.filter(|p| !walked.contains(p)) {
+            if !seen.insert(p) {
+                problems.push(format!("commit #{id}: its parent chain loops through #{p}"));
+                break;
+            }
+            chain.push(p);
+            at = parent(p).filter(|p| commits.contains_key(p));
+        }
+        walked.extend(chain);
+
+        let Some(record) = commits[&id].get("manifest").and_then(|x| x.as_u64()) else {
+            continue;
+        };
+        let Some(ev) = by_record
+            .get(&record)
+            .filter(|e| e.phenomenon.starts_with("manifest:"))
+        else {
+            problems.push(format!(
+                "commit #{id}: manifest record #{record} is missing"
+            ));
+            continue;
+        };
+        let m: Manifest = match serde_json::from_str(&ev.noumenon) {
+            Ok(m) => m,
+            Err(e) => {
+                problems.push(format!("commit #{id}: manifest record #{record}: {e}"));
+                continue;
+            }
+        };
+        report.manifests += 1;
+        for (path, entry) in &m.files {
+            match by_record.get(&entry.record) {
+                Some(f) if is_file_record(f) && paths::canonical(&f.phenomenon) == *path => {
+                    if manifest::hash(&f.noumenon) != entry.hash {
+                        problems.push(format!(
+                            "commit #{id}: {path} (record #{}) does not match its hash",
+                            entry.record
+                        ));
+                    }
+                }
+                None if entry.record <= pruned => {}
+                _ => problems.push(format!(
+                    "commit #{id}: {path}: content record #{} is missing",
+                    entry.record
+                )),
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_commits_and_manifests() {
+        let ev = |id: u64, ph: &str, no: &str| {
+            Event::builder()
+                .id(id)
+                .phenomenon(ph)
+                .noumenon(no)
+                .timestamp(0)
+                .build()
+                .unwrap()
+        };
+        let manifest =
+            |hash: &str| format!(r#"{{"files":{{"a.txt":{{"record":1,"hash":"{hash}"}}}}}}"#);
+        let good = manifest::hash("hello");
+        let mut events = vec![
+            ev(1, "a.txt", "hello"),
+            ev(2, "manifest:3", &manifest(&good)),
+            ev(3, "commit", r#"{"id":3,"manifest":2}"#),
+            ev(4, "commit", r#"{"id":4,"parent":3}"#),
+        ];
+        let mut report = Report::default();
+        assert!(check_commits(&events, &mut report).is_empty());
+        assert_eq!((report.commits, report.manifests), (2, 1));
+
+        events[1] = ev(2, "manifest:3", &manifest("00"));
+        events.push(ev(5, "commit", r#"{"id":5,"parent":9}"#));
+        events.push(ev(6, "commit", r#"{"id":6,"parent":7}"#));
+        events.push(ev(7, "commit", r#"{"id":7,"parent":6}"#));
+        let problems = check_commits(&events, &mut Report::default());
+        assert_eq!(
+            problems,
+            [
+                "commit #3: a.txt (record #1) does not match its hash",
+                "commit #5: parent #9 is missing",
+                "commit #6: its parent chain loops through #6",
+            ]
+        );
+    }
+}
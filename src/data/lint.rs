@@ -0,0 +1,103 @@
+//! Seal message checks against the `[message]` rules of the repository configuration (see
+//! `config::Message`).
+//!
+//! `ak seal` runs them once the type, summary and body are known and before anything is written:
+//! in a terminal the user fixes the message and the checks run again, elsewhere the seal fails.
+//! `--no-verify` skips them along with the pre-commit hooks.
+
+use crate::data::config::Message;
+use regex::Regex;
+use std::io;
+
+/// Violations of `rules` by the message `ty` / `summary` / `body`, empty when it passes.
+///
+/// Body lines without a space (URLs, paths) may exceed the width. Fails on an invalid `scope`
+/// expression.
+pub fn check(rules: &Message, ty: &str, summary: &str, body: &str) -> io::Result<Vec<String>> {
+    let mut out = Vec::new();
+    if !rules.types.is_empty() && !rules.types.iter().any(|t| t == ty) {
+        out.push(format!(
+            "type '{ty}' is not one of {}",
+            rules.types.join(", ")
+        ));
+    }
+    let len = summary.chars().count();
+    if let Some(max) = rules.max_summary
+        && len > max
+    {
+        out.push(format!("summary is {len} characters long (at most {max})"));
+    }
+    if let Some(scope) = &rules.scope {
+        let re = Regex::new(scope).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid message.scope: {e}"),
+            )
+        })?;
+        if !re.is_match(summary) {
+            out.push(format!("summary does not match '{scope}'"));
+        }
+    }
+    if let Some(width) = rules.body_width {
+        for (n, line) in body.lines().enumerate() {
+            let len = line.chars().count();
+            if len > width && line.trim().contains(' ') {
+                out.push(format!(
+                    "body line {} is {len} characters long (at most {width})",
+                    n + 1
+                ));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_message_rules() {
+        let rules = Message {
+            max_summary: Some(20),
+            types: vec!["feat".to_string(), "fix".to_string()],
+            scope: Some("^[a-z]+: ".to_string()),
+            body_width: Some(10),
+        };
+        assert!(
+            check(
+                &rules,
+                "fix",
+                "cli: typo",
+                "short\nhttps://example.com/long/url"
+            )
+            .unwrap()
+            .is_empty()
+        );
+        assert_eq!(
+            check(
+                &rules,
+                "perf",
+                "Faster startup of the cli",
+                "one two three four"
+            )
+            .unwrap(),
+            [
+                "type 'perf' is not one of feat, fix",
+                "summary is 25 characters long (at most 20)",
+                "summary does not match '^[a-z]+: '",
+                "body line 1 is 18 characters long (at most 10)",
+            ]
+        );
+        assert!(
+            check(&Message::default(), "any", "", "")
+                .unwrap()
+                .is_empty()
+        );
+        let broken = Message {
+            scope: Some("(".to_string()),
+            ..Message::default()
+        };
+        assert!(check(&broken, "fix", "x", "").is_err());
+    }
+}
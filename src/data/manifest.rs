@@ -0,0 +1,182 @@
+//! Commit manifests: the exact file versions a seal contains.
+//!
+//! At seal time a `manifest:<commit id>` record is written with, for every path of the sealed
+//! tree, the id of the record holding its content and the BLAKE3 hash of that content:
+//!
+//! ```json
+//! { "files": { "src/main.rs": { "record": 42, "hash": "af13…" } } }
+//! ```
+//!
+//! The commit record references it through its `manifest` field (the manifest's record id). A
+//! manifest is complete, not a delta: it starts from the parent commit's manifest and applies
+//! the staged changes (or describes the whole working directory for `seal --all`), so deletions
+//! and branches are represented exactly.
+
+use crate::data::commit;
+use crate::data::diff::collect_files;
+use crate::data::index::Index;
+use crate::data::paths;
+use crate::data::restore::is_file_record;
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path};
+
+/// Where the content of one file lives.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Id of the record holding the content.
+    pub record: u64,
+    /// BLAKE3 (hex) of the content.
+    pub hash: String,
+}
+
+/// Every file of a commit, by portable path.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub files: BTreeMap<String, Entry>,
+}
+
+/// Phenomenon of the manifest of commit `commit`.
+pub fn phenomenon(commit: u64) -> String {
+    format!("manifest:{commit}")
+}
+
+/// Hash stored in manifest entries.
+pub fn hash(content: &str) -> String {
+    blake3::hash(content.as_bytes()).to_hex().to_string()
+}
+
+impl Manifest {
+    /// Manifest of commit `id`, `None` for commits sealed before manifests existed.
+    pub fn of_commit(events: &[Event], id: u64) -> Option<Manifest> {
+        let commit = commit::find(events, id)?;
+        let v: serde_json::Value = serde_json::from_str(&commit.noumenon).ok()?;
+        let record = v.get("manifest")?.as_u64()?;
+        let ev = events.iter().find(|e| e.id == record)?;
+        serde_json::from_str(&ev.noumenon).ok()
+    }
+
+    /// Append the contents staged in `index` and update the entries accordingly.
+    pub fn apply_index(&mut self, w: &mut Writer, index: &Index) -> io::Result<()> {
+        for (path, content) in &index.entries {
+            match content {
+                Some(content) => {
+                    let record = w.append_record(path, content)?.id;
+                    self.files.insert(
+                        path.clone(),
+                        Entry {
+                            record,
+                            hash: hash(content),
+                        },
+                    );
+                }
+                None => {
+                    self.files.remove(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe every file of the working directory at `root`, reusing the latest record of a
+    /// path when its content is unchanged and appending the content otherwise. Files that are
+    /// not valid UTF-8 can't be stored and are skipped.
+    pub fn snapshot(w: &mut Writer, events: &[Event], root: &Path) -> io::Result<Manifest> {
+        let mut latest: HashMap<String, &Event> = HashMap::new();
+        for ev in events.iter().filter(|e| is_file_record(e)) {
+            latest.insert(paths::canonical(&ev.phenomenon), ev);
+        }
+        let mut out = Manifest::default();
+        for rel in collect_files(root)? {
+            let path = rel.replace(MAIN_SEPARATOR_STR, "/");
+            if Writer::too_large(&root.join(&rel)) {
+                eprintln!("skip {path}: larger than a record");
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(paths::long(&root.join(&rel))) else {
+                eprintln!("skip {path}: not a UTF-8 text file");
+                continue;
+            };
+            let record = match latest.get(&path) {
+                Some(ev) if ev.noumenon == content => ev.id,
+                _ => w.append_record(&path, &content)?.id,
+            };
+            out.files.insert(
+                path,
+                Entry {
+                    record,
+                    hash: hash(&content),
+                },
+            );
+        }
+        Ok(out)
+    }
+
+    /// Write the manifest of commit `commit`; returns the id of its record.
+    pub fn record(&self, w: &mut Writer, commit: u64) -> io::Result<u64> {
+        let json = serde_json::to_string(self).map_err(io::Error::other)?;
+        Ok(w.append_record(&phenomenon(commit), &json)?.id)
+    }
+
+    /// Content of every file, looked up in `events`. Entries whose record is missing (e.g. lost
+    /// to a retention policy) are reported as errors.
+    pub fn contents<'a>(&self, events: &'a [Event]) -> io::Result<BTreeMap<String, &'a str>> {
+        let by_id: HashMap<u64, &Event> = events.iter().map(|e| (e.id, e)).collect();
+        let mut out = BTreeMap::new();
+        for (path, entry) in &self.files {
+            let ev = by_id.get(&entry.record).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{path}: record #{} is missing", entry.record),
+                )
+            })?;
+            out.insert(path.clone(), ev.noumenon.as_str());
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_follows_index_and_snapshot() {
+        let dir = std::env::temp_dir().join(format!("akasha-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("work")).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+
+        let mut idx = Index::default();
+        idx.stage_content("a.txt", Some("a".into()));
+        idx.stage_content("b.txt", Some("b".into()));
+        let mut m = Manifest::default();
+        m.apply_index(&mut w, &idx).unwrap();
+        let first = m.clone();
+
+        let mut idx = Index::default();
+        idx.stage_content("b.txt", None);
+        m.apply_index(&mut w, &idx).unwrap();
+        assert_eq!(m.files.keys().collect::<Vec<_>>(), ["a.txt"]);
+
+        let id = m.record(&mut w, 9).unwrap();
+        w.append("commit", &format!(r#"{{"id":9,"manifest":{id}}}"#))
+            .unwrap();
+        let events = w.events().unwrap();
+        assert_eq!(Manifest::of_commit(&events, 9), Some(m.clone()));
+        assert_eq!(first.contents(&events).unwrap()["b.txt"], "b");
+
+        // Unchanged files reuse their record, changed ones get a new one.
+        fs::write(dir.join("work").join("a.txt"), "a").unwrap();
+        fs::write(dir.join("work").join("c.txt"), "c").unwrap();
+        let snap = Manifest::snapshot(&mut w, &events, &dir.join("work")).unwrap();
+        assert_eq!(snap.files["a.txt"], m.files["a.txt"]);
+        assert!(snap.files["c.txt"].record > id);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,441 @@
+//! Three-way merges: the engine behind `ak merge`.
+//!
+//! Both branch tips are compared with their common ancestor (`merge_base`), file by file
+//! (`merge_trees`) and, for text files changed on both sides, line by line (`merge_text`, in the
+//! manner of `diff3`). A change made on one side only is taken as is; the same change made on
+//! both sides is taken once; different changes to the same lines are a conflict, written into
+//! the file between conflict markers:
+//!
+//! ```text
+//! <<<<<<< main
+//! our lines
+//! =======
+//! their lines
+//! >>>>>>> topic
+//! ```
+//!
+//! Binary files changed differently on both sides can't be merged line by line: `Binary` says
+//! which version to keep and whether that is a conflict. `merge_file` merges one file and is also
+//! how `ak cherry-pick`, `ak revert` and `ak stash pop` replay changes (see `data::pick`).
+//!
+//! A merge stopped by conflicts is remembered in `.eikyu/merge/<author>.json` (`MergeState`)
+//! until the next seal, which records the merged branch tip as the commit's `merge_parent`.
+
+use crate::data::commit;
+use crate::data::diff::Tree;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
+
+/// Parents of commit `id`: its `parent` and, for a merge, its `merge_parent`.
+pub fn parents(events: &[Event], id: u64) -> Vec<u64> {
+    let Some(v) = commit::find(events, id)
+        .and_then(|ev| serde_json::from_str::<serde_json::Value>(&ev.noumenon).ok())
+    else {
+        return Vec::new();
+    };
+    ["parent", "merge_parent"]
+        .iter()
+        .filter_map(|k| v.get(k).and_then(|x| x.as_u64()))
+        .collect()
+}
+
+/// Commits reachable from `id` (itself included), nearest first.
+pub fn ancestors(events: &[Event], id: u64) -> Vec<u64> {
+    let mut seen = HashSet::from([id]);
+    let mut queue = VecDeque::from([id]);
+    let mut out = Vec::new();
+    while let Some(c) = queue.pop_front() {
+        out.push(c);
+        for p in parents(events, c) {
+            if seen.insert(p) {
+                queue.push_back(p);
+            }
+        }
+    }
+    out
+}
+
+/// Nearest commit reachable from both `a` and `b`.
+pub fn merge_base(events: &[Event], a: u64, b: u64) -> Option<u64> {
+    let from_a: HashSet<u64> = ancestors(events, a).into_iter().collect();
+    ancestors(events, b)
+        .into_iter()
+        .find(|c| from_a.contains(c))
+}
+
+/// Outcome of `merge_text`.
+#[derive(Debug, PartialEq)]
+pub struct MergedText {
+    pub text: String,
+    /// Number of conflicting regions written between markers.
+    pub conflicts: usize,
+}
+
+/// Lines of `text`, line endings included.
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// For every line of `base`, the line of `other` it is kept as, if any.
+fn matching(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut out = vec![None; base.len()];
+    let (mut i, mut j) = (0, 0);
+    for d in diff::slice(base, other) {
+        match d {
+            diff::Result::Both(..) => {
+                out[i] = Some(j);
+                i += 1;
+                j += 1;
+            }
+            diff::Result::Left(_) => i += 1,
+            diff::Result::Right(_) => j += 1,
+        }
+    }
+    out
+}
+
+/// Merge the line changes `base` -> `ours` and `base` -> `theirs`; conflicting regions are
+/// written between markers naming `ours_label` and `theirs_label`.
+pub fn merge_text(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> MergedText {
+    let (b, o, t) = (lines(base), lines(ours), lines(theirs));
+    let (in_o, in_t) = (matching(&b, &o), matching(&b, &t));
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // Next base line kept unchanged on both sides, or the end of all three.
+        let stable = (i..b.len()).find_map(|x| Some((x, in_o[x]?, in_t[x]?)));
+        let (x, y, z) = stable.unwrap_or((b.len(), o.len(), t.len()));
+        let (bc, oc, tc) = (&b[i..x], &o[j..y], &t[k..z]);
+        let chunk = if oc == bc || oc == tc {
+            tc
+        } else if tc == bc {
+            oc
+        } else {
+            conflicts += 1;
+            let end = |c: &[&str], out: &mut String| {
+                if c.last().is_some_and(|l| !l.ends_with('\n')) {
+                    out.push('\n');
+                }
+            };
+            text.push_str(&format!("<<<<<<< {ours_label}\n"));
+            text.extend(oc.iter().copied());
+            end(oc, &mut text);
+            text.push_str("=======\n");
+            text.extend(tc.iter().copied());
+            end(tc, &mut text);
+            text.push_str(&format!(">>>>>>> {theirs_label}\n"));
+            &[][..]
+        };
+        text.extend(chunk.iter().copied());
+        if stable.is_none() {
+            break;
+        }
+        text.push_str(b[x]);
+        (i, j, k) = (x + 1, y + 1, z + 1);
+    }
+    MergedText { text, conflicts }
+}
+
+/// Outcome of `merge_trees`.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergedTree {
+    /// The merged files, conflict markers included.
+    pub files: Tree,
+    /// Paths needing a manual resolution.
+    pub conflicts: Vec<String>,
+}
+
+/// What to do with a binary file changed differently on both sides.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Binary {
+    /// Keep our version and report a conflict.
+    #[default]
+    Conflict,
+    /// Keep our version.
+    Ours,
+    /// Take their version.
+    Theirs,
+}
+
+impl Binary {
+    /// The policy named `name`: `conflict`, `ours` or `theirs`.
+    pub fn parse(name: &str) -> io::Result<Self> {
+        match name {
+            "conflict" => Ok(Binary::Conflict),
+            "ours" => Ok(Binary::Ours),
+            "theirs" => Ok(Binary::Theirs),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown binary policy '{name}' (conflict, ours or theirs)"),
+            )),
+        }
+    }
+}
+
+/// Outcome of `merge_file`.
+#[derive(Debug, PartialEq)]
+pub struct MergedFile {
+    /// The merged content, conflict markers included; `None` when the file is gone.
+    pub content: Option<Vec<u8>>,
+    /// Whether the file needs a manual resolution.
+    pub conflict: bool,
+}
+
+/// Merge the changes `base` -> `ours` and `base` -> `theirs` of one file (`None` where it is
+/// absent). A file modified on one side and deleted on the other keeps the modified version and
+/// is a conflict; a binary file changed on both sides follows `binary`.
+pub fn merge_file(
+    base: Option<&Vec<u8>>,
+    ours: Option<&Vec<u8>>,
+    theirs: Option<&Vec<u8>>,
+    ours_label: &str,
+    theirs_label: &str,
+    binary: Binary,
+) -> MergedFile {
+    let clean = |content: Option<&Vec<u8>>| MergedFile {
+        content: content.cloned(),
+        conflict: false,
+    };
+    if ours == theirs || theirs == base {
+        return clean(ours);
+    }
+    if ours == base {
+        return clean(theirs);
+    }
+    let (Some(o), Some(t)) = (ours, theirs) else {
+        // Modified on one side, deleted on the other.
+        return MergedFile {
+            content: ours.or(theirs).cloned(),
+            conflict: true,
+        };
+    };
+    let text = |x: &[u8]| String::from_utf8(x.to_vec()).ok();
+    let base_text = base.map_or(Some(String::new()), |b| text(b));
+    match (base_text, text(o), text(t)) {
+        (Some(bt), Some(ot), Some(tt)) => {
+            let m = merge_text(&bt, &ot, &tt, ours_label, theirs_label);
+            MergedFile {
+                content: Some(m.text.into_bytes()),
+                conflict: m.conflicts > 0,
+            }
+        }
+        _ => match binary {
+            Binary::Conflict => MergedFile {
+                content: Some(o.clone()),
+                conflict: true,
+            },
+            Binary::Ours => clean(ours),
+            Binary::Theirs => clean(theirs),
+        },
+    }
+}
+
+/// Merge the changes `base` -> `ours` and `base` -> `theirs`, file by file (see `merge_file`).
+pub fn merge_trees(
+    base: &Tree,
+    ours: &Tree,
+    theirs: &Tree,
+    ours_label: &str,
+    theirs_label: &str,
+    binary: Binary,
+) -> MergedTree {
+    let mut out = MergedTree::default();
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    for path in paths {
+        let merged = merge_file(
+            base.get(path),
+            ours.get(path),
+            theirs.get(path),
+            ours_label,
+            theirs_label,
+            binary,
+        );
+        if merged.conflict {
+            out.conflicts.push(path.clone());
+        }
+        if let Some(content) = merged.content {
+            out.files.insert(path.clone(), content);
+        }
+    }
+    out
+}
+
+/// Whether `text` still holds conflict markers written by `merge_text`.
+pub fn has_conflict_markers(text: &str) -> bool {
+    let mut lines = text.lines();
+    lines.any(|l| l.starts_with("<<<<<<< "))
+        && lines.any(|l| l == "=======")
+        && lines.any(|l| l.starts_with(">>>>>>> "))
+}
+
+/// A merge waiting for its conflicts to be resolved and sealed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MergeState {
+    /// Branch being merged.
+    pub branch: String,
+    /// Its tip, the `merge_parent` of the coming seal.
+    pub head: u64,
+    /// Files the merge changed, restored by `ak merge --abort`.
+    pub paths: Vec<String>,
+    /// Files left with conflict markers.
+    pub conflicts: Vec<String>,
+}
+
+/// Location of `author`'s merge state under `root`.
+pub fn state_path(root: &Path, author: &str) -> PathBuf {
+    root.join(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}merge{MAIN_SEPARATOR_STR}{author}.json"
+    ))
+}
+
+impl MergeState {
+    /// `author`'s merge in progress, if any.
+    pub fn load(root: &Path, author: &str) -> io::Result<Option<Self>> {
+        let path = state_path(root, author);
+        match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map(Some).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {e}", path.display()),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remember the merge in progress.
+    pub fn save(&self, root: &Path, author: &str) -> io::Result<()> {
+        let path = state_path(root, author);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )
+    }
+
+    /// Forget `author`'s merge in progress.
+    pub fn clear(root: &Path, author: &str) -> io::Result<()> {
+        match fs::remove_file(state_path(root, author)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RecordType;
+
+    #[test]
+    fn test_merge_text() {
+        let base = "a\nb\nc\nd\n";
+        // Different lines changed on each side: both kept.
+        let m = merge_text(base, "A\nb\nc\nd\n", "a\nb\nc\nD\n", "main", "topic");
+        assert_eq!(m.text, "A\nb\nc\nD\n");
+        assert_eq!(m.conflicts, 0);
+        // The same line changed differently: conflict.
+        let m = merge_text(base, "a\nB1\nc\nd\n", "a\nB2\nc\nd\n", "main", "topic");
+        assert_eq!(
+            m.text,
+            "a\n<<<<<<< main\nB1\n=======\nB2\n>>>>>>> topic\nc\nd\n"
+        );
+        assert_eq!(m.conflicts, 1);
+        assert!(has_conflict_markers(&m.text));
+        assert!(!has_conflict_markers("=======\n<<<<<<< a\n"));
+        // The same change on both sides is taken once.
+        let m = merge_text(base, "a\nb\nc\nd\ne\n", "a\nb\nc\nd\ne\n", "main", "topic");
+        assert_eq!((m.text.as_str(), m.conflicts), ("a\nb\nc\nd\ne\n", 0));
+    }
+
+    #[test]
+    fn test_merge_trees_and_base() {
+        let ev = |id: u64, no: &str| {
+            Event::builder()
+                .id(id)
+                .phenomenon("commit")
+                .noumenon(no)
+                .timestamp(0)
+                .kind(RecordType::Commit)
+                .build()
+                .unwrap()
+        };
+        // 1 <- 2 (main), 1 <- 3 (topic), 4 merges 3 into 2.
+        let events = [
+            ev(1, r#"{"id":1}"#),
+            ev(2, r#"{"id":2,"parent":1}"#),
+            ev(3, r#"{"id":3,"parent":1}"#),
+            ev(4, r#"{"id":4,"parent":2,"merge_parent":3}"#),
+        ];
+        assert_eq!(merge_base(&events, 2, 3), Some(1));
+        assert_eq!(merge_base(&events, 4, 3), Some(3));
+
+        let tree = |files: &[(&str, &str)]| -> Tree {
+            files
+                .iter()
+                .map(|(p, c)| (p.to_string(), c.as_bytes().to_vec()))
+                .collect()
+        };
+        let base = tree(&[("kept", "1"), ("gone", "1"), ("edited", "1")]);
+        let ours = tree(&[("kept", "1"), ("edited", "1"), ("new", "x")]);
+        let theirs = tree(&[("kept", "2"), ("edited", "1")]);
+        let m = merge_trees(&base, &ours, &theirs, "main", "topic", Binary::Conflict);
+        assert!(m.conflicts.is_empty());
+        assert_eq!(
+            m.files,
+            tree(&[("kept", "2"), ("edited", "1"), ("new", "x")])
+        );
+
+        let theirs = tree(&[("kept", "1"), ("gone", "2"), ("edited", "1")]);
+        let m = merge_trees(&base, &ours, &theirs, "main", "topic", Binary::Conflict);
+        assert_eq!(m.conflicts, ["gone"]);
+        assert_eq!(m.files["gone"], b"2");
+    }
+
+    #[test]
+    fn test_binary_policies() {
+        let (base, ours, theirs) = (vec![0xff, 1], vec![0xff, 2], vec![0xff, 3]);
+        let merge = |binary| merge_file(Some(&base), Some(&ours), Some(&theirs), "a", "b", binary);
+        assert_eq!(
+            merge(Binary::Conflict),
+            MergedFile {
+                content: Some(ours.clone()),
+                conflict: true
+            }
+        );
+        assert_eq!(merge(Binary::Ours).content, Some(ours.clone()));
+        assert_eq!(merge(Binary::Theirs).content, Some(theirs.clone()));
+        assert!(!merge(Binary::Theirs).conflict);
+        // Changed on one side only: no policy needed.
+        let one_side = merge_file(
+            Some(&base),
+            Some(&base),
+            Some(&theirs),
+            "a",
+            "b",
+            Binary::Ours,
+        );
+        assert_eq!(one_side.content, Some(theirs));
+        assert_eq!(Binary::parse("theirs").unwrap(), Binary::Theirs);
+        assert!(Binary::parse("mine").is_err());
+    }
+}
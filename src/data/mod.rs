@@ -1,4 +1,55 @@
+pub mod akignore;
+pub mod archive;
+pub mod backup;
+pub mod bisect;
+pub mod blame;
+pub mod branch;
+pub mod bus;
+pub mod changelog;
+pub mod check;
+pub mod checkpoint;
+pub mod commit;
+pub mod compare;
+pub mod config;
+pub mod daemon;
 pub mod diff;
+pub mod dump;
+pub mod export;
+pub mod fork;
+pub mod gc;
+pub mod git;
+pub mod graph;
+pub mod grep;
+pub mod history;
+pub mod hooks;
+pub mod index;
+pub mod integrity;
+pub mod lint;
+pub mod manifest;
+pub mod merge;
+pub mod note;
+pub mod patch;
+pub mod paths;
+pub mod pick;
+pub mod plugin;
+pub mod provenance;
+pub mod prune;
+pub mod quota;
+pub mod reflog;
+pub mod remote;
+pub mod rename;
+pub mod replay;
+pub mod restore;
+pub mod retention;
+pub mod schema;
+pub mod script;
+pub mod sign;
+pub mod stash;
 pub mod stats;
+pub mod status;
+pub mod storage;
+pub mod tag;
 pub mod tree;
+pub mod txn;
+pub mod worktree;
 pub mod write;
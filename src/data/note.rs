@@ -0,0 +1,89 @@
+//! Notes on commits: `ak note`.
+//!
+//! A note annotates a seal after the fact (review comments, build results) without touching the
+//! commit record: it is a `commit:note` record `{"commit": <id>, "author": "ana", "text": "…"}`
+//! appended to the cube. A commit can have any number of notes; `ak show` lists them and
+//! `ak timeline --note` selects the commits whose notes mention a text.
+
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+
+/// Phenomenon of note records.
+pub const NOTE: &str = "commit:note";
+
+/// Noumenon of a `commit:note` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Note {
+    pub commit: u64,
+    pub author: String,
+    pub text: String,
+}
+
+/// Attach `text`, written by `author`, to commit `commit`.
+pub fn add(w: &mut Writer, commit: u64, author: &str, text: &str) -> io::Result<u64> {
+    let note = Note {
+        commit,
+        author: author.to_string(),
+        text: text.to_string(),
+    };
+    let json = serde_json::to_string(&note).map_err(io::Error::other)?;
+    w.append(NOTE, &json)
+}
+
+/// Notes of every commit, each with its timestamp (nanoseconds), oldest first.
+pub fn notes(events: &[Event]) -> HashMap<u64, Vec<(u128, Note)>> {
+    let mut out: HashMap<u64, Vec<(u128, Note)>> = HashMap::new();
+    for ev in events.iter().filter(|e| e.phenomenon == NOTE) {
+        if let Ok(note) = serde_json::from_str::<Note>(&ev.noumenon) {
+            out.entry(note.commit)
+                .or_default()
+                .push((ev.timestamp, note));
+        }
+    }
+    out
+}
+
+/// Notes of commit `id`, oldest first.
+pub fn of_commit(events: &[Event], id: u64) -> Vec<(u128, Note)> {
+    notes(events).remove(&id).unwrap_or_default()
+}
+
+/// Whether one of `notes` contains `text`, ignoring case.
+pub fn mention(notes: &[(u128, Note)], text: &str) -> bool {
+    let text = text.to_lowercase();
+    notes
+        .iter()
+        .any(|(_, n)| n.text.to_lowercase().contains(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_find_notes() {
+        let dir = std::env::temp_dir().join(format!("akasha-note-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        add(&mut w, 3, "ana", "Reviewed: LGTM").unwrap();
+        add(&mut w, 5, "bob", "CI failed on windows").unwrap();
+        add(&mut w, 3, "bob", "benchmarks +2%").unwrap();
+
+        let events = w.events().unwrap();
+        let texts: Vec<String> = of_commit(&events, 3)
+            .into_iter()
+            .map(|(_, n)| format!("{}: {}", n.author, n.text))
+            .collect();
+        assert_eq!(texts, ["ana: Reviewed: LGTM", "bob: benchmarks +2%"]);
+        assert!(of_commit(&events, 4).is_empty());
+        let all = notes(&events);
+        assert!(mention(&all[&5], "ci FAILED"));
+        assert!(!mention(&all[&3], "ci"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,548 @@
+//! Patches: seals as mailbox files, the engine behind `ak format-patch` and `ak apply`.
+//!
+//! Every seal becomes one mail in the format of `git format-patch`, so patches can be exchanged
+//! with Git users as well:
+//!
+//! ```text
+//! From ak#12 Mon Sep 17 00:00:00 2001
+//! From: Ana <ana@example.com>
+//! Date: Fri, 16 Oct 2026 12:00:00 +0000
+//! Subject: [PATCH 1/2] fix: keep the last line
+//!
+//! Body of the seal.
+//! ---
+//! diff --git a/src/main.rs b/src/main.rs
+//! --- a/src/main.rs
+//! +++ b/src/main.rs
+//! @@ -1,3 +1,3 @@
+//! …
+//! ```
+//!
+//! The subject and body follow `git::message`. Hunks carry three lines of context; a hunk is
+//! applied where its old lines are found, at the place the patch says or else the nearest one,
+//! so that patches survive unrelated changes elsewhere in the file. When they are found nowhere,
+//! the hunk is tried again without its first and last context line, then without two of them
+//! (the fuzz of `patch(1)`, up to `DEFAULT_FUZZ` lines).
+//!
+//! Plain unified diffs (`diff -u`, `ak diff`) read as one patch without a message, and any patch
+//! can be applied in reverse (`FilePatch::reversed`) to undo it.
+
+use crate::data::diff;
+use crate::data::git;
+use crate::data::status::Change;
+use std::io;
+
+pub use crate::data::diff::{Hunk, Line};
+
+/// Lines of context around changes.
+const CONTEXT: usize = 3;
+
+/// Context lines at each end of a hunk that may be ignored to apply it.
+pub const DEFAULT_FUZZ: usize = 2;
+
+/// Date on the separator line of every mail, as written by `git format-patch`.
+const MAGIC_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+/// Changes to one file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// Portable path.
+    pub path: String,
+    pub change: Change,
+    pub hunks: Vec<Hunk>,
+}
+
+/// A seal as a patch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    pub author: String,
+    pub email: String,
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+    pub ty: String,
+    pub summary: String,
+    pub body: String,
+    pub files: Vec<FilePatch>,
+}
+
+impl FilePatch {
+    /// The changes that undo this one: added lines become removed ones and the reverse.
+    pub fn reversed(&self) -> FilePatch {
+        let hunks = self
+            .hunks
+            .iter()
+            .map(|h| Hunk {
+                old_start: h.new_start,
+                old_len: h.new_len,
+                new_start: h.old_start,
+                new_len: h.old_len,
+                lines: h
+                    .lines
+                    .iter()
+                    .map(|l| match l {
+                        Line::Context(t) => Line::Context(t.clone()),
+                        Line::Removed(t) => Line::Added(t.clone()),
+                        Line::Added(t) => Line::Removed(t.clone()),
+                    })
+                    .collect(),
+            })
+            .collect();
+        FilePatch {
+            path: self.path.clone(),
+            change: match self.change {
+                Change::Added => Change::Deleted,
+                Change::Deleted => Change::Added,
+                Change::Modified => Change::Modified,
+            },
+            hunks,
+        }
+    }
+}
+
+/// Changes from `old` to `new`, the content of `path` before and after (`None` when absent).
+pub fn diff_file(path: &str, old: Option<&str>, new: Option<&str>) -> FilePatch {
+    let change = match (old, new) {
+        (None, _) => Change::Added,
+        (_, None) => Change::Deleted,
+        _ => Change::Modified,
+    };
+    FilePatch {
+        path: path.to_string(),
+        change,
+        hunks: diff::hunks(old.unwrap_or(""), new.unwrap_or(""), CONTEXT),
+    }
+}
+
+/// Append the unified diff of `f` to `out`.
+fn render_file(out: &mut String, f: &FilePatch) {
+    let p = &f.path;
+    out.push_str(&format!("diff --git a/{p} b/{p}\n"));
+    let (old, new) = match f.change {
+        Change::Added => {
+            out.push_str("new file mode 100644\n");
+            ("/dev/null".to_string(), format!("b/{p}"))
+        }
+        Change::Deleted => {
+            out.push_str("deleted file mode 100644\n");
+            (format!("a/{p}"), "/dev/null".to_string())
+        }
+        Change::Modified => (format!("a/{p}"), format!("b/{p}")),
+    };
+    out.push_str(&format!("--- {old}\n+++ {new}\n"));
+    for h in &f.hunks {
+        out.push_str(&h.header());
+        out.push('\n');
+        for line in &h.lines {
+            let (prefix, text) = match line {
+                Line::Context(t) => (' ', t),
+                Line::Removed(t) => ('-', t),
+                Line::Added(t) => ('+', t),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+/// Mail `number` of `total` for `patch`, the seal `commit`.
+pub fn render(patch: &Patch, commit: u64, number: usize, total: usize) -> String {
+    let message = git::message(&patch.ty, &patch.summary, &patch.body);
+    let (subject, body) = message.split_once('\n').unwrap_or((&message, ""));
+    let prefix = match total {
+        1 => "[PATCH]".to_string(),
+        _ => format!("[PATCH {number}/{total}]"),
+    };
+    let date = chrono::DateTime::from_timestamp_millis(patch.timestamp)
+        .unwrap_or_default()
+        .to_rfc2822();
+    let mut out = format!(
+        "From ak#{commit} {MAGIC_DATE}\nFrom: {} <{}>\nDate: {date}\nSubject: {prefix} {subject}\n\n",
+        patch.author, patch.email
+    );
+    if !body.trim().is_empty() {
+        out.push_str(body.trim());
+        out.push('\n');
+    }
+    out.push_str("---\n");
+    for f in &patch.files {
+        render_file(&mut out, f);
+    }
+    out.push_str("-- \nak\n\n");
+    out
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read the patches of a mailbox, as written by `render` or `git format-patch`. A plain unified
+/// diff reads as one patch without a message.
+pub fn parse(text: &str) -> io::Result<Vec<Patch>> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let starts: Vec<usize> = (0..lines.len())
+        .filter(|&k| lines[k].starts_with("From ") && lines[k].trim_end().ends_with(MAGIC_DATE))
+        .collect();
+    if starts.is_empty() {
+        return Ok(vec![parse_mail(&lines)?]);
+    }
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain([&lines.len()]))
+        .map(|(&from, &to)| parse_mail(&lines[from + 1..to]))
+        .collect()
+}
+
+/// Read one mail: headers, message, then the diffs.
+fn parse_mail(lines: &[&str]) -> io::Result<Patch> {
+    let mut patch = Patch::default();
+    let mut subject = String::new();
+    let mut k = 0;
+    let is_diff = |l: &str| l.starts_with("diff --git ") || l.starts_with("--- ");
+    // Headers, continued on lines starting with blanks.
+    let mut headers: Vec<String> = Vec::new();
+    while k < lines.len() && !lines[k].trim_end().is_empty() && !is_diff(lines[k]) {
+        match headers.last_mut() {
+            Some(h) if lines[k].starts_with([' ', '\t']) => h.push_str(lines[k].trim_end()),
+            _ => headers.push(lines[k].trim_end().to_string()),
+        }
+        k += 1;
+    }
+    for h in headers {
+        if let Some(from) = h.strip_prefix("From: ") {
+            let (name, email) = from.split_once('<').unwrap_or((from, ""));
+            patch.author = name.trim().trim_matches('"').to_string();
+            patch.email = email.trim_end_matches('>').trim().to_string();
+        } else if let Some(date) = h.strip_prefix("Date: ") {
+            patch.timestamp = chrono::DateTime::parse_from_rfc2822(date.trim())
+                .map(|d| d.timestamp_millis())
+                .unwrap_or(0);
+        } else if let Some(s) = h.strip_prefix("Subject: ") {
+            subject = match s.trim().strip_prefix('[') {
+                Some(rest) => rest.split_once(']').map_or(rest, |(_, s)| s).trim(),
+                None => s.trim(),
+            }
+            .to_string();
+        }
+    }
+    // The message ends at `---` (then comes an optional diffstat) or at the first diff, which
+    // in a plain diff is a `--- ` line followed by a `+++ ` one.
+    let mut body = String::new();
+    let plain_diff = |k: usize| {
+        lines[k].starts_with("--- ") && lines.get(k + 1).is_some_and(|l| l.starts_with("+++ "))
+    };
+    while k < lines.len()
+        && lines[k].trim_end() != "---"
+        && !lines[k].starts_with("diff --git ")
+        && !plain_diff(k)
+    {
+        body.push_str(lines[k]);
+        k += 1;
+    }
+    while k < lines.len() && !is_diff(lines[k]) {
+        k += 1;
+    }
+    if !subject.is_empty() || !body.trim().is_empty() {
+        (patch.ty, patch.summary, patch.body) =
+            git::split_message(&format!("{subject}\n\n{}", body.trim()));
+    }
+    patch.files = parse_diffs(&lines[k..])?;
+    Ok(patch)
+}
+
+/// Read the file diffs of a patch, up to its signature.
+fn parse_diffs(lines: &[&str]) -> io::Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut k = 0;
+    while k < lines.len() {
+        let line = lines[k].trim_end_matches(['\n', '\r']);
+        k += 1;
+        if line == "-- " {
+            break;
+        }
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            // `a/<path> b/<path>`: the path is the second half.
+            let n = rest.len().saturating_sub(5) / 2;
+            files.push(FilePatch {
+                path: rest[rest.len() - n..].to_string(),
+                change: Change::Modified,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        if line.starts_with("new file mode") || line.starts_with("deleted file mode") {
+            if let Some(f) = files.last_mut() {
+                f.change = if line.starts_with("new") {
+                    Change::Added
+                } else {
+                    Change::Deleted
+                };
+            }
+            continue;
+        }
+        if let Some(old) = line.strip_prefix("--- ") {
+            // Plain diffs have no `diff --git` line.
+            if files.last().is_none_or(|f| !f.hunks.is_empty()) {
+                files.push(FilePatch {
+                    path: String::new(),
+                    change: Change::Modified,
+                    hunks: Vec::new(),
+                });
+            }
+            let f = files.last_mut().expect("pushed above");
+            match old.strip_prefix("a/") {
+                Some(path) => f.path = path.to_string(),
+                None if old == "/dev/null" => f.change = Change::Added,
+                None => f.path = old.to_string(),
+            }
+            continue;
+        }
+        if let Some(new) = line.strip_prefix("+++ ") {
+            let f = files
+                .last_mut()
+                .ok_or_else(|| invalid(format!("'{line}' without '---'")))?;
+            match new.strip_prefix("b/") {
+                Some(path) => f.path = path.to_string(),
+                None if new == "/dev/null" => f.change = Change::Deleted,
+                None => f.path = new.to_string(),
+            }
+            continue;
+        }
+        if line.starts_with("@@ ") {
+            let f = files
+                .last_mut()
+                .ok_or_else(|| invalid(format!("hunk without a file: {line}")))?;
+            let mut hunk = parse_hunk_header(line)?;
+            let (mut old_left, mut new_left) = (hunk.old_len, hunk.new_len);
+            while old_left > 0 || new_left > 0 || lines.get(k).is_some_and(|l| l.starts_with('\\'))
+            {
+                let Some(raw) = lines.get(k) else {
+                    return Err(invalid(format!("{}: truncated hunk", f.path)));
+                };
+                k += 1;
+                let (tag, text) = match raw.chars().next() {
+                    // Mailers may strip the blank of empty context lines.
+                    Some('\n') | Some('\r') => (' ', *raw),
+                    Some(c) => (c, &raw[c.len_utf8()..]),
+                    None => (' ', "\n"),
+                };
+                let text = text.to_string();
+                match tag {
+                    ' ' if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                        hunk.lines.push(Line::Context(text));
+                    }
+                    '-' if old_left > 0 => {
+                        old_left -= 1;
+                        hunk.lines.push(Line::Removed(text));
+                    }
+                    '+' if new_left > 0 => {
+                        new_left -= 1;
+                        hunk.lines.push(Line::Added(text));
+                    }
+                    // `\ No newline at end of file`, about the line before.
+                    '\\' => {
+                        if let Some(Line::Context(t) | Line::Removed(t) | Line::Added(t)) =
+                            hunk.lines.last_mut()
+                            && t.ends_with('\n')
+                        {
+                            t.pop();
+                        }
+                    }
+                    _ => return Err(invalid(format!("{}: malformed hunk line {raw:?}", f.path))),
+                }
+            }
+            f.hunks.push(hunk);
+        }
+    }
+    match files.iter().find(|f| f.path.is_empty()) {
+        Some(_) => Err(invalid("a diff names no file".to_string())),
+        None => Ok(files),
+    }
+}
+
+/// An empty hunk from its `@@ -a,b +c,d @@` header.
+fn parse_hunk_header(line: &str) -> io::Result<Hunk> {
+    let bad = || invalid(format!("malformed hunk header '{line}'"));
+    let ranges = line
+        .strip_prefix("@@ -")
+        .and_then(|r| r.split_once(" @@"))
+        .map(|(r, _)| r)
+        .ok_or_else(bad)?;
+    let (old, new) = ranges.split_once(" +").ok_or_else(bad)?;
+    let parse = |r: &str| -> io::Result<(usize, usize)> {
+        let (start, len) = r.split_once(',').unwrap_or((r, "1"));
+        Ok((
+            start.parse().map_err(|_| bad())?,
+            len.parse().map_err(|_| bad())?,
+        ))
+    };
+    let (old_start, old_len) = parse(old)?;
+    let (new_start, new_len) = parse(new)?;
+    Ok(Hunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        lines: Vec::new(),
+    })
+}
+
+/// Apply `f` to `content`, the file's current content (`None` when it doesn't exist), ignoring up
+/// to `fuzz` context lines at each end of a hunk that doesn't apply as is. Returns the new
+/// content, `None` when the patch deletes the file.
+pub fn apply_file(content: Option<&str>, f: &FilePatch, fuzz: usize) -> io::Result<Option<String>> {
+    match (f.change, content) {
+        (Change::Added, Some(_)) => return Err(invalid(format!("{}: already exists", f.path))),
+        (Change::Modified | Change::Deleted, None) => {
+            return Err(invalid(format!("{}: does not exist", f.path)));
+        }
+        _ => {}
+    }
+    let mut lines: Vec<String> = content
+        .unwrap_or("")
+        .split_inclusive('\n')
+        .map(str::to_string)
+        .collect();
+    // Hunks apply in order: each one after the previous, shifted as much as the previous.
+    let (mut floor, mut shift) = (0, 0isize);
+    for (n, h) in f.hunks.iter().enumerate() {
+        let planned = if h.old_len == 0 {
+            h.old_start
+        } else {
+            h.old_start.saturating_sub(1)
+        } as isize;
+        let context = |lines: &mut dyn Iterator<Item = &Line>| {
+            lines.take_while(|l| matches!(l, Line::Context(_))).count()
+        };
+        let (leading, trailing) = (
+            context(&mut h.lines.iter()),
+            context(&mut h.lines.iter().rev()),
+        );
+        let mut found = None;
+        for fuzz in 0..=fuzz {
+            // Without `fuzz` context lines at each end, as far as there are any.
+            let (skip, cut) = (fuzz.min(leading), fuzz.min(trailing));
+            if (fuzz > 0 && skip + cut == 0) || skip + cut > h.lines.len() {
+                break;
+            }
+            let kept = &h.lines[skip..h.lines.len() - cut];
+            let old: Vec<&String> = kept
+                .iter()
+                .filter_map(|l| match l {
+                    Line::Context(t) | Line::Removed(t) => Some(t),
+                    Line::Added(_) => None,
+                })
+                .collect();
+            let matches = |at: usize| {
+                at >= floor
+                    && at + old.len() <= lines.len()
+                    && lines[at..at + old.len()].iter().eq(old.iter().copied())
+            };
+            let expected = (planned + shift + skip as isize).max(0) as usize;
+            if let Some(at) = (0..=lines.len())
+                .flat_map(|d| [expected.checked_sub(d), Some(expected + d)])
+                .flatten()
+                .find(|&at| matches(at))
+            {
+                found = Some((at, kept, old.len(), skip));
+                break;
+            }
+        }
+        let (at, kept, removed, skip) =
+            found.ok_or_else(|| invalid(format!("{}: hunk #{} does not apply", f.path, n + 1)))?;
+        let new: Vec<String> = kept
+            .iter()
+            .filter_map(|l| match l {
+                Line::Context(t) | Line::Added(t) => Some(t.clone()),
+                Line::Removed(_) => None,
+            })
+            .collect();
+        let added = new.len();
+        lines.splice(at..at + removed, new);
+        floor = at + added;
+        shift = at as isize - skip as isize - planned + added as isize - removed as isize;
+    }
+    match f.change {
+        Change::Deleted if lines.is_empty() => Ok(None),
+        Change::Deleted => Err(invalid(format!(
+            "{}: the file has lines the patch doesn't delete",
+            f.path
+        ))),
+        _ => Ok(Some(lines.concat())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_parse_and_apply() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15";
+        let new = "1\n2\nthree\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\nfifteen";
+        let patch = Patch {
+            author: "Ana".into(),
+            email: "ana@example.com".into(),
+            timestamp: 1_700_000_000_000,
+            ty: "fix".into(),
+            summary: "number the lines".into(),
+            body: "Two changes.".into(),
+            files: vec![
+                diff_file("n.txt", Some(old), Some(new)),
+                diff_file("new.txt", None, Some("hello\n")),
+                diff_file("gone.txt", Some("bye\n"), None),
+            ],
+        };
+        assert_eq!(patch.files[0].hunks.len(), 2);
+        let text = render(&patch, 12, 1, 2);
+        assert!(text.contains("Subject: [PATCH 1/2] fix: number the lines\n"));
+        assert!(text.contains("@@ -12,4 +13,4 @@\n"));
+        assert!(text.contains("+fifteen\n\\ No newline at end of file\n"));
+        let twice = format!("{text}{}", render(&patch, 13, 2, 2));
+        assert_eq!(parse(&twice).unwrap(), [patch.clone(), patch.clone()]);
+
+        // Lines added above the changes shift the hunks.
+        let moved = format!("0\n{old}");
+        let f = &patch.files[0];
+        assert_eq!(
+            apply_file(Some(&moved), f, 0).unwrap().unwrap(),
+            format!("0\n{new}")
+        );
+        assert_eq!(
+            apply_file(None, &patch.files[1], 0).unwrap().as_deref(),
+            Some("hello\n")
+        );
+        assert_eq!(apply_file(Some("bye\n"), &patch.files[2], 0).unwrap(), None);
+        assert!(apply_file(Some("1\n2\n"), f, 0).is_err());
+        assert!(apply_file(Some("x"), &patch.files[1], 0).is_err());
+    }
+
+    #[test]
+    fn test_fuzz_reverse_and_plain_diffs() {
+        // As printed by `ak diff`: the list of changed files, then the hunks.
+        let text = "+ added.txt \n~ logo.png (binary: …)\n\n--- a/n.txt\n+++ b/n.txt\n\
+                    @@ -2,5 +2,5 @@\n 2\n 3\n-4\n+four\n 5\n 6\n";
+        let patches = parse(text).unwrap();
+        assert_eq!(patches.len(), 1);
+        let f = &patches[0].files[0];
+        assert_eq!((f.path.as_str(), f.hunks.len()), ("n.txt", 1));
+
+        // The first and last context lines changed since.
+        let drifted = "1\ntwo\n3\n4\n5\nsix\n7\n";
+        assert!(apply_file(Some(drifted), f, 0).is_err());
+        let fuzzed = apply_file(Some(drifted), f, 1).unwrap().unwrap();
+        assert_eq!(fuzzed, "1\ntwo\n3\nfour\n5\nsix\n7\n");
+
+        let undone = apply_file(Some(&fuzzed), &f.reversed(), 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(undone, drifted);
+        let added = diff_file("a.txt", None, Some("a\n"));
+        assert_eq!(added.reversed().change, Change::Deleted);
+        assert_eq!(apply_file(Some("a\n"), &added.reversed(), 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Portable phenomenon paths.
+//!
+//! File records are stored under a phenomenon that is a *relative*, forward-slash separated path
+//! anchored at the save root (e.g. `src/data/write.rs`), so that a cube written on one machine or
+//! OS can be read and restored on another. Translation back to the native layout happens only
+//! when materializing files (`to_native`).
+//!
+//! On Windows, file system calls on deep trees go through `long` so that paths beyond the
+//! legacy `MAX_PATH` (260 characters) limit keep working. `sync_dir` makes renames and removals
+//! durable where the platform supports it.
+
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+/// Turn `path` (as produced by walking `root`) into a portable phenomenon.
+///
+/// Returns `None` when `path` is not below `root`, escapes it through `..`, or is not valid
+/// UTF-8. `root` itself maps to the empty string and is rejected as well.
+pub fn normalize(root: &Path, path: &Path) -> Option<String> {
+    // Compare component-wise ignoring `.` so that `./src/x` is found below `.` and `src` alike.
+    fn significant(p: &Path) -> Vec<Component<'_>> {
+        p.components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .collect()
+    }
+    let root = significant(root);
+    let path = significant(path);
+    if path.len() < root.len() || path[..root.len()] != root[..] {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for c in &path[root.len()..] {
+        match c {
+            Component::Normal(s) => parts.push(s.to_str()?),
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Canonical form of a stored phenomenon, tolerating records written before normalization
+/// (leading `./`, Windows `\` separators).
+pub fn canonical(phenomenon: &str) -> String {
+    let unified = phenomenon.replace('\\', "/");
+    let mut s = unified.as_str();
+    while let Some(rest) = s.strip_prefix("./") {
+        s = rest;
+    }
+    s.to_string()
+}
+
+/// Translate a stored phenomenon into a native relative path for restoring under some directory.
+///
+/// Rejects absolute paths, drive prefixes and `..` components so a crafted cube cannot write
+/// outside the restore target.
+pub fn to_native(phenomenon: &str) -> Option<PathBuf> {
+    let canonical = canonical(phenomenon);
+    if canonical.starts_with('/') {
+        return None;
+    }
+    let mut out = PathBuf::new();
+    for part in canonical.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            // Drive letters ("C:") or alternate data streams have no portable meaning.
+            p if p.contains(':') => return None,
+            p => out.push(p),
+        }
+    }
+    if out.as_os_str().is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+/// Return a form of `path` usable for file system calls regardless of its length.
+///
+/// On Windows, relative and drive-absolute paths are made absolute and given the verbatim `\\?\`
+/// prefix (UNC shares become `\\?\UNC\server\share`), which lifts the `MAX_PATH` limit. Other
+/// platforms have no such limit and get `path` back unchanged.
+pub fn long(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        let raw = path.as_os_str().to_string_lossy();
+        if raw.starts_with(r"\\?\") {
+            return Cow::Borrowed(path);
+        }
+        let Ok(abs) = std::path::absolute(path) else {
+            return Cow::Borrowed(path);
+        };
+        let abs = abs.to_string_lossy().into_owned();
+        let verbatim = match abs.strip_prefix(r"\\") {
+            Some(unc) => format!(r"\\?\UNC\{unc}"),
+            None => format!(r"\\?\{abs}"),
+        };
+        Cow::Owned(PathBuf::from(verbatim))
+    }
+    #[cfg(not(windows))]
+    {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Persist renames and removals in `dir` by syncing the directory itself (a no-op where
+/// directories can't be opened, i.e. Windows).
+pub fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::fs::File::open(dir)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_and_restore() {
+        let root = Path::new("/work/repo");
+        assert_eq!(
+            normalize(root, &root.join("src").join("main.rs")).as_deref(),
+            Some("src/main.rs")
+        );
+        assert_eq!(normalize(root, Path::new("/elsewhere/x")), None);
+        assert_eq!(normalize(root, root), None);
+        assert_eq!(
+            normalize(Path::new("."), Path::new("sub/x.rs")).as_deref(),
+            Some("sub/x.rs")
+        );
+        assert_eq!(
+            normalize(Path::new("sub"), Path::new("./sub/x.rs")).as_deref(),
+            Some("x.rs")
+        );
+
+        assert_eq!(canonical("./src\\data\\write.rs"), "src/data/write.rs");
+        assert_eq!(
+            to_native("src/data/write.rs"),
+            Some(["src", "data", "write.rs"].iter().collect())
+        );
+        assert_eq!(to_native("../etc/passwd"), None);
+        assert_eq!(to_native("/etc/passwd"), None);
+        assert_eq!(to_native("C:/Windows"), None);
+    }
+}
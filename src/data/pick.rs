@@ -0,0 +1,114 @@
+//! Re-applying the changes of one commit onto another tree: the engine behind `ak revert` and
+//! `ak cherry-pick`.
+//!
+//! The changes from tree `from` to tree `to` are replayed file by file onto `onto` (usually the
+//! working directory). A file that still holds its `from` version takes its `to` version; a file
+//! that already holds its `to` version is left alone; any other file is merged three ways, with
+//! `from` as the base (see `merge::merge_file`), and is a conflict unless the merge is clean.
+//! Nothing is written when there is a conflict, so a failed pick leaves the working directory as
+//! it was.
+
+use crate::data::diff::{Tree, changed_paths};
+use crate::data::merge::{self, Binary};
+use crate::data::paths;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Outcome of `pick`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Picked {
+    /// New content of every file to update, `None` to remove it.
+    pub changes: Vec<(String, Option<Vec<u8>>)>,
+    /// Files changed on both sides that don't merge.
+    pub conflicts: Vec<String>,
+}
+
+/// Replay the changes `from` -> `to` onto `onto`; binary files changed on both sides follow
+/// `binary`.
+pub fn pick(from: &Tree, to: &Tree, onto: &Tree, binary: Binary) -> Picked {
+    let mut out = Picked::default();
+    for (_, path) in changed_paths(from, to) {
+        let (base, want, have) = (from.get(&path), to.get(&path), onto.get(&path));
+        let merged = merge::merge_file(base, have, want, "ours", "theirs", binary);
+        if merged.conflict {
+            out.conflicts.push(path);
+        } else if merged.content.as_ref() != have {
+            out.changes.push((path, merged.content));
+        }
+    }
+    out
+}
+
+/// Write `changes` (portable paths) below `root`.
+pub fn apply(root: &Path, changes: &[(String, Option<Vec<u8>>)]) -> io::Result<()> {
+    for (path, content) in changes {
+        let rel = paths::to_native(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path}: not a portable relative path"),
+            )
+        })?;
+        let dest = root.join(rel);
+        match content {
+            Some(bytes) => {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(paths::long(parent))?;
+                }
+                fs::write(paths::long(&dest), bytes)?;
+            }
+            None => match fs::remove_file(paths::long(&dest)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(files: &[(&str, &str)]) -> Tree {
+        files
+            .iter()
+            .map(|(p, c)| (p.to_string(), c.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pick_inverse_of_a_commit() {
+        let parent = tree(&[("a", "1"), ("b", "1")]);
+        let commit = tree(&[("a", "2"), ("c", "1")]);
+
+        // Reverting onto the commit itself restores the parent.
+        let undo = pick(&commit, &parent, &commit, Binary::Conflict);
+        assert!(undo.conflicts.is_empty());
+        assert_eq!(
+            undo.changes,
+            [
+                ("a".to_string(), Some(b"1".to_vec())),
+                ("b".to_string(), Some(b"1".to_vec())),
+                ("c".to_string(), None),
+            ]
+        );
+
+        // `a` changed again since: conflict; `b` already restored: skipped.
+        let later = tree(&[("a", "3"), ("b", "1"), ("c", "1")]);
+        let undo = pick(&commit, &parent, &later, Binary::Conflict);
+        assert_eq!(undo.conflicts, ["a"]);
+        assert_eq!(undo.changes, [("c".to_string(), None)]);
+
+        // Other lines changed since merge.
+        let from = tree(&[("a", "1\n2\n3\n")]);
+        let to = tree(&[("a", "1\ntwo\n3\n")]);
+        let onto = tree(&[("a", "1\n2\n3\nfour\n")]);
+        let picked = pick(&from, &to, &onto, Binary::Conflict);
+        assert!(picked.conflicts.is_empty());
+        assert_eq!(
+            picked.changes,
+            [("a".to_string(), Some(b"1\ntwo\n3\nfour\n".to_vec()))]
+        );
+    }
+}
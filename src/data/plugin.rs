@@ -0,0 +1,262 @@
+//! Record processors: user extensions that transform or annotate records on append and on read.
+//!
+//! Processors are configured per cube through a JSON sidecar named `<cube>.plugins.json`:
+//!
+//! ```json
+//! { "on_append": ["plugins/redact.wasm"], "on_read": ["plugins/classify.wasm"] }
+//! ```
+//!
+//! Relative module paths are resolved against the directory containing the cube. Processors run in
+//! the listed order; each one sees the output of the previous one.
+//!
+//! WASM modules (feature `wasm`) follow a tiny ABI:
+//! - export `memory` and `ak_alloc(len: i32) -> i32` returning a buffer the host can write into;
+//! - export `ak_on_append(ptr: i32, len: i32) -> i64` and/or `ak_on_read(ptr: i32, len: i32) -> i64`.
+//!
+//! The input buffer holds the event as JSON (`id`, `phenomenon`, `noumenon`, `timestamp`). The
+//! returned i64 packs `(out_ptr << 32) | out_len` of a JSON object whose optional `phenomenon` and
+//! `noumenon` fields replace the event's; `out_len == 0` leaves the event unchanged.
+
+use crate::event::Event;
+use serde::Deserialize;
+use std::io;
+use std::path::Path;
+
+/// When a processor is invoked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Before a record is written; changes are persisted.
+    Append,
+    /// After a record is decoded; changes only affect what the caller sees.
+    Read,
+}
+
+/// A transformation applied to records flowing through a `Writer`.
+pub trait RecordProcessor {
+    /// Transform `ev` in place for the given `stage`. Errors abort the append or read.
+    fn process(&mut self, stage: Stage, ev: &mut Event) -> io::Result<()>;
+}
+
+/// On-disk shape of `<cube>.plugins.json`.
+#[derive(Deserialize, Default)]
+struct PluginConfig {
+    #[serde(default)]
+    on_append: Vec<String>,
+    #[serde(default)]
+    on_read: Vec<String>,
+}
+
+/// Load the processors configured for the cube at `cube_path`, limited to `stages`.
+///
+/// Returns an empty list when the sidecar is absent. When the crate is built without the `wasm`
+/// feature, configured `on_read` modules are reported on stderr and ignored, while configured
+/// `on_append` modules are an `Unsupported` error: records must never be written unprocessed.
+pub fn load_for_cube(
+    cube_path: &str,
+    stages: &[Stage],
+) -> io::Result<Vec<Box<dyn RecordProcessor>>> {
+    let config_path = format!("{cube_path}.plugins.json");
+    let Ok(raw) = std::fs::read_to_string(&config_path) else {
+        return Ok(Vec::new());
+    };
+    let mut config: PluginConfig = serde_json::from_str(&raw)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{config_path}: {e}")))?;
+    if !stages.contains(&Stage::Append) {
+        config.on_append.clear();
+    }
+    if !stages.contains(&Stage::Read) {
+        config.on_read.clear();
+    }
+    let base = Path::new(cube_path).parent().unwrap_or(Path::new("."));
+
+    #[cfg(feature = "wasm")]
+    {
+        let mut out: Vec<Box<dyn RecordProcessor>> = Vec::new();
+        for (stage, modules) in [
+            (Stage::Append, &config.on_append),
+            (Stage::Read, &config.on_read),
+        ] {
+            for module in modules {
+                out.push(Box::new(wasm::WasmProcessor::load(
+                    &base.join(module),
+                    stage,
+                )?));
+            }
+        }
+        Ok(out)
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    {
+        // An append processor may be load-bearing (e.g. redaction): refuse to write without it.
+        if let Some(module) = config.on_append.first() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{config_path}: append plugin {} needs the `wasm` feature",
+                    base.join(module).display()
+                ),
+            ));
+        }
+        for module in &config.on_read {
+            eprintln!(
+                "warning: plugin {} ignored (built without the `wasm` feature)",
+                base.join(module).display()
+            );
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    use super::{RecordProcessor, Stage};
+    use crate::event::Event;
+    use serde::Deserialize;
+    use std::io;
+    use std::path::Path;
+    use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+
+    /// Fields a module may replace.
+    #[derive(Deserialize)]
+    struct Patch {
+        phenomenon: Option<String>,
+        noumenon: Option<String>,
+    }
+
+    /// A WASM module bound to one stage, instantiated once and reused for every record.
+    pub struct WasmProcessor {
+        stage: Stage,
+        store: Store<()>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        hook: TypedFunc<(i32, i32), i64>,
+    }
+
+    impl WasmProcessor {
+        /// Compile and instantiate the module at `path` for `stage`.
+        pub fn load(path: &Path, stage: Stage) -> io::Result<Self> {
+            let err = |e: wasmtime::Error| io::Error::other(format!("{}: {e}", path.display()));
+            let engine = Engine::default();
+            let module = Module::from_file(&engine, path).map_err(err)?;
+            let mut store = Store::new(&engine, ());
+            let instance = Instance::new(&mut store, &module, &[]).map_err(err)?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| io::Error::other(format!("{}: missing memory", path.display())))?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&mut store, "ak_alloc")
+                .map_err(err)?;
+            let export = match stage {
+                Stage::Append => "ak_on_append",
+                Stage::Read => "ak_on_read",
+            };
+            let hook = instance
+                .get_typed_func::<(i32, i32), i64>(&mut store, export)
+                .map_err(err)?;
+            Ok(Self {
+                stage,
+                store,
+                memory,
+                alloc,
+                hook,
+            })
+        }
+    }
+
+    impl RecordProcessor for WasmProcessor {
+        fn process(&mut self, stage: Stage, ev: &mut Event) -> io::Result<()> {
+            if stage != self.stage {
+                return Ok(());
+            }
+            let input = serde_json::to_vec(&*ev).map_err(io::Error::other)?;
+            let len = i32::try_from(input.len()).map_err(io::Error::other)?;
+            let ptr = self
+                .alloc
+                .call(&mut self.store, len)
+                .map_err(io::Error::other)?;
+            self.memory
+                .write(&mut self.store, ptr as usize, &input)
+                .map_err(io::Error::other)?;
+
+            let packed = self
+                .hook
+                .call(&mut self.store, (ptr, len))
+                .map_err(io::Error::other)? as u64;
+            let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+            if out_len == 0 {
+                return Ok(());
+            }
+            let mut output = vec![0u8; out_len];
+            self.memory
+                .read(&self.store, out_ptr, &mut output)
+                .map_err(io::Error::other)?;
+            let patch: Patch = serde_json::from_slice(&output)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if let Some(ph) = patch.phenomenon {
+                ev.phenomenon = ph;
+            }
+            if let Some(no) = patch.noumenon {
+                ev.noumenon = no;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+    use std::fs;
+
+    /// Upper-cases the noumenon on append and tags the phenomenon on read.
+    struct Shout;
+
+    impl RecordProcessor for Shout {
+        fn process(&mut self, stage: Stage, ev: &mut Event) -> io::Result<()> {
+            match stage {
+                Stage::Append => ev.noumenon = ev.noumenon.to_uppercase(),
+                Stage::Read => ev.phenomenon = format!("read:{}", ev.phenomenon),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_processors_run_on_append_and_read() {
+        let dir = std::env::temp_dir().join(format!("akasha-plugin-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("c.cube");
+        let cube = cube.to_str().unwrap();
+
+        let mut w = Writer::create(cube)
+            .unwrap()
+            .with_processor(Box::new(Shout));
+        w.append("a.txt", "secret").unwrap();
+        let events = w.events().unwrap();
+        assert_eq!(events[0].phenomenon, "read:a.txt");
+        assert_eq!(events[0].noumenon, "SECRET");
+        drop(w);
+
+        // What is persisted is the append-stage output; the read stage is not.
+        let stored = Writer::decode(&fs::read(cube).unwrap()).unwrap();
+        assert_eq!(stored[0].phenomenon, "a.txt");
+        assert_eq!(stored[0].noumenon, "SECRET");
+
+        #[cfg(not(feature = "wasm"))]
+        {
+            fs::write(
+                format!("{cube}.plugins.json"),
+                r#"{"on_append":["r.wasm"]}"#,
+            )
+            .unwrap();
+            let err = Writer::create(cube).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+            // Reading does not need the append stage.
+            assert_eq!(Writer::open(cube).unwrap().events().unwrap().len(), 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Provenance of records: where each one was appended from.
+//!
+//! Writers stamp the records they create with attributes naming their origin (see
+//! `Event::attributes`):
+//! - `host`: host name of the machine;
+//! - `user`: login of the OS user, which is not necessarily the `ak` author;
+//! - `tool`: name of the executable, and `version`: version of this library (and of `ak`);
+//! - `pid`: process id.
+//!
+//! Attributes travel with their records: copies made by `ak push` and `ak pull`, by
+//! `Writer::append_events`, by compaction and by `eikyu cube fork` keep them, so a cube merged
+//! from several machines still tells where each record came from. Appends served by the daemon
+//! carry the provenance of the client, not the daemon's (see `data::daemon`).
+//!
+//! Only format v4 cubes and later have room for attributes: older cubes store records without
+//! them, and their records read back with none.
+
+use crate::event::Event;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Attribute naming the host.
+pub const HOST: &str = "host";
+/// Attribute naming the OS user.
+pub const USER: &str = "user";
+/// Attribute naming the executable.
+pub const TOOL: &str = "tool";
+/// Attribute holding the version of the library.
+pub const VERSION: &str = "version";
+/// Attribute holding the process id.
+pub const PID: &str = "pid";
+
+/// Provenance attributes of the records appended by this process, computed once.
+pub fn current() -> &'static BTreeMap<String, String> {
+    static CURRENT: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+    CURRENT.get_or_init(|| {
+        let tool = env::current_exe()
+            .ok()
+            .or_else(|| env::args_os().next().map(Into::into))
+            .and_then(|p| Some(Path::new(p.file_stem()?).to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "eikyu".to_string());
+        let user = ["USER", "USERNAME", "LOGNAME"]
+            .into_iter()
+            .find_map(|v| env::var(v).ok().filter(|u| !u.is_empty()))
+            .unwrap_or_default();
+        [
+            (HOST, hostname()),
+            (USER, user),
+            (TOOL, tool),
+            (VERSION, env!("CARGO_PKG_VERSION").to_string()),
+            (PID, std::process::id().to_string()),
+        ]
+        .into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+    })
+}
+
+/// Host name of the machine, empty when it can't be found.
+fn hostname() -> String {
+    let from_files = ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .into_iter()
+        .find_map(|p| fs::read_to_string(p).ok());
+    let from_env = || {
+        ["COMPUTERNAME", "HOSTNAME"]
+            .into_iter()
+            .find_map(|v| env::var(v).ok())
+    };
+    let from_command = || {
+        Command::new("hostname")
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    from_files
+        .or_else(from_env)
+        .or_else(from_command)
+        .map(|h| h.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Origin of `ev` for display, as `user@host, tool version, pid N`, or `None` when the record
+/// has no provenance.
+pub fn origin(ev: &Event) -> Option<String> {
+    let attr = |k: &str| ev.attributes.get(k).map(String::as_str);
+    if [HOST, USER, TOOL, PID].iter().all(|k| attr(k).is_none()) {
+        return None;
+    }
+    let mut out = match (attr(USER), attr(HOST)) {
+        (Some(user), Some(host)) => format!("{user}@{host}"),
+        (user, host) => user.or(host).unwrap_or("?").to_string(),
+    };
+    if let Some(tool) = attr(TOOL) {
+        out.push_str(&format!(", {tool}"));
+        if let Some(version) = attr(VERSION) {
+            out.push_str(&format!(" {version}"));
+        }
+    }
+    if let Some(pid) = attr(PID) {
+        out.push_str(&format!(", pid {pid}"));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_records_carry_their_provenance() {
+        let dir = std::env::temp_dir().join(format!("akasha-provenance-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b, old) = (dir.join("a.cube"), dir.join("b.cube"), dir.join("v3.cube"));
+        let mut w = Writer::create(a.to_str().unwrap()).unwrap();
+        w.append("a.txt", "1").unwrap();
+        let mut ci = current().clone();
+        ci.insert(HOST.to_string(), "ci-runner".to_string());
+        w.set_provenance(ci);
+        w.append("a.txt", "2").unwrap();
+        let events = w.events().unwrap();
+        assert_eq!(&events[0].attributes, current());
+        assert_eq!(events[0].attributes[PID], std::process::id().to_string());
+        assert_eq!(events[1].attributes[HOST], "ci-runner");
+        let from = origin(&events[1]).unwrap();
+        assert!(
+            from.contains("ci-runner") && from.ends_with(&format!("pid {}", std::process::id()))
+        );
+
+        // Copies keep the attributes of their records, cubes before v4 drop them.
+        Writer::create(b.to_str().unwrap())
+            .unwrap()
+            .append_events(&events)
+            .unwrap();
+        let copied = Writer::open(b.to_str().unwrap()).unwrap().events().unwrap();
+        assert_eq!(copied[1].attributes, events[1].attributes);
+        let mut header = b"AKLA\x03\0\x01\0\0\0\0\0\0\0\0\0".to_vec();
+        header.resize(Writer::header_len(3) as usize, 7);
+        fs::write(&old, header).unwrap();
+        let mut v3 = Writer::create(old.to_str().unwrap()).unwrap();
+        v3.append_events(&events).unwrap();
+        v3.append("b.txt", "3").unwrap();
+        let stripped = v3.events().unwrap();
+        assert_eq!(stripped.len(), 3);
+        assert!(
+            stripped
+                .iter()
+                .all(|e| e.attributes.is_empty() && origin(e).is_none())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
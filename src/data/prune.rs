@@ -0,0 +1,388 @@
+//! Pruning old monthly cubes: `ak prune`.
+//!
+//! Every month gets a new cube per author (see `data::history`) and the old ones stay forever.
+//! `prune` folds the cubes of the months before a given one into a single compacted cube per
+//! author and moves the file versions nothing still needs into an archive cube:
+//! - commits, manifests, branches, tags, notes and every other metadata record stay, so
+//!   `ak timeline` and `ak show` still list the old commits;
+//! - file versions stay when a commit of a later month, a branch head, a tag or a stash base
+//!   contains them, and so does everything written after the last commit;
+//! - the other file versions move to `<archive dir>/<author>-<YYYY-MM>.cube`, gzipped to
+//!   `.cube.gz` on request. Commits that contained them can't be restored from the cubes anymore.
+//!
+//! The compacted cube replaces the cube of the last pruned month, keeping its last record so that
+//! `ak pull` still finds where copies diverge, and the other pruned cubes are deleted. A
+//! `prune:entry` record `{"before": "2025-06", "archive": "...", "through": 812}` is then
+//! appended to the author's latest cube: `ak verify` does not report the file versions it moved
+//! (record ids up to `through`) as missing.
+//!
+//! The archive is written and synced before any cube changes. Copies of the repository that were
+//! not pruned still hold the old cubes, and `ak pull` brings them back.
+
+use crate::data::branch;
+use crate::data::checkpoint;
+use crate::data::commit;
+use crate::data::config::{Config, Layout};
+use crate::data::history;
+use crate::data::manifest::Manifest;
+use crate::data::paths;
+use crate::data::restore::is_file_record;
+use crate::data::stash;
+use crate::data::tag;
+use crate::data::write::Writer;
+use crate::event::Event;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Phenomenon of prune records.
+pub const PRUNE: &str = "prune:entry";
+
+/// Noumenon of a `prune:entry` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// First month that was kept, `YYYY-MM`.
+    pub before: String,
+    /// Archive cube the file versions moved to.
+    pub archive: String,
+    /// Highest id of a moved record.
+    pub through: u64,
+}
+
+/// What `prune` did, or would do with `dry_run`, to one author's cubes.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub author: String,
+    /// Pruned months, oldest first.
+    pub months: Vec<String>,
+    /// Compacted cube replacing them.
+    pub cube: PathBuf,
+    pub archive: PathBuf,
+    pub kept: usize,
+    pub moved: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Every prune of `events`, oldest first.
+pub fn entries(events: &[Event]) -> Vec<Entry> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == PRUNE)
+        .filter_map(|e| serde_json::from_str(&e.noumenon).ok())
+        .collect()
+}
+
+/// Highest record id a prune of `events` moved to an archive, 0 without any.
+pub fn pruned_through(events: &[Event]) -> u64 {
+    entries(events).iter().map(|e| e.through).max().unwrap_or(0)
+}
+
+/// Check that `month` is a `YYYY-MM` month no later than `current`.
+pub fn parse_month(month: &str, current: &str) -> io::Result<()> {
+    let valid = chrono::NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").is_ok()
+        && month.len() == 7;
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{month}: expected a month as YYYY-MM"),
+        ));
+    }
+    if month > current {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{month} is after the current month ({current}); its cubes are still written"),
+        ));
+    }
+    Ok(())
+}
+
+/// File versions of `events` (every record of one author) that `keep` (record ids of the records
+/// that stay) still needs: the files of the commits among them, of branch heads, tags and stash
+/// bases, and everything written after the last commit.
+fn needed(events: &[Event], keep: &HashSet<u64>) -> HashSet<u64> {
+    let mut commits: HashSet<u64> = events
+        .iter()
+        .filter(|e| e.phenomenon == "commit" && keep.contains(&e.id))
+        .map(commit::commit_id)
+        .collect();
+    commits.extend(branch::heads(events).into_values().flatten());
+    commits.extend(tag::tags(events).into_values());
+    commits.extend(
+        stash::stashes(events)
+            .into_iter()
+            .filter_map(|(_, s)| s.base),
+    );
+
+    let mut out = HashSet::new();
+    let mut latest: HashMap<String, u64> = HashMap::new();
+    for ev in events {
+        if ev.phenomenon == "commit" && commits.contains(&commit::commit_id(ev)) {
+            match Manifest::of_commit(events, commit::commit_id(ev)) {
+                Some(m) => out.extend(m.files.values().map(|e| e.record)),
+                // Pre-manifest commits hold the latest version of every path written before them.
+                None => out.extend(latest.values().copied()),
+            }
+        }
+        if is_file_record(ev) {
+            latest.insert(paths::canonical(&ev.phenomenon), ev.id);
+        }
+    }
+    let last_commit = events
+        .iter()
+        .filter(|e| e.phenomenon == "commit")
+        .map(|e| e.id)
+        .max()
+        .unwrap_or(0);
+    out.extend(
+        events
+            .iter()
+            .filter(|e| is_file_record(e) && e.id > last_commit)
+            .map(|e| e.id),
+    );
+    out
+}
+
+/// Records of the cube at `path`, refusing cubes with bytes past their last valid record:
+/// rewriting them would drop those bytes.
+fn read_cube(path: &Path) -> io::Result<(Vec<Event>, u64)> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let events = Writer::decode(&bytes)?;
+    let version = Writer::image_version(&bytes)?;
+    let valid = Writer::header_len(version)
+        + events
+            .iter()
+            .map(|e| Writer::record_len(version, e))
+            .sum::<u64>();
+    if valid != bytes.len() as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: {} trailing bytes are not valid records; refusing to prune",
+                path.display(),
+                bytes.len() as u64 - valid
+            ),
+        ));
+    }
+    Ok((events, valid))
+}
+
+/// Prune the cubes of the months before `before` (`YYYY-MM`) of every author of the repository
+/// at `root`, moving file versions to archive cubes in `archive_dir` (gzipped with `compress`).
+/// With `dry_run`, only computes the reports.
+pub fn prune(
+    root: &Path,
+    before: &str,
+    archive_dir: &Path,
+    compress: bool,
+    dry_run: bool,
+) -> io::Result<Vec<Report>> {
+    if Config::load(root)?.storage.layout == Layout::Shared {
+        return Err(io::Error::other(
+            "the shared layout keeps a single repository cube: there are no monthly cubes to prune",
+        ));
+    }
+    let cubes = history::cube_files(root)?;
+    let mut out = Vec::new();
+    for author in history::authors(root)? {
+        let mine: Vec<_> = cubes.iter().filter(|c| c.author == author).collect();
+        let old: Vec<_> = mine.iter().filter(|c| c.month.as_str() < before).collect();
+        let Some(last_old) = old.last() else {
+            continue;
+        };
+
+        let mut events = Vec::new();
+        let mut old_ids = HashSet::new();
+        let mut bytes_before = 0;
+        for cube in &mine {
+            let (records, len) = read_cube(&cube.path)?;
+            if cube.month.as_str() < before {
+                old_ids.extend(records.iter().map(|e| e.id));
+                bytes_before += len;
+            }
+            events.extend(records);
+        }
+        let mut ids = HashSet::new();
+        if let Some(ev) = events.iter().find(|e| !ids.insert(e.id)) {
+            return Err(io::Error::other(format!(
+                "{}: record id {} is used twice; cubes written before ids continued across \
+                 months can't be pruned",
+                last_old.path.display(),
+                ev.id
+            )));
+        }
+
+        let newer: HashSet<u64> = events
+            .iter()
+            .map(|e| e.id)
+            .filter(|id| !old_ids.contains(id))
+            .collect();
+        let needed = needed(&events, &newer);
+        let last = events
+            .iter()
+            .rfind(|e| old_ids.contains(&e.id))
+            .map(|e| e.id);
+        let (mut kept, mut moved) = (Vec::new(), Vec::new());
+        for ev in events.iter().filter(|e| old_ids.contains(&e.id)) {
+            if is_file_record(ev) && !needed.contains(&ev.id) && Some(ev.id) != last {
+                moved.push(ev.clone());
+            } else {
+                kept.push(ev.clone());
+            }
+        }
+        Writer::detach_refs(&mut kept, &mut moved);
+
+        let ext = if compress { "cube.gz" } else { "cube" };
+        let version = Writer::version_of(&last_old.path)?;
+        let report = Report {
+            author: author.clone(),
+            months: old.iter().map(|c| c.month.clone()).collect(),
+            cube: last_old.path.clone(),
+            archive: archive_dir.join(format!("{author}-{before}.{ext}")),
+            kept: kept.len(),
+            moved: moved.len(),
+            bytes_before,
+            bytes_after: Writer::header_len(version)
+                + kept
+                    .iter()
+                    .map(|e| Writer::record_len(version, e))
+                    .sum::<u64>(),
+        };
+        // A single old cube is already as compact as pruning makes it, unless records move out.
+        if !dry_run && (old.len() > 1 || !moved.is_empty()) {
+            let next_id = old_ids.iter().max().map_or(1, |id| id + 1);
+            write_archive(&report.archive, &moved, compress)?;
+            compact(&report.cube, &kept, next_id)?;
+            for cube in old.iter().filter(|c| c.path != report.cube) {
+                fs::remove_file(&cube.path)?;
+                checkpoint::remove(&cube.path)?;
+                if let Some(dir) = cube.path.parent() {
+                    // Only removed once the month has no cube left.
+                    let _ = fs::remove_dir(dir);
+                }
+            }
+            let latest = mine.last().map_or(&report.cube, |c| &c.path);
+            let mut w = Writer::create(&latest.to_string_lossy())?;
+            let entry = Entry {
+                before: before.to_string(),
+                archive: report.archive.to_string_lossy().into_owned(),
+                through: moved.iter().map(|e| e.id).max().unwrap_or(0),
+            };
+            w.append(
+                PRUNE,
+                &serde_json::to_string(&entry).map_err(io::Error::other)?,
+            )?;
+        }
+        out.push(report);
+    }
+    Ok(out)
+}
+
+/// Write `moved` to a new archive cube at `path`.
+fn write_archive(path: &Path, moved: &[Event], compress: bool) -> io::Result<()> {
+    if path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{}: archive already exists", path.display()),
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let next_id = moved.iter().map(|e| e.id + 1).max().unwrap_or(1);
+    if !compress {
+        return Writer::write_image(&mut File::create(path)?, moved, next_id, None);
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    Writer::write_image(&mut File::create(&tmp)?, moved, next_id, None)?;
+    let mut gz = GzEncoder::new(File::create(path)?, Compression::default());
+    io::copy(&mut File::open(&tmp)?, &mut gz)?;
+    gz.finish()?.sync_all()?;
+    fs::remove_file(&tmp)
+}
+
+/// Rewrite the cube at `path` in place with `kept`, under its lock, through a `<cube>.prune-tmp`
+/// image.
+fn compact(path: &Path, kept: &[Event], next_id: u64) -> io::Result<()> {
+    let mut f = File::options().read(true).write(true).open(path)?;
+    f.lock()?;
+    let result = (|| {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".prune-tmp");
+        let tmp = PathBuf::from(tmp);
+        // The recovery image carries the identity the pruned cube is about to get.
+        let identity = Writer::identity_of(path)?.map(|i| i.bumped());
+        Writer::write_image(&mut File::create(&tmp)?, kept, next_id, identity.as_ref())?;
+        Writer::write_image(&mut f, kept, next_id, identity.as_ref())?;
+        checkpoint::remove(path)?;
+        fs::remove_file(&tmp)
+    })();
+    f.unlock()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::index::Index;
+
+    #[test]
+    fn test_prune_keeps_what_later_months_need() {
+        let dir = std::env::temp_dir().join(format!("akasha-prune-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cubes = dir.join(".eikyu").join("cubes");
+        let mut manifest = Manifest::default();
+        let mut next = 1;
+        let mut seal = |month: &str, files: &[(&str, &str)]| {
+            fs::create_dir_all(cubes.join(month)).unwrap();
+            let path = cubes.join(month).join("u.cube");
+            let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+            w.advance_next_id(next).unwrap();
+            let id = w.append_record("commit:pending", "msg").unwrap().id;
+            let mut index = Index::default();
+            for (path, content) in files {
+                index.stage_content(path, Some(content.to_string()));
+            }
+            manifest.apply_index(&mut w, &index).unwrap();
+            let m = manifest.record(&mut w, id).unwrap();
+            w.append("commit", &format!(r#"{{"id":{id},"manifest":{m}}}"#))
+                .unwrap();
+            next = w.next_id();
+        };
+        seal("2025-01", &[("a", "1"), ("b", "1")]);
+        seal("2025-02", &[("a", "2")]);
+        seal("2025-03", &[("a", "3")]);
+
+        let archive = dir.join("archive");
+        let dry = prune(&dir, "2025-03", &archive, true, true).unwrap();
+        assert_eq!(dry.len(), 1);
+        // a=1 and a=2 move out; b=1 is still in the tree of the March commit.
+        assert_eq!((dry[0].months.len(), dry[0].moved), (2, 2));
+        assert!(cubes.join("2025-01").exists());
+
+        let done = prune(&dir, "2025-03", &archive, true, false).unwrap();
+        assert_eq!(done, dry);
+        assert!(!cubes.join("2025-01").exists());
+        assert!(archive.join("u-2025-03.cube.gz").exists());
+        let events = history::author_events(&dir, "u").unwrap();
+        assert_eq!(commit::history(&events).len(), 3);
+        assert_eq!(entries(&events)[0].through, pruned_through(&events));
+        let head = commit::commit_id(commit::history(&events)[2]);
+        assert!(crate::data::restore::tree_at(&events, head).is_ok());
+        // Nothing is left to move, so nothing is written again.
+        let again = prune(&dir, "2025-03", &archive, true, false).unwrap();
+        assert_eq!((again[0].months.len(), again[0].moved), (1, 0));
+
+        assert!(parse_month("2025-13", "2025-06").is_err());
+        assert!(parse_month("2025-07", "2025-06").is_err());
+        assert!(parse_month("2025-06", "2025-06").is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
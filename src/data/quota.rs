@@ -0,0 +1,233 @@
+//! Quotas of served cubes: per-token rate limits and a size limit (see `data::daemon`).
+//!
+//! A cube served to other processes can be given limits through a JSON sidecar named
+//! `<cube>.quota.json`:
+//!
+//! ```json
+//! { "max_bytes": 1073741824, "rate": 50, "burst": 200, "tokens": { "ci": { "rate": 5 } } }
+//! ```
+//!
+//! All limits are optional:
+//! - `max_bytes`: appends that would make the cube file larger are refused;
+//! - `rate` and `burst`: records each token may append per second, in bursts of up to `burst`
+//!   records (`rate` by default), as a token bucket. A batch larger than the burst is always
+//!   refused. Clients that send no token share the empty token;
+//! - `tokens`: the `rate` and `burst` of particular tokens, instead of the ones above.
+//!
+//! Refusals are `Exceeded` values, which the daemon returns as they are and clients turn into
+//! `io::ErrorKind::QuotaExceeded` errors. For auditing, the first refusal of a token by each
+//! limit is recorded in the cube as a `quota:exceeded` record (`Refusal`); the next ones are not,
+//! until the token appends again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+/// Phenomenon of the records of refused appends.
+pub const QUOTA: &str = "quota:exceeded";
+
+/// Rate limit of a token.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rate {
+    /// Records per second.
+    pub rate: Option<f64>,
+    /// Records appended at once after a quiet period; `rate` when unset.
+    pub burst: Option<f64>,
+}
+
+/// Limits of a served cube, from `<cube>.quota.json`.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Upper bound for the cube file size, in bytes.
+    pub max_bytes: Option<u64>,
+    /// Records per second of every token without its own entry in `tokens`.
+    pub rate: Option<f64>,
+    pub burst: Option<f64>,
+    /// Rate limits by token.
+    pub tokens: HashMap<String, Rate>,
+}
+
+impl Limits {
+    /// Rate limit of `token`, `None` when unlimited.
+    fn rate_of(&self, token: &str) -> Option<(f64, f64)> {
+        let r = self.tokens.get(token).copied().unwrap_or(Rate {
+            rate: self.rate,
+            burst: self.burst,
+        });
+        r.rate.map(|rate| (rate, r.burst.unwrap_or(rate)))
+    }
+}
+
+/// A limit an append ran into.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "limit", rename_all = "snake_case")]
+pub enum Exceeded {
+    /// The token appends faster than `rate` records per second.
+    Rate { rate: f64, retry_after_ms: u64 },
+    /// The cube would grow to `bytes`, past `max_bytes`.
+    Size { bytes: u64, max_bytes: u64 },
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::Rate {
+                rate,
+                retry_after_ms,
+            } => write!(
+                f,
+                "rate limit of {rate} record(s) per second exceeded, retry in {retry_after_ms} ms"
+            ),
+            Exceeded::Size { bytes, max_bytes } => write!(
+                f,
+                "cube quota exceeded: {bytes} bytes with the append, at most {max_bytes}"
+            ),
+        }
+    }
+}
+
+/// Noumenon of a `quota:exceeded` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Refusal {
+    pub token: String,
+    #[serde(flatten)]
+    pub exceeded: Exceeded,
+    /// Records the refused append held.
+    pub records: usize,
+    /// Milliseconds since Unix epoch (UTC).
+    pub timestamp: u64,
+}
+
+/// Load the limits configured for the cube at `cube_path`, if any.
+pub fn load_for_cube(cube_path: &str) -> io::Result<Option<Limits>> {
+    let config_path = format!("{cube_path}.quota.json");
+    let Ok(raw) = fs::read_to_string(&config_path) else {
+        return Ok(None);
+    };
+    serde_json::from_str(&raw)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{config_path}: {e}")))
+}
+
+/// Token bucket of a token.
+struct Bucket {
+    records: f64,
+    at: Instant,
+}
+
+/// Enforces `Limits` on the appends to one cube.
+pub struct Limiter {
+    limits: Limits,
+    buckets: HashMap<String, Bucket>,
+    /// Tokens whose refusal by the rate (`true`) or size (`false`) limit was recorded.
+    recorded: HashSet<(String, bool)>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            buckets: HashMap::new(),
+            recorded: HashSet::new(),
+        }
+    }
+
+    /// Let `token` append `records` records of `bytes` bytes in all to a cube of `cube_len`
+    /// bytes at `now`, or tell which limit that exceeds. Refused appends use up no rate.
+    pub fn admit(
+        &mut self,
+        token: &str,
+        records: usize,
+        bytes: u64,
+        cube_len: u64,
+        now: Instant,
+    ) -> Result<(), Exceeded> {
+        if let Some(max_bytes) = self.limits.max_bytes
+            && cube_len + bytes > max_bytes
+        {
+            return Err(Exceeded::Size {
+                bytes: cube_len + bytes,
+                max_bytes,
+            });
+        }
+        if let Some((rate, burst)) = self.limits.rate_of(token) {
+            let bucket = self.buckets.entry(token.to_string()).or_insert(Bucket {
+                records: burst,
+                at: now,
+            });
+            let elapsed = now.saturating_duration_since(bucket.at).as_secs_f64();
+            bucket.records = (bucket.records + elapsed * rate).min(burst);
+            bucket.at = now;
+            let wanted = records as f64;
+            if bucket.records < wanted {
+                let wait = (wanted - bucket.records) / rate;
+                return Err(Exceeded::Rate {
+                    rate,
+                    retry_after_ms: (wait * 1000.0).ceil() as u64,
+                });
+            }
+            bucket.records -= wanted;
+        }
+        self.recorded.retain(|(t, _)| t != token);
+        Ok(())
+    }
+
+    /// Whether the refusal of `token` by `exceeded` is the first since its last append, which
+    /// is the one to record.
+    pub fn first_refusal(&mut self, token: &str, exceeded: &Exceeded) -> bool {
+        let by_rate = matches!(exceeded, Exceeded::Rate { .. });
+        self.recorded.insert((token.to_string(), by_rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_and_size_limits() {
+        let limits: Limits = serde_json::from_str(
+            r#"{"max_bytes":1000,"rate":2,"burst":3,"tokens":{"ci":{"rate":1}}}"#,
+        )
+        .unwrap();
+        let mut l = Limiter::new(limits);
+        let t0 = Instant::now();
+        assert!(l.admit("", 3, 10, 0, t0).is_ok());
+        let refused = l.admit("", 1, 10, 0, t0).unwrap_err();
+        assert_eq!(
+            refused,
+            Exceeded::Rate {
+                rate: 2.0,
+                retry_after_ms: 500
+            }
+        );
+        assert!(l.first_refusal("", &refused));
+        assert!(!l.first_refusal("", &refused));
+        // Other tokens have buckets of their own.
+        assert!(l.admit("ci", 1, 10, 0, t0).is_ok());
+        assert!(l.admit("ci", 1, 10, 0, t0).is_err());
+        assert!(
+            l.admit("", 1, 10, 0, t0 + Duration::from_millis(500))
+                .is_ok()
+        );
+        assert!(l.first_refusal("", &refused));
+        assert!(l.admit("", 4, 10, 0, t0 + Duration::from_secs(60)).is_err());
+
+        let size = l.admit("", 1, 100, 950, t0).unwrap_err();
+        assert_eq!(
+            size,
+            Exceeded::Size {
+                bytes: 1050,
+                max_bytes: 1000
+            }
+        );
+        assert!(size.to_string().contains("at most 1000"));
+        assert!(serde_json::from_str::<Limits>(r#"{"max_size":1}"#).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Reflog: the operations that moved a head, listed by `ak reflog`.
+//!
+//! Seals (merges, reverts, picks and amends included), branch switches, restores and Git imports
+//! append a `reflog:entry` record to the author's cube, with the branch checked out and its head
+//! before and after the operation:
+//!
+//! ```json
+//! { "author": "ana", "op": "switch", "before": { "branch": "main", "head": 12 },
+//!   "after": { "branch": "topic", "head": 9 }, "message": "main -> topic" }
+//! ```
+//!
+//! Cubes are append-only, so the log keeps every state a head went through: after a mistake,
+//! `ak reflog` tells which seal to go back to.
+
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+/// Phenomenon of reflog records.
+pub const REFLOG: &str = "reflog:entry";
+
+/// Branch checked out and its head.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct State {
+    pub branch: String,
+    /// Head commit of the branch, `None` before its first seal.
+    pub head: Option<u64>,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.head {
+            Some(head) => write!(f, "{} #{head}", self.branch),
+            None => write!(f, "{} -", self.branch),
+        }
+    }
+}
+
+/// Noumenon of a `reflog:entry` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub author: String,
+    /// Operation: `seal`, `amend`, `merge`, `revert`, `pick`, `switch`, `restore`, `import-git`.
+    pub op: String,
+    pub before: State,
+    pub after: State,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Record `entry`.
+pub fn record(w: &mut Writer, entry: &Entry) -> io::Result<()> {
+    let json = serde_json::to_string(entry).map_err(io::Error::other)?;
+    w.append(REFLOG, &json).map(|_| ())
+}
+
+/// Entries of `author` with their timestamp (nanoseconds), newest first.
+pub fn entries(events: &[Event], author: &str) -> Vec<(u128, Entry)> {
+    let mut out: Vec<(u128, Entry)> = events
+        .iter()
+        .filter(|e| e.phenomenon == REFLOG)
+        .filter_map(|e| {
+            Some((
+                e.timestamp,
+                serde_json::from_str::<Entry>(&e.noumenon).ok()?,
+            ))
+        })
+        .filter(|(_, entry)| entry.author == author)
+        .collect();
+    out.reverse();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_list_entries() {
+        let dir = std::env::temp_dir().join(format!("akasha-reflog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let state = |branch: &str, head| State {
+            branch: branch.to_string(),
+            head,
+        };
+        let entry = |author: &str, op: &str, before, after| Entry {
+            author: author.to_string(),
+            op: op.to_string(),
+            before,
+            after,
+            message: String::new(),
+        };
+        record(
+            &mut w,
+            &entry("u", "seal", state("main", None), state("main", Some(1))),
+        )
+        .unwrap();
+        record(
+            &mut w,
+            &entry("v", "seal", state("main", Some(1)), state("main", Some(2))),
+        )
+        .unwrap();
+        record(
+            &mut w,
+            &entry("u", "switch", state("main", Some(1)), state("topic", None)),
+        )
+        .unwrap();
+
+        let events = w.events().unwrap();
+        let ops: Vec<String> = entries(&events, "u")
+            .into_iter()
+            .map(|(_, e)| format!("{}: {} -> {}", e.op, e.before, e.after))
+            .collect();
+        assert_eq!(
+            ops,
+            ["switch: main #1 -> topic -", "seal: main - -> main #1"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
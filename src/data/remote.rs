@@ -0,0 +1,625 @@
+//! Remotes: exchanging cubes with another copy of the repository (`ak push`, `ak pull`).
+//!
+//! A remote is another repository's root, reached either through the file system (a path or a
+//! `file://` URL) or over SSH (`[user@]host:path` or `ssh://[user@]host[:port]/path`), where
+//! `ak` must be on the `PATH` of the remote host: the SSH transport runs the hidden
+//! `ak remote-helper` command there.
+//!
+//! Every cube `YYYY-MM/<author>.cube` only ever grows by appending records with increasing ids,
+//! so two copies of a cube are compared by their last record (their `Tip`): the copy with the
+//! smaller last id is behind when the other holds that same record, and only the records past
+//! it are transferred, with their ids and timestamps (`Writer::append_events`). Branches and
+//! tags are records too and travel with the cubes. Two copies that both gained records since
+//! their last common one (the same author sealing on two machines) have diverged and are left
+//! alone.
+//!
+//! Copies of a cube share its identity (see `data::write::Identity`): a cube created by a
+//! transfer adopts the identity of its source. Two cubes with the same name but different UUIDs
+//! are unrelated, whatever records they hold, and are left alone too.
+//!
+//! Only the per-author layout can be synced: with the shared layout (see `data::config`) the
+//! whole team appends to one cube, which can't be merged record by record.
+
+use crate::data::config::{Config, Layout};
+use crate::data::history;
+use crate::data::write::{Identity, Writer};
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Last record of a cube.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tip {
+    /// Id of the last record, 0 for a cube without records.
+    pub last: u64,
+    /// `digest` of the last record, empty without records.
+    pub digest: String,
+    /// Identity of the cube, `None` before format version 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<Identity>,
+}
+
+/// Fingerprint of a record: its id, timestamp, phenomenon and noumenon.
+pub fn digest(ev: &Event) -> String {
+    let mut h = blake3::Hasher::new();
+    h.update(&ev.id.to_le_bytes());
+    h.update(&ev.timestamp.to_le_bytes());
+    h.update(&(ev.phenomenon.len() as u64).to_le_bytes());
+    h.update(ev.phenomenon.as_bytes());
+    h.update(ev.noumenon.as_bytes());
+    h.finalize().to_hex().to_string()
+}
+
+fn tip_of(events: &[Event]) -> Tip {
+    match events.last() {
+        Some(ev) => Tip {
+            last: ev.id,
+            digest: digest(ev),
+            identity: None,
+        },
+        None => Tip {
+            last: 0,
+            digest: String::new(),
+            identity: None,
+        },
+    }
+}
+
+fn check_repository(root: &Path) -> io::Result<()> {
+    if !root.join(".eikyu").is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: not an ak repository (run ak init)", root.display()),
+        ));
+    }
+    if Config::load(root)?.storage.layout == Layout::Shared {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{}: repositories with the shared layout can't be synced",
+                root.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Path of cube `rel` (`YYYY-MM/<author>.cube`) of the repository at `root`, refusing anything
+/// else so a remote can't name files outside `.eikyu/cubes`.
+fn cube_path(root: &Path, rel: &str) -> io::Result<PathBuf> {
+    let parts: Vec<Component> = Path::new(rel).components().collect();
+    let valid = rel.ends_with(".cube")
+        && !rel.contains('\\')
+        && parts.len() == 2
+        && parts.iter().all(|c| matches!(c, Component::Normal(_)));
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{rel}: not a cube of the repository"),
+        ));
+    }
+    Ok(root.join(".eikyu").join("cubes").join(rel))
+}
+
+/// Records of the cube at `path`, as stored (processors are not applied).
+fn read(path: &Path) -> io::Result<Vec<Event>> {
+    Writer::decode(&fs::read(path)?)
+}
+
+/// Tip of every cube of the repository at `root`, by `YYYY-MM/<author>.cube`.
+pub fn tips(root: &Path) -> io::Result<BTreeMap<String, Tip>> {
+    check_repository(root)?;
+    let mut out = BTreeMap::new();
+    for cube in history::cube_files(root)? {
+        let rel = format!("{}/{}.cube", cube.month, cube.author);
+        let bytes = fs::read(&cube.path)?;
+        let tip = Tip {
+            identity: Writer::image_identity(&bytes)?,
+            ..tip_of(&Writer::decode(&bytes)?)
+        };
+        out.insert(rel, tip);
+    }
+    Ok(out)
+}
+
+/// Records of cube `rel` of the repository at `root` with an id of at least `from`.
+pub fn fetch(root: &Path, rel: &str, from: u64) -> io::Result<Vec<Event>> {
+    check_repository(root)?;
+    let mut events = read(&cube_path(root, rel)?)?;
+    events.retain(|ev| ev.id >= from);
+    Ok(events)
+}
+
+/// Give the cube at `path` the `identity` of the cube its records come from, unless it already
+/// holds records: creating it if needed.
+fn adopt(path: &Path, identity: &Identity) -> io::Result<()> {
+    let mut f = fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    f.lock()?;
+    let result = (|| {
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes)?;
+        if bytes.is_empty() || Writer::check(&bytes)?.records == 0 {
+            Writer::write_image(&mut f, &[], 1, Some(identity))?;
+        }
+        Ok(())
+    })();
+    f.unlock()?;
+    result
+}
+
+/// Append `events` verbatim to cube `rel` of the repository at `root`, creating it if needed
+/// with the `identity` of their cube.
+pub fn store(
+    root: &Path,
+    rel: &str,
+    identity: Option<&Identity>,
+    events: &[Event],
+) -> io::Result<()> {
+    check_repository(root)?;
+    let path = cube_path(root, rel)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let path = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: not a UTF-8 path", path.display()),
+        )
+    })?;
+    if let Some(identity) = identity {
+        adopt(Path::new(path), identity)?;
+    }
+    Writer::create(path)?.append_events(events)?;
+    Ok(())
+}
+
+/// Access to a remote repository.
+pub trait Transport {
+    /// `tips` of the remote.
+    fn tips(&mut self) -> io::Result<BTreeMap<String, Tip>>;
+    /// `fetch` from the remote.
+    fn fetch(&mut self, rel: &str, from: u64) -> io::Result<Vec<Event>>;
+    /// `store` into the remote.
+    fn store(&mut self, rel: &str, identity: Option<&Identity>, events: &[Event])
+    -> io::Result<()>;
+}
+
+/// A repository on the file system.
+pub struct Local {
+    pub root: PathBuf,
+}
+
+impl Transport for Local {
+    fn tips(&mut self) -> io::Result<BTreeMap<String, Tip>> {
+        tips(&self.root)
+    }
+
+    fn fetch(&mut self, rel: &str, from: u64) -> io::Result<Vec<Event>> {
+        fetch(&self.root, rel, from)
+    }
+
+    fn store(
+        &mut self,
+        rel: &str,
+        identity: Option<&Identity>,
+        events: &[Event],
+    ) -> io::Result<()> {
+        store(&self.root, rel, identity, events)
+    }
+}
+
+/// A repository reached over SSH, through `ak remote-helper` on the remote host.
+#[derive(Debug, PartialEq)]
+pub struct Ssh {
+    /// `[user@]host`.
+    pub host: String,
+    pub port: Option<String>,
+    pub path: String,
+}
+
+/// Quote `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+impl Ssh {
+    /// Run `ak remote-helper <args>` on the remote host with `input` on its stdin.
+    fn helper(&self, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+        let remote: Vec<String> = ["ak", "remote-helper"]
+            .iter()
+            .chain(args)
+            .map(|a| shell_quote(a))
+            .collect();
+        let mut cmd = Command::new("ssh");
+        if let Some(port) = &self.port {
+            cmd.arg("-p").arg(port);
+        }
+        let mut child = cmd
+            .arg(&self.host)
+            .arg(remote.join(" "))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::other(format!("failed to spawn ssh: {e}")))?;
+        // The helper reads all of its input before writing anything.
+        child.stdin.take().expect("piped stdin").write_all(input)?;
+        let out = child.wait_with_output()?;
+        if !out.status.success() {
+            return Err(io::Error::other(format!(
+                "ssh {}: ak remote-helper {} failed ({})",
+                self.host, args[0], out.status
+            )));
+        }
+        Ok(out.stdout)
+    }
+}
+
+impl Transport for Ssh {
+    fn tips(&mut self) -> io::Result<BTreeMap<String, Tip>> {
+        let out = self.helper(&["tips", &self.path], &[])?;
+        serde_json::from_slice(&out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn fetch(&mut self, rel: &str, from: u64) -> io::Result<Vec<Event>> {
+        let out = self.helper(&["fetch", &self.path, rel, &from.to_string()], &[])?;
+        read_events(&out[..])
+    }
+
+    fn store(
+        &mut self,
+        rel: &str,
+        identity: Option<&Identity>,
+        events: &[Event],
+    ) -> io::Result<()> {
+        let mut input = Vec::new();
+        write_events(&mut input, events)?;
+        let mut args = vec!["store".to_string(), self.path.clone(), rel.to_string()];
+        if let Some(identity) = identity {
+            args.push("--identity".to_string());
+            args.push(serde_json::to_string(identity)?);
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.helper(&args, &input).map(drop)
+    }
+}
+
+/// Write `events` as JSON lines, the wire format of `ak remote-helper`.
+pub fn write_events(mut out: impl Write, events: &[Event]) -> io::Result<()> {
+    for ev in events {
+        serde_json::to_writer(&mut out, ev)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Read events written by `write_events`.
+pub fn read_events(input: impl BufRead) -> io::Result<Vec<Event>> {
+    let mut out = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if !line.is_empty() {
+            out.push(
+                serde_json::from_str(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            );
+        }
+    }
+    Ok(out)
+}
+
+/// Where a remote URL points.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    Path(PathBuf),
+    Ssh(Ssh),
+}
+
+/// Parse `ssh://[user@]host[:port]/path`, `[user@]host:path`, `file://path` or a plain path.
+pub fn parse_url(url: &str) -> Location {
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.to_string())),
+            None => (authority, None),
+        };
+        return Location::Ssh(Ssh {
+            host: host.to_string(),
+            port,
+            path: format!("/{path}"),
+        });
+    }
+    if let Some(path) = url.strip_prefix("file://") {
+        return Location::Path(path.into());
+    }
+    // `host:path`, unless the colon comes after a slash or is a Windows drive (`C:\`).
+    if let Some((host, path)) = url.split_once(':')
+        && host.len() > 1
+        && !host.contains(['/', '\\'])
+    {
+        return Location::Ssh(Ssh {
+            host: host.to_string(),
+            port: None,
+            path: path.to_string(),
+        });
+    }
+    Location::Path(url.into())
+}
+
+/// Transport for `url` (see `parse_url`).
+pub fn connect(url: &str) -> Box<dyn Transport> {
+    match parse_url(url) {
+        Location::Path(root) => Box::new(Local { root }),
+        Location::Ssh(ssh) => Box::new(ssh),
+    }
+}
+
+/// What a push or pull does with one cube.
+#[derive(Debug)]
+pub enum Action {
+    /// Copy these records, past the other side's tip, from a cube with this identity.
+    Copy(Vec<Event>, Option<Identity>),
+    /// Both sides hold the same records.
+    UpToDate,
+    /// The destination has records the source lacks; the other direction brings them.
+    Behind,
+    /// Both sides gained different records.
+    Diverged,
+    /// Both sides hold a cube with that name, but with different UUIDs.
+    Unrelated,
+}
+
+/// Plan for every cube, by `YYYY-MM/<author>.cube`.
+pub type Plan = BTreeMap<String, Action>;
+
+impl Action {
+    /// Number of records copied.
+    pub fn records(&self) -> usize {
+        match self {
+            Action::Copy(events, _) => events.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// Compare a source cube whose records from `tip.last` on (inclusive) are `from_tip` with a
+/// destination at `tip`, whose record with the id of the source's last one has the digest
+/// `held` (`None` without such a record, or when the destination is not ahead).
+fn action(tip: Option<&Tip>, source: &Tip, from_tip: Vec<Event>, held: Option<String>) -> Action {
+    let identity = source.identity.clone();
+    let Some(tip) = tip.filter(|t| t.last > 0) else {
+        return Action::Copy(from_tip, identity);
+    };
+    if let (Some(ours), Some(theirs)) = (&tip.identity, &identity)
+        && ours.uuid != theirs.uuid
+    {
+        return Action::Unrelated;
+    }
+    if tip.last == source.last {
+        return if tip.digest == source.digest {
+            Action::UpToDate
+        } else {
+            Action::Diverged
+        };
+    }
+    if tip.last > source.last {
+        return if source.last == 0 || held.as_ref() == Some(&source.digest) {
+            Action::Behind
+        } else {
+            Action::Diverged
+        };
+    }
+    match from_tip.split_first() {
+        Some((common, rest)) if common.id == tip.last && digest(common) == tip.digest => {
+            Action::Copy(rest.to_vec(), identity)
+        }
+        _ => Action::Diverged,
+    }
+}
+
+/// Plan copying from a source with `source` tips to a destination with `dest` tips, reading
+/// the source's records with `fetch` and the destination's with `fetch_dest`.
+fn plan(
+    source: BTreeMap<String, Tip>,
+    dest: &BTreeMap<String, Tip>,
+    mut fetch: impl FnMut(&str, u64) -> io::Result<Vec<Event>>,
+    mut fetch_dest: impl FnMut(&str, u64) -> io::Result<Vec<Event>>,
+) -> io::Result<Plan> {
+    let mut plan = Plan::new();
+    for (rel, tip) in dest {
+        if tip.last > 0 && !source.contains_key(rel) {
+            plan.insert(rel.clone(), Action::Behind);
+        }
+    }
+    for (rel, theirs) in source {
+        let tip = dest.get(&rel);
+        let from = tip.map_or(0, |t| t.last);
+        let pending = if theirs.last > from || tip.is_none() {
+            fetch(&rel, from)?
+        } else {
+            Vec::new()
+        };
+        // A destination ahead of the source is only behind it when it holds its last record.
+        let held = match tip {
+            Some(t) if t.last > theirs.last && theirs.last > 0 => fetch_dest(&rel, theirs.last)?
+                .iter()
+                .find(|e| e.id == theirs.last)
+                .map(digest),
+            _ => None,
+        };
+        plan.insert(rel, action(tip, &theirs, pending, held));
+    }
+    Ok(plan)
+}
+
+/// What `push` would send from the repository at `root`.
+pub fn plan_push(root: &Path, remote: &mut dyn Transport) -> io::Result<Plan> {
+    let theirs = remote.tips()?;
+    plan(
+        tips(root)?,
+        &theirs,
+        |rel, from| fetch(root, rel, from),
+        |rel, from| remote.fetch(rel, from),
+    )
+}
+
+/// What `pull` would bring into the repository at `root`.
+pub fn plan_pull(root: &Path, remote: &mut dyn Transport) -> io::Result<Plan> {
+    let ours = tips(root)?;
+    plan(
+        remote.tips()?,
+        &ours,
+        |rel, from| remote.fetch(rel, from),
+        |rel, from| fetch(root, rel, from),
+    )
+}
+
+/// Carry out the copies of a push `plan`.
+pub fn push(plan: &Plan, remote: &mut dyn Transport) -> io::Result<()> {
+    for (rel, action) in plan {
+        if let Action::Copy(events, identity) = action
+            && !events.is_empty()
+        {
+            remote.store(rel, identity.as_ref(), events)?;
+        }
+    }
+    Ok(())
+}
+
+/// Carry out the copies of a pull `plan` into the repository at `root`.
+pub fn pull(root: &Path, plan: &Plan) -> io::Result<()> {
+    for (rel, action) in plan {
+        if let Action::Copy(events, identity) = action
+            && !events.is_empty()
+        {
+            store(root, rel, identity.as_ref(), events)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pull_only_missing_records() {
+        let base = std::env::temp_dir().join(format!("akasha-remote-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let (a, b) = (base.join("a"), base.join("b"));
+        for root in [&a, &b] {
+            fs::create_dir_all(root.join(".eikyu").join("cubes")).unwrap();
+        }
+        let cube = |root: &Path, rel: &str| {
+            let path = cube_path(root, rel).unwrap();
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            Writer::create(path.to_str().unwrap()).unwrap()
+        };
+
+        let mut w = cube(&a, "2025-08/ana.cube");
+        w.append("commit", r#"{"id":1}"#).unwrap();
+        w.append("commit", r#"{"id":2}"#).unwrap();
+        let mut w = cube(&b, "2025-08/bo.cube");
+        w.append("commit", r#"{"id":1}"#).unwrap();
+
+        let mut remote = Local { root: b.clone() };
+        let plan = plan_push(&a, &mut remote).unwrap();
+        assert_eq!(plan["2025-08/ana.cube"].records(), 2);
+        assert!(matches!(plan["2025-08/bo.cube"], Action::Behind));
+        push(&plan, &mut remote).unwrap();
+        assert_eq!(
+            tips(&a).unwrap()["2025-08/ana.cube"],
+            tips(&b).unwrap()["2025-08/ana.cube"]
+        );
+
+        // One more record: only that one travels, with its id and timestamp.
+        let mut w = cube(&a, "2025-08/ana.cube");
+        w.append("branch:main", r#"{"head":2}"#).unwrap();
+        let plan = plan_push(&a, &mut remote).unwrap();
+        let Action::Copy(events, _) = &plan["2025-08/ana.cube"] else {
+            panic!("expected a copy");
+        };
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), [3]);
+        push(&plan, &mut remote).unwrap();
+        assert_eq!(
+            read(&cube_path(&b, "2025-08/ana.cube").unwrap()).unwrap()[2].timestamp,
+            events[0].timestamp
+        );
+
+        let plan = plan_pull(&a, &mut remote).unwrap();
+        assert!(matches!(plan["2025-08/ana.cube"], Action::UpToDate));
+        assert_eq!(plan["2025-08/bo.cube"].records(), 1);
+        pull(&a, &plan).unwrap();
+        assert_eq!(tips(&a).unwrap(), tips(&b).unwrap());
+        cube(&b, "2025-08/ana.cube").append("y", "1").unwrap();
+        assert!(matches!(
+            plan_push(&a, &mut remote).unwrap()["2025-08/ana.cube"],
+            Action::Behind
+        ));
+
+        // Both sides append to the same cube.
+        cube(&a, "2025-08/bo.cube").append("x", "a").unwrap();
+        cube(&b, "2025-08/bo.cube").append("x", "b").unwrap();
+        assert!(matches!(
+            plan_pull(&a, &mut remote).unwrap()["2025-08/bo.cube"],
+            Action::Diverged
+        ));
+        // Still diverged when the remote copy is the longer one.
+        cube(&b, "2025-08/bo.cube").append("x", "c").unwrap();
+        assert!(matches!(
+            plan_push(&a, &mut remote).unwrap()["2025-08/bo.cube"],
+            Action::Diverged
+        ));
+        // The same records in a cube created elsewhere under the same name: another cube.
+        let ana = cube_path(&a, "2025-08/ana.cube").unwrap();
+        let (ours, theirs) = (
+            cube_path(&a, "2025-09/ana.cube").unwrap(),
+            cube_path(&b, "2025-09/ana.cube").unwrap(),
+        );
+        for path in [&ours, &theirs] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+        }
+        fs::copy(&ana, &ours).unwrap();
+        let mut f = fs::File::create(&theirs).unwrap();
+        Writer::write_image(&mut f, &read(&ana).unwrap(), 4, None).unwrap();
+        assert!(matches!(
+            plan_pull(&a, &mut remote).unwrap()["2025-09/ana.cube"],
+            Action::Unrelated
+        ));
+        assert!(cube_path(&a, "../config.toml").is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let ssh = |host: &str, port: Option<&str>, path: &str| {
+            Location::Ssh(Ssh {
+                host: host.into(),
+                port: port.map(str::to_string),
+                path: path.into(),
+            })
+        };
+        assert_eq!(
+            parse_url("ana@host:srv/repo"),
+            ssh("ana@host", None, "srv/repo")
+        );
+        assert_eq!(
+            parse_url("ssh://ana@host:2222/srv/repo"),
+            ssh("ana@host", Some("2222"), "/srv/repo")
+        );
+        assert_eq!(
+            parse_url("file:///srv/repo"),
+            Location::Path("/srv/repo".into())
+        );
+        assert_eq!(parse_url("../repo"), Location::Path("../repo".into()));
+        assert_eq!(parse_url(r"C:\repo"), Location::Path(r"C:\repo".into()));
+    }
+}
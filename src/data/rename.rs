@@ -0,0 +1,266 @@
+//! Tracked renames and removals: `ak mv` and `ak rm`.
+//!
+//! `ak mv` stages a rename in the index (`Index::renames`) along with the removal of the old path
+//! and the addition of the new one; `ak rm` stages a removal. Sealing records them next to the
+//! commit, as a `path:rename` record `{"commit": <id>, "from": "a.txt", "to": "b.txt"}` per rename
+//! and a `path:remove` tombstone `{"commit": <id>, "path": "c.txt"}` per other removal, so that
+//! `status`, `diff`, `show` and `restore` follow a renamed file instead of reporting a deletion
+//! and an unrelated addition.
+//!
+//! Files deleted and added without `ak mv` are paired too when their contents match (see
+//! `detect`), and sealed as renames as well.
+
+use crate::data::index::Index;
+use crate::data::merge;
+use crate::data::write::Writer;
+use crate::event::{Event, RecordType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Phenomenon of rename records.
+pub const RENAME: &str = "path:rename";
+
+/// Phenomenon of removal tombstones.
+pub const REMOVE: &str = RecordType::REMOVE;
+
+/// Minimum similarity, in percent, for a deleted and an added file to be reported as a rename.
+pub const SIMILARITY: u32 = 50;
+
+/// Beyond this many deleted × added pairs, only identical contents are paired.
+const MAX_CANDIDATES: usize = 1000;
+
+/// Noumenon of a `path:rename` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Rename {
+    pub commit: u64,
+    pub from: String,
+    pub to: String,
+}
+
+/// Noumenon of a `path:remove` tombstone.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Removal {
+    pub commit: u64,
+    pub path: String,
+}
+
+/// Record the renames and removals staged in `index` as part of commit `commit`.
+pub fn record(w: &mut Writer, commit: u64, index: &Index) -> io::Result<()> {
+    for (to, from) in &index.renames {
+        let json = serde_json::to_string(&Rename {
+            commit,
+            from: from.clone(),
+            to: to.clone(),
+        })
+        .map_err(io::Error::other)?;
+        w.append(RENAME, &json)?;
+    }
+    let renamed = |path: &String| index.renames.values().any(|from| from == path);
+    for (path, _) in index
+        .entries
+        .iter()
+        .filter(|(p, c)| c.is_none() && !renamed(p))
+    {
+        let json = serde_json::to_string(&Removal {
+            commit,
+            path: path.clone(),
+        })
+        .map_err(io::Error::other)?;
+        w.append(REMOVE, &json)?;
+    }
+    Ok(())
+}
+
+/// Similarity of two contents in percent: the share of lines they have in common, 0 unless both
+/// are valid UTF-8.
+pub fn similarity(a: &[u8], b: &[u8]) -> u32 {
+    if a == b {
+        return 100;
+    }
+    let (Ok(a), Ok(b)) = (std::str::from_utf8(a), std::str::from_utf8(b)) else {
+        return 0;
+    };
+    let (mut common, mut total) = (0, 0);
+    for d in diff::lines(a.trim_end_matches('\n'), b.trim_end_matches('\n')) {
+        match d {
+            diff::Result::Both(..) => {
+                common += 2;
+                total += 2;
+            }
+            _ => total += 1,
+        }
+    }
+    if total == 0 {
+        return 0;
+    }
+    (100 * common / total) as u32
+}
+
+/// Pair `removed` files with `added` ones by content: identical contents first, then the most
+/// similar pairs of at least `SIMILARITY` percent. Empty files are never paired. Returns new path
+/// -> old path.
+pub fn detect(removed: &[(&str, &[u8])], added: &[(&str, &[u8])]) -> BTreeMap<String, String> {
+    let mut renames = BTreeMap::new();
+    let mut used = vec![false; removed.len()];
+    let mut left = Vec::new();
+    for (new, content) in added.iter().filter(|(_, c)| !c.is_empty()) {
+        let same = (0..removed.len()).find(|&k| !used[k] && removed[k].1 == *content);
+        match same {
+            Some(k) => {
+                used[k] = true;
+                renames.insert(new.to_string(), removed[k].0.to_string());
+            }
+            None => left.push((*new, *content)),
+        }
+    }
+
+    let candidates: Vec<usize> = (0..removed.len())
+        .filter(|&k| !used[k] && !removed[k].1.is_empty())
+        .collect();
+    if candidates.len() * left.len() > MAX_CANDIDATES {
+        return renames;
+    }
+    let mut scores = Vec::new();
+    for (a, (_, content)) in left.iter().enumerate() {
+        for &k in &candidates {
+            let score = similarity(removed[k].1, content);
+            if score >= SIMILARITY {
+                scores.push((score, a, k));
+            }
+        }
+    }
+    // Best pairs first, in path order among equals.
+    scores.sort_by(|x, y| y.0.cmp(&x.0).then((x.1, x.2).cmp(&(y.1, y.2))));
+    let mut paired = vec![false; left.len()];
+    for (_, a, k) in scores {
+        if !paired[a] && !used[k] {
+            paired[a] = true;
+            used[k] = true;
+            renames.insert(left[a].0.to_string(), removed[k].0.to_string());
+        }
+    }
+    renames
+}
+
+/// Renames sealed by commit `commit`, as `(from, to)` pairs.
+pub fn of_commit(events: &[Event], commit: u64) -> Vec<(String, String)> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == RENAME)
+        .filter_map(|e| serde_json::from_str::<Rename>(&e.noumenon).ok())
+        .filter(|r| r.commit == commit)
+        .map(|r| (r.from, r.to))
+        .collect()
+}
+
+/// Chain `earlier` renames (later name -> older name) with the `later` ones (newest name -> later
+/// name): newest name -> older name.
+pub fn compose(
+    mut earlier: BTreeMap<String, String>,
+    later: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    for (new, mid) in later {
+        let old = earlier.remove(mid).unwrap_or_else(|| mid.clone());
+        if *new != old {
+            earlier.insert(new.clone(), old);
+        }
+    }
+    earlier
+}
+
+/// Renames from commit `from` to its descendant `to` along first parents: name at `to` -> name
+/// at `from`. Empty when `from` isn't a first-parent ancestor of `to`.
+pub fn between(events: &[Event], from: u64, to: u64) -> BTreeMap<String, String> {
+    let mut chain = Vec::new();
+    let mut at = Some(to);
+    while let Some(id) = at.filter(|&id| id != from) {
+        chain.push(id);
+        at = merge::parents(events, id).first().copied();
+    }
+    if at.is_none() {
+        return BTreeMap::new();
+    }
+    chain.iter().rev().fold(BTreeMap::new(), |renames, &id| {
+        let step = of_commit(events, id)
+            .into_iter()
+            .map(|(from, to)| (to, from))
+            .collect();
+        compose(renames, &step)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_follow_renames() {
+        let dir = std::env::temp_dir().join(format!("akasha-rename-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        w.append("commit", r#"{"id":1}"#).unwrap();
+
+        let mut index = Index::default();
+        index.stage_rename("a.txt", "b.txt", "a".to_string(), true);
+        index.stage_content("gone.txt", None);
+        record(&mut w, 2, &index).unwrap();
+        w.append("commit", r#"{"id":2,"parent":1}"#).unwrap();
+
+        let mut index = Index::default();
+        index.stage_rename("b.txt", "c.txt", "a".to_string(), true);
+        record(&mut w, 4, &index).unwrap();
+        w.append("commit", r#"{"id":4,"parent":2}"#).unwrap();
+
+        let events = w.events().unwrap();
+        let removals: Vec<Removal> = events
+            .iter()
+            .filter(|e| e.phenomenon == REMOVE)
+            .map(|e| serde_json::from_str(&e.noumenon).unwrap())
+            .collect();
+        assert_eq!(
+            removals,
+            [Removal {
+                commit: 2,
+                path: "gone.txt".to_string()
+            }]
+        );
+        assert_eq!(
+            of_commit(&events, 2),
+            [("a.txt".to_string(), "b.txt".to_string())]
+        );
+        let renames = between(&events, 1, 4);
+        assert_eq!(
+            renames,
+            BTreeMap::from([("c.txt".to_string(), "a.txt".to_string())])
+        );
+        assert!(between(&events, 4, 1).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_identical_and_similar_files() {
+        let removed: [(&str, &[u8]); 4] = [
+            ("a.txt", b"same\n"),
+            ("b.txt", b"1\n2\n3\n4\n"),
+            ("c.txt", b"unrelated\n"),
+            ("empty", b""),
+        ];
+        let added: [(&str, &[u8]); 4] = [
+            ("moved/a.txt", b"same\n"),
+            ("b2.txt", b"1\n2\n3\n5\n"),
+            ("d.txt", b"other\n"),
+            ("empty2", b""),
+        ];
+        assert_eq!(similarity(b"1\n2\n3\n4\n", b"1\n2\n3\n5\n"), 75);
+        assert_eq!(
+            detect(&removed, &added),
+            BTreeMap::from([
+                ("b2.txt".to_string(), "b.txt".to_string()),
+                ("moved/a.txt".to_string(), "a.txt".to_string()),
+            ])
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Materialize the files of a past commit.
+//!
+//! File contents are ordinary cube records (phenomenon = portable path, noumenon = content).
+//! Commits carry a manifest (see `data::manifest`) naming the exact record of every file. Commits
+//! sealed before manifests existed fall back to the latest record of every path written before
+//! the commit's record, since a commit is sealed after the contents it covers. Records of other
+//! kinds than `RecordType::File` (`commit`, and prefixed records such as `commit:pending` or
+//! `branch:<name>`) are skipped.
+
+use crate::data::commit;
+use crate::data::manifest::Manifest;
+use crate::data::paths;
+use crate::event::{Event, RecordType};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Whether `ev` holds file contents rather than repository metadata.
+pub fn is_file_record(ev: &Event) -> bool {
+    ev.kind == RecordType::File
+}
+
+/// Latest content of every path written before the record `until` (a record id).
+pub fn files_before(events: &[Event], until: u64) -> BTreeMap<String, &str> {
+    let mut out = BTreeMap::new();
+    for ev in events.iter().filter(|e| e.id < until && is_file_record(e)) {
+        out.insert(paths::canonical(&ev.phenomenon), ev.noumenon.as_str());
+    }
+    out
+}
+
+/// Every file of commit `id` with its content: from the commit's manifest, or from the records
+/// written before it for commits without one.
+pub fn tree_at(events: &[Event], id: u64) -> io::Result<BTreeMap<String, &str>> {
+    let commit = commit::find(events, id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no commit #{id}")))?;
+    match Manifest::of_commit(events, id) {
+        Some(m) => m.contents(events),
+        None => Ok(files_before(events, commit.id)),
+    }
+}
+
+/// Whether portable `path` is one of `filters` or below one of them (no filter keeps all).
+pub fn selected(path: &str, filters: &[String]) -> bool {
+    filters.is_empty()
+        || filters
+            .iter()
+            .any(|f| path == f || path.starts_with(&format!("{f}/")))
+}
+
+/// Write the files of commit `id` matching `filters` (portable paths) below `into`, overwriting
+/// existing files. Returns the restored paths.
+pub fn restore(
+    events: &[Event],
+    id: u64,
+    filters: &[String],
+    into: &Path,
+) -> io::Result<Vec<String>> {
+    restore_renamed(events, id, filters, &BTreeMap::new(), into)
+}
+
+/// `restore`, following `renames` (current path -> path at commit `id`, see `data::rename`): a
+/// file the filters select by its current name is restored under that name.
+pub fn restore_renamed(
+    events: &[Event],
+    id: u64,
+    filters: &[String],
+    renames: &BTreeMap<String, String>,
+    into: &Path,
+) -> io::Result<Vec<String>> {
+    let mut tree = tree_at(events, id)?;
+    if !filters.is_empty() {
+        for (current, old) in renames {
+            if selected(current, filters)
+                && !selected(old, filters)
+                && !tree.contains_key(current)
+                && let Some(content) = tree.remove(old)
+            {
+                tree.insert(current.clone(), content);
+            }
+        }
+    }
+    let mut restored = Vec::new();
+    for (path, content) in tree {
+        if !selected(&path, filters) {
+            continue;
+        }
+        let Some(rel) = paths::to_native(&path) else {
+            eprintln!("skip {path}: not a portable relative path");
+            continue;
+        };
+        let dest = into.join(rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(paths::long(parent))?;
+        }
+        fs::write(paths::long(&dest), content)?;
+        restored.push(path);
+    }
+    Ok(restored)
+}
+
+/// Write the whole tree of commit `id` into the scratch directory `into`, which is created and
+/// must be empty: unlike `restore`, nothing is ever overwritten. Returns the written paths.
+pub fn materialize(events: &[Event], id: u64, into: &Path) -> io::Result<Vec<String>> {
+    if fs::read_dir(into).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not empty", into.display()),
+        ));
+    }
+    fs::create_dir_all(paths::long(into))?;
+    restore(events, id, &[], into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ev(id: u64, ph: &str, no: &str) -> Event {
+        Event::builder()
+            .id(id)
+            .phenomenon(ph)
+            .noumenon(no)
+            .timestamp(0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_restore_as_of_commit() {
+        let events = [
+            ev(1, "commit:pending", "x"),
+            ev(2, "src/a.rs", "a1"),
+            ev(3, "b.txt", "b1"),
+            ev(4, "commit", r#"{"id":1}"#),
+            ev(5, "src/a.rs", "a2"),
+            ev(6, "../escape", "!"),
+            ev(7, "commit", r#"{"id":5}"#),
+        ];
+        let dir = std::env::temp_dir().join(format!("akasha-restore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            restore(&events, 1, &[], &dir).unwrap(),
+            ["b.txt", "src/a.rs"]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("src").join("a.rs")).unwrap(),
+            "a1"
+        );
+
+        let only = ["src".to_string()];
+        assert_eq!(restore(&events, 5, &only, &dir).unwrap(), ["src/a.rs"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("src").join("a.rs")).unwrap(),
+            "a2"
+        );
+        assert_eq!(restore(&events, 5, &[], &dir).unwrap().len(), 2);
+        assert!(!dir.parent().unwrap().join("escape").exists());
+        assert!(restore(&events, 2, &[], &dir).is_err());
+
+        // Materializing never overwrites: the directory must be new or empty.
+        assert!(materialize(&events, 1, &dir).is_err());
+        let scratch = dir.join("scratch");
+        assert_eq!(materialize(&events, 1, &scratch).unwrap().len(), 2);
+        assert_eq!(fs::read_to_string(scratch.join("b.txt")).unwrap(), "b1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Retention policies: keep cubes bounded by moving old records into dated archive cubes.
+//!
+//! A policy is configured per cube through a JSON sidecar named `<cube>.retention.json`:
+//!
+//! ```json
+//! { "max_bytes": 104857600, "max_age": "90d", "max_versions": 5 }
+//! ```
+//!
+//! All limits are optional. `rotate` applies them in this order:
+//! - `max_versions`: only the newest N records of each phenomenon stay in the cube;
+//! - `max_age` (`s`, `m`, `h`, `d` or `w` suffix): older records leave the cube;
+//! - `max_bytes`: the oldest remaining records leave the cube until it fits.
+//!
+//! The latest record of every phenomenon is never moved, so the cube always reflects the current
+//! state of everything it tracks. Moved records keep their ids and timestamps and are appended
+//! to `<stem>.archive-YYYY-MM-DD.cube` next to the cube. The archive is written and synced before
+//! the cube is compacted, so a crash can duplicate records but never lose one.
+//!
+//! `rotate` holds the cube's exclusive lock throughout and rewrites the cube in place, so writers
+//! opened before a rotation keep working: their next append lands after the compacted records.
+//! The compacted image is first saved to `<cube>.rotate-tmp`; if the rewrite is interrupted, that
+//! file is a complete copy of the rotated cube.
+
+use crate::data::checkpoint;
+use crate::data::paths;
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Limits enforced by `rotate`.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Upper bound for the cube file size, in bytes.
+    pub max_bytes: Option<u64>,
+    /// Maximum record age, e.g. `"30d"`.
+    pub max_age: Option<String>,
+    /// Number of versions kept per phenomenon.
+    pub max_versions: Option<usize>,
+}
+
+impl Policy {
+    /// Whether no limit is set at all.
+    pub fn is_empty(&self) -> bool {
+        self.max_bytes.is_none() && self.max_age.is_none() && self.max_versions.is_none()
+    }
+}
+
+/// Outcome of a rotation.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub kept: usize,
+    pub archived: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Archive cube that received the moved records (`None` when nothing moved).
+    pub archive: Option<PathBuf>,
+}
+
+/// Load the policy configured for the cube at `cube_path`, if any.
+pub fn load_for_cube(cube_path: &str) -> io::Result<Option<Policy>> {
+    let config_path = format!("{cube_path}.retention.json");
+    let Ok(raw) = fs::read_to_string(&config_path) else {
+        return Ok(None);
+    };
+    serde_json::from_str(&raw)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{config_path}: {e}")))
+}
+
+/// Parse an age such as `90d` or `12h` into nanoseconds.
+pub fn parse_age(s: &str) -> io::Result<u128> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid age '{s}' (expected a number followed by s, m, h, d or w)"),
+        )
+    };
+    let split = s.len().checked_sub(1).ok_or_else(invalid)?;
+    let (n, unit) = s.split_at(split);
+    let secs: u128 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => return Err(invalid()),
+    };
+    let n: u128 = n.parse().map_err(|_| invalid())?;
+    Ok(n * secs * 1_000_000_000)
+}
+
+/// Archive cube receiving records moved out of `cube` on the local date of `now_ns`.
+pub fn archive_path(cube: &Path, now_ns: u128) -> PathBuf {
+    let secs = i64::try_from(now_ns / 1_000_000_000).unwrap_or(i64::MAX);
+    let date = chrono::DateTime::from_timestamp(secs, 0)
+        .unwrap_or_default()
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d");
+    let stem = cube.file_stem().and_then(|s| s.to_str()).unwrap_or("cube");
+    cube.with_file_name(format!("{stem}.archive-{date}.cube"))
+}
+
+/// Split `events` of a cube of `version` into (kept, moved) according to `policy`, preserving
+/// on-disk order.
+fn select(
+    events: Vec<Event>,
+    version: u16,
+    policy: &Policy,
+    now_ns: u128,
+) -> io::Result<(Vec<Event>, Vec<Event>)> {
+    let mut moved = vec![false; events.len()];
+    let mut latest = vec![false; events.len()];
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    // Walk newest first so version counts start at the latest record.
+    for (i, ev) in events.iter().enumerate().rev() {
+        let versions = seen.entry(paths::canonical(&ev.phenomenon)).or_default();
+        *versions += 1;
+        latest[i] = *versions == 1;
+        if policy
+            .max_versions
+            .is_some_and(|max| *versions > max.max(1))
+        {
+            moved[i] = true;
+        }
+    }
+    if let Some(age) = &policy.max_age {
+        let cutoff = now_ns.saturating_sub(parse_age(age)?);
+        for (i, ev) in events.iter().enumerate() {
+            if !latest[i] && ev.timestamp < cutoff {
+                moved[i] = true;
+            }
+        }
+    }
+    if let Some(max_bytes) = policy.max_bytes {
+        let mut size: u64 = Writer::header_len(version)
+            + events
+                .iter()
+                .zip(&moved)
+                .filter(|(_, m)| !**m)
+                .map(|(ev, _)| Writer::record_len(version, ev))
+                .sum::<u64>();
+        for (i, ev) in events.iter().enumerate() {
+            if size <= max_bytes {
+                break;
+            }
+            if !latest[i] && !moved[i] {
+                moved[i] = true;
+                size -= Writer::record_len(version, ev);
+            }
+        }
+    }
+    let (mut kept, mut out) = (Vec::new(), Vec::new());
+    for (ev, m) in events.into_iter().zip(moved) {
+        if m { out.push(ev) } else { kept.push(ev) }
+    }
+    Ok((kept, out))
+}
+
+/// Enforce `policy` on the cube at `cube`, moving records into the dated archive cube.
+///
+/// With `dry_run`, only computes the report. Records after a corrupted region are not readable
+/// and would be dropped by compaction, so such cubes are refused.
+pub fn rotate(cube: &str, policy: &Policy, now_ns: u128, dry_run: bool) -> io::Result<Report> {
+    let path = Path::new(cube);
+    let mut f = File::options().read(true).write(true).open(path)?;
+    f.lock()?;
+    let result = rotate_locked(path, &mut f, policy, now_ns, dry_run);
+    f.unlock()?;
+    result
+}
+
+fn rotate_locked(
+    path: &Path,
+    f: &mut File,
+    policy: &Policy,
+    now_ns: u128,
+    dry_run: bool,
+) -> io::Result<Report> {
+    let mut bytes = Vec::new();
+    f.read_to_end(&mut bytes)?;
+    let events = Writer::decode(&bytes)?;
+    let version = Writer::image_version(&bytes)?;
+    let valid = Writer::header_len(version)
+        + events
+            .iter()
+            .map(|e| Writer::record_len(version, e))
+            .sum::<u64>();
+    if valid != bytes.len() as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: {} trailing bytes are not valid records; refusing to compact",
+                path.display(),
+                bytes.len() as u64 - valid
+            ),
+        ));
+    }
+    let next_id = events.iter().map(|e| e.id + 1).max().unwrap_or(1);
+    let (mut kept, mut moved) = select(events, version, policy, now_ns)?;
+    Writer::detach_refs(&mut kept, &mut moved);
+
+    let mut report = Report {
+        kept: kept.len(),
+        archived: moved.len(),
+        bytes_before: bytes.len() as u64,
+        bytes_after: Writer::header_len(version)
+            + kept
+                .iter()
+                .map(|e| Writer::record_len(version, e))
+                .sum::<u64>(),
+        archive: None,
+    };
+    if moved.is_empty() || dry_run {
+        return Ok(report);
+    }
+
+    // Archive first (merged with any earlier rotation of the day), then compact the cube.
+    let archive = archive_path(path, now_ns);
+    append_to_archive(&archive, moved)?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".rotate-tmp");
+    let tmp = PathBuf::from(tmp);
+    // The recovery image carries the identity the compacted cube is about to get.
+    let identity = Writer::image_identity(&bytes)?.map(|i| i.bumped());
+    Writer::write_image(&mut File::create(&tmp)?, &kept, next_id, identity.as_ref())?;
+    Writer::write_image(f, &kept, next_id, identity.as_ref())?;
+    checkpoint::remove(path)?;
+    fs::remove_file(&tmp)?;
+
+    report.archive = Some(archive);
+    Ok(report)
+}
+
+/// Add `moved` to the archive cube at `archive`, creating it if needed.
+///
+/// Existing records are rewritten byte for byte, so only the new tail actually changes on disk.
+fn append_to_archive(archive: &Path, moved: Vec<Event>) -> io::Result<()> {
+    let mut f = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(archive)?;
+    f.lock()?;
+    let result = (|| {
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes)?;
+        let (mut archived, identity) = if bytes.is_empty() {
+            (Vec::new(), None)
+        } else {
+            (Writer::decode(&bytes)?, Writer::image_identity(&bytes)?)
+        };
+        archived.extend(moved);
+        let next_id = archived.iter().map(|e| e.id + 1).max().unwrap_or(1);
+        // Records are only added: the archive stays at its generation.
+        Writer::write_image(&mut f, &archived, next_id, identity.as_ref())
+    })();
+    f.unlock()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_keeps_latest_versions() {
+        let dir = std::env::temp_dir().join(format!("akasha-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("notes.cube");
+        let cube = cube.to_str().unwrap();
+        {
+            let mut w = Writer::create(cube).unwrap();
+            for i in 0..3 {
+                w.append("a.txt", &format!("a{i}")).unwrap();
+            }
+            w.append("b.txt", "b0").unwrap();
+        }
+        let policy = Policy {
+            max_versions: Some(1),
+            ..Policy::default()
+        };
+        let mut open_writer = Writer::create(cube).unwrap();
+        let report = rotate(cube, &policy, 0, false).unwrap();
+        assert_eq!((report.kept, report.archived), (2, 2));
+
+        // A writer opened before the rotation appends to the compacted cube.
+        open_writer.append("c.txt", "c0").unwrap();
+        drop(open_writer);
+        let mut w = Writer::create(cube).unwrap();
+        let ids: Vec<u64> = w.events().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [3, 4, 5]);
+        assert_eq!(w.next_id(), 6);
+        let archive = fs::read(report.archive.unwrap()).unwrap();
+        let ids: Vec<u64> = Writer::decode(&archive)
+            .unwrap()
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(parse_age("2h").unwrap(), 7_200_000_000_000);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+//! Schemas of the JSON noumena of metadata records.
+//!
+//! Most readers of the history pick the fields they need out of loosely parsed JSON, so that old
+//! records never stop a command. This registry is the strict counterpart: every known kind of
+//! record decodes into its typed payload, and fields the payload does not know are errors.
+//!
+//! Commit records are versioned by their `v` field (`Commit::VERSION`). Records sealed before it
+//! existed are version 1: their fields are all optional and their timestamp may be a string or
+//! in nanoseconds; they are migrated to the current version when decoded.
+//!
+//! `eikyu cube read --decode` prints records through `decode`.
+
+use crate::data::branch::{self, DEFAULT_BRANCH};
+use crate::data::changelog::{RELEASE, Release};
+use crate::data::commit::{self, SUPERSEDED, Superseded};
+use crate::data::fork::{FORK_FROM, FORK_TO, Link};
+use crate::data::manifest::Manifest;
+use crate::data::note::{NOTE, Note};
+use crate::data::prune::{self, PRUNE};
+use crate::data::quota::{QUOTA, Refusal};
+use crate::data::reflog::{self, REFLOG};
+use crate::data::rename::{REMOVE, RENAME, Removal, Rename};
+use crate::data::sign::{SIGNATURE, Signature};
+use crate::data::stash::{DROP, Dropped, STASH, Stash};
+use crate::data::tag::Tag;
+use crate::event::Event;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+
+/// Noumenon of a `commit` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Commit {
+    /// Schema version, `Commit::VERSION` when sealed by this version.
+    pub v: u32,
+    pub id: u64,
+    pub parent: Option<u64>,
+    /// Branch the commit was sealed on.
+    pub branch: String,
+    /// Record id of the commit's manifest (see `data::manifest`), `None` for commits sealed
+    /// before manifests.
+    pub manifest: Option<u64>,
+    /// Commit undone by this one, for `ak revert`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<u64>,
+    /// Commit replaced by this one, for `ak seal --amend`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amends: Option<u64>,
+    /// Commit whose changes this one re-applies, for `ak cherry-pick`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub picked_from: Option<u64>,
+    /// Tip of the branch merged by this commit, its second parent (`ak merge`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge_parent: Option<u64>,
+    /// Git commit this one was imported from (`ak import-git`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    pub ty: String,
+    pub summary: String,
+    pub body: String,
+    pub author: String,
+    pub author_email: String,
+    /// Milliseconds since Unix epoch (UTC).
+    pub timestamp: u64,
+}
+
+impl Commit {
+    /// Version of the commits sealed now.
+    pub const VERSION: u32 = 2;
+}
+
+/// Version 1 commit: anything goes.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CommitV1 {
+    id: Option<u64>,
+    parent: Option<u64>,
+    branch: Option<String>,
+    manifest: Option<u64>,
+    reverts: Option<u64>,
+    amends: Option<u64>,
+    picked_from: Option<u64>,
+    merge_parent: Option<u64>,
+    git_commit: Option<String>,
+    ty: Option<String>,
+    summary: Option<String>,
+    body: Option<String>,
+    author: Option<String>,
+    author_email: Option<String>,
+}
+
+/// The commit of record `ev`, migrated to the current version.
+pub fn decode_commit(ev: &Event) -> io::Result<Commit> {
+    let v: Value = serde_json::from_str(&ev.noumenon).map_err(invalid)?;
+    match v.get("v").map(|x| x.as_u64()) {
+        None => {
+            let timestamp = commit::timestamp_ms(&v).unwrap_or(0).max(0) as u64;
+            let old: CommitV1 = serde_json::from_value(v).map_err(invalid)?;
+            Ok(Commit {
+                v: Commit::VERSION,
+                id: old.id.unwrap_or(ev.id),
+                parent: old.parent,
+                branch: old.branch.unwrap_or_else(|| DEFAULT_BRANCH.to_string()),
+                manifest: old.manifest,
+                reverts: old.reverts,
+                amends: old.amends,
+                picked_from: old.picked_from,
+                merge_parent: old.merge_parent,
+                git_commit: old.git_commit,
+                ty: old.ty.unwrap_or_default(),
+                summary: old.summary.unwrap_or_default(),
+                body: old.body.unwrap_or_default(),
+                author: old.author.unwrap_or_default(),
+                author_email: old.author_email.unwrap_or_default(),
+                timestamp,
+            })
+        }
+        Some(Some(n)) if n == u64::from(Commit::VERSION) => strict(v),
+        Some(other) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported commit version {}",
+                other.map_or("?".to_string(), |n| n.to_string())
+            ),
+        )),
+    }
+}
+
+/// A known kind of record with a JSON noumenon.
+pub struct Schema {
+    /// Phenomenon of the records, or its prefix when it ends with `:` (`branch:<name>`).
+    pub phenomenon: &'static str,
+    /// Typed payload of the records, decoded and serialized back.
+    decode: fn(&Event) -> io::Result<Value>,
+}
+
+/// Every known kind of record. `commit:pending` records hold the plain message of a seal in
+/// progress and have no schema.
+pub static SCHEMAS: [Schema; 17] = [
+    Schema {
+        phenomenon: "commit",
+        decode: |ev| serde_json::to_value(decode_commit(ev)?).map_err(io::Error::other),
+    },
+    Schema {
+        phenomenon: SUPERSEDED,
+        decode: |ev| typed::<Superseded>(ev),
+    },
+    Schema {
+        phenomenon: SIGNATURE,
+        decode: |ev| typed::<Signature>(ev),
+    },
+    Schema {
+        phenomenon: NOTE,
+        decode: |ev| typed::<Note>(ev),
+    },
+    Schema {
+        phenomenon: RELEASE,
+        decode: |ev| typed::<Release>(ev),
+    },
+    Schema {
+        phenomenon: RENAME,
+        decode: |ev| typed::<Rename>(ev),
+    },
+    Schema {
+        phenomenon: REMOVE,
+        decode: |ev| typed::<Removal>(ev),
+    },
+    Schema {
+        phenomenon: REFLOG,
+        decode: |ev| typed::<reflog::Entry>(ev),
+    },
+    Schema {
+        phenomenon: STASH,
+        decode: |ev| typed::<Stash>(ev),
+    },
+    Schema {
+        phenomenon: DROP,
+        decode: |ev| typed::<Dropped>(ev),
+    },
+    Schema {
+        phenomenon: PRUNE,
+        decode: |ev| typed::<prune::Entry>(ev),
+    },
+    Schema {
+        phenomenon: QUOTA,
+        decode: |ev| typed::<Refusal>(ev),
+    },
+    Schema {
+        phenomenon: FORK_FROM,
+        decode: |ev| typed::<Link>(ev),
+    },
+    Schema {
+        phenomenon: FORK_TO,
+        decode: |ev| typed::<Link>(ev),
+    },
+    Schema {
+        phenomenon: "branch:",
+        decode: |ev| typed::<branch::Head>(ev),
+    },
+    Schema {
+        phenomenon: "tag:",
+        decode: |ev| typed::<Tag>(ev),
+    },
+    Schema {
+        phenomenon: "manifest:",
+        decode: |ev| typed::<Manifest>(ev),
+    },
+];
+
+/// Schema of the records with `phenomenon`, if known.
+pub fn schema(phenomenon: &str) -> Option<&'static Schema> {
+    SCHEMAS
+        .iter()
+        .find(|s| match s.phenomenon.strip_suffix(':') {
+            Some(_) => phenomenon.starts_with(s.phenomenon),
+            None => phenomenon == s.phenomenon,
+        })
+}
+
+/// Typed payload of `ev` as JSON, `None` for records of no known kind (file contents, …).
+pub fn decode(ev: &Event) -> Option<io::Result<Value>> {
+    schema(&ev.phenomenon).map(|s| (s.decode)(ev))
+}
+
+/// `ev` decoded as a `T` and serialized back.
+fn typed<T: DeserializeOwned + Serialize>(ev: &Event) -> io::Result<Value> {
+    let v: Value = serde_json::from_str(&ev.noumenon).map_err(invalid)?;
+    serde_json::to_value(strict::<T>(v)?).map_err(io::Error::other)
+}
+
+/// `v` as a `T`, failing on fields `T` does not know. Null fields left out of `T`'s
+/// serialization are fine.
+fn strict<T: DeserializeOwned + Serialize>(v: Value) -> io::Result<T> {
+    let t: T = serde_json::from_value(v.clone()).map_err(invalid)?;
+    let back = serde_json::to_value(&t).map_err(io::Error::other)?;
+    if let (Value::Object(fields), Value::Object(known)) = (&v, &back)
+        && let Some((key, _)) = fields
+            .iter()
+            .find(|(k, x)| !x.is_null() && !known.contains_key(*k))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown field `{key}`"),
+        ));
+    }
+    Ok(t)
+}
+
+fn invalid(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_and_migrate() {
+        let ev = |id: u64, ph: &str, no: &str| {
+            Event::builder()
+                .id(id)
+                .phenomenon(ph)
+                .noumenon(no)
+                .build()
+                .unwrap()
+        };
+
+        let old = decode_commit(&ev(
+            5,
+            "commit",
+            r#"{"parent":3,"summary":"first","timestamp":"1700000000000000000"}"#,
+        ))
+        .unwrap();
+        assert_eq!((old.v, old.id, old.parent), (Commit::VERSION, 5, Some(3)));
+        assert_eq!(
+            (old.branch.as_str(), old.timestamp),
+            ("main", 1_700_000_000_000)
+        );
+
+        let mut current = old.clone();
+        current.manifest = Some(4);
+        let json = serde_json::to_string(&current).unwrap();
+        assert_eq!(decode_commit(&ev(9, "commit", &json)).unwrap(), current);
+        let extra = json.replacen('{', r#"{"colour":"blue","#, 1);
+        assert!(decode_commit(&ev(9, "commit", &extra)).is_err());
+        let future = json.replacen(r#""v":2"#, r#""v":3"#, 1);
+        assert!(decode_commit(&ev(9, "commit", &future)).is_err());
+
+        let head = decode(&ev(1, "branch:topic", r#"{"head":null}"#)).unwrap();
+        assert_eq!(head.unwrap(), serde_json::json!({"head": null}));
+        assert!(
+            decode(&ev(1, "tag:v1", r#"{"commit":2,"by":1}"#))
+                .unwrap()
+                .is_err()
+        );
+        assert!(decode(&ev(1, "src/a.rs", "{}")).is_none());
+        assert!(decode(&ev(1, "commit:pending", "wip")).is_none());
+    }
+}
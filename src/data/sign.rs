@@ -0,0 +1,224 @@
+//! Signed seals.
+//!
+//! `ak seal --sign` signs the commit record (its exact JSON noumenon) with the user's SSH key,
+//! ed25519 or any other type `ssh-keygen` supports, in the `akasha` namespace:
+//!
+//! ```text
+//! ssh-keygen -Y sign -f <user.signingkey> -n akasha
+//! ```
+//!
+//! The signature is stored next to the commit as a `commit:signature` record
+//! `{"commit": <id>, "signature": "-----BEGIN SSH SIGNATURE-----…"}`. A signature is checked
+//! against the repository's allowed signers (`.eikyu/allowed_signers` unless
+//! `[signing] allowed-signers` says otherwise, in the `ssh-keygen` ALLOWED SIGNERS format), with
+//! the commit's `author_email` as principal. Without an entry for the author a valid signature
+//! is only reported as unverified. With `[signing] required = true`, `ak verify` fails on every
+//! commit that is not signed by an allowed signer.
+
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Phenomenon of signature records.
+pub const SIGNATURE: &str = "commit:signature";
+
+/// `ssh-keygen` namespace of seal signatures.
+pub const NAMESPACE: &str = "akasha";
+
+/// Noumenon of a `commit:signature` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Signature {
+    pub commit: u64,
+    /// Armored SSH signature of the commit record.
+    pub signature: String,
+}
+
+/// Outcome of checking a commit's signature.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    Unsigned,
+    /// Valid, by an allowed signer for the author.
+    Good,
+    /// Valid, but the key is not an allowed signer for the author.
+    Unverified,
+    /// Not a valid signature of the commit.
+    Bad,
+}
+
+impl Verification {
+    /// Short description for listings.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Verification::Unsigned => "unsigned",
+            Verification::Good => "good signature",
+            Verification::Unverified => "unverified signature",
+            Verification::Bad => "bad signature",
+        }
+    }
+}
+
+/// Signature of every signed commit, by commit id (the latest record wins).
+pub fn signatures(events: &[Event]) -> HashMap<u64, String> {
+    events
+        .iter()
+        .filter(|e| e.phenomenon == SIGNATURE)
+        .filter_map(|e| serde_json::from_str::<Signature>(&e.noumenon).ok())
+        .map(|s| (s.commit, s.signature))
+        .collect()
+}
+
+/// Store `signature` of commit `commit`.
+pub fn record(w: &mut Writer, commit: u64, signature: String) -> io::Result<()> {
+    let json = serde_json::to_string(&Signature { commit, signature }).map_err(io::Error::other)?;
+    w.append(SIGNATURE, &json).map(|_| ())
+}
+
+/// Run `ssh-keygen` with `args`, feeding it `input`.
+fn ssh_keygen(args: &[&str], input: &str) -> io::Result<std::process::Output> {
+    let mut child = Command::new("ssh-keygen")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::other(format!("failed to spawn 'ssh-keygen': {e}")))?;
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(input.as_bytes())?;
+    child.wait_with_output()
+}
+
+/// Sign `payload` with the SSH key at `key` (a private key, or a public key held by the agent).
+pub fn sign(key: &Path, payload: &str) -> io::Result<String> {
+    let key = key.to_str().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "signing key path is not UTF-8")
+    })?;
+    let out = ssh_keygen(&["-q", "-Y", "sign", "-f", key, "-n", NAMESPACE], payload)?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!(
+            "signing failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    String::from_utf8(out.stdout).map_err(io::Error::other)
+}
+
+/// A file holding `signature` for `ssh-keygen -s`, removed on drop.
+struct SignatureFile(PathBuf);
+
+impl SignatureFile {
+    fn new(signature: &str) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "akasha-sig-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, signature)?;
+        Ok(SignatureFile(path))
+    }
+}
+
+impl Drop for SignatureFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Check `signature` of `payload` for `principal` (the author's email) against the allowed
+/// signers file `allowed`, when it exists.
+pub fn verify(
+    payload: &str,
+    signature: &str,
+    principal: &str,
+    allowed: &Path,
+) -> io::Result<Verification> {
+    let file = SignatureFile::new(signature)?;
+    let sig = file.0.to_str().expect("temp paths are UTF-8");
+    if allowed.exists() {
+        let allowed = allowed.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "allowed signers path is not UTF-8",
+            )
+        })?;
+        let args = [
+            "-Y", "verify", "-f", allowed, "-I", principal, "-n", NAMESPACE, "-s", sig,
+        ];
+        if ssh_keygen(&args, payload)?.status.success() {
+            return Ok(Verification::Good);
+        }
+    }
+    let args = ["-Y", "check-novalidate", "-n", NAMESPACE, "-s", sig];
+    Ok(if ssh_keygen(&args, payload)?.status.success() {
+        Verification::Unverified
+    } else {
+        Verification::Bad
+    })
+}
+
+/// Check the signature of commit record `commit` given the `signatures` of its cube.
+pub fn verify_commit(
+    commit: &Event,
+    signatures: &HashMap<u64, String>,
+    allowed: &Path,
+) -> io::Result<Verification> {
+    let v: serde_json::Value = serde_json::from_str(&commit.noumenon).unwrap_or_default();
+    let id = v.get("id").and_then(|x| x.as_u64()).unwrap_or(commit.id);
+    let Some(signature) = signatures.get(&id) else {
+        return Ok(Verification::Unsigned);
+    };
+    let email = v.get("author_email").and_then(|x| x.as_str()).unwrap_or("");
+    verify(&commit.noumenon, signature, email, allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = std::env::temp_dir().join(format!("akasha-sign-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let key = dir.join("id_ed25519");
+        let generated = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "ana", "-f"])
+            .arg(&key)
+            .status();
+        if !generated.is_ok_and(|s| s.success()) {
+            // No ssh-keygen on this machine.
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let payload = r#"{"id":3,"author_email":"ana@example.com"}"#;
+        let signature = sign(&key, payload).unwrap();
+        let allowed = dir.join("allowed_signers");
+        let check =
+            |payload: &str| verify(payload, &signature, "ana@example.com", &allowed).unwrap();
+        assert_eq!(check(payload), Verification::Unverified);
+        assert_eq!(check(r#"{"id":4}"#), Verification::Bad);
+
+        let public = fs::read_to_string(key.with_extension("pub")).unwrap();
+        fs::write(
+            &allowed,
+            format!("ana@example.com namespaces=\"akasha\" {public}"),
+        )
+        .unwrap();
+        assert_eq!(check(payload), Verification::Good);
+        assert_eq!(
+            verify(payload, &signature, "bob@example.com", &allowed).unwrap(),
+            Verification::Unverified
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Stashes: unsealed changes put aside by `ak stash` and brought back by `ak stash pop`.
+//!
+//! A stash is a `stash:push` record whose noumenon holds the branch and commit it was taken on and
+//! the content of every changed file (`null` for a deletion):
+//!
+//! ```json
+//! { "branch": "main", "base": 12, "message": "wip", "files": { "src/main.rs": "…" } }
+//! ```
+//!
+//! Popping a stash writes a `stash:drop` record `{"stash": <record id>}`; the stash itself stays
+//! in the cube. Live stashes form a stack, the latest on top.
+
+use crate::data::diff::Tree;
+use crate::data::merge::Binary;
+use crate::data::pick::{self, Picked};
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Phenomenon of stash records. Like every metadata record, it contains a `:` so that it is
+/// never taken for file contents (see `restore::is_file_record`).
+pub const STASH: &str = "stash:push";
+
+/// Phenomenon of the records dropping a stash.
+pub const DROP: &str = "stash:drop";
+
+/// Noumenon of a `stash:push` record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Stash {
+    /// Branch the changes were made on.
+    pub branch: String,
+    /// Head commit of that branch when stashing, `None` before the first seal.
+    pub base: Option<u64>,
+    pub message: String,
+    /// Content of every changed file, `None` for a deletion.
+    pub files: BTreeMap<String, Option<String>>,
+}
+
+/// Noumenon of a `stash:drop` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Dropped {
+    /// Record id of the dropped stash.
+    pub stash: u64,
+}
+
+/// Live stashes by record id, oldest first.
+pub fn stashes(events: &[Event]) -> Vec<(u64, Stash)> {
+    let mut out: Vec<(u64, Stash)> = Vec::new();
+    for ev in events {
+        match ev.phenomenon.as_str() {
+            STASH => {
+                if let Ok(stash) = serde_json::from_str(&ev.noumenon) {
+                    out.push((ev.id, stash));
+                }
+            }
+            DROP => {
+                if let Ok(Dropped { stash }) = serde_json::from_str(&ev.noumenon) {
+                    out.retain(|(id, _)| *id != stash);
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Record `stash`, returning its record id.
+pub fn push(w: &mut Writer, stash: &Stash) -> io::Result<u64> {
+    let json = serde_json::to_string(stash).map_err(io::Error::other)?;
+    w.append_record(STASH, &json).map(|a| a.id)
+}
+
+/// Drop stash `id`.
+pub fn drop(w: &mut Writer, id: u64) -> io::Result<()> {
+    let json = serde_json::to_string(&Dropped { stash: id }).map_err(io::Error::other)?;
+    w.append(DROP, &json).map(|_| ())
+}
+
+/// Replay `stash` onto `onto`, given `base`, the tree of the commit it was taken on. Files
+/// changed since then on both sides are merged; those that don't merge are conflicts.
+pub fn unstash(stash: &Stash, base: &Tree, onto: &Tree) -> Picked {
+    let mut stashed = base.clone();
+    for (path, content) in &stash.files {
+        match content {
+            Some(text) => stashed.insert(path.clone(), text.as_bytes().to_vec()),
+            None => stashed.remove(path),
+        };
+    }
+    pick::pick(base, &stashed, onto, Binary::Conflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_drop_and_unstash() {
+        let path = std::env::temp_dir().join(format!("akasha-stash-{}.cube", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+
+        let stash = |message: &str| Stash {
+            branch: "main".into(),
+            base: Some(1),
+            message: message.into(),
+            files: BTreeMap::from([("a".into(), Some("2".into())), ("b".into(), None)]),
+        };
+        let first = push(&mut w, &stash("one")).unwrap();
+        push(&mut w, &stash("two")).unwrap();
+        drop(&mut w, first).unwrap();
+        let live = stashes(&w.events().unwrap());
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].1.message, "two");
+
+        let tree = |files: &[(&str, &str)]| -> Tree {
+            files
+                .iter()
+                .map(|(p, c)| (p.to_string(), c.as_bytes().to_vec()))
+                .collect()
+        };
+        let base = tree(&[("a", "1"), ("b", "1")]);
+        let picked = unstash(&live[0].1, &base, &base);
+        assert!(picked.conflicts.is_empty());
+        assert_eq!(
+            picked.changes,
+            [
+                ("a".to_string(), Some(b"2".to_vec())),
+                ("b".to_string(), None)
+            ]
+        );
+        let picked = unstash(&live[0].1, &base, &tree(&[("a", "3"), ("b", "1")]));
+        assert_eq!(picked.conflicts, ["a"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,197 @@
+//! Repository statistics: `ak stats`.
+//!
+//! Everything is computed from the cube records: commits of every author (see
+//! `history::commits`), the files each of them changed compared to its parent, and the sizes of
+//! the cubes against the working tree they describe.
+
+use crate::data::diff::collect_files;
+use crate::data::manifest::{self, Manifest};
+use crate::data::{branch, commit, config, history, paths, restore};
+use crate::event::Event;
+use chrono::DateTime;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Default, PartialEq)]
 pub struct Stats {
     pub commits: usize,
+    pub by_author: BTreeMap<String, usize>,
+    pub by_type: BTreeMap<String, usize>,
+    /// Commits per ISO week (`2025-W33`).
+    pub by_week: BTreeMap<String, usize>,
+    /// Number of commits that changed each file, most changed first.
+    pub changed_files: Vec<(String, usize)>,
+    /// Files and bytes of the current branch head.
+    pub tracked_files: usize,
+    pub tracked_bytes: u64,
+    /// On-disk size of every cube.
+    pub cube_bytes: u64,
+    /// Size of the working directory (ignored files and `.eikyu` excluded).
+    pub working_bytes: u64,
+    /// Mean time between two consecutive seals, in milliseconds.
+    pub mean_interval_ms: Option<i64>,
+}
+
+/// Hash of every file of commit `id`: from its manifest, or from its contents for commits sealed
+/// before manifests.
+fn fingerprints(events: &[Event], id: u64) -> io::Result<BTreeMap<String, String>> {
+    match Manifest::of_commit(events, id) {
+        Some(m) => Ok(m.files.into_iter().map(|(p, e)| (p, e.hash)).collect()),
+        None => Ok(restore::tree_at(events, id)?
+            .into_iter()
+            .map(|(p, c)| (p, manifest::hash(c)))
+            .collect()),
+    }
+}
+
+/// Count, into `counts`, the files every commit of `events` changed compared to its first
+/// parent. Commits whose files can't be read (lost to a retention policy) are skipped.
+fn count_changes(events: &[Event], counts: &mut HashMap<String, usize>) {
+    let mut cache: HashMap<u64, BTreeMap<String, String>> = HashMap::new();
+    let mut files = |id: u64| -> Option<BTreeMap<String, String>> {
+        if let Entry::Vacant(slot) = cache.entry(id) {
+            slot.insert(fingerprints(events, id).ok()?);
+        }
+        cache.get(&id).cloned()
+    };
+    for ev in commit::history(events) {
+        let id = commit::commit_id(ev);
+        let parent = serde_json::from_str::<serde_json::Value>(&ev.noumenon)
+            .ok()
+            .and_then(|v| v.get("parent")?.as_u64());
+        let Some(after) = files(id) else {
+            continue;
+        };
+        let before = match parent {
+            Some(p) => match files(p) {
+                Some(before) => before,
+                None => continue,
+            },
+            None => BTreeMap::new(),
+        };
+        for (path, hash) in &after {
+            if before.get(path) != Some(hash) {
+                *counts.entry(path.clone()).or_default() += 1;
+            }
+        }
+        for path in before.keys().filter(|p| !after.contains_key(*p)) {
+            *counts.entry(path.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Statistics of the repository at `root`; the tracked size is that of `author`'s current
+/// branch head.
+pub fn compute(root: &Path, author: &str) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    let mut times = Vec::new();
+    for c in history::commits(root)? {
+        let v: serde_json::Value = serde_json::from_str(&c.event.noumenon).unwrap_or_default();
+        stats.commits += 1;
+        *stats.by_author.entry(c.author).or_default() += 1;
+        let ty = v.get("ty").and_then(|x| x.as_str()).unwrap_or("commit");
+        *stats.by_type.entry(ty.to_string()).or_default() += 1;
+        if let Some(ms) = commit::timestamp_ms(&v) {
+            times.push(ms);
+            if let Some(t) = DateTime::from_timestamp_millis(ms) {
+                let week = t.format("%G-W%V").to_string();
+                *stats.by_week.entry(week).or_default() += 1;
+            }
+        }
+    }
+    times.sort();
+    if times.len() > 1 {
+        stats.mean_interval_ms =
+            Some((times[times.len() - 1] - times[0]) / (times.len() - 1) as i64);
+    }
+
+    // With the shared layout every author reads the same cube: count it once.
+    let mut counts = HashMap::new();
+    let shared = config::Config::load(root)?.storage.layout == config::Layout::Shared;
+    let authors = if shared {
+        vec![author.to_string()]
+    } else {
+        history::authors(root)?
+    };
+    for name in &authors {
+        count_changes(&history::author_events(root, name)?, &mut counts);
+    }
+    stats.changed_files = counts.into_iter().collect();
+    stats
+        .changed_files
+        .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let events = history::author_events(root, author)?;
+    let head = branch::heads(&events)
+        .get(&branch::current(root, author))
+        .copied()
+        .flatten();
+    if let Some(head) = head {
+        let files = restore::tree_at(&events, head)?;
+        stats.tracked_files = files.len();
+        stats.tracked_bytes = files.values().map(|c| c.len() as u64).sum();
+    }
+
+    let mut cubes: Vec<_> = history::cube_files(root)?
+        .into_iter()
+        .map(|c| c.path)
+        .collect();
+    cubes.push(config::shared_cube(root));
+    for cube in cubes {
+        if let Ok(meta) = fs::metadata(&cube) {
+            stats.cube_bytes += meta.len();
+        }
+    }
+    for rel in collect_files(root)? {
+        if let Ok(meta) = fs::metadata(paths::long(&root.join(&rel))) {
+            stats.working_bytes += meta.len();
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::index::Index;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_count_changes_against_parents() {
+        let dir = std::env::temp_dir().join(format!("akasha-stats-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let mut manifest = Manifest::default();
+        let mut seal = |id: u64, parent: Option<u64>, files: &[(&str, Option<&str>)]| {
+            let mut index = Index::default();
+            for (path, content) in files {
+                index.stage_content(path, content.map(str::to_string));
+            }
+            manifest.apply_index(&mut w, &index).unwrap();
+            let m = manifest.record(&mut w, id).unwrap();
+            let parent = parent.map_or("null".to_string(), |p| p.to_string());
+            w.append(
+                "commit",
+                &format!(r#"{{"id":{id},"manifest":{m},"parent":{parent}}}"#),
+            )
+            .unwrap();
+        };
+        seal(10, None, &[("a", Some("1")), ("b", Some("1"))]);
+        seal(20, Some(10), &[("a", Some("2")), ("b", Some("1"))]);
+        seal(30, Some(20), &[("a", Some("3")), ("b", None)]);
+
+        let mut counts = HashMap::new();
+        count_changes(&w.events().unwrap(), &mut counts);
+        assert_eq!(
+            counts,
+            HashMap::from([("a".to_string(), 3), ("b".to_string(), 2)])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,203 @@
+//! Working directory status relative to the last sealed reference tree.
+//!
+//! Where `diff` prints the content of every change, `status` only classifies paths: added
+//! (present in the working directory only, i.e. untracked), modified (content differs) and
+//! deleted (present in the reference tree only). Paths are relative to the repository root and
+//! enumerated with the same ignore rules as `diff`.
+
+use crate::data::diff::collect_files;
+use crate::data::index::Index;
+use crate::data::paths;
+use crate::data::rename;
+use crate::data::tree::Reference;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{MAIN_SEPARATOR_STR, Path};
+
+/// How a path changed since the last seal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Change {
+    /// One-letter code used by the compact and porcelain listings.
+    pub fn code(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+        }
+    }
+}
+
+/// Changed paths, sorted by path.
+#[derive(Debug, Default)]
+pub struct Status {
+    pub entries: Vec<(Change, String)>,
+    /// Deletions and additions paired by a staged rename, as `(from, to)`.
+    pub renamed: Vec<(String, String)>,
+}
+
+impl Status {
+    /// Number of entries of the given kind.
+    pub fn count(&self, change: Change) -> usize {
+        self.entries.iter().filter(|(c, _)| *c == change).count()
+    }
+
+    /// Paths that are not part of the reference tree.
+    pub fn untracked(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(|(c, _)| *c == Change::Added)
+            .map(|(_, p)| p.as_str())
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty() && self.renamed.is_empty()
+    }
+
+    /// Report the deletion of `from` and the addition of `to` as a rename, for every `renames`
+    /// entry `to -> from` (portable paths).
+    pub fn pair_renames(&mut self, renames: &BTreeMap<String, String>) {
+        let portable = |p: &str| p.replace(MAIN_SEPARATOR_STR, "/");
+        for (to, from) in renames {
+            let find = |change: Change, path: &str| {
+                self.entries
+                    .iter()
+                    .position(|(c, p)| *c == change && portable(p) == path)
+            };
+            let (Some(d), Some(a)) = (find(Change::Deleted, from), find(Change::Added, to)) else {
+                continue;
+            };
+            let pair = (self.entries[d].1.clone(), self.entries[a].1.clone());
+            self.entries.retain(|(_, p)| *p != pair.0 && *p != pair.1);
+            self.renamed.push(pair);
+        }
+    }
+
+    /// Report as renames the deletions and additions whose contents match (see
+    /// `rename::detect`): the deleted file as sealed in `reference`, the added one below `root`.
+    pub fn detect_renames(&mut self, root: &Path, reference: &Reference) -> io::Result<()> {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for (change, path) in &self.entries {
+            let portable = path.replace(MAIN_SEPARATOR_STR, "/");
+            match change {
+                Change::Deleted => {
+                    removed.push((portable, reference.read(path)?.unwrap_or_default()));
+                }
+                Change::Added => added.push((portable, fs::read(paths::long(&root.join(path)))?)),
+                Change::Modified => {}
+            }
+        }
+        fn view(files: &[(String, Vec<u8>)]) -> Vec<(&str, &[u8])> {
+            files
+                .iter()
+                .map(|(p, c)| (p.as_str(), c.as_slice()))
+                .collect()
+        }
+        let renames = rename::detect(&view(&removed), &view(&added));
+        self.pair_renames(&renames);
+        Ok(())
+    }
+}
+
+/// Fail unless `author` has no staged change and the working directory at `root` matches the
+/// reference tree of their current branch: commands that rewrite the working directory would
+/// lose such changes otherwise.
+pub fn require_clean(root: &Path, author: &str) -> io::Result<()> {
+    let staged = !Index::load(root, author)?.is_empty();
+    if staged || !status(root, &Reference::open(root, author)?)?.is_clean() {
+        return Err(io::Error::other(
+            "the working directory has unsealed changes; seal them first",
+        ));
+    }
+    Ok(())
+}
+
+/// Compare the working directory at `root` with the sealed state `reference`.
+///
+/// A missing reference tree (nothing sealed yet) makes every file an addition.
+pub fn status(root: &Path, reference: &Reference) -> io::Result<Status> {
+    let work: BTreeSet<String> = collect_files(root)?.into_iter().collect();
+    let tree = reference.files()?;
+
+    let mut entries = Vec::new();
+    for path in work.union(&tree) {
+        let change = match (work.contains(path), tree.contains(path)) {
+            (true, false) => Change::Added,
+            (false, true) => Change::Deleted,
+            _ => {
+                let a = fs::read(paths::long(&root.join(path)))?;
+                if Some(a) == reference.read(path)? {
+                    continue;
+                }
+                Change::Modified
+            }
+        };
+        entries.push((change, path.clone()));
+    }
+    Ok(Status {
+        entries,
+        renamed: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_classifies_changes() {
+        let dir = std::env::temp_dir().join(format!("akasha-status-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (work, tree) = (dir.join("work"), dir.join("tree"));
+        fs::create_dir_all(work.join("src")).unwrap();
+        fs::create_dir_all(tree.join("src")).unwrap();
+        fs::write(work.join("same.txt"), "x").unwrap();
+        fs::write(tree.join("same.txt"), "x").unwrap();
+        fs::write(work.join("src").join("lib.rs"), "new").unwrap();
+        fs::write(tree.join("src").join("lib.rs"), "old").unwrap();
+        fs::write(work.join("added.txt"), "a").unwrap();
+        fs::write(tree.join("gone.txt"), "g").unwrap();
+
+        let st = status(&work, &Reference::Dir(tree.clone())).unwrap();
+        let lib = Path::new("src").join("lib.rs").display().to_string();
+        assert_eq!(
+            st.entries,
+            [
+                (Change::Added, "added.txt".to_string()),
+                (Change::Deleted, "gone.txt".to_string()),
+                (Change::Modified, lib),
+            ]
+        );
+        assert_eq!(st.untracked().collect::<Vec<_>>(), ["added.txt"]);
+        assert_eq!(
+            (
+                st.count(Change::Added),
+                st.count(Change::Modified),
+                st.count(Change::Deleted)
+            ),
+            (1, 1, 1)
+        );
+
+        let st = status(&work, &Reference::Dir(dir.join("missing"))).unwrap();
+        assert_eq!(st.count(Change::Added), 3);
+
+        // The same state read back from the cube.
+        let sealed = Reference::Sealed(crate::data::diff::read_tree(&tree).unwrap());
+        let mut st = status(&work, &sealed).unwrap();
+        assert_eq!(st.entries.len(), 3);
+        fs::write(work.join("added.txt"), "g").unwrap();
+        st.detect_renames(&work, &sealed).unwrap();
+        assert_eq!(
+            st.renamed,
+            [("gone.txt".to_string(), "added.txt".to_string())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
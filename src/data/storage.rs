@@ -0,0 +1,79 @@
+//! Storage tuning for high-throughput ingestion.
+//!
+//! Settings are read per cube from a JSON sidecar named `<cube>.io.json`:
+//!
+//! ```json
+//! { "preallocate": 67108864, "bulk_buffer": 33554432 }
+//! ```
+//!
+//! - `preallocate`: reserve disk space ahead of the end of the cube in chunks of this many bytes,
+//!   so bulk imports don't grow the file block by block. On Linux this uses
+//!   `fallocate(FALLOC_FL_KEEP_SIZE)`: the file length (and thus the record log) is unchanged,
+//!   only the blocks behind it are reserved. Elsewhere it is a no-op.
+//! - `bulk_buffer`: bytes buffered in bulk mode before they are written out (default 8 MiB).
+//!
+//! Both are plain performance knobs: a cube written with any settings reads back identically.
+
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io;
+
+/// Default bulk buffer size.
+pub const DEFAULT_BULK_BUFFER: usize = 8 * 1024 * 1024;
+
+/// Storage settings of a cube.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IoConfig {
+    /// Pre-allocation chunk in bytes (0 disables pre-allocation).
+    pub preallocate: u64,
+    /// Bulk-mode buffer size in bytes.
+    pub bulk_buffer: usize,
+}
+
+impl Default for IoConfig {
+    fn default() -> Self {
+        Self {
+            preallocate: 0,
+            bulk_buffer: DEFAULT_BULK_BUFFER,
+        }
+    }
+}
+
+/// Load the storage settings configured for the cube at `cube_path`, or the defaults.
+pub fn load_for_cube(cube_path: &str) -> io::Result<IoConfig> {
+    let config_path = format!("{cube_path}.io.json");
+    let Ok(raw) = fs::read_to_string(&config_path) else {
+        return Ok(IoConfig::default());
+    };
+    serde_json::from_str(&raw)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{config_path}: {e}")))
+}
+
+/// Reserve `len` bytes of disk space starting at `offset` without changing the file length.
+///
+/// Filesystems without support for it (`EOPNOTSUPP`) are silently skipped.
+#[cfg(target_os = "linux")]
+pub fn preallocate(f: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let (Ok(offset), Ok(len)) = (i64::try_from(offset), i64::try_from(len)) else {
+        return Ok(());
+    };
+    // SAFETY: plain syscall on a file descriptor we own for the duration of the call.
+    let rc = unsafe { libc::fallocate(f.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, offset, len) };
+    if rc == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// Reserve disk space ahead of the cube (not supported on this platform: no-op).
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(f: &File, offset: u64, len: u64) -> io::Result<()> {
+    let _ = (f, offset, len);
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Tags: names for commits.
+//!
+//! Tags live in the cube as `tag:<name>` records whose noumenon is `{"commit": <id>}`, or
+//! `{"commit": null}` once deleted; the latest record of a name wins. A tag can be used anywhere
+//! `ak` accepts a commit id.
+
+use crate::data::branch;
+use crate::data::write::Writer;
+use crate::event::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Noumenon of a `tag:<name>` record.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Tag {
+    /// Tagged commit, `None` when the tag was deleted.
+    pub commit: Option<u64>,
+}
+
+/// Phenomenon of the records of tag `name`.
+pub fn phenomenon(name: &str) -> String {
+    format!("tag:{name}")
+}
+
+/// Every live tag and the commit it names.
+pub fn tags(events: &[Event]) -> BTreeMap<String, u64> {
+    let mut out = BTreeMap::new();
+    for ev in events {
+        let Some(name) = ev.phenomenon.strip_prefix("tag:") else {
+            continue;
+        };
+        match serde_json::from_str::<Tag>(&ev.noumenon) {
+            Ok(Tag { commit: Some(id) }) => {
+                out.insert(name.to_string(), id);
+            }
+            Ok(Tag { commit: None }) => {
+                out.remove(name);
+            }
+            Err(_) => {}
+        }
+    }
+    out
+}
+
+fn write(w: &mut Writer, name: &str, commit: Option<u64>) -> io::Result<u64> {
+    let json = serde_json::to_string(&Tag { commit }).map_err(io::Error::other)?;
+    w.append(&phenomenon(name), &json)
+}
+
+/// Tag commit `commit` as `name`. Existing tags (found in `events`) are only moved with `force`.
+pub fn create(
+    w: &mut Writer,
+    events: &[Event],
+    name: &str,
+    commit: u64,
+    force: bool,
+) -> io::Result<()> {
+    branch::check_name("tag", name)?;
+    if !force && tags(events).contains_key(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("tag '{name}' already exists"),
+        ));
+    }
+    write(w, name, Some(commit)).map(|_| ())
+}
+
+/// Delete tag `name`, which must exist in `events`.
+pub fn delete(w: &mut Writer, events: &[Event], name: &str) -> io::Result<()> {
+    if !tags(events).contains_key(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no tag named '{name}'"),
+        ));
+    }
+    write(w, name, None).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_move_and_delete() {
+        let path = std::env::temp_dir().join(format!("akasha-tag-{}.cube", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+
+        let mut tag = |name: &str, commit: u64, force: bool| {
+            let events = w.events().unwrap();
+            create(&mut w, &events, name, commit, force)
+        };
+        tag("v1", 3, false).unwrap();
+        assert!(tag("v1", 5, false).is_err());
+        tag("v1", 5, true).unwrap();
+        tag("v2", 7, false).unwrap();
+        assert!(tag("bad name", 7, false).is_err());
+        assert_eq!(tags(&w.events().unwrap())["v1"], 5);
+
+        let events = w.events().unwrap();
+        delete(&mut w, &events, "v1").unwrap();
+        let events = w.events().unwrap();
+        assert!(delete(&mut w, &events, "v1").is_err());
+        let all = tags(&w.events().unwrap());
+        assert_eq!(all.keys().collect::<Vec<_>>(), ["v2"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
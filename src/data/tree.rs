@@ -1,71 +1,561 @@
+use crate::data::config::{Config, Snapshot};
+use crate::data::diff::{Tree, collect_files, read_tree};
+use crate::data::manifest::{self, Manifest};
+use crate::data::status::Change;
+use crate::data::write::Writer;
+use crate::data::{akignore, branch, commit, history, paths, restore};
+use crate::event::Event;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env::current_dir;
 use std::fs;
 use std::io;
-use std::path::MAIN_SEPARATOR_STR;
+use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
 
-/// Update or recreate the on-disk snapshot tree for the given `author`.
+/// Location of `author`'s reference tree (the state of the last seal) on their current branch.
+pub fn tree_dir(root: &Path, author: &str) -> PathBuf {
+    branch_tree_dir(root, author, &branch::current(root, author))
+}
+
+/// Location of `author`'s reference tree for `branch` under `root`: `.eikyu/tree/<author>` for
+/// the default branch, `.eikyu/tree/<author>@<branch>` for the others.
+pub fn branch_tree_dir(root: &Path, author: &str, branch: &str) -> PathBuf {
+    let name = if branch == branch::DEFAULT_BRANCH {
+        author.to_string()
+    } else {
+        format!("{author}@{branch}")
+    };
+    root.join(format!(
+        ".eikyu{MAIN_SEPARATOR_STR}tree{MAIN_SEPARATOR_STR}{name}"
+    ))
+}
+
+/// The sealed state the working directory is compared with.
+pub enum Reference {
+    /// A reference tree on disk.
+    Dir(PathBuf),
+    /// The files of the branch head, read back from its manifest in the cube, by portable path:
+    /// `snapshot = "cube"` keeps no reference tree.
+    Sealed(Tree),
+}
+
+impl Reference {
+    /// `author`'s sealed state on their current branch (see `of_branch`). The cubes are only read
+    /// without a reference tree.
+    pub fn open(root: &Path, author: &str) -> io::Result<Reference> {
+        let dir = tree_dir(root, author);
+        if dir.exists() {
+            return Ok(Reference::Dir(dir));
+        }
+        let events = history::author_events(root, author)?;
+        Reference::of_branch(root, author, &branch::current(root, author), &events)
+    }
+
+    /// `author`'s sealed state on `branch`: its reference tree if there is one, else the files of
+    /// the head of `branch` in `events` (none before the first seal).
+    pub fn of_branch(
+        root: &Path,
+        author: &str,
+        branch: &str,
+        events: &[Event],
+    ) -> io::Result<Reference> {
+        let dir = branch_tree_dir(root, author, branch);
+        if dir.exists() {
+            return Ok(Reference::Dir(dir));
+        }
+        let files = match branch::heads(events).get(branch).copied().flatten() {
+            Some(head) => restore::tree_at(events, head)?
+                .into_iter()
+                .map(|(path, content)| (path, content.as_bytes().to_vec()))
+                .collect(),
+            None => Tree::new(),
+        };
+        Ok(Reference::Sealed(files))
+    }
+
+    /// Relative paths of the sealed files, with native separators.
+    pub fn files(&self) -> io::Result<BTreeSet<String>> {
+        match self {
+            Reference::Dir(dir) if dir.exists() => Ok(collect_files(dir)?.into_iter().collect()),
+            Reference::Dir(_) => Ok(BTreeSet::new()),
+            Reference::Sealed(files) => Ok(files
+                .keys()
+                .map(|p| p.replace('/', MAIN_SEPARATOR_STR))
+                .collect()),
+        }
+    }
+
+    /// Sealed content of the relative `path` (either separator), `None` if it isn't sealed.
+    pub fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Reference::Dir(dir) => {
+                let native = dir.join(path.replace('/', MAIN_SEPARATOR_STR));
+                match fs::read(paths::long(&native)) {
+                    Ok(bytes) => Ok(Some(bytes)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            Reference::Sealed(files) => {
+                Ok(files.get(&path.replace(MAIN_SEPARATOR_STR, "/")).cloned())
+            }
+        }
+    }
+
+    /// Write the sealed file at the relative `path` to `dest`: from a reference tree with its
+    /// permissions and modification time (see `place`), from the cube as bytes.
+    pub fn checkout(&self, path: &str, dest: &Path) -> io::Result<()> {
+        match self {
+            Reference::Dir(dir) => place(
+                &dir.join(path.replace('/', MAIN_SEPARATOR_STR)),
+                dest,
+                Snapshot::Copy,
+            ),
+            Reference::Sealed(_) => {
+                fs::write(paths::long(dest), self.read(path)?.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Every sealed file, by portable path.
+    pub fn tree(&self) -> io::Result<Tree> {
+        match self {
+            Reference::Dir(dir) if dir.exists() => read_tree(dir),
+            Reference::Dir(_) => Ok(Tree::new()),
+            Reference::Sealed(files) => Ok(files.clone()),
+        }
+    }
+}
+
+/// Location of the snapshot `author` keeps of commit `id`: `.eikyu/snapshots/<author>/<id>`.
+pub fn snapshot_dir(root: &Path, author: &str, id: u64) -> PathBuf {
+    snapshots_root(root, author).join(id.to_string())
+}
+
+fn snapshots_root(root: &Path, author: &str) -> PathBuf {
+    root.join(".eikyu").join("snapshots").join(author)
+}
+
+/// Commits `author` keeps a snapshot of, oldest first.
+pub fn snapshots(root: &Path, author: &str) -> io::Result<Vec<u64>> {
+    let entries = match fs::read_dir(snapshots_root(root, author)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        if let Some(id) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Keep a snapshot of commit `id`, just sealed by `author`, when `[storage] keep-snapshots` asks
+/// for any, then drop the oldest snapshots past that number. The files are placed from the
+/// reference tree the `[storage] snapshot` way, or written from the cube without one.
+pub fn record_snapshot(root: &Path, author: &str, id: u64) -> io::Result<()> {
+    let storage = Config::load(root)?.storage;
+    if storage.keep_snapshots > 0 {
+        let dest = snapshot_dir(root, author, id);
+        let reference = tree_dir(root, author);
+        if reference.exists() {
+            copy_dir(&reference, &dest, storage.snapshot)?;
+        } else {
+            let events = history::author_events(root, author)?;
+            fs::create_dir_all(paths::long(&dest))?;
+            restore::restore(&events, id, &[], &dest)?;
+        }
+    }
+    prune_snapshots(root, author, storage.keep_snapshots).map(|_| ())
+}
+
+/// Remove `author`'s snapshots but the `keep` newest. Returns the commits whose snapshot went.
+pub fn prune_snapshots(root: &Path, author: &str, keep: usize) -> io::Result<Vec<u64>> {
+    let ids = snapshots(root, author)?;
+    let gone = ids[..ids.len().saturating_sub(keep)].to_vec();
+    for id in &gone {
+        fs::remove_dir_all(paths::long(&snapshot_dir(root, author, *id)))?;
+    }
+    Ok(gone)
+}
+
+/// Disk usage of a reference tree or snapshot (see `usage`).
+#[derive(Debug, Default, PartialEq)]
+pub struct Usage {
+    pub dir: PathBuf,
+    pub files: usize,
+    /// Apparent size of the files.
+    pub bytes: u64,
+    /// Part of `bytes` in files hard-linked elsewhere (the working directory, another tree),
+    /// which takes no space of its own. Copy-on-write clones share space too, unseen.
+    pub linked: u64,
+}
+
+/// Subdirectories of `dir`, sorted; none when `dir` doesn't exist.
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut out = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            out.push(entry.path());
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Every reference tree (`.eikyu/tree/*`) and snapshot (`.eikyu/snapshots/<author>/*`) under
+/// `root`.
+fn stored_trees(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut out = subdirs(&root.join(".eikyu").join("tree"))?;
+    for author in subdirs(&root.join(".eikyu").join("snapshots"))? {
+        let mut snapshots = subdirs(&author)?;
+        snapshots.sort_by_key(|d| d.file_name()?.to_str()?.parse::<u64>().ok());
+        out.extend(snapshots);
+    }
+    Ok(out)
+}
+
+/// Disk usage of every reference tree and snapshot under `root`.
+pub fn usage(root: &Path) -> io::Result<Vec<Usage>> {
+    let mut out = Vec::new();
+    for dir in stored_trees(root)? {
+        let mut usage = Usage {
+            dir: dir.clone(),
+            ..Usage::default()
+        };
+        for entry in ignore::WalkBuilder::new(&dir)
+            .standard_filters(false)
+            .build()
+        {
+            let entry = entry.map_err(io::Error::other)?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let meta = fs::symlink_metadata(paths::long(entry.path()))?;
+            usage.files += 1;
+            usage.bytes += meta.len();
+            if hard_linked(&meta) {
+                usage.linked += meta.len();
+            }
+        }
+        out.push(usage);
+    }
+    Ok(out)
+}
+
+#[cfg(unix)]
+fn hard_linked(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+/// Hard links (not told apart from plain files on this platform: always `false`).
+#[cfg(not(unix))]
+fn hard_linked(meta: &fs::Metadata) -> bool {
+    let _ = meta;
+    false
+}
+
+/// Reference trees and snapshots under `root` that nothing reads anymore:
+/// - every reference tree with `snapshot = "cube"`, the sealed state being read from the cube;
+/// - the reference trees of branches the cubes don't know, other than an author's current one;
+/// - the snapshots of commits the cubes no longer hold (e.g. amended, then collected by `ak gc`)
+///   and those past the `keep-snapshots` newest of their author.
+pub fn stale(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let storage = Config::load(root)?.storage;
+    let name = |dir: &Path| {
+        dir.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let trees: Vec<(PathBuf, String)> = subdirs(&root.join(".eikyu").join("tree"))?
+        .into_iter()
+        .map(|dir| {
+            let name = name(&dir);
+            (dir, name)
+        })
+        .collect();
+    let snapshot_dirs = subdirs(&root.join(".eikyu").join("snapshots"))?;
+    let authors: BTreeSet<String> = trees
+        .iter()
+        .map(|(_, n)| n.split('@').next().unwrap_or_default().to_string())
+        .chain(snapshot_dirs.iter().map(|d| name(d)))
+        .collect();
+    let mut events: HashMap<String, Vec<Event>> = HashMap::new();
+    for author in authors {
+        let found = history::author_events(root, &author)?;
+        events.insert(author, found);
+    }
+
+    let mut out = Vec::new();
+    for (dir, name) in trees {
+        let (author, name) = name
+            .split_once('@')
+            .unwrap_or((name.as_str(), branch::DEFAULT_BRANCH));
+        let known = name == branch::DEFAULT_BRANCH
+            || name == branch::current(root, author)
+            || branch::heads(&events[author]).contains_key(name);
+        if storage.snapshot == Snapshot::Cube || !known {
+            out.push(dir);
+        }
+    }
+    for dir in snapshot_dirs {
+        let author = name(&dir);
+        let live: Vec<u64> = snapshots(root, &author)?
+            .into_iter()
+            .filter(|id| commit::find(&events[&author], *id).is_some())
+            .collect();
+        let kept = &live[live.len().saturating_sub(storage.keep_snapshots)..];
+        for snapshot in subdirs(&dir)? {
+            let id = snapshot.file_name().and_then(|n| n.to_str()?.parse().ok());
+            if !id.is_some_and(|id| kept.contains(&id)) {
+                out.push(snapshot);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Files of commit `id` of `events`, by portable path: from `author`'s snapshot of it when one is
+/// kept, else from the cube.
+pub fn commit_files(root: &Path, author: &str, events: &[Event], id: u64) -> io::Result<Tree> {
+    let snapshot = snapshot_dir(root, author, id);
+    if snapshot.exists() {
+        return read_tree(&snapshot);
+    }
+    Ok(restore::tree_at(events, id)?
+        .into_iter()
+        .map(|(path, content)| (path, content.as_bytes().to_vec()))
+        .collect())
+}
+
+/// A file of a stored tree that differs from the commit it was derived from.
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// `M` (other content), `D` (missing from the tree) or `A` (not sealed), as in `ak status`.
+    pub change: char,
+    /// Portable path.
+    pub path: String,
+}
+
+/// Check the stored tree at `dir` (a reference tree or a snapshot) against commit `id` of
+/// `events`: every file must hash to what the manifest of the commit recorded (to its content
+/// record for commits sealed before manifests) and nothing else may be there. Sorted by path.
+///
+/// Only the hashes of the manifest are needed, so this works on commits whose file versions
+/// `ak prune` archived; `ak verify` checks the manifests against the records.
+pub fn verify(dir: &Path, events: &[Event], id: u64) -> io::Result<Vec<Mismatch>> {
+    let sealed: BTreeMap<String, String> = match Manifest::of_commit(events, id) {
+        Some(m) => m.files.into_iter().map(|(p, e)| (p, e.hash)).collect(),
+        None => restore::tree_at(events, id)?
+            .into_iter()
+            .map(|(p, content)| (p, manifest::hash(content)))
+            .collect(),
+    };
+    let stored = read_tree(dir)?;
+    let mut out = Vec::new();
+    for path in sealed.keys().chain(stored.keys()).collect::<BTreeSet<_>>() {
+        let change = match (sealed.get(path), stored.get(path)) {
+            (Some(_), None) => Change::Deleted,
+            (None, _) => Change::Added,
+            (Some(hash), Some(bytes)) => {
+                if *hash == blake3::hash(bytes).to_hex().as_str() {
+                    continue;
+                }
+                Change::Modified
+            }
+        };
+        out.push(Mismatch {
+            change: change.code(),
+            path: path.clone(),
+        });
+    }
+    Ok(out)
+}
+
+/// Copy every file below `src` to the same relative location below `dst`, ignore rules aside,
+/// placed the `how` way (see `place`).
+pub fn copy_dir(src: &Path, dst: &Path, how: Snapshot) -> io::Result<()> {
+    fs::create_dir_all(paths::long(dst))?;
+    for entry in ignore::WalkBuilder::new(src)
+        .standard_filters(false)
+        .build()
+    {
+        let entry = entry.map_err(io::Error::other)?;
+        let path = entry.path();
+        if !entry
+            .file_type()
+            .is_some_and(|t| t.is_file() || t.is_symlink())
+        {
+            continue;
+        }
+        if let Ok(rel) = path.strip_prefix(src) {
+            let dest = dst.join(rel);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(paths::long(parent))?;
+            }
+            place(path, &dest, how)?;
+        }
+    }
+    Ok(())
+}
+
+/// Put the file `src` at `dest` the `how` way, falling back to a byte copy, with its permissions
+/// and modification time; a symbolic link is recreated as a link to the same target. Whatever
+/// was at `dest` is removed first: writing through a hard link would change `src` as well.
+pub fn place(src: &Path, dest: &Path, how: Snapshot) -> io::Result<()> {
+    let (src, dest) = (paths::long(src), paths::long(dest));
+    match fs::remove_file(&dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let meta = fs::symlink_metadata(&src)?;
+    if meta.file_type().is_symlink() {
+        return symlink(&src, &dest);
+    }
+    if how != Snapshot::Copy && reflink(&src, &dest)? {
+        fs::set_permissions(&dest, meta.permissions())?;
+        return copy_mtime(&meta, &dest);
+    }
+    if how == Snapshot::Hardlink && fs::hard_link(&src, &dest).is_ok() {
+        return Ok(());
+    }
+    // Permissions come along with the bytes.
+    fs::copy(&src, &dest)?;
+    copy_mtime(&meta, &dest)
+}
+
+/// Give `dest` the modification time of the file `meta` describes. Read-only files too: only
+/// their attributes are written.
+fn copy_mtime(meta: &fs::Metadata, dest: &Path) -> io::Result<()> {
+    let Ok(modified) = meta.modified() else {
+        return Ok(());
+    };
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_WRITE_ATTRIBUTES
+        fs::File::options().access_mode(0x100).open(dest)?
+    };
+    #[cfg(not(windows))]
+    let file = fs::File::open(dest)?;
+    file.set_modified(modified)
+}
+
+/// Create at `dest` a symbolic link to the target of the link `src`.
+#[cfg(unix)]
+fn symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dest)
+}
+
+/// Create at `dest` a symbolic link to the target of the link `src`, a directory link when `src`
+/// leads to a directory.
+#[cfg(windows)]
+fn symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    if src.is_dir() {
+        std::os::windows::fs::symlink_dir(target, dest)
+    } else {
+        std::os::windows::fs::symlink_file(target, dest)
+    }
+}
+
+/// Clone `src` to the new file `dest` with the `FICLONE` ioctl. `Ok(false)` (and no `dest`)
+/// when the filesystem can't, e.g. ext4 or `dest` on another filesystem.
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dest: &Path) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+    let from = fs::File::open(src)?;
+    let to = fs::File::create_new(dest)?;
+    // SAFETY: plain syscall on two file descriptors we own for the duration of the call.
+    let rc = unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) };
+    if rc == 0 {
+        return Ok(true);
+    }
+    drop(to);
+    fs::remove_file(dest)?;
+    Ok(false)
+}
+
+/// Copy-on-write clones (not supported on this platform: always `Ok(false)`).
+#[cfg(not(target_os = "linux"))]
+fn reflink(src: &Path, dest: &Path) -> io::Result<bool> {
+    let _ = (src, dest);
+    Ok(false)
+}
+
+/// Update or create the on-disk snapshot tree for the given `author`.
 ///
 /// Overview:
-/// - The snapshot tree is stored under `.eikyu/tree/{author}` relative to the current working directory.
-/// - The existing tree (if present) is removed entirely and then rebuilt from the current repository contents.
-/// - File enumeration respects standard ignore rules via the `ignore` crate (e.g., `.gitignore`, `.ignore`).
+/// - The snapshot tree is stored under `.eikyu/tree/{author}` relative to the current working directory
+///   (`.eikyu/tree/{author}@{branch}` off the default branch, see `branch_tree_dir`).
+/// - The snapshot is brought in line with the current repository contents by `sync_tree`, its
+///   files placed the `[storage] snapshot` way of the repository configuration.
+/// - File enumeration respects the ignore rules of `data::akignore` (`.gitignore`, `.ignore`, `.akignore`).
+/// - With `snapshot = "cube"` the snapshot tree is removed instead: the sealed state is read back
+///   from the cube (see `Reference`).
+///
+/// Example:
+/// - Given current dir `/repo` and `author="alice"`, the snapshot root will be `/repo/.eikyu/tree/alice`.
+pub fn update_tree(author: &str) -> io::Result<()> {
+    let root = current_dir()?;
+    let tree_dir = tree_dir(&root, author);
+    let how = Config::load(&root)?.storage.snapshot;
+    if how == Snapshot::Cube {
+        return match fs::remove_dir_all(paths::long(&tree_dir)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    sync_tree(&root, &tree_dir, how)
+}
+
+/// Mirror the files of the working directory `root` into the snapshot `tree_dir`, incrementally.
 ///
 /// Behavior and guarantees:
-/// - Destructive refresh: the target tree directory is deleted and recreated to mirror the current state.
+/// - Files of the snapshot that are no longer in the working directory are removed, along with the
+///   directories they leave empty.
+/// - A file is placed (see `place`) only when it is new or changed: a snapshot copy of the same
+///   size written after the file was last modified is trusted, otherwise the contents are
+///   compared.
 /// - Only regular files are copied; directories are created on demand to preserve structure.
-/// - Paths are replicated relative to the working directory, preserving hierarchy.
+/// - Files a seal leaves out are left out too: those too large for a record (see
+///   `Writer::too_large`), and anything in `.eikyu` or `.git` (see `akignore::INTERNAL`).
 /// - Best-effort traversal: errors from walker entries are logged to stderr without aborting the whole operation.
 /// - Returns `Ok(())` on success; propagates I/O errors for critical operations (remove, create, copy).
 ///
 /// Notes:
-/// - Permissions and timestamps are not preserved; this is a content mirroring step focused on bytes and structure.
-/// - Symbolic links are followed according to the default behavior of `ignore::WalkBuilder`.
-///   If your use case requires preserving symlinks as symlinks, handle them explicitly.
-/// - Large repositories: this operation is O(number_of_files) and copies bytes once per file.
-///   Consider incremental strategies if performance becomes a concern.
-/// - Internal state: callers may want to ensure `.eikyu/` itself is ignored when building the tree to avoid recursion.
-///
-/// Errors:
-/// - Returns early if removal/creation of the snapshot root fails.
-/// - Individual file copy failures cause an early return for that file; traversal continues for other entries.
-///
-/// Example:
-/// - Given current dir `/repo` and `author="alice"`, the snapshot root will be `/repo/.eikyu/tree/alice`.
-pub fn update_tree(author: &str) -> io::Result<()> {
-    let root = current_dir()?;
-    let tree_dir = root.join(format!(
-        ".eikyu{MAIN_SEPARATOR_STR}tree{MAIN_SEPARATOR_STR}{author}"
-    ));
+/// - Permissions and modification times are preserved, and symbolic links are recreated as links
+///   rather than followed (see `place`).
+/// - Internal state: the walker never enters `.eikyu/`, so the snapshot never contains itself,
+///   even when `tree_dir` lies elsewhere.
+pub fn sync_tree(root: &Path, tree_dir: &Path, how: Snapshot) -> io::Result<()> {
+    fs::create_dir_all(paths::long(tree_dir))?;
 
-    // 1) Ensure a clean destination: remove any previous snapshot then recreate the root directory.
-    if tree_dir.exists() {
-        fs::remove_dir_all(&tree_dir)?;
-    }
-    fs::create_dir_all(&tree_dir)?;
-
-    // 2) Walk the current working directory and mirror files into the snapshot tree.
-    //    `ignore::WalkBuilder` respects .gitignore and .ignore files to avoid copying undesired entries.
-    for result in ignore::WalkBuilder::new(&root).build() {
+    // 1) Walk the working directory. The walker applies .gitignore, .ignore and .akignore files
+    //    to skip undesired entries.
+    let mut files: HashSet<PathBuf> = HashSet::new();
+    for result in akignore::walker(root).build() {
         match result {
             Ok(entry) => {
                 let path = entry.path();
-
-                // Skip directories; only mirror regular files.
-                if path.is_dir() {
+                // Skip directories; only mirror regular files a seal would record, and links
+                // (not followed) as links.
+                let link = entry.file_type().is_some_and(|t| t.is_symlink());
+                if !link && (path.is_dir() || Writer::too_large(path)) {
                     continue;
                 }
-
-                // Compute the relative path with respect to `root` to preserve structure.
-                if let Ok(relative_path) = path.strip_prefix(&root) {
-                    let dest_path = tree_dir.join(relative_path);
-
-                    // Ensure parent directories exist before copying the file.
-                    if let Some(parent) = dest_path.parent() {
-                        fs::create_dir_all(parent)?;
-                    }
-
-                    // Copy file bytes to the destination. Overwrites any existing file at the location.
-                    fs::copy(path, &dest_path)?;
+                if let Ok(relative_path) = path.strip_prefix(root) {
+                    files.insert(relative_path.to_path_buf());
                 }
             }
             // Non-fatal: log walker errors and continue. This avoids failing the whole operation for a single entry.
@@ -73,5 +563,187 @@ pub fn update_tree(author: &str) -> io::Result<()> {
         }
     }
 
+    // 2) Remove what the working directory no longer has, first, so that a file replaced by a
+    //    directory of the same name (or the reverse) makes way.
+    let mut dirs = Vec::new();
+    for entry in ignore::WalkBuilder::new(tree_dir)
+        .standard_filters(false)
+        .build()
+    {
+        let entry = entry.map_err(io::Error::other)?;
+        let Ok(rel) = entry.path().strip_prefix(tree_dir) else {
+            continue;
+        };
+        if entry.file_type().is_some_and(|t| t.is_dir()) {
+            dirs.push(entry.path().to_path_buf());
+        } else if !files.contains(rel) {
+            fs::remove_file(paths::long(entry.path()))?;
+        }
+    }
+    // Deepest first; directories that still hold files stay.
+    for dir in dirs.iter().rev().filter(|d| d.as_path() != tree_dir) {
+        let _ = fs::remove_dir(paths::long(dir));
+    }
+
+    // 3) Place the new and changed files. Long-path forms keep deep trees working on Windows.
+    for relative_path in &files {
+        let (src, dest) = (root.join(relative_path), tree_dir.join(relative_path));
+        if unchanged(&src, &dest)? {
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(paths::long(parent))?;
+        }
+        place(&src, &dest, how)?;
+    }
     Ok(())
 }
+
+/// Whether the snapshot copy `dest` already holds the content, permissions or link target of
+/// `src`.
+fn unchanged(src: &Path, dest: &Path) -> io::Result<bool> {
+    let (src, dest) = (paths::long(src), paths::long(dest));
+    let Ok(copy) = fs::symlink_metadata(&dest) else {
+        return Ok(false);
+    };
+    let file = fs::symlink_metadata(&src)?;
+    if file.file_type().is_symlink() || copy.file_type().is_symlink() {
+        return Ok(
+            file.file_type() == copy.file_type() && fs::read_link(&src)? == fs::read_link(&dest)?
+        );
+    }
+    if !copy.is_file() || copy.len() != file.len() || copy.permissions() != file.permissions() {
+        return Ok(false);
+    }
+    // Copies carry the time of their file; older snapshots were written after it.
+    if let (Ok(copied), Ok(modified)) = (copy.modified(), file.modified())
+        && copied >= modified
+    {
+        return Ok(true);
+    }
+    Ok(fs::read(&src)? == fs::read(&dest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_tree() {
+        let dir = std::env::temp_dir().join(format!("akasha-tree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (work, snapshot) = (dir.join("work"), dir.join("snapshot"));
+        fs::create_dir_all(work.join("sub")).unwrap();
+        fs::write(work.join("a.txt"), "a").unwrap();
+        fs::write(work.join("sub/b.txt"), "b").unwrap();
+        fs::write(work.join("c"), "c").unwrap();
+        fs::write(work.join("big"), "x".repeat(Writer::MAX_FIELD_LEN + 1)).unwrap();
+        sync_tree(&work, &snapshot, Snapshot::Reflink).unwrap();
+        assert_eq!(fs::read_to_string(snapshot.join("sub/b.txt")).unwrap(), "b");
+        assert!(!snapshot.join("big").exists());
+
+        // Same size, new content; a directory replaced by a file and a file by a directory.
+        fs::write(work.join("a.txt"), "A").unwrap();
+        fs::remove_dir_all(work.join("sub")).unwrap();
+        fs::write(work.join("sub"), "now a file").unwrap();
+        fs::remove_file(work.join("c")).unwrap();
+        fs::create_dir_all(work.join("c")).unwrap();
+        fs::write(work.join("c/d.txt"), "d").unwrap();
+        sync_tree(&work, &snapshot, Snapshot::Copy).unwrap();
+        let read = |p: &str| fs::read_to_string(snapshot.join(p)).unwrap();
+        assert_eq!(read("a.txt"), "A");
+        assert_eq!(read("sub"), "now a file");
+        assert_eq!(read("c/d.txt"), "d");
+
+        // Placing over a hard link leaves the linked file alone.
+        place(&work.join("sub"), &snapshot.join("sub"), Snapshot::Hardlink).unwrap();
+        place(&work.join("a.txt"), &snapshot.join("sub"), Snapshot::Copy).unwrap();
+        assert_eq!(read("sub"), "A");
+        assert_eq!(fs::read_to_string(work.join("sub")).unwrap(), "now a file");
+
+        // Modes, times and links survive the trip.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{PermissionsExt, symlink};
+            fs::set_permissions(work.join("a.txt"), fs::Permissions::from_mode(0o755)).unwrap();
+            fs::set_permissions(work.join("c/d.txt"), fs::Permissions::from_mode(0o444)).unwrap();
+            symlink("a.txt", work.join("link")).unwrap();
+            sync_tree(&work, &snapshot, Snapshot::Copy).unwrap();
+            let meta = |p: &Path| fs::symlink_metadata(p).unwrap();
+            for p in ["a.txt", "c/d.txt"] {
+                let (file, copy) = (meta(&work.join(p)), meta(&snapshot.join(p)));
+                assert_eq!(file.permissions().mode(), copy.permissions().mode());
+                assert_eq!(file.modified().unwrap(), copy.modified().unwrap());
+            }
+            assert!(meta(&snapshot.join("link")).file_type().is_symlink());
+            assert_eq!(
+                fs::read_link(snapshot.join("link")).unwrap(),
+                Path::new("a.txt")
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots_and_pruning() {
+        let dir = std::env::temp_dir().join(format!("akasha-snapshots-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut w =
+            crate::data::write::Writer::create(dir.join("u.cube").to_str().unwrap()).unwrap();
+        w.append("a.txt", "sealed").unwrap();
+        w.append("commit", r#"{"id":3}"#).unwrap();
+        let events = w.events().unwrap();
+        assert_eq!(
+            commit_files(&dir, "u", &events, 3).unwrap()["a.txt"],
+            b"sealed"
+        );
+
+        for id in [3, 12, 7] {
+            fs::create_dir_all(snapshot_dir(&dir, "u", id)).unwrap();
+        }
+        fs::write(snapshot_dir(&dir, "u", 3).join("a.txt"), "kept").unwrap();
+        assert_eq!(snapshots(&dir, "u").unwrap(), [3, 7, 12]);
+        assert_eq!(
+            commit_files(&dir, "u", &events, 3).unwrap()["a.txt"],
+            b"kept"
+        );
+
+        // The snapshot of #3 no longer holds what was sealed.
+        let check = |id| -> Vec<String> {
+            verify(&snapshot_dir(&dir, "u", id), &events, id)
+                .unwrap()
+                .iter()
+                .map(|m| format!("{} {}", m.change, m.path))
+                .collect()
+        };
+        assert_eq!(check(3), ["M a.txt"]);
+        fs::write(snapshot_dir(&dir, "u", 3).join("a.txt"), "sealed").unwrap();
+        assert!(check(3).is_empty());
+        fs::write(snapshot_dir(&dir, "u", 3).join("b.txt"), "b").unwrap();
+        fs::remove_file(snapshot_dir(&dir, "u", 3).join("a.txt")).unwrap();
+        assert_eq!(check(3), ["D a.txt", "A b.txt"]);
+
+        assert_eq!(prune_snapshots(&dir, "u", 2).unwrap(), [3]);
+        assert_eq!(prune_snapshots(&dir, "u", 5).unwrap(), Vec::<u64>::new());
+        assert_eq!(snapshots(&dir, "u").unwrap(), [7, 12]);
+        assert!(snapshots(&dir, "v").unwrap().is_empty());
+
+        // Without `keep-snapshots` every snapshot is stale, as is the tree of an unknown branch.
+        fs::create_dir_all(tree_dir(&dir, "u").join("sub")).unwrap();
+        fs::write(tree_dir(&dir, "u").join("sub").join("a.txt"), "abc").unwrap();
+        fs::create_dir_all(branch_tree_dir(&dir, "u", "gone")).unwrap();
+        assert_eq!(
+            stale(&dir).unwrap(),
+            [
+                branch_tree_dir(&dir, "u", "gone"),
+                snapshot_dir(&dir, "u", 12),
+                snapshot_dir(&dir, "u", 7),
+            ]
+        );
+        let usage = usage(&dir).unwrap();
+        assert_eq!(usage.len(), 4);
+        assert_eq!((usage[0].files, usage[0].bytes), (1, 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+//! Atomic appends across several cubes.
+//!
+//! A [`Transaction`] stages records for several writers and commits them so that after a crash
+//! either every cube holds its records or none does. The protocol relies on intent files:
+//!
+//! 1. Every participant enters bulk mode, which takes its exclusive lock (in path order, so two
+//!    transactions never deadlock).
+//! 2. A pending marker `<first cube>.txn-pending` is written, then an intent sidecar
+//!    `<cube>.txn.json` per participant recording its length and NEXT_ID before the transaction.
+//! 3. The records are appended and every cube is synced.
+//! 4. Removing the pending marker is the commit point. Intents are removed afterwards and the
+//!    locks released.
+//!
+//! `Writer::create` calls `recover` for the cube it opens: if an intent is left behind, all the
+//! participants of that transaction are locked and either rolled back to their recorded length
+//! (marker still present) or kept (marker gone). Readers that open a cube with `Writer::open`
+//! while a transaction is in flight may see its records before they are committed.
+
+use crate::data::paths;
+use crate::data::write::{Appended, Writer};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Records staged for several cubes, committed together by `commit`.
+#[derive(Default)]
+pub struct Transaction {
+    /// (index of the writer, phenomenon, noumenon), in staging order.
+    staged: Vec<(usize, String, String)>,
+}
+
+/// State of one participant before the transaction, persisted in its intent sidecar.
+#[derive(Serialize, Deserialize)]
+struct Participant {
+    cube: PathBuf,
+    len: u64,
+    next_id: u64,
+}
+
+/// Content of `<cube>.txn.json`; identical for every participant of a transaction.
+#[derive(Serialize, Deserialize)]
+struct Intent {
+    /// Commit marker: the transaction is committed once this file is gone.
+    pending: PathBuf,
+    participants: Vec<Participant>,
+}
+
+fn intent_path(cube: &Path) -> PathBuf {
+    let mut p = cube.as_os_str().to_owned();
+    p.push(".txn.json");
+    PathBuf::from(p)
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    paths::sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// Create `path` with `bytes` and make it durable.
+fn write_durable(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    f.write_all(bytes)?;
+    f.sync_all()?;
+    sync_parent(path)
+}
+
+/// Remove `path` if present.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage a record for the writer at index `cube` of the slice later passed to `commit`.
+    pub fn stage(&mut self, cube: usize, phenomenon: &str, noumenon: &str) -> &mut Self {
+        self.staged
+            .push((cube, phenomenon.to_string(), noumenon.to_string()));
+        self
+    }
+
+    /// Append every staged record to its writer, all or nothing, and return where each one went
+    /// (in staging order).
+    ///
+    /// Writers must be opened by path (`Writer::create`), on distinct cubes, and not be in bulk
+    /// mode. On error, every cube is rolled back to its state before the transaction.
+    pub fn commit(self, writers: &mut [Writer]) -> io::Result<Vec<Appended>> {
+        if let Some((i, ..)) = self.staged.iter().find(|(i, ..)| *i >= writers.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "record staged for cube #{i}, but only {} given",
+                    writers.len()
+                ),
+            ));
+        }
+        let mut order: Vec<(PathBuf, usize)> = Vec::with_capacity(writers.len());
+        for (i, w) in writers.iter().enumerate() {
+            let path = w.path().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "transactions need cubes opened by path",
+                )
+            })?;
+            order.push((path.to_path_buf(), i));
+        }
+        order.sort();
+        if order.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the same cube appears twice in a transaction",
+            ));
+        }
+
+        // 1. Lock every participant, in path order.
+        let mut participants = Vec::with_capacity(order.len());
+        for (cube, i) in &order {
+            let w = &mut writers[*i];
+            match w.begin_bulk().and_then(|()| w.end_offset()) {
+                Ok(len) => participants.push(Participant {
+                    cube: cube.clone(),
+                    len,
+                    next_id: w.next_id(),
+                }),
+                Err(e) => {
+                    rollback(writers, &order, &participants)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        let intent = Intent {
+            pending: {
+                let mut p = order[0].0.as_os_str().to_owned();
+                p.push(".txn-pending");
+                PathBuf::from(p)
+            },
+            participants,
+        };
+        let result = self.apply(writers, &intent);
+        if let Err(e) = result {
+            rollback(writers, &order, &intent.participants)?;
+            remove_intents(&intent)?;
+            remove(&intent.pending)?;
+            return Err(e);
+        }
+        remove_intents(&intent)?;
+        for (_, i) in &order {
+            writers[*i].finish_bulk()?;
+        }
+        result
+    }
+
+    /// Steps 2 to 4 of the protocol, with every participant locked.
+    fn apply(&self, writers: &mut [Writer], intent: &Intent) -> io::Result<Vec<Appended>> {
+        let json = serde_json::to_vec_pretty(intent).map_err(io::Error::other)?;
+        write_durable(&intent.pending, b"")?;
+        for p in &intent.participants {
+            write_durable(&intent_path(&p.cube), &json)?;
+        }
+        let mut out = Vec::with_capacity(self.staged.len());
+        for (i, ph, no) in &self.staged {
+            out.push(writers[*i].append_record(ph, no)?);
+        }
+        for w in writers.iter_mut() {
+            w.sync_bulk()?;
+        }
+        fs::remove_file(&intent.pending)?;
+        sync_parent(&intent.pending)?;
+        Ok(out)
+    }
+}
+
+/// Undo an uncommitted transaction on the writers locked so far.
+fn rollback(
+    writers: &mut [Writer],
+    order: &[(PathBuf, usize)],
+    locked: &[Participant],
+) -> io::Result<()> {
+    for (p, (_, i)) in locked.iter().zip(order) {
+        writers[*i].abort_bulk(p.len, p.next_id)?;
+    }
+    Ok(())
+}
+
+fn remove_intents(intent: &Intent) -> io::Result<()> {
+    for p in &intent.participants {
+        remove(&intent_path(&p.cube))?;
+    }
+    Ok(())
+}
+
+/// Finish a transaction interrupted by a crash if `cube` took part in one: roll every participant
+/// back when it was not committed, then clean up. Returns whether there was anything to recover.
+pub fn recover(cube: &Path) -> io::Result<bool> {
+    let intent_file = intent_path(&crate::data::bus::key_for(cube));
+    let Ok(raw) = fs::read(&intent_file) else {
+        return Ok(false);
+    };
+    let intent: Intent = serde_json::from_slice(&raw).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", intent_file.display()),
+        )
+    })?;
+
+    // Participants are recorded in lock order already.
+    let mut locked = Vec::new();
+    for p in &intent.participants {
+        match File::options().read(true).write(true).open(&p.cube) {
+            Ok(f) => {
+                f.lock()?;
+                locked.push((p, f));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let result = (|| {
+        // The transaction may have completed (or been recovered) while we waited for the locks.
+        if !intent_file.exists() {
+            return Ok(false);
+        }
+        if intent.pending.exists() {
+            for (p, f) in locked.iter_mut() {
+                if intent_path(&p.cube).exists() {
+                    Writer::truncate_to(f, p.len, p.next_id)?;
+                }
+            }
+        }
+        remove_intents(&intent)?;
+        remove(&intent.pending)?;
+        Ok(true)
+    })();
+    for (_, f) in locked {
+        f.unlock()?;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_and_crash_recovery() {
+        let dir = std::env::temp_dir().join(format!("akasha-txn-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.cube"), dir.join("b.cube"));
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        let ids = |cube: &str| -> Vec<u64> {
+            let mut w = Writer::open(cube).unwrap();
+            w.events().unwrap().iter().map(|e| e.id).collect()
+        };
+
+        let mut writers = [Writer::create(a).unwrap(), Writer::create(b).unwrap()];
+        writers[0].append("seed", "0").unwrap();
+        let mut tx = Transaction::new();
+        tx.stage(0, "x", "1").stage(1, "x", "1").stage(1, "y", "2");
+        let appended = tx.commit(&mut writers).unwrap();
+        assert_eq!(appended.iter().map(|a| a.id).collect::<Vec<_>>(), [2, 1, 2]);
+        assert_eq!((ids(a), ids(b)), (vec![1, 2], vec![1, 2]));
+        assert!(!intent_path(&bus_key(a)).exists());
+
+        // Simulate a crash after appending to `b` but before the commit point.
+        let before = fs::metadata(b).unwrap().len();
+        let intent = Intent {
+            pending: dir.join("a.cube.txn-pending"),
+            participants: vec![Participant {
+                cube: bus_key(b),
+                len: before,
+                next_id: 3,
+            }],
+        };
+        write_durable(&intent.pending, b"").unwrap();
+        let json = serde_json::to_vec(&intent).unwrap();
+        write_durable(&intent_path(&bus_key(b)), &json).unwrap();
+        writers[1].append("z", "torn").unwrap();
+        drop(writers);
+
+        let mut w = Writer::create(b).unwrap();
+        assert_eq!(ids(b), [1, 2]);
+        assert_eq!(w.append_record("z", "again").unwrap().id, 3);
+        assert!(!intent.pending.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn bus_key(cube: &str) -> PathBuf {
+        crate::data::bus::key_for(Path::new(cube))
+    }
+}
@@ -0,0 +1,216 @@
+//! Linked working directories: `ak worktree`.
+//!
+//! `ak worktree add <path> <branch>` checks `branch` out in a new directory whose `.eikyu` is
+//! linked to the repository's store: `cubes`, `tree` and the configuration files are symbolic
+//! links into the main `.eikyu`, while the current branch, the index and the merge and bisect
+//! sessions stay private to each directory. `.eikyu/worktree` holds the path of the main
+//! directory, which lists its linked worktrees in `.eikyu/worktrees.json`.
+//!
+//! Reference trees are per branch, so a branch is checked out in one directory at a time.
+
+use crate::data::branch;
+use crate::data::restore;
+use crate::data::status;
+use crate::data::tree;
+use crate::event::Event;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Entries of `.eikyu` shared by every worktree.
+pub const SHARED: [&str; 5] = [
+    "cubes",
+    "tree",
+    "config.toml",
+    "hooks.toml",
+    "allowed_signers",
+];
+
+fn marker(root: &Path) -> PathBuf {
+    root.join(".eikyu").join("worktree")
+}
+
+fn registry(main: &Path) -> PathBuf {
+    main.join(".eikyu").join("worktrees.json")
+}
+
+/// Main directory of the repository at `root`: `root` itself unless it is a linked worktree.
+pub fn main_root(root: &Path) -> io::Result<PathBuf> {
+    match fs::read_to_string(marker(root)) {
+        Ok(path) => Ok(PathBuf::from(path.trim())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(root.to_path_buf()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Linked worktrees of the repository whose main directory is `main`.
+pub fn list(main: &Path) -> io::Result<Vec<PathBuf>> {
+    match fs::read_to_string(registry(main)) {
+        Ok(json) => serde_json::from_str(&json).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save(main: &Path, worktrees: &[PathBuf]) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(worktrees).map_err(io::Error::other)?;
+    fs::write(registry(main), json)
+}
+
+fn same(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Directories of the repository at `root` where `author` has `name` checked out, among the
+/// main one and its linked worktrees.
+fn checkouts(root: &Path, author: &str, name: &str) -> io::Result<Vec<PathBuf>> {
+    let main = main_root(root)?;
+    let mut dirs = list(&main)?;
+    dirs.insert(0, main);
+    dirs.retain(|dir| dir.join(".eikyu").is_dir() && branch::current(dir, author) == name);
+    Ok(dirs)
+}
+
+/// Directory other than `root` of the same repository where `author` has `name` checked out.
+pub fn checked_out_elsewhere(root: &Path, author: &str, name: &str) -> io::Result<Option<PathBuf>> {
+    Ok(checkouts(root, author, name)?
+        .into_iter()
+        .find(|dir| !same(dir, root)))
+}
+
+#[cfg(unix)]
+fn link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn link(target: &Path, link: &Path) -> io::Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Create the worktree `path`, linked to the repository at `root`, with `author` on branch
+/// `name`. Returns the files checked out.
+pub fn add(
+    root: &Path,
+    author: &str,
+    path: &Path,
+    name: &str,
+    events: &[Event],
+) -> io::Result<Vec<String>> {
+    let Some(&head) = branch::heads(events).get(name) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no branch named '{name}'"),
+        ));
+    };
+    if let Some(dir) = checkouts(root, author, name)?.into_iter().next() {
+        return Err(io::Error::other(format!(
+            "'{name}' is already checked out in {}",
+            dir.display()
+        )));
+    }
+    if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not empty", path.display()),
+        ));
+    }
+
+    let main = main_root(root)?.canonicalize()?;
+    let store = main.join(".eikyu");
+    fs::create_dir_all(store.join("tree"))?;
+    fs::create_dir_all(path.join(".eikyu"))?;
+    let path = path.canonicalize()?;
+    for entry in SHARED {
+        link(&store.join(entry), &path.join(".eikyu").join(entry))?;
+    }
+    fs::write(marker(&path), main.to_string_lossy().as_bytes())?;
+    branch::set_current(&path, author, name)?;
+
+    let mut files = Vec::new();
+    if let Some(head) = head {
+        files = restore::restore(events, head, &[], &path)?;
+        let reference = tree::branch_tree_dir(&path, author, name);
+        if !reference.exists() {
+            restore::restore(events, head, &[], &reference)?;
+        }
+    }
+    let mut worktrees = list(&main)?;
+    worktrees.push(path);
+    save(&main, &worktrees)?;
+    Ok(files)
+}
+
+/// Delete the worktree `path` of the repository at `root` and forget it. Refuses to drop
+/// unsealed changes unless `force`.
+pub fn remove(root: &Path, author: &str, path: &Path, force: bool) -> io::Result<()> {
+    let main = main_root(root)?;
+    let mut worktrees = list(&main)?;
+    let Some(k) = worktrees.iter().position(|w| same(w, path)) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a worktree of this repository", path.display()),
+        ));
+    };
+    if same(path, root) {
+        return Err(io::Error::other("cannot remove the current worktree"));
+    }
+    let dir = worktrees.remove(k);
+    if dir.exists() {
+        if !force {
+            status::require_clean(&dir, author)?;
+        }
+        // Only removes the links to the shared store, not what they point to.
+        fs::remove_dir_all(&dir)?;
+    }
+    save(&main, &worktrees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write::Writer;
+
+    #[test]
+    fn test_add_list_and_remove() {
+        let dir = std::env::temp_dir().join(format!("akasha-worktree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let main = dir.join("main");
+        fs::create_dir_all(main.join(".eikyu").join("cubes")).unwrap();
+        let cube = main.join(".eikyu").join("cubes").join("u.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        branch::record_head(&mut w, branch::DEFAULT_BRANCH, None).unwrap();
+        branch::record_head(&mut w, "topic", None).unwrap();
+        let events = w.events().unwrap();
+
+        let linked = dir.join("linked");
+        assert!(add(&main, "u", &linked, "nope", &events).is_err());
+        // `main` is checked out in the main directory.
+        assert!(add(&main, "u", &linked, branch::DEFAULT_BRANCH, &events).is_err());
+        add(&main, "u", &linked, "topic", &events).unwrap();
+        assert_eq!(list(&main).unwrap(), [linked.canonicalize().unwrap()]);
+        assert!(linked.join(".eikyu").join("cubes").join("u.cube").exists());
+        assert_eq!(branch::current(&linked, "u"), "topic");
+        assert!(same(&main_root(&linked).unwrap(), &main));
+        assert!(
+            checked_out_elsewhere(&main, "u", "topic")
+                .unwrap()
+                .is_some_and(|d| same(&d, &linked))
+        );
+        assert!(add(&main, "u", &dir.join("other"), "topic", &events).is_err());
+
+        assert!(remove(&linked, "u", &linked, false).is_err());
+        remove(&main, "u", &linked, false).unwrap();
+        assert!(!linked.exists());
+        assert!(cube.exists());
+        assert!(list(&main).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
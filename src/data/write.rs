@@ -1,42 +1,87 @@
 //! Append-only event log writer/reader with a fixed binary format and CRC protection.
 //!
 //! File layout:
-//! - Header (16 bytes total):
+//! - Header (40 bytes total, 16 before version 3):
 //!   - MAGIC       [0..4)   = b"AKLA"
-//!   - VERSION     [4..6)   = u16 (LE), current = 1
+//!   - VERSION     [4..6)   = u16 (LE), current = 4
 //!   - RESERVED    [6..16)  = 10 bytes
 //!     - NEXT_ID   [6..14)  = u64 (LE), next id to assign for new entries
 //!     - reserved  [14..16) = 2 bytes, currently zero
+//!   - UUID        [16..32) = 16 bytes in textual order, identity of the cube (version 3 on)
+//!   - GENERATION  [32..40) = u64 (LE), destructive rewrites of the cube so far (version 3 on)
 //!
 //! - Records (variable length), each:
 //!   - LEN_TOTAL   [0..4)           = u32 (LE), total bytes of (payload + CRC), not including this length field
 //!   - PAYLOAD     [4..4+N)         = see below
 //!   - CRC32       [4+N..4+N+4)     = CRC32 over PAYLOAD (crc32fast)
 //!
-//! PAYLOAD layout:
+//! PAYLOAD layout (version 4):
 //!   - TS          [0..16)          = u128 (LE), UNIX epoch time in nanoseconds
 //!   - ID          [16..24)         = u64 (LE), monotonically increasing id
-//!   - PH_LEN      [24..26)         = u16 (LE), length of phenomenon bytes
-//!   - NO_LEN      [26..28)         = u16 (LE), length of noumenon bytes
-//!   - PHENOMENON  [28..28+PH_LEN)  = UTF-8 bytes
+//!   - TYPE        [24..25)         = u8, kind of record (`event::RecordType`)
+//!   - PH_LEN      [25..27)         = u16 (LE), length of phenomenon bytes
+//!   - NO_LEN      [27..29)         = u16 (LE), length of noumenon bytes
+//!   - AT_LEN      [29..31)         = u16 (LE), length of the attribute block
+//!   - PHENOMENON  [31..31+PH_LEN)  = UTF-8 bytes
 //!   - NOUMENON    [..+NO_LEN)      = UTF-8 bytes
+//!   - ATTRIBUTES  [..+AT_LEN)      = attributes of the record (`Event::attributes`), sorted by
+//!     key, each as KEY_LEN (u8), KEY (UTF-8), VALUE_LEN (u16, LE), VALUE (UTF-8)
+//!
+//! Versions 2 and 3 payloads have no AT_LEN nor ATTRIBUTES (PHENOMENON starts at 29). Version 1
+//! payloads have no TYPE byte either (PH_LEN starts at 24): the kind of their records is
+//! inferred from the phenomenon. New cubes are created with version 4; existing cubes keep their
+//! version, also when compacted, so that writers holding them open stay in step, and records
+//! written to them lose what their version can't store.
+//!
+//! Writers stamp the records they create with their provenance (see `data::provenance`).
+//!
+//! Version 3 cubes carry an `Identity` in their header: a UUID drawn at creation and a
+//! generation, bumped by every rewrite that drops or replaces records (compaction, rotation,
+//! rolling back a transaction). Copies of a cube share its UUID, so sync tooling tells two
+//! unrelated cubes stored under the same name apart instead of mixing their histories.
 //!
 //! Design notes:
 //! - Append-only: records are only appended; we never rewrite existing records except for updating NEXT_ID in header.
 //! - Crash safety: each append is followed by `sync_data()`. Header’s NEXT_ID is also persisted after each append.
+//! - Bulk mode (`begin_bulk`/`finish_bulk`): records are buffered in memory and written in large chunks,
+//!   NEXT_ID is set to 0 for the duration (forcing scan recovery after a crash) and a single `sync_all()`
+//!   happens at the end. The import holds the exclusive lock throughout, and `create()` only reads or
+//!   recovers NEXT_ID under that lock, so other processes never mistake an import for a crash.
+//!   Buffer size and disk pre-allocation are tunable per cube (see `data::storage`).
+//! - Transactions across cubes (`data::txn`) use bulk mode on every participant; `create()` rolls
+//!   back an interrupted one by truncating the cube to its length before the transaction.
+//! - Replication and replay (`append_event`/`append_events`) persist events with their own ids and
+//!   timestamps, checked and written all or nothing; ids still only grow.
 //! - Integrity: each record protected by CRC32; on read, iteration stops at first invalid/truncated record.
-//! - Recovery: if NEXT_ID in header is zero or invalid, we scan the file to compute max(id)+1.
+//! - Recovery: if NEXT_ID in header is zero or invalid, we scan the file to compute max(id)+1, starting
+//!   from the scan checkpoint stored next to the cube when it is still valid (see `data::checkpoint`).
 //! - Deduplication in `store_directory`: based on BLAKE3 hash of file contents tracked per path.
-//! - Concurrency: this struct is not synchronized. External synchronization is required for multi-writer scenarios.
+//!   Since version 2, contents already stored under any path are written as a `Ref` record
+//!   naming the record that holds them (`ContentRef`); reads resolve it back to the contents.
+//! - File phenomena are portable relative paths with `/` separators (see `data::paths`).
+//! - Concurrency: this struct is not synchronized, but each append (or a whole bulk import) holds an exclusive
+//!   OS file lock (`flock` on Unix, `LockFileEx` on Windows) so concurrent processes never interleave records.
 //!
-//! Endianness: All integers are encoded little-endian.
-
-use crate::event::Event;
+//! Endianness: All integers are encoded little-endian (the UUID is bytes, not an integer).
+
+use crate::data::akignore;
+use crate::data::bus;
+use crate::data::checkpoint::{self, Anchor, Checkpoint};
+use crate::data::daemon::{self, Request, Response};
+use crate::data::paths;
+use crate::data::plugin::{self, RecordProcessor, Stage};
+use crate::data::provenance;
+use crate::data::storage::{self, IoConfig};
+use crate::data::txn;
+use crate::event::{Event, RecordType};
 use blake3;
 use crc32fast::Hasher;
-use std::collections::HashMap;
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
 use std::{
     collections::BTreeMap,
     fs::{self, File, OpenOptions},
@@ -53,19 +98,174 @@ use std::{
 pub struct Writer {
     /// Underlying file handle for the cube.
     f: File,
+    /// Format version of the cube, which decides the payload layout.
+    version: u16,
     /// Next record id to assign; persisted in the header for recovery.
     next_id: u64,
+    /// Record processors applied on append and on read (see `data::plugin`).
+    processors: Vec<Box<dyn RecordProcessor>>,
+    /// Channels handed out by `subscribe`, notified after each successful append.
+    subscribers: Vec<Sender<Event>>,
+    /// Canonical cube path used to publish on the process-wide bus; `None` for `Writer::new`.
+    bus_key: Option<PathBuf>,
+    /// Pending bytes while in bulk mode (`None` otherwise).
+    bulk: Option<Vec<u8>>,
+    /// File offset at which the bulk buffer will be written.
+    bulk_base: u64,
+    /// Events appended in bulk mode, notified once `finish_bulk` made them durable.
+    bulk_events: Vec<Event>,
+    /// Storage tuning (bulk buffer size, pre-allocation), see `data::storage`.
+    io: IoConfig,
+    /// End of the disk space reserved by pre-allocation.
+    allocated: u64,
+    /// Socket of the daemon serving the cube, which appends go through (see `data::daemon`).
+    daemon: Option<PathBuf>,
+    /// Attributes stamped on the records this writer creates (see `data::provenance`).
+    provenance: BTreeMap<String, String>,
+}
+
+/// Outcome of `Writer::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Check {
+    /// Records passing their CRC.
+    pub records: usize,
+    /// Length of the valid part of the cube: header and valid records.
+    pub valid_len: u64,
+    /// Length of the cube.
+    pub len: u64,
+}
+
+/// Outcome of `Writer::store_paths_at`, in files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stored {
+    /// Stored for the first time.
+    pub new: usize,
+    /// Stored again with new contents.
+    pub updated: usize,
+    /// Skipped, same contents as last stored.
+    pub unchanged: usize,
+    /// Length of the contents stored.
+    pub bytes: u64,
+    /// Among the new and updated ones, stored as references to identical contents already in
+    /// the cube.
+    pub referenced: usize,
+}
+
+/// Noumenon of a `RecordType::Ref` record: file contents stored by an earlier record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContentRef {
+    /// Id of the record holding the contents.
+    #[serde(rename = "ref")]
+    pub id: u64,
+    /// BLAKE3 (hex) of the contents.
+    pub hash: String,
+}
+
+/// Identity of a version 3 cube, stored in its header (see the module documentation).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Hyphenated UUID, drawn when the cube was created and kept by its copies.
+    pub uuid: String,
+    /// Destructive rewrites of the cube since its creation.
+    pub generation: u64,
+}
+
+impl Identity {
+    /// A new identity: a random (version 4) UUID at generation 0.
+    ///
+    /// The random bits are BLAKE3 of the time, the process, a per-process counter and the OS
+    /// randomness seeding the standard hash maps.
+    pub fn fresh() -> Self {
+        use std::hash::{BuildHasher, Hasher as _};
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static DRAWN: AtomicU64 = AtomicU64::new(0);
+        let mut h = blake3::Hasher::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        h.update(&now.as_nanos().to_le_bytes());
+        h.update(&std::process::id().to_le_bytes());
+        h.update(&DRAWN.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        let seed = std::collections::hash_map::RandomState::new().build_hasher();
+        h.update(&seed.finish().to_le_bytes());
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&h.finalize().as_bytes()[..16]);
+        uuid[6] = (uuid[6] & 0x0f) | 0x40;
+        uuid[8] = (uuid[8] & 0x3f) | 0x80;
+        Self::from_bytes(&[&uuid[..], &[0u8; 8]].concat())
+    }
+
+    /// The same cube after one more destructive rewrite.
+    pub fn bumped(&self) -> Self {
+        Self {
+            uuid: self.uuid.clone(),
+            generation: self.generation + 1,
+        }
+    }
+
+    /// UUID and generation as stored in the header.
+    fn to_bytes(&self) -> io::Result<[u8; 24]> {
+        let hex = self.uuid.replace('-', "");
+        let uuid = (hex.len() == 32)
+            .then(|| u128::from_str_radix(&hex, 16).ok())
+            .flatten()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid cube UUID {}", self.uuid),
+                )
+            })?;
+        let mut out = [0u8; 24];
+        out[..16].copy_from_slice(&uuid.to_be_bytes());
+        out[16..].copy_from_slice(&self.generation.to_le_bytes());
+        Ok(out)
+    }
+
+    /// Decode the 24 header bytes written by `to_bytes`.
+    fn from_bytes(b: &[u8]) -> Self {
+        let hex = format!("{:032x}", u128::from_be_bytes(b[..16].try_into().unwrap()));
+        Self {
+            uuid: format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            ),
+            generation: u64::from_le_bytes(b[16..24].try_into().unwrap()),
+        }
+    }
+}
+
+/// Where `Writer::append_record` put a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Appended {
+    /// Byte offset of the record (its length prefix) in the cube file.
+    pub offset: u64,
+    /// Id assigned to the record.
+    pub id: u64,
 }
 
 impl Writer {
+    /// Longest phenomenon or noumenon of a record, in bytes: both have u16 length prefixes.
+    pub const MAX_FIELD_LEN: usize = u16::MAX as usize;
     /// 4-byte magic to identify the file type.
     const MAGIC: [u8; 4] = *b"AKLA";
-    /// On-disk version. Bump on breaking layout changes.
-    const VERSION: u16 = 1;
+    /// On-disk version of new cubes. Bump on breaking layout changes.
+    const VERSION: u16 = 4;
+    /// First version, without record types.
+    const V1: u16 = 1;
+    /// Last version without an identity in the header.
+    const V2: u16 = 2;
+    /// Last version without record attributes.
+    const V3: u16 = 3;
     /// Number of reserved header bytes after MAGIC+VERSION.
     const HEADER_RESERVED: usize = 10;
-    /// Total header length in bytes.
+    /// Header length in bytes, without the identity.
     const HEADER_LEN: u64 = 16;
+    /// Length of the identity following the header since version 3: UUID(16) + GENERATION(8).
+    const IDENTITY_LEN: u64 = 16 + 8;
 
     // Reserved header layout:
     // [0..8): next_id (u64, LE)
@@ -73,12 +273,86 @@ impl Writer {
     /// Offset of `next_id` field from start-of-file.
     const HDR_NEXT_ID_OFF: u64 = 4 + 2; // MAGIC(4) + VERSION(2) = 6
 
+    /// Minimal payload size: ts + id + ph_len + no_len (+ type since version 2, + at_len since
+    /// version 4).
+    const MIN_PAYLOAD: usize = 16 + 8 + 2 + 2;
+    /// Size of the trailing CRC32.
+    const CRC_LEN: usize = 4;
+    /// Largest possible LEN_TOTAL: both strings and the attribute block are bounded by their u16
+    /// length prefixes.
+    const MAX_ENTRY_LEN: usize = Self::MIN_PAYLOAD + 1 + 2 + 3 * u16::MAX as usize + Self::CRC_LEN;
+
     /// Construct a Writer from an already-open file.
     ///
     /// Note: This does not validate the header or position the cursor. Prefer `create()` unless you
     /// have special needs.
     pub fn new(f: File) -> Self {
-        Self { f, next_id: 1 }
+        Self {
+            f,
+            version: Self::VERSION,
+            next_id: 1,
+            processors: Vec::new(),
+            subscribers: Vec::new(),
+            bus_key: None,
+            bulk: None,
+            bulk_base: 0,
+            bulk_events: Vec::new(),
+            io: IoConfig::default(),
+            allocated: 0,
+            daemon: None,
+            provenance: provenance::current().clone(),
+        }
+    }
+
+    /// Receive every event appended through this writer from now on.
+    ///
+    /// Use `data::bus::subscribe_cube` to also observe appends made by other writers of the
+    /// same cube within this process.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Id that the next appended record will receive.
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Make the next ids `at_least` or more, e.g. so that a new cube continues the ids of an
+    /// earlier one. Never moves `next_id` backwards.
+    pub fn advance_next_id(&mut self, at_least: u64) -> io::Result<()> {
+        if self.bulk.is_some() {
+            self.next_id = self.next_id.max(at_least);
+            return Ok(());
+        }
+        self.f.lock()?;
+        let result = Self::read_header_next_id(&mut self.f).and_then(|stored| {
+            self.next_id = self.next_id.max(stored).max(at_least);
+            Self::write_header_next_id(&mut self.f, self.next_id)
+        });
+        self.f.unlock()?;
+        result
+    }
+
+    /// Replace the storage settings loaded from `<cube>.io.json`.
+    pub fn with_io(mut self, io: IoConfig) -> Self {
+        self.io = io;
+        self
+    }
+
+    /// Register an additional record processor, run after the ones configured for the cube.
+    pub fn with_processor(mut self, processor: Box<dyn RecordProcessor>) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// Run every processor for `stage` over `ev`, in registration order.
+    fn run_processors(&mut self, stage: Stage, ev: &mut Event) -> io::Result<()> {
+        for p in self.processors.iter_mut() {
+            p.process(stage, ev)?;
+        }
+        Ok(())
     }
 
     /// Open or create a cube file at `path`, validate/initialize its header, and seek to EOF for appends.
@@ -89,8 +363,15 @@ impl Writer {
     ///   - Validate header magic.
     ///   - Read `next_id`.
     ///   - If `next_id` is 0, scan the file to recover `max(id) + 1` and persist it.
+    /// - Header initialization and recovery run under the exclusive file lock, so opening a cube
+    ///   waits for a bulk import in progress in another process.
     /// - Always leaves the cursor at end-of-file ready for append.
+    /// - Loads the record processors configured in `<path>.plugins.json`, if any. Lua hooks are
+    ///   not loaded here; see `data::script::attach`.
+    /// - Finishes or rolls back a multi-cube transaction interrupted by a crash (see `data::txn`).
+    /// - Sends appends to the daemon of the cube while one runs (see `data::daemon`).
     pub fn create(path: &str) -> io::Result<Self> {
+        txn::recover(Path::new(path))?;
         let mut f = OpenOptions::new()
             .create(true)
             .truncate(false) // preserve existing data
@@ -98,77 +379,205 @@ impl Writer {
             .write(true)
             .open(path)?;
 
-        let mut next_id = 1u64;
+        // Initialize or recover under the exclusive lock: a bulk import in another process holds
+        // it with NEXT_ID temporarily 0, which must not be mistaken for a crash to recover from.
+        f.lock()?;
+        let init = Self::init_header(&mut f, Path::new(path));
+        f.unlock()?;
+        let (next_id, version) = init?;
+
+        // Always append at the end by default
+        f.seek(SeekFrom::End(0))?;
+        let processors = plugin::load_for_cube(path, &[Stage::Append, Stage::Read])?;
+        let io = storage::load_for_cube(path)?;
+        Ok(Self {
+            f,
+            version,
+            next_id,
+            processors,
+            subscribers: Vec::new(),
+            bus_key: Some(bus::key_for(Path::new(path))),
+            bulk: None,
+            bulk_base: 0,
+            bulk_events: Vec::new(),
+            io,
+            allocated: 0,
+            daemon: daemon::socket_of(Path::new(path)),
+            provenance: provenance::current().clone(),
+        })
+    }
 
+    /// Write a fresh header into an empty file, or validate the existing one and return its
+    /// `next_id`, recovering it by a scan when it is 0, and its version. Called with the file
+    /// lock held.
+    fn init_header(f: &mut File, path: &Path) -> io::Result<(u64, u16)> {
         if f.metadata()?.len() == 0 {
-            Self::write_header(&mut f, next_id)?;
-        } else {
-            // Validate header and load next_id
-            Self::read_and_validate_header(&mut f)?;
-            next_id = Self::read_header_next_id(&mut f)?;
-            if next_id == 0 {
-                // Recover by scanning to find max id and set next_id = max+1
-                next_id = Self::compute_max_id_from_file(&mut f)?
-                    .and_then(|m| m.checked_add(1))
-                    .unwrap_or(1);
-                Self::write_header_next_id(&mut f, next_id)?;
-            }
+            Self::write_header(f, Self::VERSION, 1, &Identity::fresh())?;
+            return Ok((1, Self::VERSION));
         }
+        let version = Self::read_and_validate_header(f)?;
+        let mut next_id = Self::read_header_next_id(f)?;
+        if next_id == 0 {
+            // Recover by scanning to find max id and set next_id = max+1
+            next_id = Self::scan_checkpoint(f, Some(path))?
+                .max_id
+                .and_then(|m| m.checked_add(1))
+                .unwrap_or(1);
+            Self::write_header_next_id(f, next_id)?;
+        }
+        Ok((next_id, version))
+    }
 
-        // Always append at the end by default
-        f.seek(SeekFrom::End(0))?;
-        Ok(Self { f, next_id })
+    /// Open an existing cube at `path` for reading only.
+    ///
+    /// Unlike `create`, a missing file is an error and nothing is ever written (not even a
+    /// recovered `next_id`, which is computed in memory). Appends through the returned writer
+    /// fail with the OS error for a read-only handle.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut f = File::open(path)?;
+        let version = Self::read_and_validate_header(&mut f)?;
+        let mut next_id = Self::read_header_next_id(&mut f)?;
+        if next_id == 0 {
+            next_id = Self::scan_checkpoint(&mut f, Some(Path::new(path)))?
+                .max_id
+                .and_then(|m| m.checked_add(1))
+                .unwrap_or(1);
+        }
+        Ok(Self {
+            f,
+            version,
+            next_id,
+            processors: plugin::load_for_cube(path, &[Stage::Read])?,
+            subscribers: Vec::new(),
+            bus_key: Some(bus::key_for(Path::new(path))),
+            bulk: None,
+            bulk_base: 0,
+            bulk_events: Vec::new(),
+            io: IoConfig::default(),
+            allocated: 0,
+            daemon: None,
+            provenance: provenance::current().clone(),
+        })
     }
 
     /// Recursively scan `dir` and append contents of qualifying files to the cube,
     /// deduplicating by content hash and showing a progress bar.
     ///
+    /// Phenomena are anchored at the current directory, like `ak inscribe`, so `./foo/bar.txt`
+    /// and `foo/bar.txt` are the same phenomenon (`foo/bar.txt`) and deduplicate against records
+    /// written before paths were normalized. A `dir` outside the current directory is anchored
+    /// at itself (or at its parent when it is a single file). See `store_directory_at`.
+    pub fn store_directory<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let cwd = std::env::current_dir()?.canonicalize()?;
+        let root = if dir.canonicalize()?.starts_with(&cwd) {
+            cwd.as_path()
+        } else if dir.is_file() {
+            dir.parent().unwrap_or(Path::new("."))
+        } else {
+            dir
+        };
+        self.store_directory_at(root, dir)
+    }
+
+    /// Recursively scan `dir` and append contents of qualifying files to the cube, storing each
+    /// file under its portable path relative to `root` (see `data::paths`).
+    ///
+    /// `root` and `dir` may be relative or absolute: both are canonicalized before the walk.
+    /// See `store_paths_at` for the pipeline.
+    pub fn store_directory_at<R: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        root: R,
+        dir: P,
+    ) -> io::Result<()> {
+        self.store_paths_at(root, &[dir], &GlobSet::empty())
+            .map(|_| ())
+    }
+
+    /// Scan `targets` (files or directories, relative or absolute) and append contents of
+    /// qualifying files to the cube, storing each file under its portable path relative to
+    /// `root`. Files whose path, or one of its parent directories, matches `exclude` are skipped.
+    ///
     /// Pipeline:
-    /// - Build in-memory “seen” map from the log: path -> last stored BLAKE3(content).
-    /// - Walk `dir` using ignore rules (.ignore), select only regular files, exclude dotfiles,
-    ///   and exclude paths containing `target` or `.git`.
-    /// - For each file:
-    ///   - Compute BLAKE3(content); if equal to the last stored hash for that path, skip.
-    ///   - Otherwise, append file content under its path and update the in-memory map.
+    /// - Build in-memory “seen” map from the log: phenomenon -> last stored BLAKE3(content).
+    /// - Walk every target using ignore rules (.ignore), select only regular files, exclude
+    ///   dotfiles, and exclude paths containing `target` or `.git` components below `root`.
+    /// - For each file (once, even when several targets cover it):
+    ///   - Normalize its path relative to `root` into a forward-slash phenomenon.
+    ///   - Compute BLAKE3(content); if equal to the last stored hash for that phenomenon, skip.
+    ///   - Otherwise, append file content under its phenomenon and update the in-memory map.
     ///
     /// Error handling:
     /// - Per-file failures (hash/read/append) are logged to stderr and processing continues.
-    /// - Overall function returns `Ok(())` unless a fatal IO error occurs setting up the walk or I/O on the cube.
-    pub fn store_directory<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
-        // Build a map of path -> last stored content hash by scanning the cube.
-        let mut seen: HashMap<PathBuf, String> = self.rebuild_seen_index_from_log();
-
-        // Collect candidate files from the directory walk applying the exclusion policy.
-        let mut files: Vec<PathBuf> = ignore::WalkBuilder::new(dir)
-            .add_custom_ignore_filename(".ignore")
-            .build()
-            .filter_map(Result::ok)
-            .filter(|e| {
-                // Keep only regular files; skip directories and special file types.
-                e.file_type()
-                    .expect("failed to get the file type")
-                    .is_file()
-            })
-            .map(|e| e.into_path())
-            .filter(|p| {
+    /// - Overall function returns `Ok` unless a fatal IO error occurs setting up the walk or I/O on the cube.
+    pub fn store_paths_at<R: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        root: R,
+        targets: &[P],
+        exclude: &GlobSet,
+    ) -> io::Result<Stored> {
+        // Compare like with like: `ak inscribe /abs/path` walks absolute paths below a `.` root.
+        let root = root.as_ref().canonicalize()?;
+        // Maps of phenomenon -> last stored content hash and content hash -> record, by scanning
+        // the cube.
+        let Checkpoint {
+            mut seen,
+            mut contents,
+            ..
+        } = self.scan_state();
+        // Processors may rewrite a noumenon, reference included: references need plain records.
+        let dedup = self.version > Self::V1 && self.processors.is_empty();
+
+        // Collect candidate files from the directory walks applying the exclusion policy, keyed
+        // by phenomenon for a stable, reproducible order without duplicates.
+        let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for target in targets {
+            let dir = target.as_ref().canonicalize()?;
+            for p in akignore::walker(&dir)
+                .build()
+                .filter_map(Result::ok)
+                .filter(|e| {
+                    // Keep only regular files; skip directories and special file types.
+                    e.file_type()
+                        .expect("failed to get the file type")
+                        .is_file()
+                })
+                .map(|e| e.into_path())
+            {
+                let Some(ph) = paths::normalize(&root, &p) else {
+                    eprintln!("skip {}: not a portable path below the root", p.display());
+                    continue;
+                };
                 // Exclusions:
                 // - dotfiles
                 // - any path containing "target" or ".git" components
-                let name = p.file_name().and_then(|s| s.to_str()).unwrap_or("");
+                // - paths matching `exclude`, or below a directory matching it
+                // - files too large for a record (see `too_large`)
+                let name = ph.rsplit('/').next().unwrap_or("");
                 if name.starts_with('.') {
-                    return false;
+                    continue;
                 }
-                if p.components()
-                    .any(|c| c.as_os_str() == "target" || c.as_os_str() == ".git")
-                {
-                    return false;
+                if ph.split('/').any(|c| c == "target" || c == ".git") {
+                    continue;
                 }
-                true
-            })
-            .collect();
-
-        // Sort for stable, reproducible traversal order.
-        files.sort();
+                let excluded = ph
+                    .match_indices('/')
+                    .map(|(k, _)| &ph[..k])
+                    .chain([ph.as_str()])
+                    .any(|prefix| exclude.is_match(prefix));
+                if excluded {
+                    continue;
+                }
+                if Self::too_large(&p) {
+                    eprintln!(
+                        "skip {ph}: larger than a record ({} bytes)",
+                        Self::MAX_FIELD_LEN
+                    );
+                    continue;
+                }
+                files.insert(ph, p);
+            }
+        }
 
         // Progress bar setup.
         use indicatif::{ProgressBar, ProgressStyle};
@@ -180,7 +589,8 @@ impl Writer {
                 .progress_chars("=>-"),
         );
 
-        for path in files {
+        let mut stored = Stored::default();
+        for (phenomenon, path) in files {
             // Compute the current file's content hash.
             let h = match Self::file_hash(&path) {
                 Ok(h) => h,
@@ -196,14 +606,43 @@ impl Writer {
             pb.set_message(format!("{}", path.file_name().unwrap().to_string_lossy()));
 
             // Deduplicate: skip if unchanged relative to last stored content for this path.
-            let is_same = seen.get(&path).map(|old| old == &h).unwrap_or(false);
-            if !is_same {
-                // Append file contents to the cube; log error but do not abort on failure.
-                if let Err(e) = self.append_file_contents(&path) {
-                    eprintln!("store fail {}: {e}", path.display());
-                } else {
-                    // Update the in-memory "seen" index so subsequent duplicates in this run are skipped.
-                    seen.insert(path.clone(), h);
+            match seen.get(&phenomenon) {
+                Some(old) if old == &h => stored.unchanged += 1,
+                old => {
+                    let known = old.is_some();
+                    // Append file contents (or a reference to the same contents) to the cube; log
+                    // error but do not abort on failure.
+                    let appended = match contents.get(&h) {
+                        Some(&id) if dedup => {
+                            let json = serde_json::to_string(&ContentRef {
+                                id,
+                                hash: h.clone(),
+                            })
+                            .map_err(io::Error::other)?;
+                            stored.referenced += 1;
+                            self.append_typed(RecordType::Ref, &phenomenon, &json)
+                                .map(|_| 0)
+                        }
+                        _ => self
+                            .append_file_contents(&path, &phenomenon)
+                            .map(|(a, len)| {
+                                contents.entry(h.clone()).or_insert(a.id);
+                                len
+                            }),
+                    };
+                    match appended {
+                        Err(e) => eprintln!("store fail {}: {e}", path.display()),
+                        Ok(len) => {
+                            if known {
+                                stored.updated += 1;
+                            } else {
+                                stored.new += 1;
+                            }
+                            stored.bytes += len;
+                            // Update the in-memory "seen" index so subsequent duplicates in this run are skipped.
+                            seen.insert(phenomenon, h);
+                        }
+                    }
                 }
             }
 
@@ -211,20 +650,43 @@ impl Writer {
         }
 
         pb.finish_with_message("Done!");
-        Ok(())
+        // In bulk mode the records are not durable yet; `finish_bulk` checkpoints instead.
+        if self.bulk.is_none() {
+            self.checkpoint()?;
+        }
+        Ok(stored)
     }
 
-    /// Write a fresh header with the provided `next_id` at offset 0 and flush it.
-    fn write_header(f: &mut File, next_id: u64) -> io::Result<()> {
+    /// Write a fresh header with the provided `version`, `next_id` and `identity` (left out
+    /// before version 3) at offset 0 and flush it.
+    fn write_header(
+        f: &mut File,
+        version: u16,
+        next_id: u64,
+        identity: &Identity,
+    ) -> io::Result<()> {
         f.seek(SeekFrom::Start(0))?;
         f.write_all(Self::MAGIC.as_ref())?;
-        f.write_all(&Self::VERSION.to_le_bytes())?;
+        f.write_all(&version.to_le_bytes())?;
 
         // Initialize reserved with next_id (8 bytes) + 2 reserved zeros
         let mut reserved = [0u8; Self::HEADER_RESERVED];
         reserved[0..8].copy_from_slice(&next_id.to_le_bytes());
         f.write_all(&reserved)?;
+        if version > Self::V2 {
+            f.write_all(&identity.to_bytes()?)?;
+        }
+        f.flush()?;
+        Ok(())
+    }
+
+    /// Overwrite the identity in the header of the version 3 cube `f`, preserving the cursor.
+    fn write_header_identity(f: &mut File, identity: &Identity) -> io::Result<()> {
+        let cur = f.stream_position()?;
+        f.seek(SeekFrom::Start(Self::HEADER_LEN))?;
+        f.write_all(&identity.to_bytes()?)?;
         f.flush()?;
+        f.seek(SeekFrom::Start(cur))?;
         Ok(())
     }
 
@@ -250,68 +712,380 @@ impl Writer {
         Ok(val)
     }
 
-    /// Validate the header by checking the magic value at the start of the file.
+    /// Validate the header by checking the magic value and version at the start of the file,
+    /// and return the version.
     ///
-    /// On success, the cursor is left just after the 16-byte header.
-    fn read_and_validate_header(f: &mut File) -> io::Result<()> {
-        // Ensure we read header from the beginning
+    /// On success, the cursor is left just after the header.
+    fn read_and_validate_header(f: &mut File) -> io::Result<u16> {
+        let version = Self::image_version(&Self::read_header_bytes(f)?)?;
+        f.seek(SeekFrom::Start(Self::header_len(version)))?;
+        Ok(version)
+    }
+
+    /// The header of `f` as far as it goes, up to the longest header length.
+    fn read_header_bytes(f: &mut File) -> io::Result<Vec<u8>> {
         f.seek(SeekFrom::Start(0))?;
-        let mut hdr = [0u8; 16];
-        f.read_exact(&mut hdr)?;
-        if hdr[0..4] != Self::MAGIC {
+        let mut hdr = Vec::new();
+        Read::by_ref(f)
+            .take(Self::header_len(Self::VERSION))
+            .read_to_end(&mut hdr)?;
+        Ok(hdr)
+    }
+
+    /// Version of the cube image `bytes` (header + records), checking the magic value.
+    pub fn image_version(bytes: &[u8]) -> io::Result<u16> {
+        if bytes.len() < Self::HEADER_LEN as usize || bytes[0..4] != Self::MAGIC {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid magic"));
         }
-        Ok(())
+        let version = match u16::from_le_bytes([bytes[4], bytes[5]]) {
+            v @ Self::V1..=Self::VERSION => v,
+            v => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported cube version {v}"),
+                ));
+            }
+        };
+        if (bytes.len() as u64) < Self::header_len(version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated cube header",
+            ));
+        }
+        Ok(version)
     }
 
-    /// Scan the file and return the maximum encountered record id, if any.
-    ///
-    /// Used for recovery when the stored `next_id` is zero/invalid.
-    fn compute_max_id_from_file(f: &mut File) -> io::Result<Option<u64>> {
-        // Start right after header
-        Self::read_and_validate_header(f)?;
-        f.seek(SeekFrom::Start(Self::HEADER_LEN))?;
+    /// Length of the header of a cube of `version`, where its first record starts.
+    pub fn header_len(version: u16) -> u64 {
+        match version {
+            Self::V1 | Self::V2 => Self::HEADER_LEN,
+            _ => Self::HEADER_LEN + Self::IDENTITY_LEN,
+        }
+    }
 
-        let mut max_id: Option<u64> = None;
-        while let Some((_, payload)) = Self::read_valid_entry(f)? {
+    /// Identity of the cube image `bytes`, `None` before version 3.
+    pub fn image_identity(bytes: &[u8]) -> io::Result<Option<Identity>> {
+        let version = Self::image_version(bytes)?;
+        Ok((version > Self::V2).then(|| {
+            let start = Self::HEADER_LEN as usize;
+            Identity::from_bytes(&bytes[start..start + Self::IDENTITY_LEN as usize])
+        }))
+    }
+
+    /// Identity stored in the header of `f`, restoring the cursor position afterwards.
+    fn read_identity(f: &mut File) -> io::Result<Option<Identity>> {
+        let cur = f.stream_position()?;
+        let hdr = Self::read_header_bytes(f)?;
+        f.seek(SeekFrom::Start(cur))?;
+        Self::image_identity(&hdr)
+    }
+
+    /// Identity of the cube at `path`, `None` before version 3.
+    pub fn identity_of<P: AsRef<Path>>(path: P) -> io::Result<Option<Identity>> {
+        Self::read_identity(&mut File::open(path)?)
+    }
+
+    /// Identity of the cube, as currently stored in its header: `None` before version 3.
+    pub fn identity(&mut self) -> io::Result<Option<Identity>> {
+        Self::read_identity(&mut self.f)
+    }
+
+    /// On-disk size of the record of `ev` in a cube of `version`.
+    pub fn record_len(version: u16, ev: &Event) -> u64 {
+        let typed = (version > Self::V1) as usize;
+        let attributes = match version > Self::V3 {
+            true => 2 + Self::attributes_len(&ev.attributes),
+            false => 0,
+        };
+        (4 + Self::MIN_PAYLOAD
+            + typed
+            + attributes
+            + ev.phenomenon.len()
+            + ev.noumenon.len()
+            + Self::CRC_LEN) as u64
+    }
+
+    /// Length of the attribute block holding `attributes`.
+    fn attributes_len(attributes: &BTreeMap<String, String>) -> usize {
+        attributes
+            .iter()
+            .map(|(k, v)| 1 + k.len() + 2 + v.len())
+            .sum()
+    }
+
+    /// Attribute block of a record holding `attributes`, or `InvalidInput` when a key is longer
+    /// than 255 bytes or the block than `u16::MAX` bytes.
+    pub fn encode_attributes(attributes: &BTreeMap<String, String>) -> io::Result<Vec<u8>> {
+        let len = Self::attributes_len(attributes);
+        let too_long = attributes
+            .iter()
+            .find(|(k, v)| k.len() > u8::MAX as usize || v.len() > u16::MAX as usize);
+        if let Some((k, _)) = too_long {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("attribute {k:?} too large for a record"),
+            ));
+        }
+        if len > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("attributes too large ({len} bytes, max {})", u16::MAX),
+            ));
+        }
+        let mut out = Vec::with_capacity(len);
+        for (k, v) in attributes {
+            out.push(k.len() as u8);
+            out.extend_from_slice(k.as_bytes());
+            out.extend_from_slice(&(v.len() as u16).to_le_bytes());
+            out.extend_from_slice(v.as_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Attributes of the attribute block `b`, `None` when it is malformed.
+    fn decode_attributes(mut b: &[u8]) -> Option<BTreeMap<String, String>> {
+        fn take<'a>(b: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            let (head, rest) = b.split_at_checked(n)?;
+            *b = rest;
+            Some(head)
+        }
+        let mut out = BTreeMap::new();
+        while !b.is_empty() {
+            let k_len = take(&mut b, 1)?[0] as usize;
+            let k = std::str::from_utf8(take(&mut b, k_len)?).ok()?;
+            let v_len = u16::from_le_bytes(take(&mut b, 2)?.try_into().ok()?) as usize;
+            let v = std::str::from_utf8(take(&mut b, v_len)?).ok()?;
+            out.insert(k.to_string(), v.to_string());
+        }
+        Some(out)
+    }
+
+    /// Format version of the cube at `path`.
+    pub fn version_of<P: AsRef<Path>>(path: P) -> io::Result<u16> {
+        Self::read_and_validate_header(&mut File::open(path)?)
+    }
+
+    /// The stored checkpoint of the cube at `path` if it still describes `f` (see
+    /// `data::checkpoint`), or an empty one.
+    fn resume_point(f: &mut File, path: Option<&Path>, version: u16) -> io::Result<Checkpoint> {
+        let start = Checkpoint::start(Self::header_len(version));
+        let Some(cp) = path.and_then(checkpoint::load) else {
+            return Ok(start);
+        };
+        if cp.offset > f.metadata()?.len() {
+            return Ok(start);
+        }
+        let intact = match cp.anchor {
+            Some(anchor) => {
+                f.seek(SeekFrom::Start(anchor.offset))?;
+                Self::read_valid_entry(f)?.is_some_and(|(len, payload)| {
+                    anchor.offset + 4 + len as u64 == cp.offset
+                        && crc32fast::hash(&payload) == anchor.crc
+                })
+            }
+            None => cp.offset == Self::header_len(version),
+        };
+        Ok(if intact { cp } else { start })
+    }
+
+    /// Scan state of the whole file: the stored checkpoint brought up to date by scanning the
+    /// records written after it.
+    ///
+    /// Used for `next_id` recovery and deduplication.
+    fn scan_checkpoint(f: &mut File, path: Option<&Path>) -> io::Result<Checkpoint> {
+        let version = Self::read_and_validate_header(f)?;
+        let mut cp = Self::resume_point(f, path, version)?;
+        f.seek(SeekFrom::Start(cp.offset))?;
+        while let Some((len, payload)) = Self::read_valid_entry(f)? {
             if payload.len() >= 16 + 8 {
                 let id = u64::from_le_bytes(payload[16..24].try_into().unwrap());
-                max_id = Some(max_id.map_or(id, |m| m.max(id)));
+                cp.max_id = Some(cp.max_id.map_or(id, |m| m.max(id)));
+            }
+            if let Ok(Some(ev)) = Self::parse_payload(&payload, version) {
+                let hash = match ev.kind {
+                    RecordType::Ref => serde_json::from_str::<ContentRef>(&ev.noumenon)
+                        .map(|r| r.hash)
+                        .unwrap_or_default(),
+                    _ => blake3::hash(ev.noumenon.as_bytes()).to_hex().to_string(),
+                };
+                if ev.kind == RecordType::File {
+                    cp.contents.entry(hash.clone()).or_insert(ev.id);
+                }
+                cp.seen.insert(paths::canonical(&ev.phenomenon), hash);
             }
+            cp.anchor = Some(Anchor {
+                offset: cp.offset,
+                crc: crc32fast::hash(&payload),
+            });
+            cp.offset += 4 + len as u64;
         }
-        Ok(max_id)
+        Ok(cp)
     }
 
-    /// Append a new record with the given phenomenon and noumenon, returning its byte offset.
+    /// Scan the records written since the last checkpoint and store an up-to-date checkpoint
+    /// next to the cube, so that the next recovery or deduplication starts from here.
     ///
-    /// Guarantees:
-    /// - Appends at EOF.
-    /// - Flushes data to disk (`sync_data`) for crash safety.
-    /// - Increments and persists `next_id` in the header.
-    pub fn append(&mut self, phenomenon: &str, noumenon: &str) -> io::Result<u64> {
-        // ensure we are at the end
-        let start = self.f.seek(SeekFrom::End(0))?;
+    /// No-op for writers not opened by path.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let Some(path) = self.bus_key.clone() else {
+            return Ok(());
+        };
+        self.flush_bulk()?;
+        let cp = Self::scan_checkpoint(&mut self.f, Some(&path))?;
+        self.f.seek(SeekFrom::End(0))?;
+        checkpoint::save(&path, &cp)
+    }
 
-        // payload
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map_err(|_| io::Error::other("SystemTime before UNIX_EPOCH"))?
-            .as_nanos();
-        let ph = phenomenon.as_bytes();
-        let no = noumenon.as_bytes();
-        let id = self.next_id;
+    /// Enter bulk mode: subsequent appends are buffered and neither synced nor reflected in the header
+    /// until `finish_bulk`.
+    ///
+    /// The header's `next_id` is reset to 0 while bulk mode is active so that a crash before
+    /// `finish_bulk` is recovered by the regular scan in `create()`. No-op if already in bulk mode.
+    pub fn begin_bulk(&mut self) -> io::Result<()> {
+        if self.bulk.is_some() {
+            return Ok(());
+        }
+        self.f.lock()?;
+        // Pick up records appended by other processes since this writer was opened.
+        self.next_id = self.next_id.max(Self::read_header_next_id(&mut self.f)?);
+        Self::write_header_next_id(&mut self.f, 0)?;
+        self.f.sync_data()?;
+        self.bulk_base = self.f.seek(SeekFrom::End(0))?;
+        self.bulk = Some(Vec::with_capacity(self.io.bulk_buffer));
+        Ok(())
+    }
 
-        // len_total (u32) + ts(u128) + id(u64) + ph_len(u16) + no_len(u16) + ph + no + crc(u32)
-        let mut buf = Vec::with_capacity(4 + 16 + 8 + 2 + 2 + ph.len() + no.len() + 4);
+    /// Leave bulk mode: write pending records, persist `next_id`, `sync_all()` once, then notify
+    /// subscribers of the imported records and refresh the scan checkpoint.
+    pub fn finish_bulk(&mut self) -> io::Result<()> {
+        if self.bulk.is_none() {
+            return Ok(());
+        }
+        self.flush_bulk()?;
+        self.bulk = None;
+        Self::write_header_next_id(&mut self.f, self.next_id)?;
+        self.f.sync_all()?;
+        self.f.unlock()?;
+        for ev in std::mem::take(&mut self.bulk_events) {
+            self.notify(&ev);
+        }
+        self.checkpoint()
+    }
+
+    /// Canonical path of the cube file, for writers opened by path.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.bus_key.as_deref()
+    }
+
+    /// File offset at which the next record will be written.
+    pub(crate) fn end_offset(&mut self) -> io::Result<u64> {
+        match &self.bulk {
+            Some(buf) => Ok(self.bulk_base + buf.len() as u64),
+            None => self.f.seek(SeekFrom::End(0)),
+        }
+    }
+
+    /// Make the records buffered in bulk mode durable (records, NEXT_ID, `sync_all()`) while
+    /// staying in bulk mode with the lock held. Subscribers are still notified by `finish_bulk`.
+    pub(crate) fn sync_bulk(&mut self) -> io::Result<()> {
+        self.flush_bulk()?;
+        Self::write_header_next_id(&mut self.f, self.next_id)?;
+        self.f.sync_all()
+    }
+
+    /// Leave bulk mode discarding everything written since the cube was `len` bytes long with
+    /// `next_id` in its header, then release the lock. Nobody is notified.
+    pub(crate) fn abort_bulk(&mut self, len: u64, next_id: u64) -> io::Result<()> {
+        if self.bulk.take().is_none() {
+            return Ok(());
+        }
+        self.bulk_events.clear();
+        self.next_id = next_id;
+        let result = Self::truncate_to(&mut self.f, len, next_id);
+        self.f.unlock()?;
+        result
+    }
+
+    /// Cut the cube file `f` back to `len` bytes and store `next_id` in its header, bumping
+    /// the generation of its identity. The caller holds the file lock.
+    pub(crate) fn truncate_to(f: &mut File, len: u64, next_id: u64) -> io::Result<()> {
+        f.set_len(len)?;
+        Self::write_header_next_id(f, next_id)?;
+        if let Some(identity) = Self::read_identity(f)? {
+            Self::write_header_identity(f, &identity.bumped())?;
+        }
+        f.sync_all()?;
+        f.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Deliver an appended event to `subscribe` receivers and the process-wide bus.
+    fn notify(&mut self, ev: &Event) {
+        self.subscribers.retain(|tx| tx.send(ev.clone()).is_ok());
+        if let Some(key) = &self.bus_key {
+            bus::publish(key, ev);
+        }
+    }
+
+    /// Write the bulk buffer (if any) at EOF without syncing, pre-allocating space first when
+    /// configured.
+    fn flush_bulk(&mut self) -> io::Result<()> {
+        if let Some(buf) = self.bulk.as_mut().filter(|b| !b.is_empty()) {
+            let end = self.bulk_base + buf.len() as u64;
+            if self.io.preallocate > 0 && end > self.allocated {
+                let len = self.io.preallocate.max(buf.len() as u64);
+                storage::preallocate(&self.f, self.bulk_base, len)?;
+                self.allocated = self.bulk_base + len;
+            }
+            self.f.seek(SeekFrom::End(0))?;
+            self.f.write_all(buf)?;
+            self.bulk_base += buf.len() as u64;
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Encode one record (LEN_TOTAL prefix, payload and CRC) of a cube of `version`, ready to be
+    /// written at EOF. Version 1 records leave `kind` out, versions before 4 the attribute block
+    /// `attrs` (see `encode_attributes`).
+    ///
+    /// Callers are responsible for keeping `ph`, `no` and `attrs` within `u16::MAX` bytes.
+    fn encode_record(
+        version: u16,
+        ts: u128,
+        id: u64,
+        kind: RecordType,
+        ph: &[u8],
+        no: &[u8],
+        attrs: &[u8],
+    ) -> Vec<u8> {
+        let attrs = if version > Self::V3 {
+            Some(attrs)
+        } else {
+            None
+        };
+        // len_total (u32) + ts(u128) + id(u64) + type(u8) + ph_len(u16) + no_len(u16)
+        // + at_len(u16) + ph + no + attrs + crc(u32)
+        let at_len = attrs.map_or(0, |a| 2 + a.len());
+        let mut buf = Vec::with_capacity(4 + 16 + 8 + 1 + 2 + 2 + at_len + ph.len() + no.len() + 4);
 
         // len_total placeholder (u32)
         buf.extend_from_slice(&[0u8; 4]);
         buf.extend_from_slice(&ts.to_le_bytes());
         buf.extend_from_slice(&id.to_le_bytes());
+        if version > Self::V1 {
+            buf.push(kind.byte());
+        }
         buf.extend_from_slice(&(ph.len() as u16).to_le_bytes());
         buf.extend_from_slice(&(no.len() as u16).to_le_bytes());
+        if let Some(attrs) = attrs {
+            buf.extend_from_slice(&(attrs.len() as u16).to_le_bytes());
+        }
         buf.extend_from_slice(ph);
         buf.extend_from_slice(no);
+        if let Some(attrs) = attrs {
+            buf.extend_from_slice(attrs);
+        }
 
         // compute checksum on everything after len_total
         let mut hasher = Hasher::new();
@@ -321,47 +1095,528 @@ impl Writer {
         let len_total = (buf.len() - 4 + 4) as u32; // excluding len field, including crc
         buf[0..4].copy_from_slice(&len_total.to_le_bytes());
         buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Overwrite the open cube file `f` in place with a complete image holding `events` verbatim,
+    /// with their original ids, timestamps and kinds, and `next_id` in the header; the file is
+    /// then truncated to the image and synced. The image keeps the version of `f`, or gets the
+    /// current one when `f` is empty.
+    ///
+    /// The image gets `identity` when given. Otherwise it is a new generation of `f`, whose
+    /// identity is bumped, or a new cube with a fresh identity when `f` is empty.
+    ///
+    /// This is the building block for compaction and archiving: processors are not run and no
+    /// subscriber is notified. Rewriting in place (rather than replacing the file) keeps handles
+    /// held by other writers valid. The caller holds the file lock.
+    pub fn write_image(
+        f: &mut File,
+        events: &[Event],
+        next_id: u64,
+        identity: Option<&Identity>,
+    ) -> io::Result<()> {
+        let (version, current) = match f.metadata()?.len() {
+            0 => (Self::VERSION, None),
+            _ => (Self::read_and_validate_header(f)?, Self::read_identity(f)?),
+        };
+        let identity = match (identity, current) {
+            (Some(identity), _) => identity.clone(),
+            (None, Some(current)) => current.bumped(),
+            (None, None) => Identity::fresh(),
+        };
+        Self::write_header(f, version, next_id, &identity)?;
+        let mut out = io::BufWriter::new(&mut *f);
+        for ev in events {
+            let (ph, no) = (ev.phenomenon.as_bytes(), ev.noumenon.as_bytes());
+            if ph.len() > u16::MAX as usize || no.len() > u16::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("record {} too large", ev.id),
+                ));
+            }
+            out.write_all(&Self::encode_record(
+                version,
+                ev.timestamp,
+                ev.id,
+                ev.kind,
+                ph,
+                no,
+                &Self::encode_attributes(&ev.attributes)?,
+            ))?;
+        }
+        out.flush()?;
+        drop(out);
+        let end = f.stream_position()?;
+        f.set_len(end)?;
+        f.sync_all()
+    }
+
+    /// Append `ev` as it is, with its own id and timestamp; see `append_events`.
+    pub fn append_event(&mut self, ev: &Event) -> io::Result<Appended> {
+        let mut appended = self.append_events(std::slice::from_ref(ev))?;
+        Ok(appended.remove(0))
+    }
+
+    /// Append `events` as they are, with their original ids and timestamps, e.g. records copied
+    /// from another replica of the cube by `ak pull` (see `data::remote`) or replayed from a log.
+    ///
+    /// All or nothing: every event is checked before anything is written (ids increasing and at
+    /// least `next_id`, fields within `MAX_FIELD_LEN`, no reserved `Custom` kind), and a failed
+    /// write is cut back off the cube. Kinds are dropped by version 1 cubes, attributes (their
+    /// provenance included) by cubes before version 4. `next_id` then moves past the last id.
+    /// Processors are not run; subscribers are notified once the records are durable, which in
+    /// bulk mode is `finish_bulk`.
+    pub fn append_events(&mut self, events: &[Event]) -> io::Result<Vec<Appended>> {
+        if self.bulk.is_some() {
+            let (buf, offsets, next) = self.encode_verbatim(events, self.next_id)?;
+            let start = self.end_offset()?;
+            if let Some(pending) = self.bulk.as_mut() {
+                pending.extend_from_slice(&buf);
+                if pending.len() >= self.io.bulk_buffer {
+                    self.flush_bulk()?;
+                }
+            }
+            self.next_id = next;
+            if !self.subscribers.is_empty()
+                || self.bus_key.as_deref().is_some_and(bus::has_subscribers)
+            {
+                self.bulk_events.extend_from_slice(events);
+            }
+            return Ok(Self::appended(events, start, offsets));
+        }
+        let req = || Request::AppendEvents {
+            token: None,
+            events: events.to_vec(),
+        };
+        if let Some(appended) = self.via_daemon(req)? {
+            return Ok(appended);
+        }
+        self.f.lock()?;
+        let result = Self::read_header_next_id(&mut self.f).and_then(|stored| {
+            let (buf, offsets, next) = self.encode_verbatim(events, self.next_id.max(stored))?;
+            let start = self.f.seek(SeekFrom::End(0))?;
+            if let Err(e) = self.f.write_all(&buf).and_then(|_| self.f.sync_data()) {
+                // Leave no torn record for later appends to follow.
+                let _ = self.f.set_len(start);
+                return Err(e);
+            }
+            Self::write_header_next_id(&mut self.f, next)?;
+            self.next_id = next;
+            Ok(Self::appended(events, start, offsets))
+        });
+        self.f.unlock()?;
+        let appended = result?;
+        for ev in events {
+            self.notify(ev);
+        }
+        Ok(appended)
+    }
+
+    /// Encode `events` verbatim for `append_events`, checking them against the cube's `next`
+    /// id. Returns the records, the offset of each within them and the next id afterwards.
+    fn encode_verbatim(
+        &self,
+        events: &[Event],
+        mut next: u64,
+    ) -> io::Result<(Vec<u8>, Vec<u64>, u64)> {
+        let mut buf = Vec::new();
+        let mut offsets = Vec::with_capacity(events.len());
+        for ev in events {
+            let (ph, no) = (ev.phenomenon.as_bytes(), ev.noumenon.as_bytes());
+            if ev.id < next {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("record {} is not past the cube's ids (next {next})", ev.id),
+                ));
+            }
+            if ph.len() > Self::MAX_FIELD_LEN || no.len() > Self::MAX_FIELD_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("record {} too large", ev.id),
+                ));
+            }
+            ev.kind.validate()?;
+            let attrs = Self::encode_attributes(&ev.attributes)?;
+            offsets.push(buf.len() as u64);
+            buf.extend_from_slice(&Self::encode_record(
+                self.version,
+                ev.timestamp,
+                ev.id,
+                ev.kind,
+                ph,
+                no,
+                &attrs,
+            ));
+            next = ev
+                .id
+                .checked_add(1)
+                .ok_or_else(|| io::Error::other("id overflow"))?;
+        }
+        Ok((buf, offsets, next))
+    }
+
+    /// `Appended` of each of `events`, written at `start` plus its offset.
+    fn appended(events: &[Event], start: u64, offsets: Vec<u64>) -> Vec<Appended> {
+        events
+            .iter()
+            .zip(offsets)
+            .map(|(ev, at)| Appended {
+                offset: start + at,
+                id: ev.id,
+            })
+            .collect()
+    }
+
+    /// Append a new record with the given phenomenon and noumenon, returning its byte offset.
+    ///
+    /// Guarantees:
+    /// - Appends at EOF.
+    /// - Flushes data to disk (`sync_data`) for crash safety.
+    /// - Increments and persists `next_id` in the header.
+    /// - Rejects phenomena or noumena longer than `u16::MAX` bytes with `InvalidInput`.
+    /// - Runs `Stage::Append` processors first; their output is what gets persisted.
+    /// - Notifies `subscribe` receivers and the process-wide `data::bus` afterwards.
+    ///
+    /// In bulk mode the record is only buffered; durability is deferred to `finish_bulk`.
+    /// Otherwise the append runs under an exclusive file lock and first picks up `next_id`
+    /// from the header, in case another process appended since this writer was opened.
+    pub fn append(&mut self, phenomenon: &str, noumenon: &str) -> io::Result<u64> {
+        self.append_record(phenomenon, noumenon).map(|a| a.offset)
+    }
+
+    /// Like `append`, but also returns the id the record was assigned.
+    pub fn append_record(&mut self, phenomenon: &str, noumenon: &str) -> io::Result<Appended> {
+        self.append_kind(None, phenomenon, noumenon)
+    }
+
+    /// Like `append_record`, with an explicit kind of record instead of the one inferred from the
+    /// phenomenon (see `RecordType::infer`). Version 1 cubes do not store it.
+    pub fn append_typed(
+        &mut self,
+        kind: RecordType,
+        phenomenon: &str,
+        noumenon: &str,
+    ) -> io::Result<Appended> {
+        kind.validate()?;
+        self.append_kind(Some(kind), phenomenon, noumenon)
+    }
+
+    /// `append_record` or `append_typed`, taking the file lock outside bulk mode.
+    fn append_kind(
+        &mut self,
+        kind: Option<RecordType>,
+        phenomenon: &str,
+        noumenon: &str,
+    ) -> io::Result<Appended> {
+        if self.bulk.is_some() {
+            // The lock is already held for the whole bulk import.
+            return self.append_locked(kind, phenomenon, noumenon);
+        }
+        let attributes = match self.daemon {
+            Some(_) => self.provenance.clone(),
+            None => BTreeMap::new(),
+        };
+        let req = move || Request::Append {
+            token: None,
+            kind,
+            phenomenon: phenomenon.to_string(),
+            noumenon: noumenon.to_string(),
+            attributes,
+        };
+        if let Some(mut appended) = self.via_daemon(req)? {
+            return Ok(appended.remove(0));
+        }
+        self.f.lock()?;
+        let result = Self::read_header_next_id(&mut self.f).and_then(|stored| {
+            self.next_id = self.next_id.max(stored);
+            self.append_locked(kind, phenomenon, noumenon)
+        });
+        self.f.unlock()?;
+        result
+    }
+
+    /// Send the append `req` to the daemon of the cube, if one serves it. `None` without a
+    /// daemon, or once it stopped: this writer then appends to the file itself from now on.
+    fn via_daemon(&mut self, req: impl FnOnce() -> Request) -> io::Result<Option<Vec<Appended>>> {
+        let Some(socket) = &self.daemon else {
+            return Ok(None);
+        };
+        match daemon::call(socket, &req())? {
+            None => {
+                self.daemon = None;
+                Ok(None)
+            }
+            Some(Response::Appended { offsets, events }) => {
+                if let Some(last) = events.last() {
+                    self.next_id = self.next_id.max(last.id.saturating_add(1));
+                }
+                for ev in &events {
+                    self.notify(ev);
+                }
+                Ok(Some(Self::appended(&events, 0, offsets)))
+            }
+            Some(Response::Refused { exceeded }) => Err(io::Error::new(
+                io::ErrorKind::QuotaExceeded,
+                exceeded.to_string(),
+            )),
+            Some(Response::Error {
+                message,
+                invalid_input,
+            }) => Err(io::Error::new(
+                if invalid_input {
+                    io::ErrorKind::InvalidInput
+                } else {
+                    io::ErrorKind::Other
+                },
+                message,
+            )),
+            Some(other) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected daemon reply {other:?}"),
+            )),
+        }
+    }
+
+    /// Body of `append`, called with the file lock held. Without a `kind`, the one of the record
+    /// is inferred from its phenomenon once processors have run.
+    fn append_locked(
+        &mut self,
+        kind: Option<RecordType>,
+        phenomenon: &str,
+        noumenon: &str,
+    ) -> io::Result<Appended> {
+        // ensure we are at the end
+        let start = match &self.bulk {
+            Some(buf) => self.bulk_base + buf.len() as u64,
+            None => self.f.seek(SeekFrom::End(0))?,
+        };
+
+        // payload
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| io::Error::other("SystemTime before UNIX_EPOCH"))?
+            .as_nanos();
+        let id = self.next_id;
 
-        // Write record
-        self.f.write_all(&buf)?;
-        self.f.sync_data()?; // crash-safety for appended record
+        let mut builder = Event::builder()
+            .id(id)
+            .phenomenon(phenomenon)
+            .noumenon(noumenon)
+            .timestamp(ts)
+            .attributes(self.provenance.clone());
+        if let Some(kind) = kind {
+            builder = builder.kind(kind);
+        }
+        let mut ev = builder.build()?;
+        if !self.processors.is_empty() {
+            self.run_processors(Stage::Append, &mut ev)?;
+            ev.kind = kind.unwrap_or_else(|| RecordType::infer(&ev.phenomenon));
+        }
+        let ph = ev.phenomenon.as_bytes();
+        let no = ev.noumenon.as_bytes();
+        // Lengths are stored as u16: refuse instead of silently truncating the prefix.
+        if ph.len() > Self::MAX_FIELD_LEN || no.len() > Self::MAX_FIELD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "record too large (phenomenon {} bytes, noumenon {} bytes, max {} each)",
+                    ph.len(),
+                    no.len(),
+                    Self::MAX_FIELD_LEN
+                ),
+            ));
+        }
+
+        let attrs = Self::encode_attributes(&ev.attributes)?;
+        let buf = Self::encode_record(self.version, ts, id, ev.kind, ph, no, &attrs);
 
-        // Bump and persist next_id
+        // Bump next_id
         self.next_id = self
             .next_id
             .checked_add(1)
             .ok_or_else(|| io::Error::other("id overflow"))?;
-        Self::write_header_next_id(&mut self.f, self.next_id)?;
 
-        Ok(start) // offset useful for external indexing
+        if let Some(pending) = self.bulk.as_mut() {
+            // Bulk mode: buffer only, flushing in large chunks.
+            pending.extend_from_slice(&buf);
+            if pending.len() >= self.io.bulk_buffer {
+                self.flush_bulk()?;
+            }
+        } else {
+            // Write record
+            self.f.write_all(&buf)?;
+            self.f.sync_data()?; // crash-safety for appended record
+            Self::write_header_next_id(&mut self.f, self.next_id)?;
+        }
+
+        // Notify subscribers once the record is durable; in bulk mode that is `finish_bulk`.
+        if self.bulk.is_some() {
+            if !self.subscribers.is_empty()
+                || self.bus_key.as_deref().is_some_and(bus::has_subscribers)
+            {
+                self.bulk_events.push(ev);
+            }
+        } else {
+            self.notify(&ev);
+        }
+
+        Ok(Appended { offset: start, id })
     }
 
     /// Iterate over the file and print all valid records in a human-readable form.
     ///
     /// Stops on the first invalid/truncated record (typical for append-only logs with partial tails).
     pub fn read_all(&mut self) -> io::Result<()> {
+        self.flush_bulk()?;
         Self::read_and_validate_header(&mut self.f)?;
-        self.f.seek(SeekFrom::Start(Self::HEADER_LEN))?;
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
 
-        let mut off = Self::HEADER_LEN;
+        let mut off = Self::header_len(self.version);
         while let Some((len, payload)) = Self::read_valid_entry(&mut self.f)? {
-            if let Some((ts, id, ph, no)) = Self::parse_payload(&payload)? {
-                println!("\nid={id} ts={ts} ph={ph} no={no}\n");
+            if let Some(mut ev) = Self::parse_payload(&payload, self.version)? {
+                self.run_processors(Stage::Read, &mut ev)?;
+                let from = provenance::origin(&ev)
+                    .map(|o| format!(" from=({o})"))
+                    .unwrap_or_default();
+                println!(
+                    "\nid={} ts={} ph={} no={}{from}\n",
+                    ev.id, ev.timestamp, ev.phenomenon, ev.noumenon
+                );
             }
             off = off.saturating_add(4 + len as u64);
         }
         Ok(())
     }
 
+    /// Collect every valid record of the cube as `Event`s, in on-disk order.
+    ///
+    /// Stops on the first invalid/truncated record, like `read_all`. `Stage::Read` processors are
+    /// applied to every returned event.
+    pub fn events(&mut self) -> io::Result<Vec<Event>> {
+        self.flush_bulk()?;
+        Self::read_and_validate_header(&mut self.f)?;
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
+
+        let mut out = Vec::new();
+        while let Some((_, payload)) = Self::read_valid_entry(&mut self.f)? {
+            if let Some(ev) = Self::parse_payload(&payload, self.version)? {
+                out.push(ev);
+            }
+        }
+        Self::resolve_refs(&mut out, &HashMap::new());
+        if !self.processors.is_empty() {
+            for ev in out.iter_mut() {
+                self.run_processors(Stage::Read, ev)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Like `events`, keeping the records of the given `kinds` only.
+    pub fn events_of(&mut self, kinds: &[RecordType]) -> io::Result<Vec<Event>> {
+        let mut out = self.events()?;
+        out.retain(|ev| kinds.contains(&ev.kind));
+        Ok(out)
+    }
+
+    /// Format version of the cube (see the module documentation).
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Attributes stamped on the records this writer creates: the provenance of this process
+    /// (`data::provenance::current`) unless set otherwise.
+    pub fn provenance(&self) -> &BTreeMap<String, String> {
+        &self.provenance
+    }
+
+    /// Stamp the records this writer creates from now on with `attributes` instead, e.g. the
+    /// provenance of the client a service appends for. Records copied by `append_events` keep
+    /// their own.
+    pub fn set_provenance(&mut self, attributes: BTreeMap<String, String>) {
+        self.provenance = attributes;
+    }
+
+    /// Return the first `n` valid records, reading no further than needed.
+    pub fn head(&mut self, n: usize) -> io::Result<Vec<Event>> {
+        self.flush_bulk()?;
+        Self::read_and_validate_header(&mut self.f)?;
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
+
+        let mut out = Vec::with_capacity(n.min(1024));
+        while out.len() < n {
+            let Some((_, payload)) = Self::read_valid_entry(&mut self.f)? else {
+                break;
+            };
+            if let Some(ev) = Self::parse_payload(&payload, self.version)? {
+                out.push(ev);
+            }
+        }
+        // Referenced contents come first in the log, hence among these records.
+        Self::resolve_refs(&mut out, &HashMap::new());
+        for ev in out.iter_mut() {
+            self.run_processors(Stage::Read, ev)?;
+        }
+        Ok(out)
+    }
+
+    /// Return the last `n` valid records, in on-disk order.
+    ///
+    /// Always the last `n` elements of `events`: the scan verifies every record and stops at the
+    /// first invalid one, like `events` does, but only keeps `n` payloads in memory.
+    pub fn tail(&mut self, n: usize) -> io::Result<Vec<Event>> {
+        self.flush_bulk()?;
+        Self::read_and_validate_header(&mut self.f)?;
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
+
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let mut last = std::collections::VecDeque::with_capacity(n.min(1024) + 1);
+        while let Some((_, payload)) = Self::read_valid_entry(&mut self.f)? {
+            last.push_back(payload);
+            if last.len() > n {
+                last.pop_front();
+            }
+        }
+
+        let mut out = Vec::with_capacity(last.len());
+        for payload in last {
+            if let Some(ev) = Self::parse_payload(&payload, self.version)? {
+                out.push(ev);
+            }
+        }
+        let shown: HashSet<u64> = out.iter().map(|e| e.id).collect();
+        let missing: HashSet<u64> = out
+            .iter()
+            .filter(|e| e.kind == RecordType::Ref)
+            .filter_map(|e| serde_json::from_str::<ContentRef>(&e.noumenon).ok())
+            .map(|r| r.id)
+            .filter(|id| !shown.contains(id))
+            .collect();
+        let found = self.contents_of(&missing)?;
+        Self::resolve_refs(&mut out, &found);
+        for ev in out.iter_mut() {
+            self.run_processors(Stage::Read, ev)?;
+        }
+        Ok(out)
+    }
+
     /// Build an index of id -> file offset for all valid records.
     ///
     /// If duplicate ids are present (unexpected), the last one wins.
     pub fn rebuild_index(&mut self) -> io::Result<BTreeMap<u64, u64>> {
+        self.flush_bulk()?;
         let mut idx = BTreeMap::new();
         Self::read_and_validate_header(&mut self.f)?;
-        self.f.seek(SeekFrom::Start(Self::HEADER_LEN))?;
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
 
-        let mut off = Self::HEADER_LEN;
+        let mut off = Self::header_len(self.version);
         while let Some((len, payload)) = Self::read_valid_entry(&mut self.f)? {
             // id is located after ts (16 bytes)
             if payload.len() < 16 + 8 {
@@ -381,7 +1636,7 @@ impl Writer {
     /// - `Ok(Some((len, payload)))` for a valid record
     /// - `Ok(None)` on EOF, partial tail, invalid length, truncated entry, or CRC mismatch
     /// - `Err(_)` on underlying IO errors during reads
-    fn read_valid_entry(f: &mut File) -> io::Result<Option<(usize, Vec<u8>)>> {
+    fn read_valid_entry<R: Read>(f: &mut R) -> io::Result<Option<(usize, Vec<u8>)>> {
         let mut len_buf = [0u8; 4];
         let n = f.read(&mut len_buf)?;
         if n == 0 {
@@ -393,10 +1648,9 @@ impl Writer {
         }
 
         let len = u32::from_le_bytes(len_buf) as usize;
-        // minimal payload (ts + id + ph_len + no_len) + crc
-        const MIN_PAYLOAD: usize = 16 + 8 + 2 + 2;
-        const CRC_LEN: usize = 4;
-        if len < MIN_PAYLOAD + CRC_LEN {
+        // minimal payload (ts + id + ph_len + no_len) + crc; anything above the maximum is garbage
+        // and must not drive a huge allocation.
+        if !(Self::MIN_PAYLOAD + Self::CRC_LEN..=Self::MAX_ENTRY_LEN).contains(&len) {
             return Ok(None);
         }
 
@@ -407,7 +1661,7 @@ impl Writer {
         }
 
         // split payload / checksum
-        let (payload, crc_bytes) = entry.split_at(len - CRC_LEN);
+        let (payload, crc_bytes) = entry.split_at(len - Self::CRC_LEN);
         let mut hasher = Hasher::new();
         hasher.update(payload);
         let expected_crc = hasher.finalize();
@@ -421,56 +1675,144 @@ impl Writer {
         Ok(Some((len, payload.to_vec())))
     }
 
-    /// Build an in-memory map of path -> last known content hash by scanning the log.
+    /// Scan state of the whole log, with the in-memory maps of path -> last known content hash
+    /// and content hash -> record.
     ///
     /// The content hash is computed as BLAKE3 over the noumenon bytes of the last valid record
-    /// for each path (phenomenon). This supports deduplication in `store_directory`.
-    fn rebuild_seen_index_from_log(&mut self) -> HashMap<PathBuf, String> {
-        let mut seen = HashMap::new();
+    /// for each path (phenomenon, in canonical form so pre-normalization records still match).
+    /// This supports deduplication in `store_directory`. Only the records after the stored
+    /// checkpoint are scanned.
+    fn scan_state(&mut self) -> Checkpoint {
+        if self.flush_bulk().is_err() {
+            return Checkpoint::start(Self::header_len(self.version));
+        }
+        Self::scan_checkpoint(&mut self.f, self.bus_key.as_deref())
+            .unwrap_or_else(|_| Checkpoint::start(Self::header_len(self.version)))
+    }
+
+    /// Id of the first file record of the cube whose contents have BLAKE3 (hex) `hash`.
+    ///
+    /// Answered from the scan checkpoint, so only the records written after it are read.
+    pub fn find_by_hash(&mut self, hash: &str) -> io::Result<Option<u64>> {
+        self.flush_bulk()?;
+        let cp = Self::scan_checkpoint(&mut self.f, self.bus_key.as_deref())?;
+        Ok(cp.contents.get(hash).copied())
+    }
+
+    /// Read a file and append its contents to the log; returns where the record went and the
+    /// length of the contents.
+    ///
+    /// `phenomenon` is the portable path of the file, and its contents are the noumenon.
+    fn append_file_contents(
+        &mut self,
+        path: &Path,
+        phenomenon: &str,
+    ) -> io::Result<(Appended, u64)> {
+        let content = read_to_string(paths::long(path))?;
+        let appended = self.append_record(phenomenon, &content)?;
+        Ok((appended, content.len() as u64))
+    }
 
-        // Validate header and position after it; return empty on failure for safety.
-        if Self::read_and_validate_header(&mut self.f).is_err() {
-            return seen;
+    /// Give the `Ref` records of `events` the contents they name, taken from `events` or else
+    /// from `found` (record id -> contents). References to records found in neither stay as
+    /// they are.
+    fn resolve_refs(events: &mut [Event], found: &HashMap<u64, String>) {
+        if !events.iter().any(|e| e.kind == RecordType::Ref) {
+            return;
         }
-        if self.f.seek(SeekFrom::Start(Self::HEADER_LEN)).is_err() {
-            return seen;
+        let mut files: HashMap<u64, usize> = HashMap::new();
+        for i in 0..events.len() {
+            match events[i].kind {
+                RecordType::File => {
+                    files.insert(events[i].id, i);
+                }
+                RecordType::Ref => {
+                    let Ok(r) = serde_json::from_str::<ContentRef>(&events[i].noumenon) else {
+                        continue;
+                    };
+                    let contents = match files.get(&r.id) {
+                        Some(&j) => Some(events[j].noumenon.clone()),
+                        None => found.get(&r.id).cloned(),
+                    };
+                    if let Some(contents) = contents {
+                        events[i].noumenon = contents;
+                        events[i].kind = RecordType::File;
+                    }
+                }
+                _ => {}
+            }
         }
+    }
 
-        // Scan all valid entries; the last one for a given path wins.
-        while let Ok(Some((_, payload))) = Self::read_valid_entry(&mut self.f) {
-            if let Ok(Some((_ts, _id, ph, no))) = Self::parse_payload(&payload) {
-                let hash = blake3::hash(no.as_bytes()).to_hex().to_string();
-                seen.insert(PathBuf::from(ph), hash);
+    /// Make the `Ref` records of `a` whose target is in `b`, and those of `b` whose target is in
+    /// `a`, file records holding the target's contents, so that each part of a split cube can be
+    /// written on its own (see `retention`, `gc` and `prune`).
+    pub fn detach_refs(a: &mut [Event], b: &mut [Event]) {
+        fn detach(part: &mut [Event], other: &[Event]) {
+            let here: HashSet<u64> = part.iter().map(|e| e.id).collect();
+            for ev in part.iter_mut().filter(|e| e.kind == RecordType::Ref) {
+                let Ok(r) = serde_json::from_str::<ContentRef>(&ev.noumenon) else {
+                    continue;
+                };
+                if here.contains(&r.id) {
+                    continue;
+                }
+                if let Some(target) = other
+                    .iter()
+                    .find(|e| e.id == r.id && e.kind == RecordType::File)
+                {
+                    ev.noumenon = target.noumenon.clone();
+                    ev.kind = RecordType::File;
+                }
             }
         }
+        detach(a, b);
+        detach(b, a);
+    }
 
-        seen
+    /// Contents of the records `ids`, read from the whole log.
+    fn contents_of(&mut self, ids: &HashSet<u64>) -> io::Result<HashMap<u64, String>> {
+        let mut out = HashMap::new();
+        if ids.is_empty() {
+            return Ok(out);
+        }
+        self.f
+            .seek(SeekFrom::Start(Self::header_len(self.version)))?;
+        while let Some((_, payload)) = Self::read_valid_entry(&mut self.f)? {
+            let id = u64::from_le_bytes(payload[16..24].try_into().unwrap());
+            if ids.contains(&id)
+                && let Some(ev) = Self::parse_payload(&payload, self.version)?
+            {
+                out.insert(id, ev.noumenon);
+            }
+        }
+        Ok(out)
     }
 
-    /// Read a file and append its contents to the log.
-    ///
-    /// The file path is stored as the phenomenon, and its contents as the noumenon.
-    fn append_file_contents(&mut self, path: &Path) -> io::Result<u64> {
-        let content = read_to_string(path)?;
-        self.append(&path.display().to_string(), &content)
+    /// Whether the file at `path` is too large to be the noumenon of a record: `inscribe`, seals
+    /// and the reference tree leave such files out.
+    pub fn too_large(path: &Path) -> bool {
+        fs::metadata(paths::long(path)).is_ok_and(|m| m.len() > Self::MAX_FIELD_LEN as u64)
     }
 
     /// Compute a BLAKE3 hash of a file's raw bytes, returned as a lowercase hex string.
     ///
     /// This function reads bytes (not text) so it works for both text and binary files.
     fn file_hash(path: &Path) -> io::Result<String> {
-        let bytes = fs::read(path)?;
+        let bytes = fs::read(paths::long(path))?;
         let hash = blake3::hash(&bytes);
         Ok(hash.to_hex().to_string())
     }
 
-    /// Parse a payload into (timestamp, id, phenomenon, noumenon), validating bounds and UTF-8.
+    /// Parse a payload of a cube of `version` into an `Event`, validating bounds and UTF-8.
     ///
     /// Returns `Ok(Some(..))` on success, `Ok(None)` on malformed payload.
-    fn parse_payload(payload: &[u8]) -> io::Result<Option<(u128, u64, String, String)>> {
+    fn parse_payload(payload: &[u8], version: u16) -> io::Result<Option<Event>> {
         let mut p = 0usize;
 
-        if payload.len() < 16 + 8 + 2 + 2 {
+        let typed = version > Self::V1;
+        let attributed = version > Self::V3;
+        if payload.len() < Self::MIN_PAYLOAD + typed as usize + 2 * attributed as usize {
             return Ok(None);
         }
 
@@ -478,15 +1820,28 @@ impl Writer {
         p += 16;
         let id = u64::from_le_bytes(payload[p..p + 8].try_into().unwrap());
         p += 8;
+        let kind = if typed {
+            p += 1;
+            Some(RecordType::from_byte(payload[p - 1]))
+        } else {
+            None
+        };
 
         let ph_len = u16::from_le_bytes(payload[p..p + 2].try_into().unwrap()) as usize;
         p += 2;
         let no_len = u16::from_le_bytes(payload[p..p + 2].try_into().unwrap()) as usize;
         p += 2;
+        let at_len = if attributed {
+            p += 2;
+            u16::from_le_bytes(payload[p - 2..p].try_into().unwrap()) as usize
+        } else {
+            0
+        };
 
         // Bounds check
         if p.checked_add(ph_len)
             .and_then(|end| end.checked_add(no_len))
+            .and_then(|end| end.checked_add(at_len))
             .map(|end| end <= payload.len())
             != Some(true)
         {
@@ -496,33 +1851,70 @@ impl Writer {
         let ph_bytes = &payload[p..p + ph_len];
         p += ph_len;
         let no_bytes = &payload[p..p + no_len];
-
-        let ph = match std::str::from_utf8(ph_bytes) {
-            Ok(s) => s.to_string(),
-            Err(_) => return Ok(None),
+        p += no_len;
+        let Some(attributes) = Self::decode_attributes(&payload[p..p + at_len]) else {
+            return Ok(None);
         };
-        let no = match std::str::from_utf8(no_bytes) {
-            Ok(s) => s.to_string(),
-            Err(_) => return Ok(None),
+
+        // The builder checks UTF-8 and infers the kind of v1 records.
+        let Ok(mut ev) = Event::builder()
+            .id(id)
+            .phenomenon(ph_bytes)
+            .noumenon(no_bytes)
+            .timestamp(ts)
+            .attributes(attributes)
+            .build()
+        else {
+            return Ok(None);
         };
+        // Stored kinds are kept as they are, reserved bytes unknown to this version included.
+        if let Some(kind) = kind {
+            ev.kind = kind;
+        }
+        Ok(Some(ev))
+    }
 
-        Ok(Some((ts, id, ph, no)))
+    /// Read the raw bytes of the record starting at `offset` (LEN_TOTAL prefix included) without
+    /// validating them, for inspection with `data::dump`.
+    ///
+    /// The declared length is trusted only up to `MAX_ENTRY_LEN` and the end of the file, so a
+    /// corrupted record yields whatever bytes are actually there.
+    pub fn read_raw_at<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Vec<u8>> {
+        let mut f = File::open(path)?;
+        f.seek(SeekFrom::Start(offset))?;
+        let mut raw = Vec::new();
+        Read::by_ref(&mut f).take(4).read_to_end(&mut raw)?;
+        if raw.len() == 4 {
+            let len = u32::from_le_bytes(raw[..4].try_into().unwrap()) as u64;
+            f.take(len.min(Self::MAX_ENTRY_LEN as u64))
+                .read_to_end(&mut raw)?;
+        }
+        Ok(raw)
     }
 
     /// Random-access read of a record at `offset` in `path`, verifying CRC and returning an `Event`.
+    ///
+    /// Errors with `InvalidData` on an out-of-range length, CRC mismatch, or malformed payload.
     pub fn read_one_at<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Event> {
         let mut f = File::open(path)?;
+        let version = Self::read_and_validate_header(&mut f)?;
         f.seek(SeekFrom::Start(offset))?;
 
         let mut len_buf = [0u8; 4];
         f.read_exact(&mut len_buf)?;
         let len = u32::from_le_bytes(len_buf) as usize;
+        if !(Self::MIN_PAYLOAD + Self::CRC_LEN..=Self::MAX_ENTRY_LEN).contains(&len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid record length",
+            ));
+        }
 
         let mut entry = vec![0u8; len];
         f.read_exact(&mut entry)?;
 
         // CRC
-        let (payload, crc_bytes) = entry.split_at(len - 4);
+        let (payload, crc_bytes) = entry.split_at(len - Self::CRC_LEN);
         let mut h = Hasher::new();
         h.update(payload);
         if h.finalize() != u32::from_le_bytes(crc_bytes.try_into().unwrap()) {
@@ -530,26 +1922,51 @@ impl Writer {
         }
 
         // Parse
-        let mut p = 0;
-        let ts = u128::from_le_bytes(payload[p..p + 16].try_into().unwrap());
-        p += 16;
-        let id = u64::from_le_bytes(payload[p..p + 8].try_into().unwrap());
-        p += 8;
-        let ph_len = u16::from_le_bytes(payload[p..p + 2].try_into().unwrap()) as usize;
-        p += 2;
-        let no_len = u16::from_le_bytes(payload[p..p + 2].try_into().unwrap()) as usize;
-        p += 2;
+        Self::parse_payload(payload, version)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed payload"))
+    }
 
-        let ph = std::str::from_utf8(&payload[p..p + ph_len]).unwrap();
-        p += ph_len;
-        let no = std::str::from_utf8(&payload[p..p + no_len]).unwrap();
+    /// Verify every record of the cube image `bytes` (header + records): how many pass their CRC
+    /// and where the valid log ends. Bytes past that point are corrupt or truncated records.
+    pub fn check(bytes: &[u8]) -> io::Result<Check> {
+        let start = Self::header_len(Self::image_version(bytes)?);
+        let mut cursor = &bytes[start as usize..];
+        let mut check = Check {
+            records: 0,
+            valid_len: start,
+            len: bytes.len() as u64,
+        };
+        while let Some((len, _)) = Self::read_valid_entry(&mut cursor)? {
+            check.records += 1;
+            check.valid_len += 4 + len as u64;
+        }
+        Ok(check)
+    }
 
-        Ok(Event {
-            timestamp: ts,
-            id,
-            phenomenon: ph.to_string(),
-            noumenon: no.to_string(),
-        })
+    /// Decode an in-memory cube image (header + records) into its valid events.
+    ///
+    /// Mirrors the on-disk scan: errors on a missing/invalid header and stops at the first
+    /// invalid or truncated record. Processors are not applied. Never panics on arbitrary input,
+    /// which makes it the entry point for property and fuzz testing.
+    pub fn decode(bytes: &[u8]) -> io::Result<Vec<Event>> {
+        let version = Self::image_version(bytes)?;
+        let mut cursor = &bytes[Self::header_len(version) as usize..];
+        let mut out = Vec::new();
+        while let Some((_, payload)) = Self::read_valid_entry(&mut cursor)? {
+            if let Some(ev) = Self::parse_payload(&payload, version)? {
+                out.push(ev);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Drop for Writer {
+    /// Best-effort completion of an unfinished bulk import.
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_bulk() {
+            eprintln!("failed to finish bulk append: {e}");
+        }
     }
 }
 
@@ -569,3 +1986,451 @@ pub fn open_cube(path: &str) -> io::Result<Writer> {
 pub fn read_cube(path: &str) -> io::Result<Writer> {
     Writer::create(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Append `records` to a fresh temp cube and return its raw bytes.
+    fn cube_bytes(tag: &str, records: &[(String, String)]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!(
+            "akasha-prop-{tag}-{}-{:?}.cube",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_file(&path);
+        {
+            let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+            for (ph, no) in records {
+                w.append(ph, no).unwrap();
+            }
+        }
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    fn records() -> impl Strategy<Value = Vec<(String, String)>> {
+        prop::collection::vec((".{0,64}", ".{0,256}"), 0..8)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_append_decode_roundtrip(recs in records()) {
+            let bytes = cube_bytes("rt", &recs);
+            let events = Writer::decode(&bytes).unwrap();
+            prop_assert_eq!(events.len(), recs.len());
+            let check = Writer::check(&bytes).unwrap();
+            prop_assert_eq!((check.records, check.valid_len), (recs.len(), bytes.len() as u64));
+            for (i, (ev, (ph, no))) in events.iter().zip(&recs).enumerate() {
+                prop_assert_eq!(ev.id, i as u64 + 1);
+                prop_assert_eq!(&ev.phenomenon, ph);
+                prop_assert_eq!(&ev.noumenon, no);
+            }
+        }
+
+        #[test]
+        fn test_corrupted_cube_yields_valid_prefix(
+            recs in records(),
+            flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..4),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let original = Writer::decode(&cube_bytes("corrupt", &recs)).unwrap();
+            let mut bytes = cube_bytes("corrupt", &recs);
+            let body = bytes.len() - Writer::header_len(Writer::VERSION) as usize;
+            if body > 0 {
+                for (at, x) in &flips {
+                    bytes[Writer::header_len(Writer::VERSION) as usize + at.index(body)] ^= x;
+                }
+                bytes.truncate(Writer::header_len(Writer::VERSION) as usize + cut.index(body + 1));
+            }
+            // Must not panic; whatever survives is a prefix of the original records.
+            let decoded = Writer::decode(&bytes).unwrap();
+            prop_assert!(decoded.len() <= original.len());
+            let check = Writer::check(&bytes).unwrap();
+            prop_assert!(check.valid_len <= check.len);
+            for (d, o) in decoded.iter().zip(&original) {
+                prop_assert_eq!(d.id, o.id);
+                prop_assert_eq!(&d.phenomenon, &o.phenomenon);
+                prop_assert_eq!(&d.noumenon, &o.noumenon);
+            }
+        }
+
+        #[test]
+        fn test_arbitrary_bytes_never_panic(tail in prop::collection::vec(any::<u8>(), 0..512)) {
+            let mut bytes = b"AKLA\x01\0\x01\0\0\0\0\0\0\0\0\0".to_vec();
+            bytes.extend_from_slice(&tail);
+            let _ = Writer::decode(&bytes);
+            let _ = Writer::parse_payload(&tail, 1);
+            let _ = Writer::parse_payload(&tail, 2);
+        }
+    }
+
+    #[test]
+    fn test_bulk_import_and_recovery() {
+        let path = std::env::temp_dir().join(format!("akasha-bulk-{}.cube", std::process::id()));
+        let crashed = path.with_extension("crashed.cube");
+        let _ = fs::remove_file(&path);
+        let header_next_id = |p: &Path| {
+            let bytes = fs::read(p).unwrap();
+            u64::from_le_bytes(bytes[6..14].try_into().unwrap())
+        };
+
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+        w.append("before", "0").unwrap();
+        let rx = w.subscribe();
+        w.begin_bulk().unwrap();
+        for i in 0..3 {
+            w.append(&format!("f{i}"), "x").unwrap();
+        }
+        assert_eq!(w.events().unwrap().len(), 4); // flushes the buffer
+        assert_eq!(header_next_id(&path), 0);
+        assert!(
+            rx.try_recv().is_err(),
+            "no notification before the import is durable"
+        );
+
+        // A copy taken now is what a crash before `finish_bulk` leaves behind.
+        fs::copy(&path, &crashed).unwrap();
+
+        w.finish_bulk().unwrap();
+        assert_eq!(header_next_id(&path), 5);
+        assert_eq!(rx.try_iter().map(|e| e.id).collect::<Vec<_>>(), [2, 3, 4]);
+        drop(w);
+
+        let recovered = Writer::create(crashed.to_str().unwrap()).unwrap();
+        assert_eq!(recovered.next_id(), 5);
+        assert_eq!(header_next_id(&crashed), 5);
+        drop(recovered);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&crashed).unwrap();
+    }
+
+    #[test]
+    fn test_head_and_tail() {
+        let path = std::env::temp_dir().join(format!("akasha-ht-{}.cube", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+        for i in 0..5 {
+            w.append(&format!("f{i}"), "x").unwrap();
+        }
+        let ids = |evs: Vec<Event>| evs.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(w.head(2).unwrap()), [1, 2]);
+        assert_eq!(ids(w.tail(2).unwrap()), [4, 5]);
+        assert_eq!(ids(w.tail(10).unwrap()), [1, 2, 3, 4, 5]);
+        assert!(w.tail(0).unwrap().is_empty());
+        let third = w.rebuild_index().unwrap()[&3] as usize;
+        drop(w);
+
+        // Corrupt the payload of record 3: both ends stop there, as `events` does.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[third + 4 + 16] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let mut w = Writer::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(ids(w.events().unwrap()), [1, 2]);
+        assert_eq!(ids(w.tail(2).unwrap()), [1, 2]);
+        assert_eq!(ids(w.head(5).unwrap()), [1, 2]);
+        drop(w);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_store_directory_at_mixed_path_forms() {
+        let dir = std::env::temp_dir().join(format!("akasha-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        let cube = dir.join("store.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        // A root spelled differently from the walked directory, like `.` versus an absolute path.
+        w.store_directory_at(dir.join("src/.."), dir.join("src"))
+            .unwrap();
+        let phenomena: Vec<String> = w
+            .events()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.phenomenon)
+            .collect();
+        assert_eq!(phenomena, ["src/main.rs"]);
+        drop(w);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_paths_at_excludes_and_counts() {
+        let dir = std::env::temp_dir().join(format!("akasha-store-paths-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cube = dir.join("store.cube");
+        let dir = dir.join("work");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("docs/gen")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/debug.log"), "noise").unwrap();
+        fs::write(dir.join("docs/gen/api.md"), "api").unwrap();
+        fs::write(dir.join("README"), "hi").unwrap();
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let mut exclude = globset::GlobSetBuilder::new();
+        exclude.add(globset::Glob::new("*.log").unwrap());
+        exclude.add(globset::Glob::new("docs/gen").unwrap());
+        let exclude = exclude.build().unwrap();
+
+        // Overlapping targets store each file once.
+        let stored = w
+            .store_paths_at(&dir, &[dir.join("src"), dir.join("src/main.rs")], &exclude)
+            .unwrap();
+        assert_eq!((stored.new, stored.updated, stored.unchanged), (1, 0, 0));
+        assert_eq!(stored.bytes, 12);
+
+        fs::write(dir.join("src/main.rs"), "fn main() { }").unwrap();
+        let stored = w.store_paths_at(&dir, &[&dir], &exclude).unwrap();
+        assert_eq!((stored.new, stored.updated, stored.unchanged), (1, 1, 0));
+        let phenomena: Vec<String> = w
+            .events()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.phenomenon)
+            .collect();
+        assert_eq!(phenomena, ["src/main.rs", "README", "src/main.rs"]);
+        drop(w);
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_resumes_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("akasha-cp-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("c.cube");
+        let cube = path.to_str().unwrap();
+        let zero_next_id = || {
+            let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+            Writer::write_header_next_id(&mut f, 0).unwrap();
+        };
+
+        let mut w = Writer::create(cube).unwrap();
+        w.append("a", "1").unwrap();
+        w.append("b", "2").unwrap();
+        w.checkpoint().unwrap();
+        w.append("c", "3").unwrap();
+        drop(w);
+
+        // Records before the checkpoint are not rescanned: its (doctored) max id wins.
+        let mut cp = checkpoint::load(&path).unwrap();
+        assert_eq!((cp.max_id, cp.seen.len()), (Some(2), 2));
+        cp.max_id = Some(100);
+        checkpoint::save(&path, &cp).unwrap();
+        zero_next_id();
+        assert_eq!(Writer::create(cube).unwrap().next_id(), 101);
+
+        // A rewritten cube no longer matches the checkpoint anchor: full scan.
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let events = Writer::decode(&fs::read(&path).unwrap()).unwrap();
+        Writer::write_image(&mut f, &events[1..], 0, None).unwrap();
+        drop(f);
+        assert_eq!(Writer::create(cube).unwrap().next_id(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_record_is_rejected() {
+        let path = std::env::temp_dir().join(format!("akasha-big-{}.cube", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+        let big = "x".repeat(u16::MAX as usize + 1);
+        let err = w.append("big.txt", &big).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(w.events().unwrap().is_empty());
+        drop(w);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_types_and_v1_cubes() {
+        let dir = std::env::temp_dir().join(format!("akasha-types-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let fill = |w: &mut Writer| {
+            w.append("a.txt", "1").unwrap();
+            w.append("commit", r#"{"id":1}"#).unwrap();
+            w.append_typed(RecordType::Custom(0x90), "sensor", "42")
+                .unwrap();
+            assert!(w.append_typed(RecordType::Custom(5), "x", "").is_err());
+        };
+
+        let v4 = dir.join("v4.cube");
+        let mut w = Writer::create(v4.to_str().unwrap()).unwrap();
+        fill(&mut w);
+        assert_eq!(w.version(), 4);
+        let kinds: Vec<RecordType> = w.events().unwrap().iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                RecordType::File,
+                RecordType::Commit,
+                RecordType::Custom(0x90)
+            ]
+        );
+        let custom = w.events_of(&[RecordType::Custom(0x90)]).unwrap();
+        assert_eq!(custom[0].phenomenon, "sensor");
+        drop(w);
+
+        // A version 1 cube stays one: no type byte, kinds inferred, also after compaction.
+        let v1 = dir.join("v1.cube");
+        fs::write(&v1, b"AKLA\x01\0\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut w = Writer::create(v1.to_str().unwrap()).unwrap();
+        fill(&mut w);
+        assert_eq!(w.version(), 1);
+        let events = w.events().unwrap();
+        assert_eq!(events[2].kind, RecordType::File);
+        let len = |e: &Event| Writer::record_len(1, e);
+        assert_eq!(
+            fs::metadata(&v1).unwrap().len(),
+            16 + events.iter().map(len).sum::<u64>()
+        );
+        drop(w);
+        let mut f = File::options().read(true).write(true).open(&v1).unwrap();
+        Writer::write_image(&mut f, &events[1..], 4, None).unwrap();
+        assert_eq!(Writer::version_of(&v1).unwrap(), 1);
+        assert_eq!(Writer::decode(&fs::read(&v1).unwrap()).unwrap().len(), 2);
+
+        fs::write(&v1, b"AKLA\x04\0\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        assert!(Writer::open(v1.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_identity_and_generations() {
+        let dir = std::env::temp_dir().join(format!("akasha-identity-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.cube"), dir.join("b.cube"));
+        let mut w = Writer::create(a.to_str().unwrap()).unwrap();
+        w.append("a.txt", "1").unwrap();
+        w.append("a.txt", "2").unwrap();
+        let identity = w.identity().unwrap().unwrap();
+        assert_eq!((identity.uuid.len(), identity.generation), (36, 0));
+        assert_eq!(&identity.uuid[14..15], "4");
+        let other = Writer::create(b.to_str().unwrap())
+            .unwrap()
+            .identity()
+            .unwrap()
+            .unwrap();
+        assert_ne!(other.uuid, identity.uuid);
+
+        // Rewrites bump the generation, explicit identities are kept as given.
+        let events = w.events().unwrap();
+        let mut f = File::options().read(true).write(true).open(&a).unwrap();
+        Writer::write_image(&mut f, &events[1..], 3, None).unwrap();
+        assert_eq!(w.identity().unwrap(), Some(identity.bumped()));
+        Writer::write_image(&mut f, &events[1..], 3, Some(&other)).unwrap();
+        let bytes = fs::read(&a).unwrap();
+        assert_eq!(Writer::image_identity(&bytes).unwrap(), Some(other.clone()));
+        assert_eq!(Writer::decode(&bytes).unwrap().len(), 1);
+        Writer::truncate_to(&mut f, Writer::header_len(3), 1).unwrap();
+        assert_eq!(Writer::identity_of(&a).unwrap(), Some(other.bumped()));
+        // Older cubes have none, and a header cut short is an error.
+        fs::write(&b, b"AKLA\x02\0\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        assert_eq!(Writer::identity_of(&b).unwrap(), None);
+        assert!(Writer::image_identity(&bytes[..20]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_append_events_verbatim() {
+        let path =
+            std::env::temp_dir().join(format!("akasha-verbatim-{}.cube", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut w = Writer::create(path.to_str().unwrap()).unwrap();
+        let ev = |id: u64, ph: &str| {
+            Event::builder()
+                .id(id)
+                .phenomenon(ph)
+                .noumenon(format!("n{id}"))
+                .timestamp(1_000 + id as u128)
+                .build()
+                .unwrap()
+        };
+        let a = w.append_event(&ev(10, "a")).unwrap();
+        assert_eq!((a.offset, a.id), (40, 10));
+        assert_eq!(w.next_id(), 11);
+        assert_eq!(w.append("b", "n").unwrap(), 83);
+
+        // Nothing is written when any event is rejected.
+        let mut big = ev(20, "big");
+        big.noumenon = "x".repeat(u16::MAX as usize + 1);
+        for bad in [
+            vec![ev(12, "c"), ev(12, "d")],
+            vec![ev(5, "old")],
+            vec![big],
+        ] {
+            let err = w.append_events(&bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(w.events().unwrap().len(), 2);
+
+        w.begin_bulk().unwrap();
+        let ids: Vec<u64> = w
+            .append_events(&[ev(12, "c"), ev(30, "d")])
+            .unwrap()
+            .iter()
+            .map(|a| a.id)
+            .collect();
+        w.finish_bulk().unwrap();
+        assert_eq!(ids, [12, 30]);
+        assert_eq!(w.next_id(), 31);
+        let fields = |e: &Event| (e.id, e.timestamp, e.phenomenon.clone(), e.noumenon.clone());
+        let events: Vec<_> = w.events().unwrap().iter().map(fields).collect();
+        assert_eq!(events[1].0, 11);
+        let expected = [ev(10, "a"), ev(12, "c"), ev(30, "d")].map(|e| fields(&e));
+        assert_eq!([&events[0], &events[2], &events[3]], expected.each_ref());
+        drop(w);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_identical_contents_are_referenced() {
+        let dir = std::env::temp_dir().join(format!("akasha-dedup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a.txt"), "same").unwrap();
+        fs::write(dir.join("b/copy.txt"), "same").unwrap();
+        let cube = dir.join("dedup.cube");
+        let mut w = Writer::create(cube.to_str().unwrap()).unwrap();
+        let stored = w
+            .store_paths_at(&dir, &[dir.join("a.txt"), dir.join("b")], &GlobSet::empty())
+            .unwrap();
+        assert_eq!((stored.new, stored.referenced, stored.bytes), (2, 1, 4));
+
+        let hash = blake3::hash(b"same").to_hex().to_string();
+        assert_eq!(w.find_by_hash(&hash).unwrap(), Some(1));
+        assert_eq!(w.find_by_hash("00").unwrap(), None);
+        let stored = Writer::decode(&fs::read(&cube).unwrap()).unwrap();
+        assert_eq!(stored[1].kind, RecordType::Ref);
+        let r: ContentRef = serde_json::from_str(&stored[1].noumenon).unwrap();
+        assert_eq!((r.id, r.hash.as_str()), (1, hash.as_str()));
+        for events in [w.events().unwrap(), w.tail(1).unwrap()] {
+            let copy = events.last().unwrap();
+            assert_eq!(
+                (copy.phenomenon.as_str(), copy.noumenon.as_str(), copy.kind),
+                ("b/copy.txt", "same", RecordType::File)
+            );
+        }
+
+        // Dropping the referenced record gives the reference its contents.
+        let (mut dropped, mut kept) = (stored[..1].to_vec(), stored[1..].to_vec());
+        Writer::detach_refs(&mut kept, &mut dropped);
+        assert_eq!(
+            (kept[0].noumenon.as_str(), kept[0].kind),
+            ("same", RecordType::File)
+        );
+        drop(w);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,11 @@
+use crate::data::write::Writer;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[doc = "Represent an Event"]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     #[doc = "id of the event"]
     pub id: u64,
@@ -12,20 +15,103 @@ pub struct Event {
     pub noumenon: String,
     #[doc = "timestamp of the event"]
     pub timestamp: u128,
+    #[doc = "kind of record holding the event"]
+    #[serde(default)]
+    pub kind: RecordType,
+    /// Attributes of the record, such as its provenance (see `data::provenance`). Stored by
+    /// format v4 cubes and later only.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Kind of a record, stored as one byte by cubes of format v2 and later (see `data::write`).
+///
+/// Format v1 records have no such byte: their kind is inferred from the phenomenon
+/// (`RecordType::infer`), as it is for records appended without an explicit kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecordType {
+    /// File contents, under the portable path of the file.
+    #[default]
+    File,
+    /// A seal (`commit`).
+    Commit,
+    /// Repository metadata: `branch:<name>`, `manifest:<id>`, `commit:pending`, …
+    Meta,
+    /// Removal of a path (`path:remove`).
+    Tombstone,
+    /// Part of a content split across several records.
+    Chunk,
+    /// File contents already stored by an earlier record of the cube, which it names instead
+    /// of repeating them (see `data::write::ContentRef`).
+    Ref,
+    /// Defined by an application, from `RecordType::CUSTOM_MIN` up.
+    Custom(u8),
+}
+
+impl RecordType {
+    /// Smallest byte of `Custom` kinds; the ones below are reserved for this library.
+    pub const CUSTOM_MIN: u8 = 0x80;
+
+    /// Phenomenon of removal tombstones, the records of kind `Tombstone`.
+    pub const REMOVE: &'static str = "path:remove";
+
+    /// Kind of a record with `phenomenon`, going by the phenomena `ak` writes.
+    pub fn infer(phenomenon: &str) -> Self {
+        match phenomenon {
+            "commit" => Self::Commit,
+            Self::REMOVE => Self::Tombstone,
+            ph if ph.contains(':') => Self::Meta,
+            _ => Self::File,
+        }
+    }
+
+    /// On-disk byte.
+    pub fn byte(self) -> u8 {
+        match self {
+            Self::File => 0,
+            Self::Commit => 1,
+            Self::Meta => 2,
+            Self::Tombstone => 3,
+            Self::Chunk => 4,
+            Self::Ref => 5,
+            Self::Custom(b) => b,
+        }
+    }
+
+    /// Kind of the on-disk byte `b`. Reserved bytes unknown to this version read as `Custom`.
+    pub fn from_byte(b: u8) -> Self {
+        match b {
+            0 => Self::File,
+            1 => Self::Commit,
+            2 => Self::Meta,
+            3 => Self::Tombstone,
+            4 => Self::Chunk,
+            5 => Self::Ref,
+            b => Self::Custom(b),
+        }
+    }
+
+    /// `InvalidInput` for a `Custom` kind below `CUSTOM_MIN`, which would not read back as
+    /// itself.
+    pub fn validate(self) -> io::Result<()> {
+        match self {
+            Self::Custom(b) if b < Self::CUSTOM_MIN => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "custom record type {b} is reserved (custom types start at {})",
+                    Self::CUSTOM_MIN
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Event {
+    /// Start building an event; see `EventBuilder`.
     #[must_use]
-    pub fn new(id: u64, phenomenon: &str, noumenon: &str) -> Self {
-        Self {
-            id,
-            phenomenon: phenomenon.to_string(),
-            noumenon: noumenon.to_string(),
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis(),
-        }
+    pub fn builder() -> EventBuilder {
+        EventBuilder::default()
     }
 
     pub fn get_id(&self) -> u64 {
@@ -48,12 +134,129 @@ impl Event {
     }
 }
 
+/// Builder of an `Event` that fits in a cube record.
+///
+/// Phenomenon and noumenon may be given as strings or raw bytes; `build` checks that they are
+/// UTF-8 and within `Writer::MAX_FIELD_LEN` bytes, and that the attributes fit in the attribute
+/// block of a record, so that the event can be written as is (see `Writer::append_event`). The
+/// timestamp defaults to now, in nanoseconds like the ones the writer assigns.
+///
+/// There are no setters for tags or parents: a record has neither. The parents of a seal are
+/// part of its noumenon (`parent`, `merge_parent`), and a tag is a record of its own
+/// (`tag:<name>`, see `data::tag`).
+#[derive(Debug, Clone, Default)]
+pub struct EventBuilder {
+    id: u64,
+    phenomenon: Vec<u8>,
+    noumenon: Vec<u8>,
+    timestamp: Option<u128>,
+    kind: Option<RecordType>,
+    attributes: BTreeMap<String, String>,
+}
+
+impl EventBuilder {
+    #[must_use]
+    pub fn id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    #[must_use]
+    pub fn phenomenon(mut self, phenomenon: impl AsRef<[u8]>) -> Self {
+        self.phenomenon = phenomenon.as_ref().to_vec();
+        self
+    }
+
+    #[must_use]
+    pub fn noumenon(mut self, noumenon: impl AsRef<[u8]>) -> Self {
+        self.noumenon = noumenon.as_ref().to_vec();
+        self
+    }
+
+    /// UNIX epoch time in nanoseconds.
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: u128) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Kind of record, inferred from the phenomenon by default.
+    #[must_use]
+    pub fn kind(mut self, kind: RecordType) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Set the attribute `key` to `value`.
+    #[must_use]
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Replace all attributes with `attributes`.
+    #[must_use]
+    pub fn attributes(mut self, attributes: BTreeMap<String, String>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// The event, or `InvalidInput` if a field is not UTF-8 or too large for a record, the
+    /// attributes do not fit in one, or the kind is a reserved `Custom` one.
+    pub fn build(self) -> io::Result<Event> {
+        let timestamp = match self.timestamp {
+            Some(ts) => ts,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| io::Error::other("SystemTime before UNIX_EPOCH"))?
+                .as_nanos(),
+        };
+        let phenomenon = field("phenomenon", self.phenomenon)?;
+        let kind = self.kind.unwrap_or_else(|| RecordType::infer(&phenomenon));
+        kind.validate()?;
+        Writer::encode_attributes(&self.attributes)?;
+        Ok(Event {
+            id: self.id,
+            noumenon: field("noumenon", self.noumenon)?,
+            phenomenon,
+            timestamp,
+            kind,
+            attributes: self.attributes,
+        })
+    }
+}
+
+/// `bytes` of the field `name` as a string, checked for a record.
+fn field(name: &str, bytes: Vec<u8>) -> io::Result<String> {
+    if bytes.len() > Writer::MAX_FIELD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{name} too large ({} bytes, max {})",
+                bytes.len(),
+                Writer::MAX_FIELD_LEN
+            ),
+        ));
+    }
+    String::from_utf8(bytes).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} is not UTF-8: {e}"),
+        )
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     pub fn test_event() {
-        let mut e = Event::new(1, "test", "test");
+        let mut e = Event::builder()
+            .id(1)
+            .phenomenon("test")
+            .noumenon("test")
+            .build()
+            .unwrap();
         assert_eq!(e.get_id(), 1);
         assert_eq!(e.get_phenomenon(), "test");
         assert_eq!(e.get_noumenon(), "test");
@@ -62,4 +265,52 @@ mod tests {
         assert_eq!(e.get_phenomenon(), "test2");
         assert_eq!(e.get_noumenon(), "test3");
     }
+
+    #[test]
+    pub fn test_builder_validation() {
+        let e = Event::builder()
+            .phenomenon(b"a.txt")
+            .timestamp(7)
+            .build()
+            .unwrap();
+        assert_eq!((e.phenomenon.as_str(), e.noumenon.as_str()), ("a.txt", ""));
+        assert_eq!(e.timestamp, 7);
+
+        let big = vec![b'x'; Writer::MAX_FIELD_LEN + 1];
+        for b in [
+            Event::builder().noumenon(&big),
+            Event::builder().phenomenon([0xff, 0xfe]),
+        ] {
+            assert_eq!(b.build().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(Event::builder().noumenon(&big[1..]).build().is_ok());
+        let e = Event::builder().attribute("host", "h").build().unwrap();
+        assert_eq!(e.attributes["host"], "h");
+        let long_key = "k".repeat(256);
+        assert!(Event::builder().attribute(&long_key, "").build().is_err());
+        assert!(
+            Event::builder()
+                .kind(RecordType::Custom(7))
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_record_type() {
+        let infer = |ph: &str| Event::builder().phenomenon(ph).build().unwrap().kind;
+        assert_eq!(infer("src/a.rs"), RecordType::File);
+        assert_eq!(infer("commit"), RecordType::Commit);
+        assert_eq!(infer("commit:pending"), RecordType::Meta);
+        assert_eq!(infer("path:remove"), RecordType::Tombstone);
+        for kind in [
+            RecordType::Chunk,
+            RecordType::Ref,
+            RecordType::Custom(0x80),
+            RecordType::Custom(0xff),
+        ] {
+            assert_eq!(RecordType::from_byte(kind.byte()), kind);
+        }
+        assert_eq!(RecordType::from_byte(9), RecordType::Custom(9));
+    }
 }
@@ -0,0 +1,7 @@
+//! Akasha (Eikyu) core library: append-only cubes, events, and the data layer shared by the
+//! `eikyu` and `ak` binaries.
+
+#[cfg(feature = "aio")]
+pub mod aio;
+pub mod data;
+pub mod event;
@@ -1,10 +1,16 @@
-use crate::data::write::{self, Writer};
 use clap::{Arg, ArgMatches, Command};
+use eikyu::data::backup;
+use eikyu::data::compare;
+use eikyu::data::dump;
+use eikyu::data::export;
+use eikyu::data::fork;
+use eikyu::data::replay;
+use eikyu::data::retention;
+use eikyu::data::schema;
+use eikyu::data::script;
+use eikyu::data::write::{self, Writer};
 use std::path::Path;
-
-// ... existing code ...
-pub mod data;
-pub mod event;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn cli() -> ArgMatches {
     Command::new("akasha")
@@ -24,7 +30,13 @@ fn cli() -> ArgMatches {
                     Command::new("directory")
                         .about("Save directory content in a cube")
                         .arg(Arg::new("path").required(true))
-                        .arg(Arg::new("of").required(true)),
+                        .arg(Arg::new("of").required(true))
+                        .arg(
+                            Arg::new("bulk")
+                                .long("bulk")
+                                .help("Buffer writes and sync once at the end (fast imports)")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
                 )
                 .subcommand(
                     Command::new("hierarchy")
@@ -32,6 +44,41 @@ fn cli() -> ArgMatches {
                         .arg(Arg::new("directory").required(true)),
                 ),
         )
+        .subcommand(
+            Command::new("replay")
+                .about("Re-emit selected records of a cube into another cube or stdout")
+                .arg(
+                    Arg::new("src")
+                        .help("Source cube")
+                        .required(true)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Destination cube (defaults to stdout)"),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .help("Selection, e.g. 'ph=src/**,id>=10,no~TODO'"),
+                )
+                .arg(
+                    Arg::new("map")
+                        .long("map")
+                        .help("Phenomenon template using {ph}, {id}, {ts}, {name}, {dir}"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Only records at or after this time (YYYY-MM-DD or RFC 3339)"),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .help("Only records before this time (YYYY-MM-DD or RFC 3339)"),
+                ),
+        )
         .subcommand(Command::new("sonar").about("Probe semantic hyperspace with a query"))
         .subcommand(Command::new("merge").about("Merge multiple cubes into one"))
         .subcommand(Command::new("connect").about("Connect two cubes to exchange wisdom"))
@@ -69,13 +116,202 @@ fn cli() -> ArgMatches {
                                 .help("Name of the cube")
                                 .required(true)
                                 .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("raw")
+                                .long("raw")
+                                .help("Hexdump the on-disk bytes of one record (needs --id or --offset)")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("decode")
+                                .long("decode")
+                                .help("Pretty-print the payloads of known record types, checked against their schema")
+                                .conflicts_with("raw")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("id")
+                                .long("id")
+                                .help("Record id to dump")
+                                .requires("raw")
+                                .conflicts_with("offset")
+                                .value_parser(clap::value_parser!(u64)),
+                        )
+                        .arg(
+                            Arg::new("offset")
+                                .long("offset")
+                                .help("File offset of the record to dump (for records past a corruption)")
+                                .requires("raw")
+                                .value_parser(clap::value_parser!(u64)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("head")
+                        .about("Print the first records of a cube")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("n")
+                                .short('n')
+                                .help("Number of records")
+                                .default_value("10")
+                                .value_parser(clap::value_parser!(usize)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("tail")
+                        .about("Print the last records of a cube")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("n")
+                                .short('n')
+                                .help("Number of records")
+                                .default_value("10")
+                                .value_parser(clap::value_parser!(usize)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rotate")
+                        .about("Move old records into a dated archive cube per the retention policy")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("max-bytes")
+                                .long("max-bytes")
+                                .help("Override the policy's maximum cube size in bytes")
+                                .value_parser(clap::value_parser!(u64)),
+                        )
+                        .arg(
+                            Arg::new("max-age")
+                                .long("max-age")
+                                .help("Override the policy's maximum record age (e.g. 90d, 12h)"),
+                        )
+                        .arg(
+                            Arg::new("max-versions")
+                                .long("max-versions")
+                                .help("Override the policy's number of versions kept per phenomenon")
+                                .value_parser(clap::value_parser!(usize)),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Only report what would be archived")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("backup")
+                        .about("Snapshot a cube, verify the copy and prune old generations")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .help("Destination directory or s3://bucket/prefix (default: backups/ next to the cube)"),
+                        )
+                        .arg(
+                            Arg::new("keep")
+                                .long("keep")
+                                .help("Number of generations to keep")
+                                .value_parser(clap::value_parser!(usize)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("Compare the records of two cubes, or of one cube at two record ids")
+                        .arg(
+                            Arg::new("a")
+                                .help("Cube (left side)")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("b")
+                                .help("Cube (right side)")
+                                .required_unless_present_any(["from-id", "to-id"])
+                                .conflicts_with_all(["from-id", "to-id"])
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("from-id")
+                                .long("from-id")
+                                .help("Left side: the cube up to this record id (default: empty)")
+                                .value_parser(clap::value_parser!(u64)),
+                        )
+                        .arg(
+                            Arg::new("to-id")
+                                .long("to-id")
+                                .help("Right side: the cube up to this record id (default: all)")
+                                .value_parser(clap::value_parser!(u64)),
                         ),
                 )
                 .subcommand(Command::new("ping").about("Check if a cube is responsive"))
                 .subcommand(Command::new("validate").about("Validate the integrity of a cube"))
                 .subcommand(Command::new("clone").about("Clone a cube"))
+                .subcommand(
+                    Command::new("fork")
+                        .about("Copy a cube into a new one, linking both to the fork point")
+                        .arg(
+                            Arg::new("src")
+                                .help("Cube to fork")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("dst")
+                                .help("New cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        ),
+                )
                 .subcommand(Command::new("bubble").about("Create a ephemeral clone of a cube"))
-                .subcommand(Command::new("export").about("Export a cube to a file"))
+                .subcommand(
+                    Command::new("export")
+                        .about("Export a cube to a file")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the cube")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .help("Output format")
+                                .default_value("sqlite")
+                                .value_parser(["sqlite", "parquet"]),
+                        )
+                        .arg(
+                            Arg::new("with-content")
+                                .long("with-content")
+                                .help("Include record content in columnar exports")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .help("Output file")
+                                .required(true)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                        ),
+                )
                 .subcommand(Command::new("import").about("Import a cube from a file"))
                 .subcommand(
                     Command::new("mode")
@@ -104,6 +340,56 @@ fn main() {
                     let name: &String = create_matches
                         .get_one::<String>("name")
                         .expect("name is required");
+                    if create_matches.get_flag("raw") {
+                        let offset = match (
+                            create_matches.get_one::<u64>("id"),
+                            create_matches.get_one::<u64>("offset"),
+                        ) {
+                            (_, Some(offset)) => *offset,
+                            (Some(id), None) => {
+                                let mut reader = write::read_cube(name.as_str())
+                                    .expect("failed to open cube file");
+                                let index = reader.rebuild_index().expect("failed to index cube");
+                                match index.get(id) {
+                                    Some(offset) => *offset,
+                                    None => {
+                                        println!("No valid record with id {id}.");
+                                        return;
+                                    }
+                                }
+                            }
+                            (None, None) => {
+                                println!("--raw needs --id <id> or --offset <offset>.");
+                                return;
+                            }
+                        };
+                        let raw =
+                            Writer::read_raw_at(name, offset).expect("failed to read cube file");
+                        let version = Writer::version_of(name).expect("failed to read cube header");
+                        print!("{}", dump::annotate(offset, &raw, version));
+                        return;
+                    }
+                    if create_matches.get_flag("decode") {
+                        let mut reader =
+                            write::read_cube(name.as_str()).expect("failed to open cube file");
+                        for ev in reader.events().expect("failed to read cube file") {
+                            println!(
+                                "\nid={} ts={} kind={:?} ph={}",
+                                ev.id, ev.timestamp, ev.kind, ev.phenomenon
+                            );
+                            match schema::decode(&ev) {
+                                Some(Ok(v)) => println!(
+                                    "{}",
+                                    serde_json::to_string_pretty(&v).expect("serialize payload")
+                                ),
+                                Some(Err(e)) => {
+                                    println!("!! does not match its schema: {e}\n{}", ev.noumenon)
+                                }
+                                None => println!("{}", ev.noumenon),
+                            }
+                        }
+                        return;
+                    }
                     println!("\nReading cube: {name}\n");
                     // Use helper to get a reader-capable Writer and print all records.
                     let mut reader =
@@ -114,6 +400,226 @@ fn main() {
                     println!("Cube not exists.");
                 }
             }
+            Some((which @ ("head" | "tail"), edge_matches)) => {
+                let name: &String = edge_matches
+                    .get_one::<String>("name")
+                    .expect("name is required");
+                let n = *edge_matches.get_one::<usize>("n").expect("n has a default");
+                if !Path::new(name).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                let mut reader = write::read_cube(name.as_str()).expect("failed to open cube file");
+                let events = if which == "head" {
+                    reader.head(n)
+                } else {
+                    reader.tail(n)
+                }
+                .expect("failed to read cube file");
+                for ev in events {
+                    println!(
+                        "\nid={} ts={} ph={} no={}\n",
+                        ev.id, ev.timestamp, ev.phenomenon, ev.noumenon
+                    );
+                }
+            }
+            Some(("diff", diff_matches)) => {
+                let a: &String = diff_matches.get_one::<String>("a").expect("a is required");
+                let b = diff_matches.get_one::<String>("b");
+                for name in std::iter::once(a).chain(b) {
+                    if !Path::new(name).exists() {
+                        println!("Cube not exists: {name}");
+                        return;
+                    }
+                }
+                let events = |name: &str| {
+                    Writer::open(name)
+                        .and_then(|mut r| r.events())
+                        .expect("failed to read cube file")
+                };
+                let left = events(a);
+                let (left, right) = match b {
+                    Some(b) => (left, events(b)),
+                    None => {
+                        let from = diff_matches.get_one::<u64>("from-id").copied().unwrap_or(0);
+                        let to = diff_matches
+                            .get_one::<u64>("to-id")
+                            .copied()
+                            .unwrap_or(u64::MAX);
+                        (compare::as_of(&left, from), compare::as_of(&left, to))
+                    }
+                };
+                let cmp = compare::compare(&left, &right);
+                for ev in &cmp.only_left {
+                    println!("- id={} ts={} ph={}", ev.id, ev.timestamp, ev.phenomenon);
+                }
+                for ev in &cmp.only_right {
+                    println!("+ id={} ts={} ph={}", ev.id, ev.timestamp, ev.phenomenon);
+                }
+                for c in &cmp.changed {
+                    let id = |e: Option<u64>| e.map_or("none".to_string(), |id| format!("id={id}"));
+                    println!(
+                        "~ {} ({} -> {})",
+                        c.phenomenon,
+                        id(c.left.as_ref().map(|e| e.id)),
+                        id(c.right.as_ref().map(|e| e.id))
+                    );
+                }
+                println!(
+                    "{} only in left, {} only in right, {} in common, {} phenomena changed.",
+                    cmp.only_left.len(),
+                    cmp.only_right.len(),
+                    cmp.common,
+                    cmp.changed.len()
+                );
+            }
+            Some(("show", show_matches)) => {
+                let name: &String = show_matches
+                    .get_one::<String>("name")
+                    .expect("name is required");
+                if !Path::new(name).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                let bytes = std::fs::read(name).expect("failed to read cube");
+                let check = Writer::check(&bytes).expect("failed to check cube");
+                println!("Cube: {name}");
+                println!(
+                    "Version: {}",
+                    Writer::image_version(&bytes).expect("invalid cube header")
+                );
+                match Writer::image_identity(&bytes).expect("invalid cube header") {
+                    Some(identity) => {
+                        println!("UUID: {}", identity.uuid);
+                        println!("Generation: {}", identity.generation);
+                    }
+                    None => println!("UUID: none (format before version 3)"),
+                }
+                println!("Records: {} ({} bytes)", check.records, check.len);
+                if check.valid_len < check.len {
+                    println!(
+                        "Corrupt or truncated: {} byte(s) after the last valid record",
+                        check.len - check.valid_len
+                    );
+                }
+            }
+            Some(("fork", fork_matches)) => {
+                let src: &String = fork_matches
+                    .get_one::<String>("src")
+                    .expect("src is required");
+                let dst: &String = fork_matches
+                    .get_one::<String>("dst")
+                    .expect("dst is required");
+                if !Path::new(src).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                let forked =
+                    fork::fork(Path::new(src), Path::new(dst)).expect("failed to fork cube");
+                println!(
+                    "Forked {src} into {dst}: {} records in common, fork point id={}.",
+                    forked.records, forked.fork_point
+                );
+            }
+            Some(("backup", backup_matches)) => {
+                let name: &String = backup_matches
+                    .get_one::<String>("name")
+                    .expect("name is required");
+                if !Path::new(name).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                let mut opts = backup::load_for_cube(name).expect("invalid backup settings");
+                if let Some(to) = backup_matches.get_one::<String>("to") {
+                    opts.to = Some(to.clone());
+                }
+                if let Some(keep) = backup_matches.get_one::<usize>("keep") {
+                    opts.keep = *keep;
+                }
+                let report = backup::backup(name, &opts, chrono::Local::now())
+                    .expect("failed to back up cube");
+                println!(
+                    "Backed up {} records ({} bytes) to {}",
+                    report.records, report.bytes, report.location
+                );
+                for old in report.pruned {
+                    println!("Pruned {old}");
+                }
+            }
+            Some(("rotate", rotate_matches)) => {
+                let name: &String = rotate_matches
+                    .get_one::<String>("name")
+                    .expect("name is required");
+                if !Path::new(name).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                let mut policy = retention::load_for_cube(name)
+                    .expect("invalid retention policy")
+                    .unwrap_or_default();
+                if let Some(v) = rotate_matches.get_one::<u64>("max-bytes") {
+                    policy.max_bytes = Some(*v);
+                }
+                if let Some(v) = rotate_matches.get_one::<String>("max-age") {
+                    retention::parse_age(v).expect("invalid --max-age");
+                    policy.max_age = Some(v.clone());
+                }
+                if let Some(v) = rotate_matches.get_one::<usize>("max-versions") {
+                    policy.max_versions = Some(*v);
+                }
+                if policy.is_empty() {
+                    println!("No retention policy: create {name}.retention.json or pass limits.");
+                    return;
+                }
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("system time before UNIX epoch")
+                    .as_nanos();
+                let dry_run = rotate_matches.get_flag("dry-run");
+                let report =
+                    retention::rotate(name, &policy, now, dry_run).expect("failed to rotate cube");
+                let verb = if dry_run { "would be" } else { "were" };
+                println!(
+                    "{} records kept, {} {verb} archived ({} -> {} bytes).",
+                    report.kept, report.archived, report.bytes_before, report.bytes_after
+                );
+                if let Some(archive) = report.archive {
+                    println!("Archive: {}", archive.display());
+                }
+            }
+            Some(("export", export_matches)) => {
+                let name: &String = export_matches
+                    .get_one::<String>("name")
+                    .expect("name is required");
+                let out: &String = export_matches
+                    .get_one::<String>("out")
+                    .expect("out is required");
+                let format: &String = export_matches
+                    .get_one::<String>("format")
+                    .expect("format has a default");
+                if !Path::new(name).exists() {
+                    println!("Cube not exists.");
+                    return;
+                }
+                println!("Exporting cube {name} to {out} ({format})");
+                let count = match format.as_str() {
+                    "sqlite" => export::to_sqlite(name, Path::new(out)),
+                    #[cfg(feature = "parquet")]
+                    "parquet" => export::to_parquet(
+                        name,
+                        Path::new(out),
+                        export_matches.get_flag("with-content"),
+                    ),
+                    #[cfg(not(feature = "parquet"))]
+                    "parquet" => {
+                        println!("Parquet export requires building with `--features parquet`.");
+                        return;
+                    }
+                    other => unreachable!("unsupported format {other}"),
+                }
+                .expect("failed to export cube");
+                println!("{count} records exported.");
+            }
             Some((cmd, _)) => {
                 println!("cube subcommand: {cmd}");
             }
@@ -133,8 +639,9 @@ fn main() {
                 println!("Saving filename {name} to the {cube} cube");
 
                 // Open or create the cube in append-safe mode.
-                let mut writer =
-                    write::open_cube(cube.as_str()).expect("failed to open/create cube");
+                let mut writer = write::open_cube(cube.as_str())
+                    .and_then(script::attach)
+                    .expect("failed to open/create cube");
                 writer
                     .store_directory(name)
                     .expect("failed to save the directory content to the cube");
@@ -151,10 +658,19 @@ fn main() {
                 println!("Saving directory {name} content to the {cube} cube");
 
                 // Use Writer::create to append without truncating and keep header/id state
-                let mut writer = Writer::create(cube.as_str()).expect("failed to open/create cube");
+                let mut writer = Writer::create(cube.as_str())
+                    .and_then(script::attach)
+                    .expect("failed to open/create cube");
+                let bulk = file_matches.get_flag("bulk");
+                if bulk {
+                    writer.begin_bulk().expect("failed to enter bulk mode");
+                }
                 writer
                     .store_directory(Path::new(name))
                     .expect("failed to save the directory to the cube");
+                if bulk {
+                    writer.finish_bulk().expect("failed to finish bulk import");
+                }
             }
             Some((cmd, _)) => {
                 println!("save subcommand: {cmd}");
@@ -163,6 +679,33 @@ fn main() {
                 println!("Use a save subcommand (e.g., file, directory, hierarchy)");
             }
         }
+    } else if let Some(("replay", replay_matches)) = app.subcommand() {
+        let src: &String = replay_matches
+            .get_one::<String>("src")
+            .expect("src is required");
+        if !Path::new(src).exists() {
+            println!("Cube not exists.");
+            return;
+        }
+        let expr = replay_matches
+            .get_one::<String>("filter")
+            .map(String::as_str)
+            .unwrap_or("");
+        let mut filter = replay::Filter::parse(expr).expect("invalid filter");
+        filter.since = replay_matches
+            .get_one::<String>("since")
+            .map(|s| replay::parse_time(s).expect("invalid --since"));
+        filter.until = replay_matches
+            .get_one::<String>("until")
+            .map(|s| replay::parse_time(s).expect("invalid --until"));
+        let dst = replay_matches.get_one::<String>("to").map(String::as_str);
+        let map = replay_matches.get_one::<String>("map").map(String::as_str);
+
+        let count = replay::replay(src, dst, &filter, map).expect("failed to replay cube");
+        match dst {
+            Some(d) => println!("{count} records replayed into {d}."),
+            None => println!("{count} records replayed."),
+        }
     } else {
         println!("{app:?}");
     }